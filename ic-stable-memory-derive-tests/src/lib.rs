@@ -90,6 +90,155 @@ mod derive_tests {
 
         assert_eq!(c, c_copy);
     }
+
+    #[derive(StableType, AsFixedSizeBytes, PartialEq, Eq, Debug)]
+    #[stable_type(extensible)]
+    struct D1 {
+        x: u64,
+        y: u32,
+    }
+
+    #[derive(StableType, AsFixedSizeBytes, PartialEq, Eq, Debug)]
+    #[stable_type(extensible)]
+    struct D2 {
+        x: u64,
+        y: u32,
+        z: u16,
+    }
+
+    #[derive(StableType, AsFixedSizeBytes, PartialEq, Eq, Debug)]
+    struct E1 {
+        x: u64,
+        #[stable_type(skip)]
+        cache: Vec<u64>,
+        y: u32,
+    }
+
+    #[derive(StableType, AsFixedSizeBytes, PartialEq, Eq, Debug)]
+    struct E2(#[stable_type(skip)] Option<String>, u16);
+
+    #[test]
+    fn skip_works_fine() {
+        use ic_stable_memory::AsFixedSizeBytes;
+
+        assert_eq!(E1::SIZE, u64::SIZE + u32::SIZE);
+        assert_eq!(E2::SIZE, u16::SIZE);
+
+        let e1 = E1 {
+            x: 1,
+            cache: vec![1, 2, 3],
+            y: 2,
+        };
+        let e1_copy = E1::from_fixed_size_bytes(&e1.as_new_fixed_size_bytes());
+
+        assert_eq!(
+            e1_copy,
+            E1 {
+                x: 1,
+                cache: Vec::new(),
+                y: 2
+            }
+        );
+
+        let e2 = E2(Some(String::from("cached")), 10);
+        let e2_copy = E2::from_fixed_size_bytes(&e2.as_new_fixed_size_bytes());
+
+        assert_eq!(e2_copy, E2(None, 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid B enum tag: 3")]
+    fn invalid_enum_tag_should_panic() {
+        use ic_stable_memory::AsFixedSizeBytes;
+
+        let mut buf = B::Y(10).as_new_fixed_size_bytes();
+        buf[0] = 3;
+
+        B::from_fixed_size_bytes(&buf);
+    }
+
+    #[test]
+    fn extensible_works_fine() {
+        use ic_stable_memory::{AsDynSizeBytes, AsFixedSizeBytes};
+
+        assert_eq!(D1::SIZE, u16::SIZE + u64::SIZE + u32::SIZE);
+        assert_eq!(D2::SIZE, D1::SIZE + u16::SIZE);
+
+        let d_1 = D1 { x: 1, y: 2 };
+        let d_1_buf = d_1.as_dyn_size_bytes();
+        let d_2_from_d_1 = D2::from_dyn_size_bytes(&d_1_buf);
+
+        assert_eq!(d_2_from_d_1, D2 { x: 1, y: 2, z: 0 });
+
+        // trailing bytes (e.g. the padding of a memory block) are not mistaken for new fields
+        let mut d_1_buf = d_1.as_dyn_size_bytes();
+        d_1_buf.extend([u8::MAX; 10]);
+        let d_2_from_d_1 = D2::from_dyn_size_bytes(&d_1_buf);
+
+        assert_eq!(d_2_from_d_1, D2 { x: 1, y: 2, z: 0 });
+
+        let d_2 = D2 { x: 1, y: 2, z: 3 };
+        let d_2_buf = d_2.as_new_fixed_size_bytes();
+        let d_2_copy = D2::from_fixed_size_bytes(&d_2_buf);
+
+        assert_eq!(d_2, d_2_copy);
+    }
+
+    #[test]
+    fn extensible_storage_works_fine() {
+        use ic_stable_memory::collections::SVec;
+        use ic_stable_memory::{
+            _debug_validate_allocator, get_allocated_size, retrieve_custom_data,
+            stable_memory_init, store_custom_data, SBox,
+        };
+
+        ic_stable_memory::stable::clear();
+        stable_memory_init();
+
+        {
+            let boxed = SBox::new(D1 { x: 1, y: 2 }).unwrap();
+            store_custom_data(1, boxed);
+
+            let mut vec = SVec::new();
+            for i in 0..10 {
+                vec.push(SBox::new(D1 { x: i, y: i as u32 }).unwrap())
+                    .unwrap();
+            }
+            store_custom_data(2, SBox::new(vec).unwrap());
+
+            // the struct got a new field
+            let boxed = retrieve_custom_data::<D2>(1).unwrap();
+            assert_eq!(*boxed, D2 { x: 1, y: 2, z: 0 });
+
+            let mut vec = retrieve_custom_data::<SVec<SBox<D2>>>(2)
+                .unwrap()
+                .into_inner();
+            for i in 0..10 {
+                assert_eq!(
+                    *vec.get(i).unwrap(),
+                    D2 {
+                        x: i as u64,
+                        y: i as u32,
+                        z: 0
+                    }
+                );
+            }
+
+            vec.push(
+                SBox::new(D2 {
+                    x: 10,
+                    y: 10,
+                    z: 10,
+                })
+                .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(vec.get(10).unwrap().z, 10);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 }
 
 #[cfg(test)]
@@ -104,8 +253,8 @@ mod tests {
     };
     use ic_stable_memory::utils::DebuglessUnwrap;
     use ic_stable_memory::{
-        get_allocated_size, retrieve_custom_data, stable_memory_init, stable_memory_pre_upgrade,
-        store_custom_data, SBox, _debug_validate_allocator, stable_memory_post_upgrade,
+        _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable_memory_init,
+        stable_memory_post_upgrade, stable_memory_pre_upgrade, store_custom_data, SBox,
     };
     use rand::rngs::ThreadRng;
     use rand::{thread_rng, Rng};
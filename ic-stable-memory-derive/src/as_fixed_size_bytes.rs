@@ -1,20 +1,46 @@
 use proc_macro2::{self, TokenStream};
 use quote::{format_ident, quote};
-use syn::{Data, Fields, Generics, Ident, Index};
+use syn::{Attribute, Data, Fields, Generics, Ident, Index, Meta, NestedMeta};
+
+// checks for #[stable_type(<flag>)], e.g. #[stable_type(extensible)] or #[stable_type(skip)]
+pub(crate) fn has_stable_type_flag(attrs: &[Attribute], flag: &str) -> bool {
+    attrs
+        .iter()
+        .filter(|a| a.path.is_ident("stable_type"))
+        .any(|a| match a.parse_meta() {
+            Ok(Meta::List(list)) => list.nested.iter().any(|n| match n {
+                NestedMeta::Meta(Meta::Path(p)) => p.is_ident(flag),
+                _ => false,
+            }),
+            _ => panic!("Invalid stable_type attribute"),
+        })
+}
 
 pub fn derive_as_fixed_size_bytes_impl(
     ident: &Ident,
     data: &Data,
     generics: &Generics,
+    attrs: &[Attribute],
 ) -> TokenStream {
     if !generics.params.is_empty() {
         panic!("Generics not supported");
     }
 
+    let extensible = has_stable_type_flag(attrs, "extensible");
+
     let (as_fixed_size_body, from_fixed_size_body, size) = match data {
         Data::Struct(d) => {
-            let mut before = quote! { 0 };
-            let mut after = quote! { 0 };
+            // extensible structs are prefixed with the length of their fields, as it was at the
+            // moment of encoding - this prefix, and not the length of the buffer (which may be
+            // padded, e.g. by the allocator), tells which fields are present
+            let prefix = if extensible {
+                quote! { <u16 as ic_stable_memory::AsFixedSizeBytes>::SIZE }
+            } else {
+                quote! { 0 }
+            };
+
+            let mut before = quote! { #prefix };
+            let mut after = quote! { #prefix };
 
             let mut as_fixed_size_body = quote! {};
             let mut from_fixed_size_body = quote! {};
@@ -22,16 +48,41 @@ pub fn derive_as_fixed_size_bytes_impl(
             for (idx, f) in d.fields.iter().enumerate() {
                 let t = &f.ty;
 
+                // skipped fields are not encoded at all and are reset to default on decoding
+                if has_stable_type_flag(&f.attrs, "skip") {
+                    let default = quote! { <#t as Default>::default() };
+
+                    if let Some(i) = f.ident.clone() {
+                        from_fixed_size_body = quote! { #from_fixed_size_body #i: #default, };
+                    } else {
+                        from_fixed_size_body = quote! { #from_fixed_size_body #default, };
+                    }
+
+                    continue;
+                }
+
                 after = quote! { #after + <#t as ic_stable_memory::AsFixedSizeBytes>::SIZE };
 
+                let from_field = if extensible {
+                    quote! {
+                        if encoded_len >= (#after) {
+                            ic_stable_memory::AsFixedSizeBytes::from_fixed_size_bytes(&buf[(#before)..(#after)])
+                        } else {
+                            <#t as Default>::default()
+                        }
+                    }
+                } else {
+                    quote! { ic_stable_memory::AsFixedSizeBytes::from_fixed_size_bytes(&buf[(#before)..(#after)]) }
+                };
+
                 if let Some(i) = f.ident.clone() {
                     as_fixed_size_body = quote! { #as_fixed_size_body ic_stable_memory::AsFixedSizeBytes::as_fixed_size_bytes(&self.#i, &mut buf[(#before)..(#after)]); };
-                    from_fixed_size_body = quote! { #from_fixed_size_body #i: ic_stable_memory::AsFixedSizeBytes::from_fixed_size_bytes(&buf[(#before)..(#after)]), };
+                    from_fixed_size_body = quote! { #from_fixed_size_body #i: #from_field, };
                 } else {
                     let idx = Index::from(idx);
 
                     as_fixed_size_body = quote! { #as_fixed_size_body ic_stable_memory::AsFixedSizeBytes::as_fixed_size_bytes(&self.#idx, &mut buf[(#before)..(#after)]); };
-                    from_fixed_size_body = quote! { #from_fixed_size_body ic_stable_memory::AsFixedSizeBytes::from_fixed_size_bytes(&buf[(#before)..(#after)]), };
+                    from_fixed_size_body = quote! { #from_fixed_size_body #from_field, };
                 };
 
                 before = quote! { #after };
@@ -49,23 +100,59 @@ pub fn derive_as_fixed_size_bytes_impl(
                 },
             };
 
+            if extensible {
+                as_fixed_size_body = quote! {
+                    ((Self::SIZE - #prefix) as u16).as_fixed_size_bytes(&mut buf[0..(#prefix)]);
+                    #as_fixed_size_body
+                };
+
+                from_fixed_size_body = quote! {
+                    let encoded_len = u16::from_fixed_size_bytes(&buf[0..(#prefix)]) as usize + (#prefix);
+                    #from_fixed_size_body
+                };
+            }
+
             let mut sizes = Vec::new();
 
-            for f in &d.fields {
+            for f in d
+                .fields
+                .iter()
+                .filter(|f| !has_stable_type_flag(&f.attrs, "skip"))
+            {
                 let t = &f.ty;
 
                 sizes.push(quote! { <#t as ic_stable_memory::AsFixedSizeBytes>::SIZE });
             }
 
-            let size = if sizes.is_empty() {
+            let mut size = if sizes.is_empty() {
                 quote! { 0 }
             } else {
                 quote! { #(#sizes)+* }
             };
 
+            if extensible {
+                size = quote! {{
+                    let size = #size;
+                    assert!(size <= u16::MAX as usize, "Extensible structs can't be bigger than 65535 bytes");
+
+                    #prefix + size
+                }};
+            }
+
             (as_fixed_size_body, from_fixed_size_body, size)
         }
         Data::Enum(d) => {
+            if extensible {
+                panic!("Extensible layout is only supported for structs");
+            }
+
+            // the tag is encoded as a single byte
+            if d.variants.len() > u8::MAX as usize + 1 {
+                panic!("Enums with more than 256 variants are not supported");
+            }
+
+            let variants_count = d.variants.len();
+
             let mut as_fixed_size_body_total = quote! {};
             let mut from_fixed_size_body_total = quote! {};
 
@@ -84,6 +171,10 @@ pub fn derive_as_fixed_size_bytes_impl(
                 for (idx, f) in v.fields.iter().enumerate() {
                     let t = &f.ty;
 
+                    if has_stable_type_flag(&f.attrs, "skip") {
+                        panic!("Skipped fields are only supported for structs");
+                    }
+
                     after = quote! { #after + <#t as ic_stable_memory::AsFixedSizeBytes>::SIZE };
 
                     if let Some(i) = f.ident.clone() {
@@ -168,11 +259,17 @@ pub fn derive_as_fixed_size_bytes_impl(
                 }
             };
 
+            // a corrupted tag should trap, instead of decoding some other variant's payload
             from_fixed_size_body_total = quote! {
                 let f = buf[0];
                 match f {
                     #from_fixed_size_body_total,
-                    _ => unreachable!(),
+                    _ => panic!(
+                        "Invalid {} enum tag: {} (only {} variants are known)",
+                        stringify!(#ident),
+                        f,
+                        #variants_count
+                    ),
                 }
             };
 
@@ -13,7 +13,9 @@ mod fixed_size_as_dyn_size_bytes;
 mod stable_type;
 
 /// Derives [ic_stable_memory::StableType] proxying flag toggling calls
-#[proc_macro_derive(StableType)]
+///
+/// Struct fields marked with `#[stable_type(skip)]` are ignored - see [derive_as_fixed_size_bytes].
+#[proc_macro_derive(StableType, attributes(stable_type))]
 pub fn derive_stable_type(input: Tokens) -> Tokens {
     let DeriveInput {
         ident,
@@ -26,16 +28,29 @@ pub fn derive_stable_type(input: Tokens) -> Tokens {
 }
 
 /// Derives [ic_stable_memory::AsFixedSizeBytes]. Does not support generics at the moment.
-#[proc_macro_derive(AsFixedSizeBytes)]
+///
+/// Structs marked with `#[stable_type(extensible)]` are prefixed with two bytes, holding the length
+/// of their encoded fields. When decoded, fields which are not covered by this recorded length are
+/// set to `Default::default()`. This allows adding new trailing fields to a struct between canister
+/// upgrades, as long as it is stored somewhere that keeps the encoded value as is (e.g. inside an
+/// `SBox`), and not inline in a collection. The very first version of such a struct should already be
+/// marked as extensible, and all of its fields should implement [Default].
+///
+/// Struct fields marked with `#[stable_type(skip)]` are not encoded: they don't contribute to `SIZE`
+/// and are set to `Default::default()` when decoded. This is useful for runtime-only data, like
+/// in-memory caches. Such fields only have to implement [Default] (neither
+/// [ic_stable_memory::AsFixedSizeBytes] nor [ic_stable_memory::StableType] is required for them).
+#[proc_macro_derive(AsFixedSizeBytes, attributes(stable_type))]
 pub fn derive_as_fixed_size_bytes(input: Tokens) -> Tokens {
     let DeriveInput {
         ident,
         data,
         generics,
+        attrs,
         ..
     } = parse_macro_input!(input);
 
-    derive_as_fixed_size_bytes_impl(&ident, &data, &generics).into()
+    derive_as_fixed_size_bytes_impl(&ident, &data, &generics, &attrs).into()
 }
 
 /// Derives [ic_stable_memory::AsDynSizeBytes] for a type that already implements [candid::CandidType] and [candid::Deserialize].
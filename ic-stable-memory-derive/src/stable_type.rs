@@ -1,3 +1,4 @@
+use crate::as_fixed_size_bytes::has_stable_type_flag;
 use proc_macro2::{self, TokenStream};
 use quote::{format_ident, quote};
 use syn::{Data, Fields, Generics, Ident, Index};
@@ -15,6 +16,11 @@ pub fn derive_stable_type_impl(ident: &Ident, data: &Data, generics: &Generics)
             for (idx, f) in d.fields.iter().enumerate() {
                 let t = &f.ty;
 
+                // skipped fields are never persisted, so they can't own any stable memory
+                if has_stable_type_flag(&f.attrs, "skip") {
+                    continue;
+                }
+
                 if let Some(i) = f.ident.clone() {
                     flag_off_body = quote! { #flag_off_body <#t as ic_stable_memory::StableType>::stable_drop_flag_off(&mut self.#i); };
                     flag_on_body = quote! { #flag_on_body <#t as ic_stable_memory::StableType>::stable_drop_flag_on(&mut self.#i); };
@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod encoding_benchmark {
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::measure;
+
+    const ITERATIONS: usize = 10_000_000;
+
+    #[test]
+    #[ignore]
+    fn body_direct() {
+        let arr = [42u8; 32];
+        let mut buf = [0u8; 32];
+
+        measure!("Per-element [u8; 32] encoding", ITERATIONS, {
+            for _ in 0..ITERATIONS {
+                for i in 0..32 {
+                    arr[i].as_fixed_size_bytes(&mut buf[i..(i + 1)]);
+                }
+
+                let mut it = [0u8; 32];
+                for i in 0..32 {
+                    it[i] = u8::from_fixed_size_bytes(&buf[i..(i + 1)]);
+                }
+
+                std::hint::black_box(it);
+            }
+        });
+
+        measure!("Single-copy [u8; 32] encoding", ITERATIONS, {
+            for _ in 0..ITERATIONS {
+                arr.as_fixed_size_bytes(&mut buf);
+                let it = <[u8; 32]>::from_fixed_size_bytes(&buf);
+
+                std::hint::black_box(it);
+            }
+        });
+    }
+}
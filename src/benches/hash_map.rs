@@ -1,9 +1,10 @@
 #[cfg(test)]
 mod hash_map_benchmark {
-    use crate::collections::hash_map::SHashMap;
+    use crate::collections::hash_map::hasher::{IdentityHasherFactory, StableHasherFactory};
+    use crate::collections::hash_map::{IndexingScheme, ProbeSequence, SHashMap};
     use crate::{measure, stable, stable_memory_init};
     use rand::seq::SliceRandom;
-    use rand::thread_rng;
+    use rand::{thread_rng, Rng};
     use std::collections::HashMap;
 
     const ITERATIONS: usize = 1_000_000;
@@ -64,4 +65,113 @@ mod hash_map_benchmark {
             });
         }
     }
+
+    #[test]
+    #[ignore]
+    fn probe_length_distribution() {
+        let random_keys = (0..ITERATIONS)
+            .map(|_| thread_rng().gen::<u64>())
+            .collect::<Vec<_>>();
+        let sequential_keys = (0..ITERATIONS as u64).collect::<Vec<_>>();
+        let strided_keys = (0..ITERATIONS as u64).map(|i| i << 12).collect::<Vec<_>>();
+
+        stable::clear();
+        stable_memory_init();
+
+        for (name, keys) in [
+            ("random", &random_keys),
+            ("sequential", &sequential_keys),
+            ("strided", &strided_keys),
+        ] {
+            for scheme in [
+                IndexingScheme::Modulo,
+                IndexingScheme::Fibonacci,
+                IndexingScheme::Prime,
+            ] {
+                let mut map = SHashMap::new_with_scheme(scheme);
+
+                measure!("Stable hash map insert", ITERATIONS, {
+                    for k in keys.iter() {
+                        map.insert(*k, *k).unwrap();
+                    }
+                });
+
+                measure!("Stable hash map search", ITERATIONS, {
+                    for k in keys.iter() {
+                        map.get(k).unwrap();
+                    }
+                });
+
+                print_probe_lengths(&format!("{:?} {} keys", scheme, name), &map);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn prime_capacity_clustering() {
+        // multiples of 5 with an identity hasher - a table with modulo indexing grows through
+        // capacities like 98305 (= 5 * 19661), so these keys can only land in every fifth slot, while
+        // with prime indexing the closest capacity is 98317
+        let keys = (0..60_000u64).map(|i| i * 5).collect::<Vec<_>>();
+
+        stable::clear();
+        stable_memory_init();
+
+        for (capacity, scheme) in [
+            (98305, IndexingScheme::Modulo),
+            (98317, IndexingScheme::Prime),
+        ] {
+            let mut map = SHashMap::<u64, u64, IdentityHasherFactory>::with_exact_table_capacity(
+                capacity,
+                scheme,
+                ProbeSequence::Linear,
+                IdentityHasherFactory,
+            )
+            .unwrap();
+
+            measure!("Stable hash map insert", keys.len(), {
+                for k in keys.iter() {
+                    map.insert(*k, *k).unwrap();
+                }
+            });
+
+            measure!("Stable hash map search", keys.len(), {
+                for k in keys.iter() {
+                    map.get(k).unwrap();
+                }
+            });
+
+            print_probe_lengths(&format!("{:?} multiples of 5", scheme), &map);
+        }
+    }
+
+    fn print_probe_lengths<S: StableHasherFactory>(label: &str, map: &SHashMap<u64, u64, S>) {
+        let mut lengths = map.probe_lengths();
+        lengths.sort();
+
+        let mut histogram = [0usize; 6];
+        for l in lengths.iter() {
+            let bucket = match *l {
+                0 => 0,
+                1 => 1,
+                2 => 2,
+                3..=4 => 3,
+                5..=8 => 4,
+                _ => 5,
+            };
+
+            histogram[bucket] += 1;
+        }
+
+        println!(
+            "{} keys, capacity {}: mean {:.3}, p99 {}, max {}, histogram [0, 1, 2, 3-4, 5-8, 9+] {:?}",
+            label,
+            map.capacity(),
+            lengths.iter().sum::<usize>() as f64 / lengths.len() as f64,
+            lengths[lengths.len() * 99 / 100],
+            lengths[lengths.len() - 1],
+            histogram
+        );
+    }
 }
@@ -3,6 +3,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 mod btree_map;
 mod btree_set;
 mod certified_map;
+mod encoding;
 mod hash_map;
 mod hash_set;
 mod log;
@@ -0,0 +1,258 @@
+use crate::collections::hash_map::iter::SHashMapIter;
+use crate::collections::hash_map::SHashMap;
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::primitive::StableType;
+use crate::{OutOfMemory, SBox};
+use std::borrow::Borrow;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+
+/// Hashmap, which stores its values boxed
+///
+/// This is just a wrapper around [SHashMap]`<K, `[SBox]`<V>>`, read it's documentation to get info on the internals.
+///
+/// Each slot of the table only stores an 8-byte pointer to the value, instead of the value itself.
+/// This makes the table smaller and cheaper to rehash, when `V` is big and is rarely accessed. The
+/// price is an additional allocation per entry and an additional stable memory read per access. Also
+/// `V` is encoded with [AsDynSizeBytes], so its size does not have to be fixed.
+///
+/// Values are boxed and unboxed transparently: [SBoxedHashMap::insert] and [SBoxedHashMap::remove]
+/// accept and return `V`.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SBoxedHashMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut map = SBoxedHashMap::new();
+///
+/// map.insert(1u64, String::from("a long description")).expect("Out of memory");
+///
+/// assert_eq!(map.get(&1).unwrap(), "a long description");
+///
+/// map.with_value(&1, |it| it.push('!')).unwrap().expect("Out of memory");
+/// assert_eq!(map.remove(&1).unwrap(), "a long description!");
+/// ```
+pub struct SBoxedHashMap<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsDynSizeBytes,
+> {
+    map: SHashMap<K, SBox<V>>,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsDynSizeBytes>
+    SBoxedHashMap<K, V>
+{
+    /// See [SHashMap::new]
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            map: SHashMap::new(),
+        }
+    }
+
+    /// See [SHashMap::new_with_capacity]
+    #[inline]
+    pub fn new_with_capacity(capacity: usize) -> Result<Self, OutOfMemory> {
+        Ok(Self {
+            map: SHashMap::new_with_capacity(capacity)?,
+        })
+    }
+
+    /// Boxes the value and inserts the key-value pair, see [SHashMap::insert]
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the key-value pair. Otherwise returns
+    /// the unboxed previous value stored by this key, if there was one.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        let value = match SBox::new(value) {
+            Ok(b) => b,
+            Err(v) => return Err((key, v)),
+        };
+
+        match self.map.insert(key, value) {
+            Ok(prev) => Ok(prev.map(|it| it.into_inner())),
+            Err((k, v)) => Err((k, v.into_inner())),
+        }
+    }
+
+    /// Removes the key-value pair, returning the unboxed value, see [SHashMap::remove]
+    #[inline]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key).map(|it| it.into_inner())
+    }
+
+    /// Returns a copy of the value stored by the key
+    ///
+    /// The value is read from stable memory and decoded. Modifying it won't modify the value stored
+    /// inside this map - use [SBoxedHashMap::with_value] for that.
+    #[inline]
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map(|it| it.read_for_reference())
+    }
+
+    /// Modifies the value stored by the key with the provided lambda, see [SBox::with]
+    ///
+    /// Returns [None] if there is no such key. If the modified value has grown and the canister is
+    /// out of stable memory, returns `Some(Err(OutOfMemory))` - in that case the stored value is left
+    /// unchanged.
+    pub fn with_value<Q, R, F>(&mut self, key: &Q, func: F) -> Option<Result<R, OutOfMemory>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&mut V) -> R,
+    {
+        let mut value = self.map.get_mut(key)?;
+
+        Some(value.with(func))
+    }
+
+    /// See [SHashMap::contains_key]
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// See [SHashMap::len]
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// See [SHashMap::capacity]
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    /// See [SHashMap::is_empty]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// See [SHashMap::iter]
+    ///
+    /// Values are presented boxed.
+    #[inline]
+    pub fn iter(&self) -> SHashMapIter<'_, K, SBox<V>> {
+        self.map.iter()
+    }
+
+    /// See [SHashMap::clear]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsDynSizeBytes> Default
+    for SBoxedHashMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        SBoxedHashMap::new()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsDynSizeBytes> AsFixedSizeBytes
+    for SBoxedHashMap<K, V>
+{
+    const SIZE: usize = SHashMap::<K, SBox<V>>::SIZE;
+    type Buf = <SHashMap<K, SBox<V>> as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.map.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let map = SHashMap::<K, SBox<V>>::from_fixed_size_bytes(arr);
+        Self { map }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsDynSizeBytes> StableType
+    for SBoxedHashMap<K, V>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.map.stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.map.stable_drop_flag_on();
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
+        V: StableType + AsDynSizeBytes + Debug,
+    > Debug for SBoxedHashMap<K, V>
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::boxed_hash_map::SBoxedHashMap;
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBoxedHashMap::default();
+            assert!(map.is_empty());
+
+            for i in 0..100u64 {
+                assert!(map.insert(i, "a".repeat(i as usize)).unwrap().is_none());
+            }
+
+            assert_eq!(map.len(), 100);
+            assert_eq!(map.insert(10, String::new()).unwrap(), Some("a".repeat(10)));
+            assert_eq!(map.get(&10).unwrap(), "");
+            assert!(map.get(&100).is_none());
+
+            map.with_value(&20, |it| it.push_str(&"b".repeat(100)))
+                .unwrap()
+                .unwrap();
+            assert_eq!(map.get(&20).unwrap().len(), 120);
+            assert!(map.with_value(&100, |it| it.clear()).is_none());
+
+            let buf = map.as_new_fixed_size_bytes();
+            let restored = SBoxedHashMap::<u64, String>::from_fixed_size_bytes(buf._deref());
+            assert_eq!(restored.get(&50).unwrap(), "a".repeat(50));
+
+            for i in 0..50u64 {
+                assert!(map.remove(&i).is_some());
+                assert!(!map.contains_key(&i));
+            }
+
+            assert_eq!(map.iter().count(), 50);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::collections::btree_map::leaf_node::LeafBTreeNode;
+use crate::collections::btree_map::{SBTreeMap, DEFAULT_B};
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+
+/// A view into a single entry of an [SBTreeMap], which may either be vacant or occupied
+///
+/// Constructed by [SBTreeMap::entry].
+pub enum Entry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+    const B: usize = DEFAULT_B,
+> {
+    /// There is a value stored by the key
+    Occupied(OccupiedEntry<'a, K, V, B>),
+    /// There is no value stored by the key
+    Vacant(VacantEntry<'a, K, V, B>),
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > Entry<'a, K, V, B>
+{
+    /// Returns the key of this entry
+    #[inline]
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value of this entry, inserting the provided
+    /// default value first, if the entry is vacant
+    ///
+    /// If the entry is occupied, the default value is dropped.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    #[inline]
+    pub fn or_insert(self, default: V) -> SRefMut<'a, V> {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Same as [Entry::or_insert], but the default value is only constructed, if the entry is vacant
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    #[inline]
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> SRefMut<'a, V> {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    /// Modifies the value of an occupied entry with the provided lambda, does nothing for a vacant one
+    ///
+    /// The lambda receives an [SRefMut], which writes the value back to stable memory, when it is
+    /// dropped at the end of the lambda. The location of the value is the one found by
+    /// [SBTreeMap::entry], so the tree is not traversed again. The lambda can't access the map
+    /// itself, since this entry holds a mutable borrow of it - so nothing can move the value, while
+    /// it is being modified.
+    #[inline]
+    pub fn and_modify<F: FnOnce(SRefMut<'_, V>)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+}
+
+/// A view into an occupied entry of an [SBTreeMap], see [Entry]
+pub struct OccupiedEntry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+    const B: usize = DEFAULT_B,
+> {
+    pub(crate) map: &'a mut SBTreeMap<K, V, B>,
+    pub(crate) key: K,
+    pub(crate) leaf: LeafBTreeNode<K, V, B>,
+    pub(crate) idx: usize,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > OccupiedEntry<'a, K, V, B>
+{
+    /// Returns the key, which was passed to [SBTreeMap::entry]
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns an immutable reference [SRef] to the value of this entry
+    #[inline]
+    pub fn get(&self) -> SRef<'_, V> {
+        self.leaf.get_value(self.idx)
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value of this entry
+    #[inline]
+    pub fn get_mut(&mut self) -> SRefMut<'_, V> {
+        self.leaf.get_value_mut(self.idx)
+    }
+
+    /// Converts this entry into a mutable reference [SRefMut] to its value, bound to the map
+    #[inline]
+    pub fn into_mut(mut self) -> SRefMut<'a, V> {
+        self.leaf.get_value_mut(self.idx)
+    }
+
+    /// Replaces the value of this entry, returning the previous one
+    #[inline]
+    pub fn insert(&mut self, value: V) -> V {
+        let prev = self.leaf.read_and_disown_value(self.idx);
+        self.leaf.write_and_own_value(self.idx, value);
+
+        prev
+    }
+
+    /// Removes this entry from the map, returning its value
+    ///
+    /// Removal may merge leaves, so unlike other methods of this entry, it traverses the tree again.
+    #[inline]
+    pub fn remove(self) -> V {
+        self.map.remove(&self.key).unwrap()
+    }
+}
+
+/// A view into a vacant entry of an [SBTreeMap], see [Entry]
+pub struct VacantEntry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+    const B: usize = DEFAULT_B,
+> {
+    pub(crate) map: &'a mut SBTreeMap<K, V, B>,
+    pub(crate) key: K,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > VacantEntry<'a, K, V, B>
+{
+    /// Returns the key, which was passed to [SBTreeMap::entry]
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the key back, without inserting anything
+    #[inline]
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts the value by the key of this entry, returning a mutable reference [SRefMut] to it
+    ///
+    /// The value is inserted with [SBTreeMap::insert] and then looked up again, since inserting may
+    /// split leaves and move the key.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    pub fn insert(self, value: V) -> SRefMut<'a, V> {
+        let key_buf = self.key.as_new_fixed_size_bytes();
+        if self.map.insert(self.key, value).is_err() {
+            panic!("Out of memory");
+        }
+
+        let mut key = K::from_fixed_size_bytes(key_buf._deref());
+        unsafe { key.stable_drop_flag_off() };
+
+        let (mut leaf, idx) = self.map.lookup(&key, false).unwrap();
+
+        leaf.get_value_mut(idx)
+    }
+}
@@ -1,8 +1,5 @@
 use crate::collections::btree_map::{BTreeNode, IBTreeNode};
-use crate::collections::btree_map::{
-    B, CAPACITY, CHILDREN_CAPACITY, CHILDREN_MIN_LEN_AFTER_SPLIT, MIN_LEN_AFTER_SPLIT,
-    NODE_TYPE_INTERNAL, NODE_TYPE_OFFSET,
-};
+use crate::collections::btree_map::{DEFAULT_B, NODE_TYPE_INTERNAL, NODE_TYPE_OFFSET};
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::mem::{stable_ptr_buf, StablePtr, StablePtrBuf};
 use crate::primitive::StableType;
@@ -17,26 +14,34 @@ use std::marker::PhantomData;
 // node_type: u8
 // len: usize
 // children: [u64; CHILDREN_CAPACITY]
+// child_counts: [u64; CHILDREN_CAPACITY] -- number of entries in the subtree of each child
 // keys: [K; CAPACITY]
 // root_hash: Hash -- ONLY IF certified == true
 
 const LEN_OFFSET: u64 = NODE_TYPE_OFFSET + u8::SIZE as u64;
 const CHILDREN_OFFSET: u64 = LEN_OFFSET + usize::SIZE as u64;
-const KEYS_OFFSET: u64 = CHILDREN_OFFSET + (u64::SIZE * CHILDREN_CAPACITY) as u64;
 
-const fn root_hash_offset<K: AsFixedSizeBytes>() -> u64 {
-    KEYS_OFFSET + (K::SIZE * CAPACITY) as u64
+const fn child_counts_offset<const B: usize>() -> u64 {
+    CHILDREN_OFFSET + (u64::SIZE * 2 * B) as u64
+}
+const fn keys_offset<const B: usize>() -> u64 {
+    child_counts_offset::<B>() + (u64::SIZE * 2 * B) as u64
+}
+const fn root_hash_offset<K: AsFixedSizeBytes, const B: usize>() -> u64 {
+    keys_offset::<B>() + (K::SIZE * (2 * B - 1)) as u64
 }
 
-pub struct InternalBTreeNode<K> {
+pub struct InternalBTreeNode<K, const B: usize = DEFAULT_B> {
     ptr: u64,
     _marker_k: PhantomData<K>,
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
+impl<K: StableType + AsFixedSizeBytes + Ord, const B: usize> InternalBTreeNode<K, B> {
+    pub(crate) const MIN_LEN_AFTER_SPLIT: usize = B - 1;
+
     #[inline]
     pub const fn calc_byte_size(certified: bool) -> u64 {
-        let mut size = root_hash_offset::<K>();
+        let mut size = root_hash_offset::<K, B>();
 
         if certified {
             size += Hash::SIZE as u64
@@ -97,7 +102,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
         let mut mid = (max - min) / 2;
 
         loop {
-            let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (mid * K::SIZE) as u64);
+            let ptr = SSlice::_offset(self.ptr, keys_offset::<B>() + (mid * K::SIZE) as u64);
 
             let key: K = unsafe { crate::mem::read_fixed_for_reference(ptr) };
 
@@ -196,25 +201,30 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
         &mut self,
         buf: &mut Vec<u8>,
         certified: bool,
-    ) -> Result<(InternalBTreeNode<K>, K::Buf), OutOfMemory> {
-        let mut right = InternalBTreeNode::<K>::create_empty(certified)?;
+    ) -> Result<(InternalBTreeNode<K, B>, K::Buf), OutOfMemory> {
+        let mut right = InternalBTreeNode::<K, B>::create_empty(certified)?;
 
-        self.read_many_keys_to_buf(B, MIN_LEN_AFTER_SPLIT, buf);
+        self.read_many_keys_to_buf(B, Self::MIN_LEN_AFTER_SPLIT, buf);
         right.write_many_keys_from_buf(0, buf);
 
-        self.read_many_child_ptrs_to_buf(B, CHILDREN_MIN_LEN_AFTER_SPLIT, buf);
+        self.read_many_child_ptrs_to_buf(B, B, buf);
         right.write_many_child_ptrs_from_buf(0, buf);
 
-        Ok((right, self.read_key_buf(MIN_LEN_AFTER_SPLIT)))
+        Ok((right, self.read_key_buf(Self::MIN_LEN_AFTER_SPLIT)))
     }
 
-    pub fn merge_min_len(&mut self, mid: &K::Buf, right: InternalBTreeNode<K>, buf: &mut Vec<u8>) {
-        self.push_key_buf(mid, MIN_LEN_AFTER_SPLIT);
+    pub fn merge_min_len(
+        &mut self,
+        mid: &K::Buf,
+        right: InternalBTreeNode<K, B>,
+        buf: &mut Vec<u8>,
+    ) {
+        self.push_key_buf(mid, Self::MIN_LEN_AFTER_SPLIT);
 
-        right.read_many_keys_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
+        right.read_many_keys_to_buf(0, Self::MIN_LEN_AFTER_SPLIT, buf);
         self.write_many_keys_from_buf(B, buf);
 
-        right.read_many_child_ptrs_to_buf(0, CHILDREN_MIN_LEN_AFTER_SPLIT, buf);
+        right.read_many_child_ptrs_to_buf(0, B, buf);
         self.write_many_child_ptrs_from_buf(B, buf);
 
         right.destroy();
@@ -301,7 +311,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
     #[inline]
     pub fn read_key_buf(&self, idx: usize) -> K::Buf {
         let mut b = K::Buf::new(K::SIZE);
-        let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (idx * K::SIZE) as u64);
+        let ptr = SSlice::_offset(self.ptr, keys_offset::<B>() + (idx * K::SIZE) as u64);
 
         unsafe { crate::mem::read_bytes(ptr, b._deref_mut()) }
 
@@ -322,7 +332,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
     #[inline]
     fn read_many_keys_to_buf(&self, from_idx: usize, len: usize, buf: &mut Vec<u8>) {
         buf.resize(len * K::SIZE, 0);
-        let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (from_idx * K::SIZE) as u64);
+        let ptr = SSlice::_offset(self.ptr, keys_offset::<B>() + (from_idx * K::SIZE) as u64);
 
         unsafe { crate::mem::read_bytes(ptr, buf) }
     }
@@ -347,13 +357,13 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
 
     #[inline]
     pub fn write_key_buf(&mut self, idx: usize, key: &K::Buf) {
-        let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (idx * K::SIZE) as u64);
+        let ptr = SSlice::_offset(self.ptr, keys_offset::<B>() + (idx * K::SIZE) as u64);
         unsafe { crate::mem::write_bytes(ptr, key._deref()) };
     }
 
     #[inline]
     fn write_many_keys_from_buf(&mut self, from_idx: usize, buf: &Vec<u8>) {
-        let ptr = SSlice::_offset(self.ptr, KEYS_OFFSET + (from_idx * K::SIZE) as u64);
+        let ptr = SSlice::_offset(self.ptr, keys_offset::<B>() + (from_idx * K::SIZE) as u64);
 
         unsafe { crate::mem::write_bytes(ptr, buf) };
     }
@@ -372,11 +382,53 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
         unsafe { crate::mem::write_bytes(ptr, buf) };
     }
 
+    #[inline]
+    pub fn write_child_count(&mut self, idx: usize, mut count: u64) {
+        let ptr = SSlice::_offset(
+            self.ptr,
+            child_counts_offset::<B>() + (idx * u64::SIZE) as u64,
+        );
+
+        unsafe { crate::mem::write_fixed(ptr, &mut count) };
+    }
+
+    // adjusts the count of a child, after an entry was inserted into or removed from its subtree
+    #[inline]
+    pub fn add_child_count(&mut self, idx: usize, delta: i64) {
+        let ptr = SSlice::_offset(
+            self.ptr,
+            child_counts_offset::<B>() + (idx * u64::SIZE) as u64,
+        );
+
+        let count = unsafe { crate::mem::read_fixed_for_reference::<u64>(ptr) };
+        let mut count = count.checked_add_signed(delta).unwrap();
+
+        unsafe { crate::mem::write_fixed(ptr, &mut count) };
+    }
+
+    // reads counts of the first `children_len` children with a single read
+    pub fn read_child_counts(&self, children_len: usize) -> Vec<u64> {
+        let mut buf = vec![0u8; children_len * u64::SIZE];
+        let ptr = SSlice::_offset(self.ptr, child_counts_offset::<B>());
+
+        unsafe { crate::mem::read_bytes(ptr, &mut buf) };
+
+        buf.chunks_exact(u64::SIZE)
+            .map(u64::from_fixed_size_bytes)
+            .collect()
+    }
+
+    // returns the number of entries in the subtree of this node
+    #[inline]
+    pub fn read_total_count(&self) -> u64 {
+        self.read_child_counts(self.read_len() + 1).iter().sum()
+    }
+
     #[inline]
     pub fn write_root_hash(&mut self, root_hash: &Hash, certified: bool) {
         debug_assert!(certified);
 
-        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K>());
+        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, B>());
         unsafe { crate::mem::write_bytes(ptr, root_hash) };
     }
 
@@ -385,7 +437,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord> InternalBTreeNode<K> {
         debug_assert!(certified);
 
         let mut buf = EMPTY_HASH;
-        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K>());
+        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, B>());
         unsafe { crate::mem::read_bytes(ptr, &mut buf) };
 
         buf
@@ -432,7 +484,7 @@ impl<K: StableType + AsFixedSizeBytes + AsHashableBytes + Ord> InternalBTreeNode
     }
 }
 
-impl<K> IBTreeNode for InternalBTreeNode<K> {
+impl<K, const B: usize> IBTreeNode for InternalBTreeNode<K, B> {
     #[inline]
     unsafe fn from_ptr(ptr: StablePtr) -> Self {
         Self {
@@ -452,7 +504,7 @@ impl<K> IBTreeNode for InternalBTreeNode<K> {
     }
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord + Debug> InternalBTreeNode<K> {
+impl<K: StableType + AsFixedSizeBytes + Ord + Debug, const B: usize> InternalBTreeNode<K, B> {
     pub fn to_string(&self) -> String {
         let mut result = format!(
             "InternalBTreeNode(&{}, {})[",
@@ -480,12 +532,14 @@ impl<K: StableType + AsFixedSizeBytes + Ord + Debug> InternalBTreeNode<K> {
 #[cfg(test)]
 mod tests {
     use crate::collections::btree_map::internal_node::InternalBTreeNode;
-    use crate::collections::btree_map::{
-        B, CAPACITY, CHILDREN_MIN_LEN_AFTER_SPLIT, MIN_LEN_AFTER_SPLIT,
-    };
+    use crate::collections::btree_map::DEFAULT_B as B;
     use crate::encoding::AsFixedSizeBytes;
     use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
 
+    const CAPACITY: usize = 2 * B - 1;
+    const MIN_LEN_AFTER_SPLIT: usize = InternalBTreeNode::<u64>::MIN_LEN_AFTER_SPLIT;
+    const CHILDREN_MIN_LEN_AFTER_SPLIT: usize = B;
+
     #[test]
     fn works_fine() {
         stable::clear();
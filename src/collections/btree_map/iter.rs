@@ -1,32 +1,98 @@
 use crate::collections::btree_map::leaf_node::LeafBTreeNode;
-use crate::collections::btree_map::{BTreeNode, IBTreeNode, SBTreeMap};
+use crate::collections::btree_map::{BTreeNode, IBTreeNode, SBTreeMap, DEFAULT_B};
 use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::StableType;
+use std::marker::PhantomData;
+use std::ops::Bound;
 
-pub struct SBTreeMapIter<'a, K, V> {
-    root: &'a Option<BTreeNode<K, V>>,
-    node: Option<LeafBTreeNode<K, V>>,
+pub struct SBTreeMapIter<'a, K, V, const B: usize = DEFAULT_B> {
+    root: &'a Option<BTreeNode<K, V, B>>,
+    from: Bound<K>,
+    to: Bound<K>,
+    node: Option<LeafBTreeNode<K, V, B>>,
     node_idx: usize,
     node_len: usize,
 }
 
-impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
-    SBTreeMapIter<'a, K, V>
+/// Iterator returned by [SBTreeMap::range]
+///
+/// The same iterator as the one returned by [SBTreeMap::iter], but bounded from both sides.
+pub type SBTreeMapRangeIter<'a, K, V, const B: usize = DEFAULT_B> = SBTreeMapIter<'a, K, V, B>;
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > SBTreeMapIter<'a, K, V, B>
 {
     #[inline]
-    pub(crate) fn new(map: &'a SBTreeMap<K, V>) -> Self {
+    pub(crate) fn new(map: &'a SBTreeMap<K, V, B>) -> Self {
+        Self::new_range(map, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    #[inline]
+    pub(crate) fn new_range(map: &'a SBTreeMap<K, V, B>, from: Bound<K>, to: Bound<K>) -> Self {
         Self {
             root: &map.root,
+            from,
+            to,
             node: None,
             node_idx: 0,
             node_len: 0,
         }
     }
+
+    // positions the iterator right away, so the start key doesn't have to be owned
+    pub(crate) fn new_from(map: &'a SBTreeMap<K, V, B>, start: &K) -> Self {
+        let mut it = Self::new(map);
+
+        if let Some((leaf, idx)) = SBTreeMap::seek(&map.root, Bound::Included(start)) {
+            let prev_ptr = u64::from_fixed_size_bytes(&leaf.read_prev_ptr_buf());
+
+            // same as next_back() does, stay at the end of the previous leaf instead of the
+            // beginning of this one, so both directions work
+            if idx == 0 && prev_ptr != 0 {
+                let prev = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(prev_ptr) };
+
+                it.node_len = prev.read_len();
+                it.node_idx = it.node_len;
+                it.node = Some(prev);
+            } else {
+                it.node_len = leaf.read_len();
+                it.node_idx = idx;
+                it.node = Some(leaf);
+            }
+        }
+
+        it
+    }
+
+    fn below_upper_bound(&self, key: &K) -> bool {
+        match &self.to {
+            Bound::Unbounded => true,
+            Bound::Included(to) => key <= to,
+            Bound::Excluded(to) => key < to,
+        }
+    }
+
+    fn above_lower_bound(&self, key: &K) -> bool {
+        match &self.from {
+            Bound::Unbounded => true,
+            Bound::Included(from) => key >= from,
+            Bound::Excluded(from) => key > from,
+        }
+    }
 }
 
-impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Iterator
-    for SBTreeMapIter<'a, K, V>
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > Iterator for SBTreeMapIter<'a, K, V, B>
 {
     type Item = (SRef<'a, K>, SRef<'a, V>);
 
@@ -39,7 +105,7 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
                     return None;
                 }
 
-                let new_node = unsafe { LeafBTreeNode::<K, V>::from_ptr(ptr) };
+                let new_node = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(ptr) };
                 let len = new_node.read_len();
 
                 self.node = Some(new_node);
@@ -47,26 +113,20 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
                 self.node_len = len;
             }
 
-            let res = (&self.node)
-                .as_ref()
-                .map(|it| (it.get_key(self.node_idx), it.get_value(self.node_idx)));
+            let node = self.node.as_ref().unwrap();
+            let k = node.get_key(self.node_idx);
+
+            // the position is not advanced, so all subsequent calls return None as well
+            if !self.below_upper_bound(&k) {
+                return None;
+            }
 
+            let v = node.get_value(self.node_idx);
             self.node_idx += 1;
 
-            res
+            Some((k, v))
         } else {
-            let mut node = unsafe { self.root.as_ref()?.copy() };
-            let leaf = loop {
-                match node {
-                    BTreeNode::Internal(i) => {
-                        let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(0));
-                        node = BTreeNode::<K, V>::from_ptr(child_ptr);
-                    }
-                    BTreeNode::Leaf(l) => {
-                        break l;
-                    }
-                }
-            };
+            let (leaf, idx) = SBTreeMap::seek(self.root, self.from.as_ref())?;
 
             self.node_len = leaf.read_len();
 
@@ -74,7 +134,7 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
                 return None;
             }
 
-            self.node_idx = 0;
+            self.node_idx = idx;
             self.node = Some(leaf);
 
             self.next()
@@ -82,8 +142,12 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
     }
 }
 
-impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>
-    DoubleEndedIterator for SBTreeMapIter<'a, K, V>
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > DoubleEndedIterator for SBTreeMapIter<'a, K, V, B>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
         if let Some(node) = &self.node {
@@ -91,16 +155,20 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
                 return None;
             }
 
-            self.node_idx -= 1;
+            let k = node.get_key(self.node_idx - 1);
 
-            let k = node.get_key(self.node_idx);
+            if !self.above_lower_bound(&k) {
+                return None;
+            }
+
+            self.node_idx -= 1;
             let v = node.get_value(self.node_idx);
 
             if self.node_idx == 0 {
                 let ptr = u64::from_fixed_size_bytes(&node.read_prev_ptr_buf());
 
                 if ptr != 0 {
-                    let new_node = unsafe { LeafBTreeNode::<K, V>::from_ptr(ptr) };
+                    let new_node = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(ptr) };
                     let len = new_node.read_len();
 
                     self.node = Some(new_node);
@@ -111,19 +179,7 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
 
             Some((k, v))
         } else {
-            let mut node = unsafe { self.root.as_ref()?.copy() };
-            let leaf = loop {
-                match node {
-                    BTreeNode::Internal(i) => {
-                        let len = i.read_len();
-                        let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(len));
-                        node = BTreeNode::<K, V>::from_ptr(child_ptr);
-                    }
-                    BTreeNode::Leaf(l) => {
-                        break l;
-                    }
-                }
-            };
+            let (leaf, idx) = SBTreeMap::seek_back(self.root, self.to.as_ref())?;
 
             self.node_len = leaf.read_len();
 
@@ -131,10 +187,179 @@ impl<'a, K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeByte
                 return None;
             }
 
-            self.node_idx = self.node_len;
+            self.node_idx = idx;
             self.node = Some(leaf);
 
             self.next_back()
         }
     }
 }
+
+/// Opaque pointer to a subtree of an [SBTreeMap], see [SBTreeMap::root_children]
+///
+/// Not validated in any way - it becomes dangling, once the map is modified.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SubtreePtr(pub(crate) StablePtr);
+
+/// Iterator returned by [SBTreeMap::iter_subtree]
+pub struct SBTreeMapSubtreeIter<'a, K, V, const B: usize = DEFAULT_B> {
+    node: Option<LeafBTreeNode<K, V, B>>,
+    last_node_ptr: StablePtr,
+    node_idx: usize,
+    node_len: usize,
+    _marker: PhantomData<&'a (K, V)>,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Ord,
+        V: StableType + AsFixedSizeBytes,
+        const B: usize,
+    > SBTreeMapSubtreeIter<'a, K, V, B>
+{
+    #[inline]
+    pub(crate) fn new(first_node: LeafBTreeNode<K, V, B>, last_node_ptr: StablePtr) -> Self {
+        let node_len = first_node.read_len();
+
+        Self {
+            node: Some(first_node),
+            last_node_ptr,
+            node_idx: 0,
+            node_len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V, const B: usize> Iterator for SBTreeMapSubtreeIter<'a, K, V, B>
+where
+    K: StableType + AsFixedSizeBytes + Ord + Clone,
+    V: StableType + AsFixedSizeBytes + Clone,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.node.as_ref()?;
+
+        if self.node_idx == self.node_len {
+            // the rightmost leaf of the subtree is done, the next one belongs to another subtree
+            if node.as_ptr() == self.last_node_ptr {
+                self.node = None;
+                return None;
+            }
+
+            let ptr = u64::from_fixed_size_bytes(&node.read_next_ptr_buf());
+            let new_node = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(ptr) };
+
+            self.node_len = new_node.read_len();
+            self.node_idx = 0;
+            self.node = Some(new_node);
+
+            return self.next();
+        }
+
+        let k = node.get_key(self.node_idx).clone();
+        let v = node.get_value(self.node_idx).clone();
+        self.node_idx += 1;
+
+        Some((k, v))
+    }
+}
+
+pub struct SBTreeMapDrain<
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+    const B: usize = DEFAULT_B,
+> {
+    node: Option<LeafBTreeNode<K, V, B>>,
+    node_idx: usize,
+    node_len: usize,
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    SBTreeMapDrain<K, V, B>
+{
+    // internal nodes are released right away - only the linked list of leaves is needed for draining
+    pub(crate) fn new(root: Option<BTreeNode<K, V, B>>) -> Self {
+        let mut level = match root {
+            Some(it) => vec![it],
+            None => {
+                return Self {
+                    node: None,
+                    node_idx: 0,
+                    node_len: 0,
+                }
+            }
+        };
+
+        loop {
+            if let BTreeNode::Leaf(l) = &level[0] {
+                let leaf = unsafe { l.copy() };
+                let len = leaf.read_len();
+
+                return Self {
+                    node: Some(leaf),
+                    node_idx: 0,
+                    node_len: len,
+                };
+            }
+
+            let mut next_level = Vec::new();
+
+            for node in level {
+                if let BTreeNode::Internal(i) = node {
+                    for j in 0..(i.read_len() + 1) {
+                        let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(j));
+                        next_level.push(BTreeNode::<K, V, B>::from_ptr(child_ptr));
+                    }
+
+                    i.destroy();
+                }
+            }
+
+            level = next_level;
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    Iterator for SBTreeMapDrain<K, V, B>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.node.as_mut()?;
+
+            if self.node_idx < self.node_len {
+                let k = node.read_and_disown_key(self.node_idx);
+                let v = node.read_and_disown_value(self.node_idx);
+
+                self.node_idx += 1;
+
+                return Some((k, v));
+            }
+
+            let ptr = u64::from_fixed_size_bytes(&node.read_next_ptr_buf());
+            unsafe { self.node.take().unwrap_unchecked() }.destroy();
+
+            if ptr == 0 {
+                return None;
+            }
+
+            let new_node = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(ptr) };
+
+            self.node_len = new_node.read_len();
+            self.node_idx = 0;
+            self.node = Some(new_node);
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize> Drop
+    for SBTreeMapDrain<K, V, B>
+{
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+    }
+}
@@ -1,7 +1,5 @@
 use crate::collections::btree_map::internal_node::InternalBTreeNode;
-use crate::collections::btree_map::{
-    IBTreeNode, B, CAPACITY, MIN_LEN_AFTER_SPLIT, NODE_TYPE_LEAF, NODE_TYPE_OFFSET,
-};
+use crate::collections::btree_map::{IBTreeNode, DEFAULT_B, NODE_TYPE_LEAF, NODE_TYPE_OFFSET};
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::mem::{stable_ptr_buf, StablePtrBuf};
 use crate::primitive::s_ref::SRef;
@@ -27,23 +25,28 @@ const NEXT_OFFSET: u64 = PREV_OFFSET + u64::SIZE as u64;
 const LEN_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
 const KEYS_OFFSET: u64 = LEN_OFFSET + usize::SIZE as u64;
 
-const fn values_offset<K: AsFixedSizeBytes>() -> u64 {
-    KEYS_OFFSET + (K::SIZE * CAPACITY) as u64
+const fn values_offset<K: AsFixedSizeBytes, const B: usize>() -> u64 {
+    KEYS_OFFSET + (K::SIZE * (2 * B - 1)) as u64
 }
-const fn root_hash_offset<K: AsFixedSizeBytes, V: AsFixedSizeBytes>() -> u64 {
-    values_offset::<K>() + (V::SIZE * CAPACITY) as u64
+const fn root_hash_offset<K: AsFixedSizeBytes, V: AsFixedSizeBytes, const B: usize>() -> u64 {
+    values_offset::<K, B>() + (V::SIZE * (2 * B - 1)) as u64
 }
 
-pub struct LeafBTreeNode<K, V> {
+pub struct LeafBTreeNode<K, V, const B: usize = DEFAULT_B> {
     ptr: u64,
     _marker_k: PhantomData<K>,
     _marker_v: PhantomData<V>,
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> LeafBTreeNode<K, V> {
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    LeafBTreeNode<K, V, B>
+{
+    pub(crate) const CAPACITY: usize = 2 * B - 1;
+    pub(crate) const MIN_LEN_AFTER_SPLIT: usize = B - 1;
+
     #[inline]
     pub const fn calc_size_bytes(certified: bool) -> u64 {
-        let mut size = root_hash_offset::<K, V>();
+        let mut size = root_hash_offset::<K, V, B>();
 
         if certified {
             size += Hash::SIZE as u64;
@@ -124,7 +127,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         self_len: usize,
         left_sibling: &mut Self,
         left_sibling_len: usize,
-        parent: &mut InternalBTreeNode<K>,
+        parent: &mut InternalBTreeNode<K, B>,
         parent_idx: usize,
         left_insert_last_element: Option<(&K::Buf, &V::Buf)>,
         buf: &mut Vec<u8>,
@@ -150,7 +153,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         self_len: usize,
         right_sibling: &mut Self,
         right_sibling_len: usize,
-        parent: &mut InternalBTreeNode<K>,
+        parent: &mut InternalBTreeNode<K, B>,
         parent_idx: usize,
         right_insert_first_element: Option<(&K::Buf, &V::Buf)>,
         buf: &mut Vec<u8>,
@@ -183,12 +186,16 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
     ) -> Result<Self, OutOfMemory> {
         let mut right = Self::create(certified)?;
 
-        let min_idx = if right_biased { MIN_LEN_AFTER_SPLIT } else { B };
+        let min_idx = if right_biased {
+            Self::MIN_LEN_AFTER_SPLIT
+        } else {
+            B
+        };
 
-        self.read_many_keys_to_buf(min_idx, CAPACITY - min_idx, buf);
+        self.read_many_keys_to_buf(min_idx, Self::CAPACITY - min_idx, buf);
         right.write_many_keys_from_buf(0, buf);
 
-        self.read_many_values_to_buf(min_idx, CAPACITY - min_idx, buf);
+        self.read_many_values_to_buf(min_idx, Self::CAPACITY - min_idx, buf);
         right.write_many_values_from_buf(0, buf);
 
         let self_next = self.read_next_ptr_buf();
@@ -201,15 +208,23 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         right.write_prev_ptr_buf(&buf);
         right.write_next_ptr_buf(&self_next);
 
+        if self_next != [0u8; u64::SIZE] {
+            let self_next_ptr = u64::from_fixed_size_bytes(&self_next);
+            let mut self_next = unsafe { Self::from_ptr(self_next_ptr) };
+
+            right.ptr.as_fixed_size_bytes(buf._deref_mut());
+            self_next.write_prev_ptr_buf(&buf);
+        }
+
         Ok(right)
     }
 
     pub fn merge_min_len(&mut self, right: Self, buf: &mut Vec<u8>) {
-        right.read_many_keys_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
-        self.write_many_keys_from_buf(MIN_LEN_AFTER_SPLIT, buf);
+        right.read_many_keys_to_buf(0, Self::MIN_LEN_AFTER_SPLIT, buf);
+        self.write_many_keys_from_buf(Self::MIN_LEN_AFTER_SPLIT, buf);
 
-        right.read_many_values_to_buf(0, MIN_LEN_AFTER_SPLIT, buf);
-        self.write_many_values_from_buf(MIN_LEN_AFTER_SPLIT, buf);
+        right.read_many_values_to_buf(0, Self::MIN_LEN_AFTER_SPLIT, buf);
+        self.write_many_values_from_buf(Self::MIN_LEN_AFTER_SPLIT, buf);
 
         let right_next_buf = right.read_next_ptr_buf();
         self.write_next_ptr_buf(&right_next_buf);
@@ -364,6 +379,16 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
         unsafe { crate::mem::read_bytes(self.get_key_ptr(from_idx), buf) };
     }
 
+    // reads both keys and values with a single read, returns the offset of values in the buffer
+    pub fn read_entries_to_buf(&self, len: usize, buf: &mut Vec<u8>) -> usize {
+        let values_start = (values_offset::<K, B>() - KEYS_OFFSET) as usize;
+        buf.resize(values_start + len * V::SIZE, 0);
+
+        unsafe { crate::mem::read_bytes(self.get_key_ptr(0), buf) };
+
+        values_start
+    }
+
     #[inline]
     pub fn write_value_buf(&mut self, idx: usize, value: &V::Buf) {
         unsafe { crate::mem::write_bytes(self.get_value_ptr(idx), value._deref()) };
@@ -376,7 +401,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
 
     #[inline]
     fn get_value_ptr(&self, idx: usize) -> u64 {
-        SSlice::_offset(self.ptr, values_offset::<K>() + (idx * V::SIZE) as u64)
+        SSlice::_offset(self.ptr, values_offset::<K, B>() + (idx * V::SIZE) as u64)
     }
 
     #[inline]
@@ -443,7 +468,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
     pub fn write_root_hash(&mut self, root_hash: &Hash, certified: bool) {
         debug_assert!(certified);
 
-        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, V>());
+        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, V, B>());
         unsafe { crate::mem::write_bytes(ptr, root_hash) };
     }
 
@@ -451,7 +476,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
     pub fn read_root_hash(&self, certified: bool) -> Hash {
         debug_assert!(certified);
 
-        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, V>());
+        let ptr = SSlice::_offset(self.ptr, root_hash_offset::<K, V, B>());
         let mut buf = EMPTY_HASH;
 
         unsafe { crate::mem::read_bytes(ptr, &mut buf) };
@@ -481,7 +506,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> L
     }
 }
 
-impl<K, V> IBTreeNode for LeafBTreeNode<K, V> {
+impl<K, V, const B: usize> IBTreeNode for LeafBTreeNode<K, V, B> {
     #[inline]
     unsafe fn from_ptr(ptr: u64) -> Self {
         Self {
@@ -502,8 +527,11 @@ impl<K, V> IBTreeNode for LeafBTreeNode<K, V> {
     }
 }
 
-impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSizeBytes + Debug>
-    LeafBTreeNode<K, V>
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+        const B: usize,
+    > LeafBTreeNode<K, V, B>
 {
     pub fn to_string(&self) -> String {
         let mut result = format!("LeafBTreeNode(&{}, {})[", self.as_ptr(), self.read_len());
@@ -525,10 +553,13 @@ impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSize
 #[cfg(test)]
 mod tests {
     use crate::collections::btree_map::leaf_node::LeafBTreeNode;
-    use crate::collections::btree_map::{B, CAPACITY, MIN_LEN_AFTER_SPLIT};
+    use crate::collections::btree_map::DEFAULT_B as B;
     use crate::encoding::AsFixedSizeBytes;
     use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
 
+    const CAPACITY: usize = LeafBTreeNode::<u64, u64>::CAPACITY;
+    const MIN_LEN_AFTER_SPLIT: usize = LeafBTreeNode::<u64, u64>::MIN_LEN_AFTER_SPLIT;
+
     #[test]
     fn works_fine() {
         stable::clear();
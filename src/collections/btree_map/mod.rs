@@ -1,6 +1,10 @@
+use crate::collections::btree_map::entry::{Entry, OccupiedEntry, VacantEntry};
 use crate::collections::btree_map::internal_node::InternalBTreeNode;
-use crate::collections::btree_map::iter::SBTreeMapIter;
+use crate::collections::btree_map::iter::{
+    SBTreeMapDrain, SBTreeMapIter, SBTreeMapRangeIter, SBTreeMapSubtreeIter, SubtreePtr,
+};
 use crate::collections::btree_map::leaf_node::LeafBTreeNode;
+use crate::collections::SVec;
 use crate::encoding::AsFixedSizeBytes;
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::free_block::FreeBlock;
@@ -11,20 +15,21 @@ use crate::primitive::StableType;
 use crate::utils::math::shuffle_bits;
 use crate::{isoprint, make_sure_can_allocate, OutOfMemory, SSlice};
 use std::borrow::Borrow;
+use std::collections::VecDeque;
 use std::fmt::{Debug, Formatter};
 use std::mem;
+use std::ops::Bound;
 
-pub(crate) const B: usize = 8;
-pub(crate) const CAPACITY: usize = 2 * B - 1;
-pub(crate) const MIN_LEN_AFTER_SPLIT: usize = B - 1;
-
-pub(crate) const CHILDREN_CAPACITY: usize = 2 * B;
-pub(crate) const CHILDREN_MIN_LEN_AFTER_SPLIT: usize = B;
+/// The branching factor of an [SBTreeMap], which is used, unless another one is specified
+pub const DEFAULT_B: usize = 8;
 
 pub(crate) const NODE_TYPE_INTERNAL: u8 = 127;
 pub(crate) const NODE_TYPE_LEAF: u8 = 255;
 pub(crate) const NODE_TYPE_OFFSET: u64 = 0;
+// internal nodes store the number of entries in each child subtree since version 1
+pub(crate) const NODE_LAYOUT_VERSION: u8 = 1;
 
+pub mod entry;
 pub(crate) mod internal_node;
 pub mod iter;
 pub(crate) mod leaf_node;
@@ -34,7 +39,14 @@ pub(crate) mod leaf_node;
 /// Entries are stored in ascending order of their keys. Use [std::cmp::Reverse] or a custom [std::cmp::Ord]
 /// impl, to differ the order.
 ///
-/// `B` is `8`. This implementation is optimized to perform as few stable memory (de)allocations
+/// The branching factor `B` is a const generic parameter, which is [DEFAULT_B] (`8`) by default -
+/// each node holds up to `2 * B - 1` keys. Smaller keys benefit from bigger nodes, while a smaller
+/// `B` makes each node cheaper to read and rewrite. Use [SBTreeMap::with_branching_factor] to create
+/// a map with a custom `B`, which has to be in `2..=255`. The branching factor is stored in the
+/// header of the map, so restoring it as a map with another `B` panics, instead of misreading its
+/// nodes.
+///
+/// This implementation is optimized to perform as few stable memory (de)allocations
 /// as possible. Also, this data structure implements several non-conventional functions in order to
 /// share code with other data structures, based on this one.
 ///
@@ -42,21 +54,68 @@ pub(crate) mod leaf_node;
 ///
 /// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SBTreeMap] also
 /// implements these trait, so you can nest it in other stable structures.
-pub struct SBTreeMap<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> {
-    root: Option<BTreeNode<K, V>>,
+pub struct SBTreeMap<
+    K: StableType + AsFixedSizeBytes + Ord,
+    V: StableType + AsFixedSizeBytes,
+    const B: usize = DEFAULT_B,
+> {
+    root: Option<BTreeNode<K, V, B>>,
     len: u64,
     certified: bool,
     stable_drop_flag: bool,
-    _stack: Vec<(InternalBTreeNode<K>, usize, usize)>,
+    _stack: Vec<(InternalBTreeNode<K, B>, usize, usize)>,
     _buf: Vec<u8>,
 }
 
 impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> SBTreeMap<K, V> {
-    /// Creates a new [SBTreeMap]
+    /// Creates a new [SBTreeMap] with the default branching factor
     ///
     /// Does not allocate any heap or stable memory.
     #[inline]
     pub fn new() -> Self {
+        Self::with_branching_factor()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    SBTreeMap<K, V, B>
+{
+    const CAPACITY: usize = 2 * B - 1;
+    const MIN_LEN_AFTER_SPLIT: usize = B - 1;
+    const CHILDREN_CAPACITY: usize = 2 * B;
+
+    // the branching factor is stored in the highest byte of the root pointer in the header
+    const HEADER_B_TAG: u64 = {
+        assert!(B >= 2 && B <= u8::MAX as usize, "B should be in 2..=255");
+        (B as u64) << 56
+    };
+    // the layout version of internal nodes is stored in the next byte
+    const HEADER_LAYOUT_TAG: u64 = (NODE_LAYOUT_VERSION as u64) << 48;
+    const HEADER_PTR_MASK: u64 = (1 << 48) - 1;
+
+    /// Creates a new [SBTreeMap] with the branching factor `B`
+    ///
+    /// Does not allocate any heap or stable memory. Does not compile, if `B` is not in `2..=255`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // nodes of this map hold up to 31 keys
+    /// let mut map = SBTreeMap::<u32, u32, 16>::with_branching_factor();
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(*map.get(&10).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn with_branching_factor() -> Self {
+        let _ = Self::HEADER_B_TAG;
+
         Self {
             root: None,
             len: 0,
@@ -69,6 +128,8 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
 
     #[inline]
     pub(crate) fn new_certified() -> Self {
+        let _ = Self::HEADER_B_TAG;
+
         Self {
             root: None,
             len: 0,
@@ -102,7 +163,11 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
     /// ```
     #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
-        self._insert(key, value, &mut LeveledList::None)
+        let mut modified = LeveledList::counts();
+        let res = self._insert(key, value, &mut modified);
+        Self::update_child_counts(&modified);
+
+        res
     }
 
     pub(crate) fn _insert(
@@ -112,7 +177,7 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
         modified: &mut LeveledList,
     ) -> Result<Option<V>, (K, V)> {
         if let Ok(mut node) = self.get_or_create_root() {
-            let mut leaf = loop {
+            let leaf = loop {
                 match unsafe { node.copy() } {
                     BTreeNode::Internal(internal_node) => {
                         let node_len = internal_node.read_len();
@@ -124,1808 +189,5143 @@ impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> S
                         let child_ptr = internal_node.read_child_ptr_buf(child_idx);
                         self.push_stack(internal_node, node_len, child_idx);
 
-                        node = BTreeNode::<K, V>::from_ptr(u64::from_fixed_size_bytes(&child_ptr));
+                        node =
+                            BTreeNode::<K, V, B>::from_ptr(u64::from_fixed_size_bytes(&child_ptr));
                     }
                     BTreeNode::Leaf(leaf_node) => break unsafe { leaf_node.copy() },
                 }
             };
 
-            // this call makes sure there is enough free stable memory to allocate everything else
-            // if it returns Ok - every other allocation after that should simply .unwrap()
-            let right_leaf = match self.insert_leaf(&mut leaf, key, value, modified)? {
-                Ok(v) => {
-                    self.clear_stack(modified);
+            self.insert_into_leaf(leaf, key, value, modified)
+        } else {
+            Err((key, value))
+        }
+    }
 
-                    return Ok(Some(v));
-                }
-                Err(right_leaf_opt) => {
-                    if let Some(right_leaf) = right_leaf_opt {
-                        right_leaf
-                    } else {
-                        self.clear_stack(modified);
-                        self.len += 1;
+    // inserts the pair into the leaf, which the stack leads to, splitting nodes up to the root, if needed
+    fn insert_into_leaf(
+        &mut self,
+        mut leaf: LeafBTreeNode<K, V, B>,
+        key: K,
+        value: V,
+        modified: &mut LeveledList,
+    ) -> Result<Option<V>, (K, V)> {
+        // this call makes sure there is enough free stable memory to allocate everything else
+        // if it returns Ok - every other allocation after that should simply .unwrap()
+        let res = self.insert_leaf(&mut leaf, key, value, modified);
 
-                        return Ok(None);
-                    }
-                }
-            };
+        // nothing was modified, but the stack should be empty for the next operation
+        if res.is_err() {
+            self._stack.clear();
+        }
 
-            let mut key_to_index = right_leaf.read_key_buf(0);
-            let mut ptr = right_leaf.as_ptr();
+        let right_leaf = match res? {
+            Ok(v) => {
+                self.clear_stack(modified, 0);
 
-            while let Some((mut parent, parent_len, idx)) = self.pop_stack() {
-                if let Some((right, _k)) = self.insert_internal(
-                    &mut parent,
-                    parent_len,
-                    idx,
-                    key_to_index,
-                    ptr.as_new_fixed_size_bytes(),
-                    modified,
-                ) {
-                    key_to_index = _k;
-                    ptr = right.as_ptr();
-                    node = BTreeNode::Internal(parent);
+                return Ok(Some(v));
+            }
+            Err(right_leaf_opt) => {
+                if let Some(right_leaf) = right_leaf_opt {
+                    right_leaf
                 } else {
-                    self.clear_stack(modified);
+                    self.clear_stack(modified, 1);
                     self.len += 1;
 
                     return Ok(None);
                 }
             }
+        };
 
-            // stack is empty now
-
-            let new_root = InternalBTreeNode::<K>::create(
-                &key_to_index,
-                &node.as_ptr().as_new_fixed_size_bytes(),
-                &ptr.as_new_fixed_size_bytes(),
-                self.certified,
-            )
-            .unwrap();
+        self.insert_split_leaf(leaf, right_leaf, modified);
 
-            modified.insert_root(new_root.as_ptr());
+        Ok(None)
+    }
 
-            self.root = Some(BTreeNode::Internal(new_root));
-            self.len += 1;
+    // inserts the right half of a split leaf into its parent, which the stack leads to, splitting
+    // nodes up to the root, if needed
+    fn insert_split_leaf(
+        &mut self,
+        leaf: LeafBTreeNode<K, V, B>,
+        right_leaf: LeafBTreeNode<K, V, B>,
+        modified: &mut LeveledList,
+    ) {
+        let mut node = BTreeNode::Leaf(leaf);
+        let mut key_to_index = right_leaf.read_key_buf(0);
+        let mut ptr = right_leaf.as_ptr();
+
+        while let Some((mut parent, parent_len, idx)) = self.pop_stack() {
+            if let Some((right, _k)) = self.insert_internal(
+                &mut parent,
+                parent_len,
+                idx,
+                key_to_index,
+                ptr.as_new_fixed_size_bytes(),
+                modified,
+            ) {
+                key_to_index = _k;
+                ptr = right.as_ptr();
+                node = BTreeNode::Internal(parent);
+            } else {
+                self.clear_stack(modified, 1);
+                self.len += 1;
 
-            Ok(None)
-        } else {
-            Err((key, value))
+                return;
+            }
         }
+
+        // stack is empty now
+
+        let new_root = InternalBTreeNode::<K, B>::create(
+            &key_to_index,
+            &node.as_ptr().as_new_fixed_size_bytes(),
+            &ptr.as_new_fixed_size_bytes(),
+            self.certified,
+        )
+        .unwrap();
+
+        modified.insert_root(new_root.as_ptr());
+
+        self.root = Some(BTreeNode::Internal(new_root));
+        self.len += 1;
     }
 
-    /// Removes a key-value pair by the provided key
-    ///
-    /// Returns [None] if no pair was found by this key. May release some of stable memory occupied
-    /// by this stable structure.
+    /// Inserts or updates key-value pairs from a slice sorted by keys in ascending order
     ///
-    /// # Examples
-    /// ```rust
-    /// # use ic_stable_memory::collections::SBTreeMap;
-    /// # use ic_stable_memory::stable_memory_init;
-    /// # unsafe { ic_stable_memory::mem::clear(); }
-    /// # stable_memory_init();
-    /// let mut map = SBTreeMap::new();
+    /// Works the same way as calling [SBTreeMap::insert] for each pair, but touches each affected
+    /// leaf only once: the path to the current leaf is kept between pairs, and the next pair is
+    /// searched for only in the smallest subtree of this path, which can contain it. So, pairs which
+    /// fall into the same leaf are inserted or replaced in place, without descending from the root for
+    /// each of them, and far apart pairs take a single descent. Only splitting a full leaf (or passing
+    /// the pair itself to its sibling) makes the next pair start from the root again. Replaced values
+    /// are stable-dropped.
     ///
-    /// map.insert(1, 10).expect("Out of memory");
+    /// Returns [OutOfMemory], if it was impossible to insert some new key. In that case all pairs
+    /// before it are already merged into this [SBTreeMap], and all pairs starting from it are not.
     ///
-    /// assert_eq!(map.remove(&1).unwrap(), 10);
-    /// ```
+    /// # Panics
+    /// In debug builds, panics if the slice is not sorted.
     ///
-    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
-    /// then you can remove the pair by [String].
+    /// # Example
     /// ```rust
     /// # use ic_stable_memory::collections::SBTreeMap;
-    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # use ic_stable_memory::stable_memory_init;
     /// # unsafe { ic_stable_memory::mem::clear(); }
     /// # stable_memory_init();
     /// let mut map = SBTreeMap::new();
     ///
-    /// let str_key = String::from("The key");
-    /// let key = SBox::new(str_key.clone()).expect("Out of memory");
+    /// for i in 0..100u64 {
+    ///     map.insert(i, 0u64).expect("Out of memory");
+    /// }
     ///
-    /// map.insert(key, 10).expect("Out of memory");
+    /// let batch = (50..150u64).map(|i| (i, 1u64)).collect::<Vec<_>>();
+    /// map.merge_sorted_slice(&batch).expect("Out of memory");
     ///
-    /// assert_eq!(map.remove(&str_key).unwrap(), 10);
+    /// assert_eq!(map.len(), 150);
+    /// assert_eq!(*map.get(&49).unwrap(), 0);
+    /// assert_eq!(*map.get(&50).unwrap(), 1);
+    /// assert_eq!(*map.get(&149).unwrap(), 1);
     /// ```
-    #[inline]
-    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    pub fn merge_sorted_slice(&mut self, sorted: &[(K, V)]) -> Result<(), OutOfMemory>
     where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
+        K: Clone,
+        V: Clone,
     {
-        self._remove(key, &mut LeveledList::None)
+        self._merge_sorted_slice(sorted).map(|_| ())
     }
 
-    pub(crate) fn _remove<Q>(&mut self, key: &Q, modified: &mut LeveledList) -> Option<V>
+    // returns the number of visited nodes
+    fn _merge_sorted_slice(&mut self, sorted: &[(K, V)]) -> Result<usize, OutOfMemory>
     where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
+        K: Clone,
+        V: Clone,
     {
-        self.root.as_ref()?;
+        debug_assert!(
+            sorted.windows(2).all(|it| it[0].0 <= it[1].0),
+            "The slice is not sorted"
+        );
 
-        let mut node = unsafe { self.get_or_create_root().unwrap_unchecked() };
-        let mut found_internal_node = None;
+        let mut modified = LeveledList::new();
+        let mut visited = 0;
 
-        // lookup for the leaf that may contain the key
-        let mut leaf = loop {
-            match node {
-                BTreeNode::Internal(internal_node) => {
-                    let node_len = internal_node.read_len();
-                    let child_idx = match internal_node.binary_search(key, node_len) {
-                        Ok(idx) => {
-                            debug_assert!(found_internal_node.is_none());
-                            found_internal_node = Some((unsafe { internal_node.copy() }, idx));
+        // for each frame of the stack: the upper bound of its child (the node and the index of the
+        // separator key) and whether something was inserted into that child
+        let mut bounds: Vec<(Option<(StablePtr, usize)>, bool)> = Vec::new();
+        let mut leaf: Option<LeafBTreeNode<K, V, B>> = None;
 
-                            idx + 1
-                        }
-                        Err(idx) => idx,
-                    };
+        self._stack.clear();
 
-                    let child_ptr = internal_node.read_child_ptr_buf(child_idx);
-                    self.push_stack(internal_node, node_len, child_idx);
+        for (key, value) in sorted {
+            let mut from = None;
+
+            if leaf.is_some() {
+                // leave subtrees, which can't contain the key
+                while let Some((Some((ptr, idx)), _)) = bounds.last() {
+                    let separator = unsafe { InternalBTreeNode::<K, B>::from_ptr(*ptr) }
+                        .read_key_as_reference(*idx);
+
+                    if *key < separator {
+                        break;
+                    }
+
+                    let (node, _, _) = self.pop_stack().unwrap();
+                    let (_, dirty) = bounds.pop().unwrap();
+
+                    if dirty {
+                        modified.push(self.current_depth(), node.as_ptr());
+                    }
 
-                    node = BTreeNode::<K, V>::from_ptr(u64::from_fixed_size_bytes(&child_ptr));
+                    from = Some(BTreeNode::Internal(node));
+                }
+            } else {
+                match self.get_or_create_root() {
+                    Ok(root) => from = Some(root),
+                    Err(_) => {
+                        Self::update_child_counts(&modified);
+                        return Err(OutOfMemory);
+                    }
                 }
-                BTreeNode::Leaf(leaf_node) => break unsafe { leaf_node.copy() },
             }
-        };
 
-        let leaf_len = leaf.read_len();
-        let idx = leaf.binary_search(key, leaf_len).ok()?;
+            if let Some(mut node) = from {
+                leaf = Some(loop {
+                    visited += 1;
 
-        self.len -= 1;
+                    match node {
+                        BTreeNode::Internal(internal_node) => {
+                            let node_len = internal_node.read_len();
+                            let child_idx = match internal_node.binary_search(key, node_len) {
+                                Ok(idx) => idx + 1,
+                                Err(idx) => idx,
+                            };
 
-        // if possible to simply remove the key without violating - return early
-        if leaf_len > MIN_LEN_AFTER_SPLIT {
-            let v = leaf.remove_and_disown_by_idx(idx, leaf_len, &mut self._buf);
-            leaf.write_len(leaf_len - 1);
+                            // the rightmost child inherits the upper bound of its parent
+                            let bound = if child_idx < node_len {
+                                Some((internal_node.as_ptr(), child_idx))
+                            } else {
+                                bounds.last().and_then(|(b, _)| *b)
+                            };
 
-            if let Some((mut fin, i)) = found_internal_node {
-                fin.write_key_buf(i, &leaf.read_key_buf(0));
+                            let child_ptr = internal_node.read_child_ptr_buf(child_idx);
+                            self.push_stack(internal_node, node_len, child_idx);
+                            bounds.push((bound, false));
+
+                            node = BTreeNode::from_ptr(u64::from_fixed_size_bytes(&child_ptr));
+                        }
+                        BTreeNode::Leaf(leaf_node) => break leaf_node,
+                    }
+                });
             }
 
-            modified.push(self.current_depth(), leaf.as_ptr());
-            self.clear_stack(modified);
+            let mut l = unsafe { leaf.as_ref().unwrap().copy() };
+            let len = l.read_len();
 
-            return Some(v);
-        };
+            let existing = l.binary_search(key, len).is_ok();
 
-        let stack_top_frame = self.peek_stack();
+            match self.insert_leaf(&mut l, key.clone(), value.clone(), &mut modified) {
+                // replaced in place
+                Ok(Ok(_prev)) => {}
+                // inserted in place or passed an element to a sibling
+                Ok(Err(None)) => {
+                    self.len += 1;
 
-        // if the only node in the tree is the root - return early
-        if stack_top_frame.is_none() {
-            let v = leaf.remove_and_disown_by_idx(idx, leaf_len, &mut self._buf);
-            leaf.write_len(leaf_len - 1);
+                    for (_, dirty) in bounds.iter_mut() {
+                        *dirty = true;
+                    }
 
-            modified.push(0, leaf.as_ptr());
+                    // if the key itself went to the left sibling, the next keys may belong there too
+                    if !existing
+                        && len == Self::CAPACITY
+                        && l.binary_search(key, l.read_len()).is_err()
+                    {
+                        self.leave_merge_path(&mut bounds, &mut modified);
+                        leaf = None;
+                    }
+                }
+                // the leaf was split - the path is not reliable anymore, so the next pair starts
+                // from the root
+                Ok(Err(Some(right))) => {
+                    bounds.clear();
+                    leaf = None;
 
-            return Some(v);
+                    self.insert_split_leaf(l, right, &mut modified);
+                }
+                Err(_) => {
+                    self.leave_merge_path(&mut bounds, &mut modified);
+                    Self::update_child_counts(&modified);
+
+                    return Err(OutOfMemory);
+                }
+            }
         }
 
-        self.steal_from_sibling_leaf_or_merge(
-            stack_top_frame,
-            leaf,
-            idx,
-            found_internal_node,
-            modified,
-        )
+        self.leave_merge_path(&mut bounds, &mut modified);
+        Self::update_child_counts(&modified);
+
+        Ok(visited)
     }
 
-    /// Returns an immutable reference [SRef] to a value stored by the key
-    ///
-    /// See also [SBTreeMap::get_mut].
+    // clears the stack, marking nodes, which had something inserted into them, as modified
+    fn leave_merge_path(
+        &mut self,
+        bounds: &mut Vec<(Option<(StablePtr, usize)>, bool)>,
+        modified: &mut LeveledList,
+    ) {
+        while let Some((node, _, _)) = self.pop_stack() {
+            let (_, dirty) = bounds.pop().unwrap();
+
+            if dirty {
+                modified.push(self.current_depth(), node.as_ptr());
+            }
+        }
+    }
+
+    /// Builds a new [SBTreeMap] from pairs, sorted in the ascending order of their keys
     ///
-    /// If no such key-value pair is found, returns [None]
+    /// Same as [SBTreeMap::try_from_sorted_iter], but panics if the canister is out of stable memory.
     ///
-    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
-    /// then you can get the value by [String].
+    /// # Panics
+    /// Panics if the keys are not strictly ascending or if the canister is out of stable memory.
     ///
     /// # Example
     /// ```rust
     /// # use ic_stable_memory::collections::SBTreeMap;
-    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # use ic_stable_memory::stable_memory_init;
     /// # unsafe { ic_stable_memory::mem::clear(); }
     /// # stable_memory_init();
-    /// let mut map = SBTreeMap::new();
-    ///
-    /// let str_key = String::from("The key");
-    /// let key = SBox::new(str_key.clone()).expect("Out of memory");
-    ///
-    /// map.insert(key, 10).expect("Out of memory");
+    /// let map = SBTreeMap::<u64, u64>::from_sorted_iter((0..100).map(|i| (i, i * 10)));
     ///
-    /// assert_eq!(*map.get(&str_key).unwrap(), 10);
+    /// assert_eq!(map.len(), 100);
+    /// assert_eq!(*map.get(&42).unwrap(), 420);
     /// ```
     #[inline]
-    pub fn get<Q>(&self, key: &Q) -> Option<SRef<V>>
-    where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
-    {
-        let (leaf_node, idx) = self.lookup(key, false)?;
-
-        Some(leaf_node.get_value(idx))
+    pub fn from_sorted_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self::try_from_sorted_iter(iter).expect("Out of memory")
     }
 
-    /// Returns a random key, deterministically deriving the randomness from the seed.
-    /// This function is usefull, when you have a source of real randomness.
+    /// Builds a new [SBTreeMap] from pairs, sorted in the ascending order of their keys
     ///
-    /// If the collection is empty, returns [None].
+    /// Instead of inserting the pairs one by one, the tree is bulk-loaded bottom-up: pairs are
+    /// written into leaves, which are filled up to their capacity, and then the levels of internal
+    /// nodes are built on top of them. This way each node is allocated and written exactly once.
+    /// Only a couple of pairs and the first key of each node of the level being built are kept on
+    /// the heap.
     ///
-    /// Same seed on the same collection leads to the same returned key.
-    /// Same seed on a modified collection may still lead to the same returned key.
-    /// You can use [utils::math::shuffle_bits] function to pseudo-randomly generate more seeds.
-    pub fn get_random_key(&self, mut seed: u32) -> Option<SRef<K>> {
-        if self.is_empty() {
-            return None;
+    /// If the canister is out of stable memory, returns [OutOfMemory], releasing everything which was
+    /// built so far, as well as the pairs which were already taken from the iterator.
+    ///
+    /// # Panics
+    /// Panics if the keys are not strictly ascending.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let map = SBTreeMap::<u64, u64>::try_from_sorted_iter((0..100).map(|i| (i, i * 10)))
+    ///     .expect("Out of memory");
+    ///
+    /// assert_eq!(map.len(), 100);
+    /// ```
+    pub fn try_from_sorted_iter<I: IntoIterator<Item = (K, V)>>(
+        iter: I,
+    ) -> Result<Self, OutOfMemory> {
+        let mut map = Self::with_branching_factor();
+
+        let capacity = LeafBTreeNode::<K, V, B>::CAPACITY;
+        let min_len = LeafBTreeNode::<K, V, B>::MIN_LEN_AFTER_SPLIT;
+
+        // a leaf is only written, once it is known that enough pairs are left for the next one
+        let mut pending = VecDeque::new();
+        let mut level = Vec::new();
+        let mut prev_leaf = None;
+
+        for (key, value) in iter {
+            if let Some((prev_key, _)) = pending.back() {
+                assert!(*prev_key < key, "The keys are not sorted");
+            }
+
+            pending.push_back((key, value));
+            map.len += 1;
+
+            if pending.len() == capacity + min_len {
+                Self::push_bulk_leaf(&mut pending, capacity, &mut prev_leaf, &mut level)
+                    .map_err(|e| Self::drop_bulk_level(level.drain(..), e))?;
+            }
         }
 
-        let mut node = self.get_root()?;
+        // the rest is split in two, if it doesn't fit into a single leaf
+        if pending.len() > capacity {
+            let len = pending.len() / 2;
+            Self::push_bulk_leaf(&mut pending, len, &mut prev_leaf, &mut level)
+                .map_err(|e| Self::drop_bulk_level(level.drain(..), e))?;
+        }
 
-        loop {
-            match node {
-                BTreeNode::Internal(i) => {
-                    let len = i.read_len();
-                    let idx = seed as usize % (len + 1);
-                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(idx));
+        if !pending.is_empty() {
+            let len = pending.len();
+            Self::push_bulk_leaf(&mut pending, len, &mut prev_leaf, &mut level)
+                .map_err(|e| Self::drop_bulk_level(level.drain(..), e))?;
+        }
 
-                    seed = shuffle_bits(seed);
+        while level.len() > 1 {
+            let children_len = level.len();
+            let nodes_len = children_len.div_ceil(2 * B);
+            let mut next_level = Vec::with_capacity(nodes_len);
+            let mut children = level.into_iter();
+
+            for i in 0..nodes_len {
+                // children are spread evenly, so each node gets at least B of them
+                let len = children_len / nodes_len + usize::from(i < children_len % nodes_len);
+
+                let mut node = match InternalBTreeNode::<K, B>::create_empty(false) {
+                    Ok(node) => node,
+                    Err(e) => {
+                        return Err(Self::drop_bulk_level(
+                            next_level.into_iter().chain(children),
+                            e,
+                        ))
+                    }
+                };
+                let (first_key, first_ptr, first_count): (K::Buf, StablePtr, u64) =
+                    children.next().unwrap();
+                node.push_child_ptr_buf(&first_ptr.as_new_fixed_size_bytes(), 0);
+                node.write_child_count(0, first_count);
 
-                    node = BTreeNode::from_ptr(child_ptr);
-                }
-                BTreeNode::Leaf(l) => {
-                    let len = l.read_len();
-                    let idx = seed as usize % len;
+                let mut count = first_count;
 
-                    break Some(l.get_key(idx));
+                for j in 1..len {
+                    let (key, ptr, child_count) = children.next().unwrap();
+
+                    node.push_key_buf(&key, j - 1);
+                    node.push_child_ptr_buf(&ptr.as_new_fixed_size_bytes(), j);
+                    node.write_child_count(j, child_count);
+
+                    count += child_count;
                 }
+
+                node.write_len(len - 1);
+                next_level.push((first_key, node.as_ptr(), count));
             }
+
+            level = next_level;
+        }
+
+        if let Some((_, root_ptr, _)) = level.pop() {
+            map.root = Some(BTreeNode::from_ptr(root_ptr));
         }
+
+        Ok(map)
     }
 
-    /// Returns a mutable reference [SRefMut] to a value stored by the key
-    ///
-    /// See also [SBTreeMap::get].
-    ///
-    /// If no such key-value pair is found, returns [None]
-    ///
-    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
-    /// then you can get the value by [String].
-    #[inline]
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<V>>
-    where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
-    {
-        self._get_mut(key, &mut LeveledList::None)
-    }
-
-    #[inline]
-    pub(crate) fn _get_mut<Q>(&mut self, key: &Q, modified: &mut LeveledList) -> Option<SRefMut<V>>
-    where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
-    {
-        if modified.is_some() {
-            let mut modified_buf = Vec::new();
-
-            let mut level = 0;
-            let mut node = self.get_root()?;
-            loop {
-                match node {
-                    BTreeNode::Internal(internal_node) => {
-                        let child_idx =
-                            match internal_node.binary_search(key, internal_node.read_len()) {
-                                Ok(idx) => idx + 1,
-                                Err(idx) => idx,
-                            };
-
-                        modified_buf.push((level, internal_node.as_ptr()));
-                        level += 1;
-
-                        let child_ptr = u64::from_fixed_size_bytes(
-                            &internal_node.read_child_ptr_buf(child_idx),
-                        );
-                        node = BTreeNode::from_ptr(child_ptr);
-                    }
-                    BTreeNode::Leaf(mut leaf_node) => {
-                        return match leaf_node.binary_search(key, leaf_node.read_len()) {
-                            Ok(idx) => {
-                                for (l, ptr) in modified_buf {
-                                    modified.push(l, ptr);
-                                }
-
-                                modified.push(level, leaf_node.as_ptr());
-
-                                Some(leaf_node.get_value_mut(idx))
-                            }
-                            _ => None,
-                        }
-                    }
-                }
-            }
+    // stable-drops subtrees of a partially bulk-loaded tree
+    fn drop_bulk_level<I: Iterator<Item = (K::Buf, StablePtr, u64)>>(
+        level: I,
+        e: OutOfMemory,
+    ) -> OutOfMemory {
+        for (_, ptr, _) in level {
+            let mut subtree = Self::with_branching_factor();
+            subtree.root = Some(BTreeNode::from_ptr(ptr));
         }
 
-        let (mut leaf_node, idx) = self.lookup(key, false)?;
-
-        Some(leaf_node.get_value_mut(idx))
-    }
-
-    /// Returns true if there exists a key-value pair stored by the provided key
-    ///
-    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
-    /// then you can get the value by [String].
-    #[inline]
-    pub fn contains_key<Q>(&self, key: &Q) -> bool
-    where
-        K: Borrow<Q>,
-        Q: Ord + ?Sized,
-    {
-        self.lookup(key, true).is_some()
+        e
     }
 
-    /// Returns an iterator over entries of this [SBTreeMap]
+    /// Builds a new [SBTreeMap] from the pairs of an [SVec]
     ///
-    /// Elements of this iterator are presented in ascending order.
+    /// Same as [SBTreeMap::try_from_svec], but panics if the canister is out of stable memory.
     ///
     /// # Example
     /// ```rust
-    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::collections::{SBTreeMap, SVec};
     /// # use ic_stable_memory::stable_memory_init;
     /// # unsafe { ic_stable_memory::mem::clear(); }
     /// # stable_memory_init();
-    /// let mut map = SBTreeMap::new();
+    /// let mut pairs = SVec::new();
     ///
-    /// for i in 0..100 {
-    ///     map.insert(i, i).expect("Out of memory");
+    /// for (k, v) in [(3u64, 30u64), (1, 10), (2, 20), (1, 11)] {
+    ///     pairs.push((k, v)).expect("Out of memory");
     /// }
     ///
-    /// let mut i = 0;
-    /// for (k, v) in map.iter() {
-    ///     assert_eq!(*k, i);
-    ///     assert_eq!(*v, i);
-    ///
-    ///     i += 1;
-    /// }
+    /// let map = SBTreeMap::<u64, u64>::from_svec(pairs);
     ///
-    /// assert_eq!(i, 100);
+    /// assert_eq!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![1, 2, 3]);
+    /// assert_eq!(*map.get(&1).unwrap(), 11);
     /// ```
+    #[inline]
+    pub fn from_svec(v: SVec<(K, V)>) -> Self {
+        Self::try_from_svec(v).expect("Out of memory")
+    }
+
+    /// Builds a new [SBTreeMap] from the pairs of an [SVec]
     ///
-    /// One can use `.rev()` to get elements in reverse order.
+    /// The [SVec] is sorted in place with [SVec::sort_by], and then the tree is bulk-loaded from it
+    /// with [SBTreeMap::try_from_sorted_iter], without copying the pairs into a heap [Vec]. The
+    /// [SVec] is released afterwards. If several pairs have the same key, the last one of them wins,
+    /// like with repeated [SBTreeMap::insert] calls, and the others are dropped.
+    ///
+    /// To keep pairs with equal keys in their original order, positions of pairs are sorted first,
+    /// which takes additional `8` bytes of stable memory per pair.
+    ///
+    /// If the canister is out of stable memory, returns [OutOfMemory], releasing the pairs.
     ///
     /// # Example
     /// ```rust
-    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::collections::{SBTreeMap, SVec};
     /// # use ic_stable_memory::stable_memory_init;
     /// # unsafe { ic_stable_memory::mem::clear(); }
     /// # stable_memory_init();
-    /// let mut map = SBTreeMap::new();
+    /// let mut pairs = SVec::new();
     ///
-    /// for i in 0..100 {
-    ///     map.insert(i, i).expect("Out of memory");
+    /// for i in [3u64, 1, 2] {
+    ///     pairs.push((i, i * 10)).expect("Out of memory");
     /// }
     ///
-    /// let mut i = 100;
-    /// for (k, v) in map.iter().rev() {
-    ///     i -= 1;
-    ///
-    ///     assert_eq!(*k, i);
-    ///     assert_eq!(*v, i);
-    /// }
+    /// let map = SBTreeMap::<u64, u64>::try_from_svec(pairs).expect("Out of memory");
     ///
-    /// assert_eq!(i, 0);
+    /// assert_eq!(map.len(), 3);
     /// ```
-    #[inline]
-    pub fn iter(&self) -> SBTreeMapIter<K, V> {
-        SBTreeMapIter::<K, V>::new(self)
-    }
+    pub fn try_from_svec(mut v: SVec<(K, V)>) -> Result<Self, OutOfMemory> {
+        let mut order = SVec::<u64>::new_with_capacity(v.len())?;
+        for i in 0..v.len() {
+            order.push(i as u64).map_err(|_| OutOfMemory)?;
+        }
 
-    /// Returns the length of this [SBTreeMap]
-    #[inline]
-    pub fn len(&self) -> u64 {
-        self.len
-    }
+        // sorted in the descending order of (key, position), so popping pairs from the end yields
+        // the ascending order of keys, and pairs with equal keys come in their original order
+        order.sort_by(|a, b| {
+            let key_a = &v.get(*a as usize).unwrap().0;
+            let key_b = &v.get(*b as usize).unwrap().0;
 
-    /// Returns [true] if the length of this [SBTreeMap] is `0`
-    #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
+            key_b.cmp(key_a).then(b.cmp(a))
+        });
 
-    /// Removes all key-value pairs from this collection, releasing all occupied stable memory
-    #[inline]
-    pub fn clear(&mut self) {
-        let mut old = mem::replace(self, Self::new());
-        self.stable_drop_flag = old.stable_drop_flag;
-        self.certified = old.certified;
+        // moves each pair to its place in the sorted order, following the cycles of the permutation
+        for i in 0..order.len() {
+            let mut cur = i;
+            let mut src = order.replace(cur, u64::MAX);
 
-        unsafe { old.stable_drop() };
-    }
+            while src != u64::MAX && src as usize != i {
+                v.swap(cur, src as usize);
 
-    #[inline]
-    fn clear_stack(&mut self, modified: &mut LeveledList) {
-        match modified {
-            LeveledList::None => {
-                self._stack.clear();
-            }
-            LeveledList::Some(_) => {
-                while let Some((p, _, _)) = self._stack.pop() {
-                    modified.push(self.current_depth(), p.as_ptr());
-                }
+                cur = src as usize;
+                src = order.replace(cur, u64::MAX);
             }
         }
-    }
 
-    #[inline]
-    fn current_depth(&self) -> usize {
-        self._stack.len()
-    }
+        drop(order);
 
-    #[inline]
-    fn push_stack(&mut self, node: InternalBTreeNode<K>, len: usize, child_idx: usize) {
-        self._stack.push((node, len, child_idx));
-    }
+        let mut pairs = std::iter::from_fn(|| v.pop()).peekable();
 
-    #[inline]
-    fn pop_stack(&mut self) -> Option<(InternalBTreeNode<K>, usize, usize)> {
-        self._stack.pop()
+        Self::try_from_sorted_iter(std::iter::from_fn(move || {
+            let mut pair = pairs.next()?;
+            while let Some(next) = pairs.next_if(|next| next.0 == pair.0) {
+                pair = next;
+            }
+
+            Some(pair)
+        }))
     }
 
-    pub(crate) fn get_root(&self) -> Option<BTreeNode<K, V>> {
-        unsafe { self.root.as_ref().map(|it| it.copy()) }
+    fn push_bulk_leaf(
+        pending: &mut VecDeque<(K, V)>,
+        len: usize,
+        prev_leaf: &mut Option<LeafBTreeNode<K, V, B>>,
+        level: &mut Vec<(K::Buf, StablePtr, u64)>,
+    ) -> Result<(), OutOfMemory> {
+        let mut leaf = LeafBTreeNode::<K, V, B>::create(false)?;
+        level.push((
+            pending[0].0.as_new_fixed_size_bytes(),
+            leaf.as_ptr(),
+            len as u64,
+        ));
+
+        for i in 0..len {
+            let (key, value) = pending.pop_front().unwrap();
+
+            leaf.write_and_own_key(i, key);
+            leaf.write_and_own_value(i, value);
+        }
+
+        leaf.write_len(len);
+
+        if let Some(prev) = prev_leaf {
+            prev.write_next_ptr_buf(&leaf.as_ptr().as_new_fixed_size_bytes());
+            leaf.write_prev_ptr_buf(&prev.as_ptr().as_new_fixed_size_bytes());
+        }
+
+        *prev_leaf = Some(leaf);
+
+        Ok(())
     }
 
-    pub(crate) fn set_certified(&mut self, val: bool) {
-        self.certified = val;
+    /// Removes a key-value pair by the provided key
+    ///
+    /// Returns [None] if no pair was found by this key. May release some of stable memory occupied
+    /// by this stable structure.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// map.insert(1, 10).expect("Out of memory");
+    ///
+    /// assert_eq!(map.remove(&1).unwrap(), 10);
+    /// ```
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can remove the pair by [String].
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// let str_key = String::from("The key");
+    /// let key = SBox::new(str_key.clone()).expect("Out of memory");
+    ///
+    /// map.insert(key, 10).expect("Out of memory");
+    ///
+    /// assert_eq!(map.remove(&str_key).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut modified = LeveledList::counts();
+        let res = self._remove(key, &mut modified);
+        Self::update_child_counts(&modified);
+
+        res
     }
 
-    // WARNING: return_early == true will return nonsense leaf node and idx
-    fn lookup<Q>(&self, key: &Q, return_early: bool) -> Option<(LeafBTreeNode<K, V>, usize)>
+    pub(crate) fn _remove<Q>(&mut self, key: &Q, modified: &mut LeveledList) -> Option<V>
     where
         K: Borrow<Q>,
         Q: Ord + ?Sized,
     {
-        let mut node = self.get_root()?;
-        loop {
+        self.root.as_ref()?;
+
+        let mut node = unsafe { self.get_or_create_root().unwrap_unchecked() };
+        let mut found_internal_node = None;
+
+        // lookup for the leaf that may contain the key
+        let mut leaf = loop {
             match node {
                 BTreeNode::Internal(internal_node) => {
-                    let child_idx = match internal_node.binary_search(key, internal_node.read_len())
-                    {
+                    let node_len = internal_node.read_len();
+                    let child_idx = match internal_node.binary_search(key, node_len) {
                         Ok(idx) => {
-                            if return_early {
-                                return unsafe { Some((LeafBTreeNode::from_ptr(0), 0)) };
-                            } else {
-                                idx + 1
-                            }
+                            debug_assert!(found_internal_node.is_none());
+                            found_internal_node = Some((unsafe { internal_node.copy() }, idx));
+
+                            idx + 1
                         }
                         Err(idx) => idx,
                     };
 
-                    let child_ptr =
-                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
-                    node = BTreeNode::from_ptr(child_ptr);
-                }
-                BTreeNode::Leaf(leaf_node) => {
-                    return match leaf_node.binary_search(key, leaf_node.read_len()) {
-                        Ok(idx) => Some((leaf_node, idx)),
-                        _ => None,
-                    }
+                    let child_ptr = internal_node.read_child_ptr_buf(child_idx);
+                    self.push_stack(internal_node, node_len, child_idx);
+
+                    node = BTreeNode::<K, V, B>::from_ptr(u64::from_fixed_size_bytes(&child_ptr));
                 }
+                BTreeNode::Leaf(leaf_node) => break unsafe { leaf_node.copy() },
             }
-        }
-    }
-
-    fn insert_leaf(
-        &mut self,
-        leaf_node: &mut LeafBTreeNode<K, V>,
-        mut key: K,
-        mut value: V,
-        modified: &mut LeveledList,
-    ) -> Result<Result<V, Option<LeafBTreeNode<K, V>>>, (K, V)> {
-        let leaf_node_len = leaf_node.read_len();
-        let insert_idx = match leaf_node.binary_search(&key, leaf_node_len) {
-            Ok(existing_idx) => {
-                // if there is already a key like that, return early
-                let prev_value: V = leaf_node.read_and_disown_value(existing_idx);
-                leaf_node.write_and_own_value(existing_idx, value);
+        };
 
-                modified.push(self.current_depth(), leaf_node.as_ptr());
+        let leaf_len = leaf.read_len();
+        let idx = match leaf.binary_search(key, leaf_len) {
+            Ok(idx) => idx,
+            Err(_) => {
+                // nothing was modified, but the stack should be empty for the next operation
+                self._stack.clear();
 
-                return Ok(Ok(prev_value));
+                return None;
+            }
+        };
+
+        self.len -= 1;
+
+        // if possible to simply remove the key without violating - return early
+        if leaf_len > Self::MIN_LEN_AFTER_SPLIT {
+            let v = leaf.remove_and_disown_by_idx(idx, leaf_len, &mut self._buf);
+            leaf.write_len(leaf_len - 1);
+
+            if let Some((mut fin, i)) = found_internal_node {
+                fin.write_key_buf(i, &leaf.read_key_buf(0));
+            }
+
+            modified.push(self.current_depth(), leaf.as_ptr());
+            self.clear_stack(modified, -1);
+
+            return Some(v);
+        };
+
+        let stack_top_frame = self.peek_stack();
+
+        // if the only node in the tree is the root - return early
+        if stack_top_frame.is_none() {
+            let v = leaf.remove_and_disown_by_idx(idx, leaf_len, &mut self._buf);
+            leaf.write_len(leaf_len - 1);
+
+            modified.push(0, leaf.as_ptr());
+
+            return Some(v);
+        }
+
+        self.steal_from_sibling_leaf_or_merge(
+            stack_top_frame,
+            leaf,
+            idx,
+            found_internal_node,
+            modified,
+        )
+    }
+
+    /// Returns an immutable reference [SRef] to a value stored by the key
+    ///
+    /// See also [SBTreeMap::get_mut].
+    ///
+    /// If no such key-value pair is found, returns [None]
+    ///
+    /// The value is not copied out of the leaf by this function - it is only read and decoded on the
+    /// first dereference of the returned [SRef] and then cached by it.
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the value by [String].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// let str_key = String::from("The key");
+    /// let key = SBox::new(str_key.clone()).expect("Out of memory");
+    ///
+    /// map.insert(key, 10).expect("Out of memory");
+    ///
+    /// assert_eq!(*map.get(&str_key).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn get<Q>(&self, key: &Q) -> Option<SRef<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (leaf_node, idx) = self.lookup(key, false)?;
+
+        Some(leaf_node.get_value(idx))
+    }
+
+    /// Returns a random key, deterministically deriving the randomness from the seed.
+    /// This function is usefull, when you have a source of real randomness.
+    ///
+    /// If the collection is empty, returns [None].
+    ///
+    /// Same seed on the same collection leads to the same returned key.
+    /// Same seed on a modified collection may still lead to the same returned key.
+    /// You can use [utils::math::shuffle_bits] function to pseudo-randomly generate more seeds.
+    pub fn get_random_key(&self, mut seed: u32) -> Option<SRef<K>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut node = self.get_root()?;
+
+        loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let len = i.read_len();
+                    let idx = seed as usize % (len + 1);
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(idx));
+
+                    seed = shuffle_bits(seed);
+
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => {
+                    let len = l.read_len();
+                    let idx = seed as usize % len;
+
+                    break Some(l.get_key(idx));
+                }
+            }
+        }
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key
+    ///
+    /// See also [SBTreeMap::get].
+    ///
+    /// If no such key-value pair is found, returns [None]
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the value by [String].
+    ///
+    /// The value is modified in place - the returned [SRefMut] writes it back to the same location
+    /// inside the leaf on drop, so no second descent is needed. Since it mutably borrows this
+    /// [SBTreeMap], only one such reference can be held at a time.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u8 {
+    ///     map.insert(i, [i; 128]).expect("Out of memory");
+    /// }
+    ///
+    /// {
+    ///     let mut value = map.get_mut(&50).unwrap();
+    ///     value[0] = 255;
+    /// }
+    ///
+    /// assert_eq!(map.get(&50).unwrap()[0], 255);
+    /// assert_eq!(map.get(&50).unwrap()[1], 50);
+    /// ```
+    #[inline]
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<SRefMut<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self._get_mut(key, &mut LeveledList::None)
+    }
+
+    #[inline]
+    pub(crate) fn _get_mut<Q>(&mut self, key: &Q, modified: &mut LeveledList) -> Option<SRefMut<V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if modified.is_some() {
+            let mut modified_buf = Vec::new();
+
+            let mut level = 0;
+            let mut node = self.get_root()?;
+            loop {
+                match node {
+                    BTreeNode::Internal(internal_node) => {
+                        let child_idx =
+                            match internal_node.binary_search(key, internal_node.read_len()) {
+                                Ok(idx) => idx + 1,
+                                Err(idx) => idx,
+                            };
+
+                        modified_buf.push((level, internal_node.as_ptr()));
+                        level += 1;
+
+                        let child_ptr = u64::from_fixed_size_bytes(
+                            &internal_node.read_child_ptr_buf(child_idx),
+                        );
+                        node = BTreeNode::from_ptr(child_ptr);
+                    }
+                    BTreeNode::Leaf(mut leaf_node) => {
+                        return match leaf_node.binary_search(key, leaf_node.read_len()) {
+                            Ok(idx) => {
+                                for (l, ptr) in modified_buf {
+                                    modified.push(l, ptr);
+                                }
+
+                                modified.push(level, leaf_node.as_ptr());
+
+                                Some(leaf_node.get_value_mut(idx))
+                            }
+                            _ => None,
+                        }
+                    }
+                }
+            }
+        }
+
+        let (mut leaf_node, idx) = self.lookup(key, false)?;
+
+        Some(leaf_node.get_value_mut(idx))
+    }
+
+    /// Returns a mutable reference [SRefMut] to a value stored by the key, inserting the provided
+    /// default value first, if there is no such key
+    ///
+    /// The returned [bool] is `true`, if the default value was inserted, and `false` if the key was
+    /// already present - in that case both the key and the default value are dropped. An existing key
+    /// is found in a single descent, while a new one is inserted with [SBTreeMap::insert] and then
+    /// looked up again, since inserting may split leaves and move the key.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut visits = SBTreeMap::new();
+    ///
+    /// for user in [1u64, 2, 1, 1] {
+    ///     let (mut count, first_visit) = visits.get_or_insert(user, 0u64);
+    ///     if first_visit {
+    ///         println!("Welcome, {user}!");
+    ///     }
+    ///
+    ///     *count += 1;
+    /// }
+    ///
+    /// assert_eq!(*visits.get(&1).unwrap(), 3);
+    /// assert_eq!(*visits.get(&2).unwrap(), 1);
+    /// ```
+    pub fn get_or_insert(&mut self, key: K, default: V) -> (SRefMut<'_, V>, bool) {
+        match self.entry(key) {
+            Entry::Occupied(e) => (e.into_mut(), false),
+            Entry::Vacant(e) => (e.insert(default), true),
+        }
+    }
+
+    /// Returns an [Entry] for the key, which can be used to insert or update its value
+    ///
+    /// The tree is descended once and the location of an existing value is remembered by the entry,
+    /// so [Entry::and_modify], [Entry::or_insert] and other methods of an occupied entry don't
+    /// traverse it again. Inserting into a vacant entry works like [SBTreeMap::insert] - it may split
+    /// leaves, so the inserted value is looked up again afterwards.
+    ///
+    /// The entry holds a mutable borrow of this [SBTreeMap], so the map can't be modified (e.g. from
+    /// inside of the [Entry::and_modify] lambda) while the entry is alive, which means that the
+    /// remembered location can't be invalidated.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut counters = SBTreeMap::new();
+    ///
+    /// for key in [1u64, 2, 1, 1] {
+    ///     counters
+    ///         .entry(key)
+    ///         .and_modify(|mut it| *it += 1)
+    ///         .or_insert(1u64);
+    /// }
+    ///
+    /// assert_eq!(*counters.get(&1).unwrap(), 3);
+    /// assert_eq!(*counters.get(&2).unwrap(), 1);
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, B> {
+        match self.lookup(&key, false) {
+            Some((leaf, idx)) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                key,
+                leaf,
+                idx,
+            }),
+            None => Entry::Vacant(VacantEntry { map: self, key }),
+        }
+    }
+
+    /// Returns the smallest key and a mutable reference [SRefMut] to its value, or [None] if the
+    /// map is empty
+    ///
+    /// Descends straight to the leftmost leaf, without comparing any keys. The returned key is a
+    /// copy, read for reference - it does not own any stable memory, dropping it won't affect the
+    /// key stored inside this map.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// {
+    ///     let (k, mut v) = map.first_entry_mut().unwrap();
+    ///     assert_eq!(k, 0);
+    ///     *v = 42;
+    /// }
+    ///
+    /// assert_eq!(*map.get(&0).unwrap(), 42);
+    /// ```
+    #[inline]
+    pub fn first_entry_mut(&mut self) -> Option<(K, SRefMut<'_, V>)> {
+        self.edge_entry_mut(false)
+    }
+
+    /// Returns the biggest key and a mutable reference [SRefMut] to its value, or [None] if the
+    /// map is empty
+    ///
+    /// See [SBTreeMap::first_entry_mut].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// {
+    ///     let (k, mut v) = map.last_entry_mut().unwrap();
+    ///     assert_eq!(k, 99);
+    ///     *v = 42;
+    /// }
+    ///
+    /// assert_eq!(*map.get(&99).unwrap(), 42);
+    /// ```
+    #[inline]
+    pub fn last_entry_mut(&mut self) -> Option<(K, SRefMut<'_, V>)> {
+        self.edge_entry_mut(true)
+    }
+
+    fn edge_entry_mut(&mut self, last: bool) -> Option<(K, SRefMut<'_, V>)> {
+        let (mut leaf_node, idx) = self.edge_leaf(last)?;
+
+        Some((
+            leaf_node.read_key_as_reference(idx),
+            leaf_node.get_value_mut(idx),
+        ))
+    }
+
+    /// Returns references [SRef] to the smallest key and its value, or [None] if the map is empty
+    ///
+    /// Descends straight to the leftmost leaf in `O(height)`, without comparing any keys or
+    /// constructing an iterator.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    /// assert!(map.first_key_value().is_none());
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let (k, v) = map.first_key_value().unwrap();
+    /// assert_eq!((*k, *v), (0, 0));
+    /// ```
+    #[inline]
+    pub fn first_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        let (leaf_node, idx) = self.edge_leaf(false)?;
+
+        Some((leaf_node.get_key(idx), leaf_node.get_value(idx)))
+    }
+
+    /// Returns references [SRef] to the largest key and its value, or [None] if the map is empty
+    ///
+    /// Descends straight to the rightmost leaf in `O(height)`, without comparing any keys or
+    /// constructing an iterator.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    /// assert!(map.last_key_value().is_none());
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let (k, v) = map.last_key_value().unwrap();
+    /// assert_eq!((*k, *v), (99, 198));
+    /// ```
+    #[inline]
+    pub fn last_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        let (leaf_node, idx) = self.edge_leaf(true)?;
+
+        Some((leaf_node.get_key(idx), leaf_node.get_value(idx)))
+    }
+
+    /// Returns references [SRef] to the largest key, which is less than or equal to the provided
+    /// one, and its value
+    ///
+    /// Returns [None] if all keys of this [SBTreeMap] are bigger than the provided one. Descends
+    /// once, just like [SBTreeMap::get]. If the leaf it ends up in only contains bigger keys, the
+    /// result is the last entry of the previous leaf.
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can search by [String].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut rate_limits = SBTreeMap::new();
+    ///
+    /// rate_limits.insert(0u64, 100u64).expect("Out of memory");
+    /// rate_limits.insert(1000u64, 50u64).expect("Out of memory");
+    /// rate_limits.insert(10000u64, 10u64).expect("Out of memory");
+    ///
+    /// let (threshold, limit) = rate_limits.floor(&5000).unwrap();
+    /// assert_eq!((*threshold, *limit), (1000, 50));
+    ///
+    /// assert_eq!(*rate_limits.floor(&10000).unwrap().1, 10);
+    /// ```
+    pub fn floor<Q>(&self, key: &Q) -> Option<(SRef<'_, K>, SRef<'_, V>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (leaf_node, res) = self.find_leaf(key)?;
+
+        let idx = match res {
+            Ok(idx) => idx,
+            Err(0) => {
+                // all keys to the left of this leaf are less than the provided one
+                let prev_ptr = u64::from_fixed_size_bytes(&leaf_node.read_prev_ptr_buf());
+                if prev_ptr == 0 {
+                    return None;
+                }
+
+                let prev_leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(prev_ptr) };
+                let idx = prev_leaf.read_len() - 1;
+
+                return Some((prev_leaf.get_key(idx), prev_leaf.get_value(idx)));
+            }
+            Err(idx) => idx - 1,
+        };
+
+        Some((leaf_node.get_key(idx), leaf_node.get_value(idx)))
+    }
+
+    /// Returns references [SRef] to the smallest key, which is greater than or equal to the
+    /// provided one, and its value
+    ///
+    /// Returns [None] if all keys of this [SBTreeMap] are less than the provided one. Works the same
+    /// way as [SBTreeMap::floor], but continues to the next leaf instead.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 1..10u64 {
+    ///     map.insert(i * 10, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(*map.ceiling(&15).unwrap().0, 20);
+    /// assert_eq!(*map.ceiling(&20).unwrap().0, 20);
+    /// assert!(map.ceiling(&91).is_none());
+    /// ```
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(SRef<'_, K>, SRef<'_, V>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (leaf_node, res) = self.find_leaf(key)?;
+
+        let idx = match res {
+            Ok(idx) => idx,
+            Err(idx) if idx == leaf_node.read_len() => {
+                // all keys to the right of this leaf are greater than the provided one
+                let next_ptr = u64::from_fixed_size_bytes(&leaf_node.read_next_ptr_buf());
+                if next_ptr == 0 {
+                    return None;
+                }
+
+                let next_leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+
+                return Some((next_leaf.get_key(0), next_leaf.get_value(0)));
+            }
+            Err(idx) => idx,
+        };
+
+        Some((leaf_node.get_key(idx), leaf_node.get_value(idx)))
+    }
+
+    /// Returns the number of keys in this [SBTreeMap], which are strictly less than `key`
+    ///
+    /// The key doesn't have to be present in the map. Each internal node stores the number of
+    /// entries in the subtree of each of its children, so this function only descends from the root
+    /// once, summing up counts of the children to the left of the path, and doesn't iterate over
+    /// entries. Together with [SBTreeMap::select] this allows percentile queries.
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the rank by [String].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i * 10, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(map.rank(&0), 0);
+    /// assert_eq!(map.rank(&500), 50);
+    /// assert_eq!(map.rank(&505), 51);
+    /// assert_eq!(map.rank(&10_000), 100);
+    /// ```
+    pub fn rank<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = match &self.root {
+            Some(root) => unsafe { root.copy() },
+            None => return 0,
+        };
+
+        let mut rank = 0;
+
+        loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let len = i.read_len();
+                    let child_idx = match i.binary_search(key, len) {
+                        Ok(idx) => idx + 1,
+                        Err(idx) => idx,
+                    };
+
+                    rank += i.read_child_counts(child_idx).iter().sum::<u64>();
+
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => {
+                    return match l.binary_search(key, l.read_len()) {
+                        Ok(idx) | Err(idx) => rank + idx as u64,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Returns the key-value pair, which key is the `index`-th smallest key of this [SBTreeMap]
+    /// (starting from `0`)
+    ///
+    /// If `index` is out of bounds, returns [None]. Just like [SBTreeMap::rank], uses the numbers of
+    /// entries, which are stored in internal nodes, to descend from the root straight to the leaf
+    /// with the requested entry. `map.select(map.rank(&key))` returns the entry of `key`, if it is
+    /// present in the map.
+    ///
+    /// The key and the value are cloned out of the leaf, so they don't borrow this [SBTreeMap].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i * 10, i).expect("Out of memory");
+    /// }
+    ///
+    /// // the 90th percentile
+    /// assert_eq!(map.select(map.len() * 9 / 10), Some((900, 90)));
+    ///
+    /// assert!(map.select(100).is_none());
+    /// ```
+    pub fn select(&self, mut index: u64) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if index >= self.len {
+            return None;
+        }
+
+        let mut node = unsafe { self.root.as_ref()?.copy() };
+
+        loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let counts = i.read_child_counts(i.read_len() + 1);
+                    let mut child_idx = 0;
+
+                    while index >= counts[child_idx] {
+                        index -= counts[child_idx];
+                        child_idx += 1;
+                    }
+
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => {
+                    let idx = index as usize;
+
+                    return Some(((*l.get_key(idx)).clone(), (*l.get_value(idx)).clone()));
+                }
+            }
+        }
+    }
+
+    // returns the leaf, which should contain the key, and the result of the binary search inside it
+    fn find_leaf<Q>(&self, key: &Q) -> Option<(LeafBTreeNode<K, V, B>, Result<usize, usize>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.get_root()?;
+
+        loop {
+            match node {
+                BTreeNode::Internal(internal_node) => {
+                    let child_idx = match internal_node.binary_search(key, internal_node.read_len())
+                    {
+                        Ok(idx) => idx + 1,
+                        Err(idx) => idx,
+                    };
+
+                    let child_ptr =
+                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(leaf_node) => {
+                    let res = leaf_node.binary_search(key, leaf_node.read_len());
+
+                    return Some((leaf_node, res));
+                }
+            }
+        }
+    }
+
+    // returns the leftmost (or the rightmost) leaf and the index of its first (or last) entry
+    fn edge_leaf(&self, last: bool) -> Option<(LeafBTreeNode<K, V, B>, usize)> {
+        let leaf_node = Self::descend_to_edge(self.get_root()?, last);
+
+        let len = leaf_node.read_len();
+        if len == 0 {
+            return None;
+        }
+
+        let idx = if last { len - 1 } else { 0 };
+
+        Some((leaf_node, idx))
+    }
+
+    // returns the leftmost (or the rightmost) leaf of the subtree, starting at the provided node
+    fn descend_to_edge(mut node: BTreeNode<K, V, B>, last: bool) -> LeafBTreeNode<K, V, B> {
+        loop {
+            match node {
+                BTreeNode::Internal(internal_node) => {
+                    let child_idx = if last { internal_node.read_len() } else { 0 };
+                    let child_ptr =
+                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
+
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(leaf_node) => break leaf_node,
+            }
+        }
+    }
+
+    /// Returns true if there exists a key-value pair stored by the provided key
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the value by [String].
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lookup(key, true).is_some()
+    }
+
+    /// Returns an iterator over entries of this [SBTreeMap]
+    ///
+    /// Elements of this iterator are presented in ascending order.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut i = 0;
+    /// for (k, v) in map.iter() {
+    ///     assert_eq!(*k, i);
+    ///     assert_eq!(*v, i);
+    ///
+    ///     i += 1;
+    /// }
+    ///
+    /// assert_eq!(i, 100);
+    /// ```
+    ///
+    /// One can use `.rev()` to get elements in reverse order.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut i = 100;
+    /// for (k, v) in map.iter().rev() {
+    ///     i -= 1;
+    ///
+    ///     assert_eq!(*k, i);
+    ///     assert_eq!(*v, i);
+    /// }
+    ///
+    /// assert_eq!(i, 0);
+    /// ```
+    #[inline]
+    pub fn iter(&self) -> SBTreeMapIter<K, V, B> {
+        SBTreeMapIter::<K, V, B>::new(self)
+    }
+
+    /// Returns an iterator over entries of this [SBTreeMap], which keys lie between `from` and `to`
+    ///
+    /// Descends to the leaf containing the lower bound and walks leaves forward, until the upper bound
+    /// is passed, so only the requested part of the tree is read. Bounds don't have to be keys which
+    /// are present in the map - iteration starts from the first key, which satisfies the lower bound.
+    /// Just like [SBTreeMap::iter], elements are in ascending order and `.rev()` can be used to
+    /// get them in reverse order (in that case iteration starts from the upper bound).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// use std::ops::Bound;
+    ///
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i * 10, i).expect("Out of memory");
+    /// }
+    ///
+    /// let keys = map
+    ///     .range(Bound::Included(15), Bound::Excluded(50))
+    ///     .map(|(k, _)| *k)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(keys, vec![20, 30, 40]);
+    ///
+    /// let keys = map
+    ///     .range(Bound::Excluded(950), Bound::Unbounded)
+    ///     .rev()
+    ///     .map(|(k, _)| *k)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(keys, vec![990, 980, 970, 960]);
+    /// ```
+    #[inline]
+    pub fn range(&self, from: Bound<K>, to: Bound<K>) -> SBTreeMapRangeIter<'_, K, V, B> {
+        SBTreeMapRangeIter::<K, V, B>::new_range(self, from, to)
+    }
+
+    /// Returns an iterator over entries of this [SBTreeMap], starting from the first key, which is
+    /// greater than or equal to `start`, and running to the end of the map
+    ///
+    /// Works like `range(Bound::Included(start), Bound::Unbounded)`, but doesn't need an owned key -
+    /// the iterator is positioned in the leaf, found by a single descent from the root, before it is
+    /// returned. Since the iterator is already positioned, `.rev()` walks backwards from that
+    /// position, yielding the keys, which are less than `start`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i * 10, i).expect("Out of memory");
+    /// }
+    ///
+    /// let keys = map.iter_from(&955).map(|(k, _)| *k).collect::<Vec<_>>();
+    /// assert_eq!(keys, vec![960, 970, 980, 990]);
+    /// ```
+    #[inline]
+    pub fn iter_from(&self, start: &K) -> SBTreeMapIter<'_, K, V, B> {
+        SBTreeMapIter::<K, V, B>::new_from(self, start)
+    }
+
+    /// Calls the provided function for each leaf node of this [SBTreeMap], in ascending order of keys
+    ///
+    /// Useful for bulk export. Each leaf is read from stable memory with a single read, instead of
+    /// reading each entry separately. The first argument of the function contains keys of the leaf,
+    /// the second one - its values. Both are in the on-disk fixed-size layout: entries are packed one
+    /// after another, each encoded with [AsFixedSizeBytes], so they are `len * K::SIZE` and
+    /// `len * V::SIZE` bytes long respectively.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let mut keys = Vec::new();
+    /// map.for_each_leaf(|k, _v| {
+    ///     keys.extend(k.chunks(u64::SIZE).map(u64::from_fixed_size_bytes));
+    /// });
+    ///
+    /// assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    /// ```
+    pub fn for_each_leaf<F: FnMut(&[u8], &[u8])>(&self, mut f: F) {
+        let mut node = if let Some(root) = &self.root {
+            unsafe { root.copy() }
+        } else {
+            return;
+        };
+
+        let mut leaf = loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(0));
+                    node = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => break l,
+            }
+        };
+
+        let mut buf = Vec::new();
+
+        loop {
+            let len = leaf.read_len();
+
+            if len > 0 {
+                let values_start = leaf.read_entries_to_buf(len, &mut buf);
+                f(&buf[0..(len * K::SIZE)], &buf[values_start..]);
+            }
+
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+            if next_ptr == 0 {
+                break;
+            }
+
+            leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+        }
+    }
+
+    /// Returns pointers to subtrees of this [SBTreeMap], rooted in children of its root node, in
+    /// ascending order of keys
+    ///
+    /// Together these subtrees cover the whole map, so they can be used to split its processing
+    /// (e.g. export) into chunks of bounded size, one chunk per message - pass each of them to
+    /// [SBTreeMap::iter_subtree]. There are at most `2 * B` (16) of them. If the root node is a leaf,
+    /// the only returned subtree is the root itself. If the map is empty, returns an empty [Vec].
+    ///
+    /// A [SubtreePtr] is only valid until the next modification of this [SBTreeMap] - inserting or
+    /// removing entries may rebalance the tree and deallocate the node it points to. If the map was
+    /// modified between messages, obtain the subtrees again. See the safety section of
+    /// [SBTreeMap::iter_subtree].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..1000u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let mut entries = Vec::new();
+    /// for subtree in map.root_children() {
+    ///     // the map is not modified between these calls
+    ///     entries.extend(unsafe { map.iter_subtree(subtree) });
+    /// }
+    ///
+    /// assert_eq!(entries, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    /// ```
+    pub fn root_children(&self) -> Vec<SubtreePtr> {
+        let root = match &self.root {
+            Some(it) if !self.is_empty() => it,
+            _ => return Vec::new(),
+        };
+
+        match root {
+            BTreeNode::Internal(internal_node) => (0..=internal_node.read_len())
+                .map(|idx| {
+                    SubtreePtr(u64::from_fixed_size_bytes(
+                        &internal_node.read_child_ptr_buf(idx),
+                    ))
+                })
+                .collect(),
+            BTreeNode::Leaf(leaf_node) => vec![SubtreePtr(leaf_node.as_ptr())],
+        }
+    }
+
+    /// Returns an iterator over copies of entries of the subtree, in ascending order of keys
+    ///
+    /// Only leaves of the subtree are read - the iterator walks them from the leftmost to the
+    /// rightmost one.
+    ///
+    /// # Safety
+    /// `subtree` should be obtained with [SBTreeMap::root_children] of this very [SBTreeMap], and the
+    /// map should not be modified since then (by any method, which takes `&mut self`). Otherwise the
+    /// node it points to may already be deallocated, and this method would read freed memory.
+    pub unsafe fn iter_subtree(&self, subtree: SubtreePtr) -> SBTreeMapSubtreeIter<'_, K, V, B>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let first = Self::descend_to_edge(BTreeNode::from_ptr(subtree.0), false);
+        let last = Self::descend_to_edge(BTreeNode::from_ptr(subtree.0), true);
+
+        SBTreeMapSubtreeIter::new(first, last.as_ptr())
+    }
+
+    /// Returns up to `limit` key-value pairs of this [SBTreeMap], starting from `start`, in ascending order of keys
+    ///
+    /// Along with the entries, returns a cursor - the first key, which was not included into the page,
+    /// or [None] if there are no more entries. Pass it back as [Bound::Included] to fetch the next page.
+    /// Keys and values are cloned to the heap.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// use std::ops::Bound;
+    ///
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let (page, cursor) = map.page(Bound::Unbounded, 10);
+    /// assert_eq!(page, (0..10).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    /// assert_eq!(cursor, Some(10));
+    ///
+    /// let (page, cursor) = map.page(Bound::Excluded(&94), 10);
+    /// assert_eq!(page, (95..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    /// assert_eq!(cursor, None);
+    /// ```
+    pub fn page(&self, start: Bound<&K>, limit: usize) -> (Vec<(K, V)>, Option<K>)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut result = Vec::new();
+
+        let (mut leaf, mut idx) = match Self::seek(&self.root, start) {
+            Some(it) => it,
+            None => return (result, None),
+        };
+
+        let mut len = leaf.read_len();
+
+        loop {
+            if idx == len {
+                let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+                if next_ptr == 0 {
+                    return (result, None);
+                }
+
+                leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+                len = leaf.read_len();
+                idx = 0;
+
+                continue;
+            }
+
+            if result.len() == limit {
+                return (result, Some(leaf.get_key(idx).clone()));
+            }
+
+            result.push((leaf.get_key(idx).clone(), leaf.get_value(idx).clone()));
+            idx += 1;
+        }
+    }
+
+    /// Returns copies of all key-value pairs of this [SBTreeMap], which keys lie between `from` and
+    /// `to`, in ascending order of keys
+    ///
+    /// Keys and values are cloned to the heap, so this function is only intended for small ranges -
+    /// e.g. to return them from a query method. See also [SBTreeMap::page].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// use std::ops::Bound;
+    ///
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let range = map.range_copy(Bound::Included(&10), Bound::Excluded(&15));
+    /// assert_eq!(range, (10..15).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    ///
+    /// let range = map.range_copy(Bound::Excluded(&95), Bound::Unbounded);
+    /// assert_eq!(range, (96..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    /// ```
+    pub fn range_copy(&self, from: Bound<&K>, to: Bound<&K>) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut result = Vec::new();
+
+        let (mut leaf, mut idx) = match Self::seek(&self.root, from) {
+            Some(it) => it,
+            None => return result,
+        };
+
+        let mut len = leaf.read_len();
+
+        loop {
+            if idx == len {
+                let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+                if next_ptr == 0 {
+                    return result;
+                }
+
+                leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+                len = leaf.read_len();
+                idx = 0;
+
+                continue;
+            }
+
+            let key = leaf.get_key(idx);
+            let in_range = match to {
+                Bound::Unbounded => true,
+                Bound::Included(to) => *key <= *to,
+                Bound::Excluded(to) => *key < *to,
+            };
+
+            if !in_range {
+                return result;
+            }
+
+            result.push((key.clone(), leaf.get_value(idx).clone()));
+            idx += 1;
+        }
+    }
+
+    /// Returns a new independent [SBTreeMap], which contains copies of all key-value pairs of this
+    /// [SBTreeMap], which keys lie between `from` and `to`
+    ///
+    /// Works like `split_off`, but leaves this [SBTreeMap] untouched. Entries are read with
+    /// [SBTreeMap::range] and cloned one by one straight into the new tree, which is bulk-loaded
+    /// the same way as in [SBTreeMap::from_sorted_iter], so the range is never copied to the heap as
+    /// a whole. Since keys and values are copied with [Clone], the new map doesn't share any stable
+    /// memory with this one.
+    ///
+    /// If the canister is out of stable memory, returns [OutOfMemory], releasing everything which was
+    /// copied so far.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// use std::ops::Bound;
+    ///
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let shard = map
+    ///     .clone_range(Bound::Included(&50), Bound::Unbounded)
+    ///     .expect("Out of memory");
+    ///
+    /// assert_eq!(shard.len(), 50);
+    /// assert_eq!(*shard.get(&75).unwrap(), 150);
+    /// assert_eq!(map.len(), 100);
+    /// ```
+    pub fn clone_range(
+        &self,
+        from: Bound<&K>,
+        to: Bound<&K>,
+    ) -> Result<SBTreeMap<K, V, B>, OutOfMemory>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let range = self.range(from.cloned(), to.cloned());
+
+        Self::try_from_sorted_iter(range.map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    // finds the leaf and the index of the first entry, which key satisfies the lower bound
+    pub(crate) fn seek(
+        root: &Option<BTreeNode<K, V, B>>,
+        start: Bound<&K>,
+    ) -> Option<(LeafBTreeNode<K, V, B>, usize)> {
+        let mut node = unsafe { root.as_ref()?.copy() };
+
+        let res = loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let child_idx = match start {
+                        Bound::Unbounded => 0,
+                        Bound::Included(key) | Bound::Excluded(key) => {
+                            match i.binary_search(key, i.read_len()) {
+                                Ok(idx) => idx + 1,
+                                Err(idx) => idx,
+                            }
+                        }
+                    };
+
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => {
+                    let idx = match start {
+                        Bound::Unbounded => 0,
+                        Bound::Included(key) => match l.binary_search(key, l.read_len()) {
+                            Ok(idx) | Err(idx) => idx,
+                        },
+                        Bound::Excluded(key) => match l.binary_search(key, l.read_len()) {
+                            Ok(idx) => idx + 1,
+                            Err(idx) => idx,
+                        },
+                    };
+
+                    break (l, idx);
+                }
+            }
+        };
+
+        Some(res)
+    }
+
+    // finds the leaf and the index right after the last entry, which key satisfies the upper bound
+    pub(crate) fn seek_back(
+        root: &Option<BTreeNode<K, V, B>>,
+        end: Bound<&K>,
+    ) -> Option<(LeafBTreeNode<K, V, B>, usize)> {
+        let mut node = unsafe { root.as_ref()?.copy() };
+
+        let (leaf, idx) = loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let len = i.read_len();
+                    let child_idx = match end {
+                        Bound::Unbounded => len,
+                        Bound::Included(key) | Bound::Excluded(key) => {
+                            match i.binary_search(key, len) {
+                                Ok(idx) => idx + 1,
+                                Err(idx) => idx,
+                            }
+                        }
+                    };
+
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => {
+                    let len = l.read_len();
+                    let idx = match end {
+                        Bound::Unbounded => len,
+                        Bound::Included(key) => match l.binary_search(key, len) {
+                            Ok(idx) => idx + 1,
+                            Err(idx) => idx,
+                        },
+                        Bound::Excluded(key) => match l.binary_search(key, len) {
+                            Ok(idx) | Err(idx) => idx,
+                        },
+                    };
+
+                    break (l, idx);
+                }
+            }
+        };
+
+        // all entries of this leaf are past the bound, but the previous leaf may still have some
+        if idx == 0 {
+            let prev_ptr = u64::from_fixed_size_bytes(&leaf.read_prev_ptr_buf());
+            if prev_ptr != 0 {
+                let prev = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(prev_ptr) };
+                let len = prev.read_len();
+
+                return Some((prev, len));
+            }
+        }
+
+        Some((leaf, idx))
+    }
+
+    /// Transforms each value of this [SBTreeMap] with the provided lambda, moving all entries into a
+    /// new [SBTreeMap]
+    ///
+    /// Useful for schema migrations, when the encoding of `V` changes between canister upgrades.
+    /// Entries are streamed leaf by leaf in ascending order of keys. Keys and values are moved, not
+    /// copied, so the lambda receives each value by value. Nodes of this [SBTreeMap] are released at
+    /// the end.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u32 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let map = map.migrate_values(|v| (v as u64, true));
+    ///
+    /// assert_eq!(*map.get(&10).unwrap(), (10, true));
+    /// ```
+    pub fn migrate_values<V2, F>(mut self, mut f: F) -> SBTreeMap<K, V2, B>
+    where
+        V2: StableType + AsFixedSizeBytes,
+        F: FnMut(V) -> V2,
+    {
+        let mut new_map = SBTreeMap::<K, V2, B>::with_branching_factor();
+
+        let mut node = if let Some(root) = &self.root {
+            unsafe { root.copy() }
+        } else {
+            return new_map;
+        };
+
+        let mut leaf = loop {
+            match node {
+                BTreeNode::Internal(i) => {
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(0));
+                    node = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(l) => break l,
+            }
+        };
+
+        loop {
+            for i in 0..leaf.read_len() {
+                let k = leaf.read_and_disown_key(i);
+                let v = leaf.read_and_disown_value(i);
+
+                if new_map.insert(k, f(v)).is_err() {
+                    panic!("Out of memory");
+                }
+            }
+
+            // entries are moved out, so they should not be stable-dropped together with the node
+            leaf.write_len(0);
+
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+            if next_ptr == 0 {
+                break;
+            }
+
+            leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+        }
+
+        self.len = 0;
+
+        new_map
+    }
+
+    /// Filters this [SBTreeMap], so only entries for which the provided lambda returns [true] are left
+    ///
+    /// Entries are visited in ascending order of keys. Removed keys and values are dropped,
+    /// releasing any stable memory they own. Most entries are removed right inside their leaf,
+    /// without descending from the root again - only when a removal would make the leaf too small,
+    /// or when the key may be used as a separator in an internal node, the regular removal
+    /// (with stealing from or merging with a sibling) is performed.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// map.retain(|k, _| k % 10 == 0);
+    ///
+    /// assert_eq!(map.len(), 10);
+    /// assert_eq!(*map.get(&90).unwrap(), 180);
+    /// assert!(map.get(&91).is_none());
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let (mut leaf, mut idx) = match Self::seek(&self.root, Bound::Unbounded) {
+            Some(it) => it,
+            None => return,
+        };
+
+        let len_before = self.len;
+
+        loop {
+            let len = leaf.read_len();
+
+            if idx == len {
+                let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+                if next_ptr == 0 {
+                    break;
+                }
+
+                leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+                idx = 0;
+
+                continue;
+            }
+
+            if f(&leaf.get_key(idx), &leaf.get_value(idx)) {
+                idx += 1;
+                continue;
+            }
+
+            // only the first key of a leaf can be a separator in some internal node
+            if idx > 0 && len > Self::MIN_LEN_AFTER_SPLIT {
+                leaf.remove_and_disown_by_idx(idx, len, &mut self._buf);
+                leaf.write_len(len - 1);
+                self.len -= 1;
+
+                continue;
+            }
+
+            // the tree gets rebalanced, so the position is restored by the key that goes next
+            let next_key = if idx + 1 < len {
+                Some(leaf.read_key_as_reference(idx + 1))
+            } else {
+                let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+
+                if next_ptr == 0 {
+                    None
+                } else {
+                    let next_leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(next_ptr) };
+                    Some(next_leaf.read_key_as_reference(0))
+                }
+            };
+
+            let key = leaf.read_key_as_reference(idx);
+            let v = self._remove(&key, &mut LeveledList::None);
+            debug_assert!(v.is_some());
+
+            match next_key {
+                Some(next_key) => {
+                    (leaf, idx) = unsafe {
+                        Self::seek(&self.root, Bound::Included(&next_key)).unwrap_unchecked()
+                    };
+                }
+                None => break,
+            }
+        }
+
+        // entries were removed right from leaves, so counts of internal nodes are rebuilt at once
+        if self.len != len_before {
+            if let Some(root) = &self.root {
+                Self::recount_subtree(unsafe { root.copy() });
+            }
+        }
+    }
+
+    /// Removes all entries from this [SBTreeMap], returning them as an iterator
+    ///
+    /// Entries are yielded in ascending order of keys and are moved out, so the caller owns each key
+    /// and value and can put them into another stable structure. This [SBTreeMap] becomes empty right
+    /// away and can be reused, even while the iterator is still alive. Internal nodes are released
+    /// immediately, while leaves are released one by one, as the iterator moves past them. If the
+    /// iterator is dropped before it is exhausted, the remaining entries are stable-dropped.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::{SBTreeMap, SHashMap};
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let mut rehomed = SHashMap::new();
+    /// for (k, v) in map.drain() {
+    ///     rehomed.insert(k, v).expect("Out of memory");
+    /// }
+    ///
+    /// assert!(map.is_empty());
+    /// assert_eq!(*rehomed.get(&10).unwrap(), 20);
+    /// ```
+    #[inline]
+    pub fn drain(&mut self) -> SBTreeMapDrain<K, V, B> {
+        self.len = 0;
+
+        SBTreeMapDrain::new(self.root.take())
+    }
+
+    /// Returns the length of this [SBTreeMap]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if the length of this [SBTreeMap] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the header of this [SBTreeMap] into `dst`
+    ///
+    /// The header is the pointer to the root node (tagged with the branching factor and the node
+    /// layout) and the length. Nodes are written to stable memory as the map changes, so only the
+    /// header has to be persisted - see [persistence model](crate::mem#persistence-model). Restore
+    /// the map with [AsFixedSizeBytes::from_fixed_size_bytes].
+    ///
+    /// # Panics
+    /// Panics if `dst` is shorter than [AsFixedSizeBytes::SIZE] bytes.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::<u64, u64>::new();
+    /// map.insert(1, 10).expect("Out of memory");
+    ///
+    /// let mut header = [0u8; SBTreeMap::<u64, u64>::SIZE];
+    /// map.flush(&mut header);
+    ///
+    /// let restored = SBTreeMap::<u64, u64>::from_fixed_size_bytes(&header);
+    /// assert_eq!(*restored.get(&1).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn flush(&self, dst: &mut [u8]) {
+        self.as_fixed_size_bytes(&mut dst[0..Self::SIZE]);
+    }
+
+    /// Removes all key-value pairs from this collection, releasing all occupied stable memory
+    ///
+    /// Every node of the tree is deallocated and every key and value is dropped, so nested stable
+    /// structures (e.g. [SBox]-ed keys or values) are released as well. The map stays usable afterwards.
+    #[inline]
+    pub fn clear(&mut self) {
+        let mut old = mem::replace(self, Self::with_branching_factor());
+        self.stable_drop_flag = old.stable_drop_flag;
+        self.certified = old.certified;
+
+        unsafe { old.stable_drop() };
+    }
+
+    /// Checks the internal structure of this [SBTreeMap], returning [Err] with a description of
+    /// the first problem found
+    ///
+    /// Checks that:
+    /// 1. every non-root node contains from `7` to `15` keys;
+    /// 2. keys are sorted inside each node and separator keys of internal nodes bound keys of their
+    ///    children;
+    /// 3. all leaves are at the same depth and are linked with each other in ascending order;
+    /// 4. the total number of entries in leaves is equal to [SBTreeMap::len].
+    ///
+    /// Reads the whole tree, so it is only intended for tests and post-upgrade sanity checks.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SBTreeMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SBTreeMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert!(map.verify().is_ok());
+    /// ```
+    pub fn verify(&self) -> Result<(), String> {
+        let root = if let Some(root) = &self.root {
+            unsafe { root.copy() }
+        } else {
+            return if self.len == 0 {
+                Ok(())
+            } else {
+                Err(format!("No root, but len is {}", self.len))
+            };
+        };
+
+        let mut leaves = Vec::new();
+        let mut leaf_depth = None;
+
+        let len = Self::verify_node(root, 0, None, None, &mut leaf_depth, &mut leaves)?;
+
+        if len != self.len {
+            return Err(format!(
+                "Leaves contain {} entries, but len is {}",
+                len, self.len
+            ));
+        }
+
+        for (i, leaf_ptr) in leaves.iter().enumerate() {
+            let leaf = unsafe { LeafBTreeNode::<K, V, B>::from_ptr(*leaf_ptr) };
+
+            let prev_ptr = u64::from_fixed_size_bytes(&leaf.read_prev_ptr_buf());
+            let expected_prev_ptr = if i == 0 { 0 } else { leaves[i - 1] };
+            if prev_ptr != expected_prev_ptr {
+                return Err(format!("Invalid prev pointer of leaf {}", leaf_ptr));
+            }
+
+            let next_ptr = u64::from_fixed_size_bytes(&leaf.read_next_ptr_buf());
+            let expected_next_ptr = leaves.get(i + 1).copied().unwrap_or_default();
+            if next_ptr != expected_next_ptr {
+                return Err(format!("Invalid next pointer of leaf {}", leaf_ptr));
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns the number of entries in the subtree
+    fn verify_node(
+        node: BTreeNode<K, V, B>,
+        depth: usize,
+        lower_bound: Option<&K>,
+        upper_bound: Option<&K>,
+        leaf_depth: &mut Option<usize>,
+        leaves: &mut Vec<StablePtr>,
+    ) -> Result<u64, String> {
+        let ptr = node.as_ptr();
+
+        let keys = match &node {
+            BTreeNode::Internal(i) => (0..i.read_len())
+                .map(|idx| i.read_key_as_reference(idx))
+                .collect::<Vec<_>>(),
+            BTreeNode::Leaf(l) => (0..l.read_len())
+                .map(|idx| l.read_key_as_reference(idx))
+                .collect::<Vec<_>>(),
+        };
+
+        if keys.len() > Self::CAPACITY {
+            return Err(format!("Node {} has {} keys", ptr, keys.len()));
+        }
+
+        if depth > 0 && keys.len() < Self::MIN_LEN_AFTER_SPLIT {
+            return Err(format!("Node {} has {} keys", ptr, keys.len()));
+        }
+
+        for i in 1..keys.len() {
+            if keys[i - 1] >= keys[i] {
+                return Err(format!("Keys of node {} are not sorted", ptr));
+            }
+        }
+
+        if let (Some(lower_bound), Some(first)) = (lower_bound, keys.first()) {
+            if first < lower_bound {
+                return Err(format!("Keys of node {} are less than its separator", ptr));
+            }
+        }
+
+        if let (Some(upper_bound), Some(last)) = (upper_bound, keys.last()) {
+            if last >= upper_bound {
+                return Err(format!(
+                    "Keys of node {} are not less than its separator",
+                    ptr
+                ));
+            }
+        }
+
+        match node {
+            BTreeNode::Internal(i) => {
+                if keys.is_empty() {
+                    return Err(format!("Internal node {} is empty", ptr));
+                }
+
+                let mut len = 0;
+
+                let counts = i.read_child_counts(keys.len() + 1);
+
+                for (idx, count) in counts.into_iter().enumerate() {
+                    let child_ptr = u64::from_fixed_size_bytes(&i.read_child_ptr_buf(idx));
+                    let child = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+
+                    let lower = if idx == 0 {
+                        lower_bound
+                    } else {
+                        keys.get(idx - 1)
+                    };
+                    let upper = if idx == keys.len() {
+                        upper_bound
+                    } else {
+                        keys.get(idx)
+                    };
+
+                    let child_len =
+                        Self::verify_node(child, depth + 1, lower, upper, leaf_depth, leaves)?;
+
+                    if child_len != count {
+                        return Err(format!(
+                            "Child {} of node {} has {} entries, but its count is {}",
+                            idx, ptr, child_len, count
+                        ));
+                    }
+
+                    len += child_len;
+                }
+
+                Ok(len)
+            }
+            BTreeNode::Leaf(_) => {
+                match leaf_depth {
+                    Some(d) if *d != depth => {
+                        return Err(format!(
+                            "Leaf {} is at depth {}, but other leaves are at depth {}",
+                            ptr, depth, d
+                        ));
+                    }
+                    _ => *leaf_depth = Some(depth),
+                }
+
+                leaves.push(ptr);
+
+                Ok(keys.len() as u64)
+            }
+        }
+    }
+
+    // recalculates child counts of modified internal nodes, starting from the deepest ones
+    fn update_child_counts(modified: &LeveledList) {
+        if let LeveledList::Some((levels, max_level)) | LeveledList::Counts((levels, max_level)) =
+            modified
+        {
+            for level in levels.iter().take(*max_level + 1).rev() {
+                for ptr in level {
+                    if let BTreeNode::Internal(mut node) = BTreeNode::<K, V, B>::from_ptr(*ptr) {
+                        Self::count_children(&mut node);
+                    }
+                }
+            }
+        }
+    }
+
+    // child counts of the children themselves should be correct at this point
+    pub(crate) fn count_children(node: &mut InternalBTreeNode<K, B>) {
+        let children_len = node.read_len() + 1;
+
+        let first_child_ptr = u64::from_fixed_size_bytes(&node.read_child_ptr_buf(0));
+        let leaf_children = matches!(
+            BTreeNode::<K, V, B>::from_ptr(first_child_ptr),
+            BTreeNode::Leaf(_)
+        );
+
+        for idx in 0..children_len {
+            let child_ptr = u64::from_fixed_size_bytes(&node.read_child_ptr_buf(idx));
+
+            let count = if leaf_children {
+                unsafe { LeafBTreeNode::<K, V, B>::from_ptr(child_ptr) }.read_len() as u64
+            } else {
+                unsafe { InternalBTreeNode::<K, B>::from_ptr(child_ptr) }.read_total_count()
+            };
+
+            node.write_child_count(idx, count);
+        }
+    }
+
+    // recalculates child counts of all internal nodes of the subtree, returning its number of entries
+    fn recount_subtree(node: BTreeNode<K, V, B>) -> u64 {
+        match node {
+            BTreeNode::Leaf(leaf) => leaf.read_len() as u64,
+            BTreeNode::Internal(mut internal) => {
+                let mut total = 0;
+
+                for idx in 0..(internal.read_len() + 1) {
+                    let child_ptr = u64::from_fixed_size_bytes(&internal.read_child_ptr_buf(idx));
+                    let count = Self::recount_subtree(BTreeNode::from_ptr(child_ptr));
+
+                    internal.write_child_count(idx, count);
+                    total += count;
+                }
+
+                total
+            }
+        }
+    }
+
+    // empties the stack after an operation, which changed the number of entries by `delta`
+    fn clear_stack(&mut self, modified: &mut LeveledList, delta: i64) {
+        match modified {
+            LeveledList::None => {
+                self._stack.clear();
+            }
+            LeveledList::Some(_) => {
+                while let Some((p, _, _)) = self._stack.pop() {
+                    modified.push(self.current_depth(), p.as_ptr());
+                }
+            }
+            LeveledList::Counts(_) => {
+                while let Some((mut p, _, child_idx)) = self._stack.pop() {
+                    let depth = self.current_depth();
+                    let child_ptr = u64::from_fixed_size_bytes(&p.read_child_ptr_buf(child_idx));
+
+                    // if siblings of the child were changed too, the node has to be recounted,
+                    // otherwise only the count of the child changes
+                    if modified.has_other_than(depth + 1, child_ptr) {
+                        modified.push(depth, p.as_ptr());
+                    } else if delta != 0 {
+                        p.add_child_count(child_idx, delta);
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn current_depth(&self) -> usize {
+        self._stack.len()
+    }
+
+    #[inline]
+    fn push_stack(&mut self, node: InternalBTreeNode<K, B>, len: usize, child_idx: usize) {
+        self._stack.push((node, len, child_idx));
+    }
+
+    #[inline]
+    fn pop_stack(&mut self) -> Option<(InternalBTreeNode<K, B>, usize, usize)> {
+        self._stack.pop()
+    }
+
+    pub(crate) fn get_root(&self) -> Option<BTreeNode<K, V, B>> {
+        unsafe { self.root.as_ref().map(|it| it.copy()) }
+    }
+
+    pub(crate) fn set_certified(&mut self, val: bool) {
+        self.certified = val;
+    }
+
+    // WARNING: return_early == true will return nonsense leaf node and idx
+    fn lookup<Q>(&self, key: &Q, return_early: bool) -> Option<(LeafBTreeNode<K, V, B>, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut node = self.get_root()?;
+        loop {
+            match node {
+                BTreeNode::Internal(internal_node) => {
+                    let child_idx = match internal_node.binary_search(key, internal_node.read_len())
+                    {
+                        Ok(idx) => {
+                            if return_early {
+                                return unsafe { Some((LeafBTreeNode::from_ptr(0), 0)) };
+                            } else {
+                                idx + 1
+                            }
+                        }
+                        Err(idx) => idx,
+                    };
+
+                    let child_ptr =
+                        u64::from_fixed_size_bytes(&internal_node.read_child_ptr_buf(child_idx));
+                    node = BTreeNode::from_ptr(child_ptr);
+                }
+                BTreeNode::Leaf(leaf_node) => {
+                    return match leaf_node.binary_search(key, leaf_node.read_len()) {
+                        Ok(idx) => Some((leaf_node, idx)),
+                        _ => None,
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf_node: &mut LeafBTreeNode<K, V, B>,
+        mut key: K,
+        mut value: V,
+        modified: &mut LeveledList,
+    ) -> Result<Result<V, Option<LeafBTreeNode<K, V, B>>>, (K, V)> {
+        let leaf_node_len = leaf_node.read_len();
+        let insert_idx = match leaf_node.binary_search(&key, leaf_node_len) {
+            Ok(existing_idx) => {
+                // if there is already a key like that, return early
+                let prev_value: V = leaf_node.read_and_disown_value(existing_idx);
+                leaf_node.write_and_own_value(existing_idx, value);
+
+                modified.push(self.current_depth(), leaf_node.as_ptr());
+
+                return Ok(Ok(prev_value));
             }
             Err(idx) => idx,
         };
 
-        let k = key.as_new_fixed_size_bytes();
-        let v = value.as_new_fixed_size_bytes();
+        let k = key.as_new_fixed_size_bytes();
+        let v = value.as_new_fixed_size_bytes();
+
+        // if there is enough space - simply insert and return early
+        if leaf_node_len < Self::CAPACITY {
+            leaf_node.insert_key_buf(insert_idx, &k, leaf_node_len, &mut self._buf);
+            leaf_node.insert_value_buf(insert_idx, &v, leaf_node_len, &mut self._buf);
+
+            leaf_node.write_len(leaf_node_len + 1);
+
+            modified.push(self.current_depth(), leaf_node.as_ptr());
+
+            unsafe { key.stable_drop_flag_off() };
+            unsafe { value.stable_drop_flag_off() };
+
+            return Ok(Err(None));
+        }
+
+        // try passing an element to a neighbor, to make room for a new one
+        if self.pass_elem_to_sibling_leaf(leaf_node, &k, &v, insert_idx, modified) {
+            unsafe { key.stable_drop_flag_off() };
+            unsafe { value.stable_drop_flag_off() };
+
+            return Ok(Err(None));
+        }
+
+        // cheking if it is possible to allocate worst-case scenario amount of memory
+        let memory_to_allocate = (self._stack.len() + 1) as u64
+            * FreeBlock::to_total_size(InternalBTreeNode::<K, B>::calc_byte_size(self.certified))
+            + FreeBlock::to_total_size(LeafBTreeNode::<K, V, B>::calc_size_bytes(self.certified));
+
+        // we can unwrap all OutOfMemory errors if this check passes, without any consequences
+        if !make_sure_can_allocate(memory_to_allocate) {
+            return Err((key, value));
+        }
+
+        unsafe { key.stable_drop_flag_off() };
+        unsafe { value.stable_drop_flag_off() };
+
+        // split the leaf and insert so both leaves now have length of B
+        let mut right = if insert_idx < B {
+            let right = leaf_node
+                .split_max_len(true, &mut self._buf, self.certified)
+                .unwrap();
+            leaf_node.insert_key_buf(insert_idx, &k, Self::MIN_LEN_AFTER_SPLIT, &mut self._buf);
+            leaf_node.insert_value_buf(insert_idx, &v, Self::MIN_LEN_AFTER_SPLIT, &mut self._buf);
+
+            right
+        } else {
+            let mut right = leaf_node
+                .split_max_len(false, &mut self._buf, self.certified)
+                .unwrap();
+            right.insert_key_buf(
+                insert_idx - B,
+                &k,
+                Self::MIN_LEN_AFTER_SPLIT,
+                &mut self._buf,
+            );
+            right.insert_value_buf(
+                insert_idx - B,
+                &v,
+                Self::MIN_LEN_AFTER_SPLIT,
+                &mut self._buf,
+            );
+
+            right
+        };
+
+        leaf_node.write_len(B);
+        right.write_len(B);
+
+        modified.push(self.current_depth(), leaf_node.as_ptr());
+        modified.push(self.current_depth(), right.as_ptr());
+
+        Ok(Err(Some(right)))
+    }
+
+    fn insert_internal(
+        &mut self,
+        internal_node: &mut InternalBTreeNode<K, B>,
+        len: usize,
+        idx: usize,
+        key: K::Buf,
+        child_ptr: StablePtrBuf,
+        modified: &mut LeveledList,
+    ) -> Option<(InternalBTreeNode<K, B>, K::Buf)> {
+        if len < Self::CAPACITY {
+            internal_node.insert_key_buf(idx, &key, len, &mut self._buf);
+            internal_node.insert_child_ptr_buf(idx + 1, &child_ptr, len + 1, &mut self._buf);
+
+            internal_node.write_len(len + 1);
+
+            modified.push(self.current_depth(), internal_node.as_ptr());
+
+            return None;
+        }
+
+        if self.pass_elem_to_sibling_internal(internal_node, idx, &key, &child_ptr, modified) {
+            return None;
+        }
+
+        // TODO: possible to optimize when idx == MIN_LEN_AFTER_SPLIT
+        let (mut right, mid) = internal_node
+            .split_max_len(&mut self._buf, self.certified)
+            .unwrap();
+
+        if idx <= Self::MIN_LEN_AFTER_SPLIT {
+            internal_node.insert_key_buf(idx, &key, Self::MIN_LEN_AFTER_SPLIT, &mut self._buf);
+            internal_node.insert_child_ptr_buf(idx + 1, &child_ptr, B, &mut self._buf);
+
+            internal_node.write_len(B);
+            right.write_len(Self::MIN_LEN_AFTER_SPLIT);
+        } else {
+            right.insert_key_buf(idx - B, &key, Self::MIN_LEN_AFTER_SPLIT, &mut self._buf);
+            right.insert_child_ptr_buf(idx - B + 1, &child_ptr, B, &mut self._buf);
+
+            internal_node.write_len(Self::MIN_LEN_AFTER_SPLIT);
+            right.write_len(B);
+        }
+
+        modified.push(self.current_depth(), internal_node.as_ptr());
+        modified.push(self.current_depth(), right.as_ptr());
+
+        Some((right, mid))
+    }
+
+    fn pass_elem_to_sibling_leaf(
+        &mut self,
+        leaf_node: &mut LeafBTreeNode<K, V, B>,
+        key: &K::Buf,
+        value: &V::Buf,
+        insert_idx: usize,
+        modified: &mut LeveledList,
+    ) -> bool {
+        let stack_top_frame = self.peek_stack();
+        if stack_top_frame.is_none() {
+            return false;
+        }
+
+        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+
+        if let Some(mut left_sibling) =
+            parent.read_left_sibling::<LeafBTreeNode<K, V, B>>(parent_idx)
+        {
+            let left_sibling_len = left_sibling.read_len();
+
+            // if it is possible to pass to the left sibling - do that
+            if left_sibling_len < Self::CAPACITY {
+                self.pass_to_left_sibling_leaf(
+                    &mut parent,
+                    parent_idx,
+                    leaf_node,
+                    &mut left_sibling,
+                    left_sibling_len,
+                    insert_idx,
+                    key,
+                    value,
+                );
+
+                modified.push(self.current_depth(), leaf_node.as_ptr());
+                modified.push(self.current_depth(), left_sibling.as_ptr());
+
+                return true;
+            }
+        }
+
+        if let Some(mut right_sibling) =
+            parent.read_right_sibling::<LeafBTreeNode<K, V, B>>(parent_idx, parent_len)
+        {
+            let right_sibling_len = right_sibling.read_len();
+
+            if right_sibling_len < Self::CAPACITY {
+                self.pass_to_right_sibling_leaf(
+                    &mut parent,
+                    parent_idx,
+                    leaf_node,
+                    &mut right_sibling,
+                    right_sibling_len,
+                    insert_idx,
+                    key,
+                    value,
+                );
+
+                modified.push(self.current_depth(), leaf_node.as_ptr());
+                modified.push(self.current_depth(), right_sibling.as_ptr());
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn pass_to_right_sibling_leaf(
+        &mut self,
+        p: &mut InternalBTreeNode<K, B>,
+        p_idx: usize,
+        leaf: &mut LeafBTreeNode<K, V, B>,
+        rs: &mut LeafBTreeNode<K, V, B>,
+        rs_len: usize,
+        i_idx: usize,
+        key: &K::Buf,
+        value: &V::Buf,
+    ) {
+        if i_idx != Self::CAPACITY {
+            rs.steal_from_left(rs_len, leaf, Self::CAPACITY, p, p_idx, None, &mut self._buf);
+
+            leaf.insert_key_buf(i_idx, key, Self::CAPACITY - 1, &mut self._buf);
+            leaf.insert_value_buf(i_idx, value, Self::CAPACITY - 1, &mut self._buf);
+
+            rs.write_len(rs_len + 1);
+            return;
+        }
+
+        let last = Some((key, value));
+        rs.steal_from_left(rs_len, leaf, Self::CAPACITY, p, p_idx, last, &mut self._buf);
+        rs.write_len(rs_len + 1);
+    }
+
+    fn pass_to_left_sibling_leaf(
+        &mut self,
+        p: &mut InternalBTreeNode<K, B>,
+        p_idx: usize,
+        leaf: &mut LeafBTreeNode<K, V, B>,
+        ls: &mut LeafBTreeNode<K, V, B>,
+        ls_len: usize,
+        i_idx: usize,
+        key: &K::Buf,
+        value: &V::Buf,
+    ) {
+        if i_idx != 1 {
+            ls.steal_from_right(
+                ls_len,
+                leaf,
+                Self::CAPACITY,
+                p,
+                p_idx - 1,
+                None,
+                &mut self._buf,
+            );
+
+            leaf.insert_key_buf(i_idx - 1, key, Self::CAPACITY - 1, &mut self._buf);
+            leaf.insert_value_buf(i_idx - 1, value, Self::CAPACITY - 1, &mut self._buf);
+
+            ls.write_len(ls_len + 1);
+            return;
+        };
+
+        let first = Some((key, value));
+        ls.steal_from_right(
+            ls_len,
+            leaf,
+            Self::CAPACITY,
+            p,
+            p_idx - 1,
+            first,
+            &mut self._buf,
+        );
+        ls.write_len(ls_len + 1);
+    }
+
+    fn pass_elem_to_sibling_internal(
+        &mut self,
+        internal_node: &mut InternalBTreeNode<K, B>,
+        idx: usize,
+        key: &K::Buf,
+        child_ptr: &StablePtrBuf,
+        modified: &mut LeveledList,
+    ) -> bool {
+        let stack_top_frame = self.peek_stack();
+        if stack_top_frame.is_none() {
+            return false;
+        }
+
+        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+
+        if let Some(mut left_sibling) =
+            parent.read_left_sibling::<InternalBTreeNode<K, B>>(parent_idx)
+        {
+            let left_sibling_len = left_sibling.read_len();
+
+            if left_sibling_len < Self::CAPACITY {
+                self.pass_to_left_sibling_internal(
+                    &mut parent,
+                    parent_idx,
+                    internal_node,
+                    &mut left_sibling,
+                    left_sibling_len,
+                    idx,
+                    key,
+                    child_ptr,
+                );
+
+                modified.push(self.current_depth(), internal_node.as_ptr());
+                modified.push(self.current_depth(), left_sibling.as_ptr());
+
+                return true;
+            }
+        }
+
+        if let Some(mut right_sibling) =
+            parent.read_right_sibling::<InternalBTreeNode<K, B>>(parent_idx, parent_len)
+        {
+            let right_sibling_len = right_sibling.read_len();
+
+            if right_sibling_len < Self::CAPACITY {
+                self.pass_to_right_sibling_internal(
+                    &mut parent,
+                    parent_idx,
+                    internal_node,
+                    &mut right_sibling,
+                    right_sibling_len,
+                    idx,
+                    key,
+                    child_ptr,
+                );
+
+                modified.push(self.current_depth(), internal_node.as_ptr());
+                modified.push(self.current_depth(), right_sibling.as_ptr());
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn pass_to_right_sibling_internal(
+        &mut self,
+        p: &mut InternalBTreeNode<K, B>,
+        p_idx: usize,
+        node: &mut InternalBTreeNode<K, B>,
+        rs: &mut InternalBTreeNode<K, B>,
+        rs_len: usize,
+        i_idx: usize,
+        key: &K::Buf,
+        child_ptr: &StablePtrBuf,
+    ) {
+        if i_idx != Self::CAPACITY {
+            rs.steal_from_left(rs_len, node, Self::CAPACITY, p, p_idx, None, &mut self._buf);
+
+            node.insert_key_buf(i_idx, key, Self::CAPACITY - 1, &mut self._buf);
+            node.insert_child_ptr_buf(i_idx + 1, child_ptr, Self::CAPACITY, &mut self._buf);
+
+            rs.write_len(rs_len + 1);
+            return;
+        }
+
+        let last = Some((key, child_ptr));
+        rs.steal_from_left(rs_len, node, Self::CAPACITY, p, p_idx, last, &mut self._buf);
+        rs.write_len(rs_len + 1);
+    }
+
+    fn pass_to_left_sibling_internal(
+        &mut self,
+        p: &mut InternalBTreeNode<K, B>,
+        p_idx: usize,
+        node: &mut InternalBTreeNode<K, B>,
+        ls: &mut InternalBTreeNode<K, B>,
+        ls_len: usize,
+        i_idx: usize,
+        key: &K::Buf,
+        child_ptr: &StablePtrBuf,
+    ) {
+        if i_idx != 0 {
+            ls.steal_from_right(
+                ls_len,
+                node,
+                Self::CAPACITY,
+                p,
+                p_idx - 1,
+                None,
+                &mut self._buf,
+            );
+
+            node.insert_key_buf(i_idx - 1, key, Self::CAPACITY - 1, &mut self._buf);
+            node.insert_child_ptr_buf(i_idx, child_ptr, Self::CAPACITY, &mut self._buf);
+
+            ls.write_len(ls_len + 1);
+            return;
+        }
+
+        let first = Some((key, child_ptr));
+        ls.steal_from_right(
+            ls_len,
+            node,
+            Self::CAPACITY,
+            p,
+            p_idx - 1,
+            first,
+            &mut self._buf,
+        );
+        ls.write_len(ls_len + 1);
+    }
+
+    fn steal_from_sibling_leaf_or_merge(
+        &mut self,
+        stack_top_frame: Option<(InternalBTreeNode<K, B>, usize, usize)>,
+        mut leaf: LeafBTreeNode<K, V, B>,
+        idx: usize,
+        found_internal_node: Option<(InternalBTreeNode<K, B>, usize)>,
+        modified: &mut LeveledList,
+    ) -> Option<V> {
+        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+
+        if let Some(mut left_sibling) =
+            parent.read_left_sibling::<LeafBTreeNode<K, V, B>>(parent_idx)
+        {
+            let left_sibling_len = left_sibling.read_len();
+
+            // if possible to steal - return early
+            if left_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                self.steal_from_left_sibling_leaf(
+                    &mut leaf,
+                    &mut left_sibling,
+                    left_sibling_len,
+                    &mut parent,
+                    parent_idx - 1,
+                );
+
+                // idx + 1, because after the rotation the leaf has one more key added before
+                let v = leaf.remove_and_disown_by_idx(idx + 1, B, &mut self._buf);
+
+                if let Some((mut fin, i)) = found_internal_node {
+                    fin.write_key_buf(i, &leaf.read_key_buf(0));
+                }
+
+                modified.push(self.current_depth(), leaf.as_ptr());
+                modified.push(self.current_depth(), left_sibling.as_ptr());
+                self.clear_stack(modified, -1);
+
+                return Some(v);
+            }
+
+            if let Some(mut right_sibling) =
+                parent.read_right_sibling::<LeafBTreeNode<K, V, B>>(parent_idx, parent_len)
+            {
+                let right_sibling_len = right_sibling.read_len();
+
+                // if possible to steal - return early
+                if right_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                    self.steal_from_right_sibling_leaf(
+                        &mut leaf,
+                        &mut right_sibling,
+                        right_sibling_len,
+                        &mut parent,
+                        parent_idx,
+                    );
+
+                    // just idx, because after rotation leaf has one more key added to the end
+                    let v = leaf.remove_and_disown_by_idx(idx, B, &mut self._buf);
+
+                    if let Some((mut fin, i)) = found_internal_node {
+                        fin.write_key_buf(i, &leaf.read_key_buf(0));
+                    }
+
+                    modified.push(self.current_depth(), leaf.as_ptr());
+                    modified.push(self.current_depth(), right_sibling.as_ptr());
+                    self.clear_stack(modified, -1);
+
+                    return Some(v);
+                }
+
+                return self.merge_with_right_sibling_leaf(
+                    leaf,
+                    right_sibling,
+                    idx,
+                    found_internal_node,
+                    modified,
+                );
+            }
+
+            return self.merge_with_left_sibling_leaf(leaf, left_sibling, idx, modified);
+        }
+
+        if let Some(mut right_sibling) =
+            parent.read_right_sibling::<LeafBTreeNode<K, V, B>>(parent_idx, parent_len)
+        {
+            let right_sibling_len = right_sibling.read_len();
+
+            // if possible to steal - return early
+            if right_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                self.steal_from_right_sibling_leaf(
+                    &mut leaf,
+                    &mut right_sibling,
+                    right_sibling_len,
+                    &mut parent,
+                    parent_idx,
+                );
+
+                // just idx, because after rotation leaf has one more key added to the end
+                let v = leaf.remove_and_disown_by_idx(idx, B, &mut self._buf);
+
+                if let Some((mut fin, i)) = found_internal_node {
+                    fin.write_key_buf(i, &leaf.read_key_buf(0));
+                }
+
+                modified.push(self.current_depth(), leaf.as_ptr());
+                modified.push(self.current_depth(), right_sibling.as_ptr());
+                self.clear_stack(modified, -1);
+
+                return Some(v);
+            }
+
+            return self.merge_with_right_sibling_leaf(
+                leaf,
+                right_sibling,
+                idx,
+                found_internal_node,
+                modified,
+            );
+        }
+
+        unreachable!();
+    }
+
+    fn merge_with_right_sibling_leaf(
+        &mut self,
+        mut leaf: LeafBTreeNode<K, V, B>,
+        right_sibling: LeafBTreeNode<K, V, B>,
+        idx: usize,
+        found_internal_node: Option<(InternalBTreeNode<K, B>, usize)>,
+        modified: &mut LeveledList,
+    ) -> Option<V> {
+        modified.remove(self.current_depth(), right_sibling.as_ptr());
+        modified.push(self.current_depth(), leaf.as_ptr());
+
+        // otherwise merge with right
+        leaf.merge_min_len(right_sibling, &mut self._buf);
+
+        // just idx, because leaf keys stay unchanged
+        let v = leaf.remove_and_disown_by_idx(idx, Self::CAPACITY - 1, &mut self._buf);
+        leaf.write_len(Self::CAPACITY - 2);
+
+        if let Some((mut fin, i)) = found_internal_node {
+            fin.write_key_buf(i, &leaf.read_key_buf(0));
+        }
+
+        self.handle_stack_after_merge(true, leaf, modified);
+
+        Some(v)
+    }
+
+    fn merge_with_left_sibling_leaf(
+        &mut self,
+        leaf: LeafBTreeNode<K, V, B>,
+        mut left_sibling: LeafBTreeNode<K, V, B>,
+        idx: usize,
+        modified: &mut LeveledList,
+    ) -> Option<V> {
+        modified.remove(self.current_depth(), leaf.as_ptr());
+        modified.push(self.current_depth(), left_sibling.as_ptr());
+
+        // if there is no right sibling - merge with left
+        left_sibling.merge_min_len(leaf, &mut self._buf);
+        // idx + MIN_LEN_AFTER_SPLIT, because all keys of leaf are added to the
+        // end of left_sibling
+        let v = left_sibling.remove_and_disown_by_idx(
+            idx + Self::MIN_LEN_AFTER_SPLIT,
+            Self::CAPACITY - 1,
+            &mut self._buf,
+        );
+        left_sibling.write_len(Self::CAPACITY - 2);
+
+        // no reason to handle 'found_internal_node', because the key is
+        // guaranteed to be in the nearest parent and left_sibling keys are all
+        // continue to present
+
+        self.handle_stack_after_merge(false, left_sibling, modified);
+
+        Some(v)
+    }
+
+    fn steal_from_left_sibling_leaf(
+        &mut self,
+        leaf: &mut LeafBTreeNode<K, V, B>,
+        left_sibling: &mut LeafBTreeNode<K, V, B>,
+        left_sibling_len: usize,
+        parent: &mut InternalBTreeNode<K, B>,
+        parent_idx: usize,
+    ) {
+        leaf.steal_from_left(
+            Self::MIN_LEN_AFTER_SPLIT,
+            left_sibling,
+            left_sibling_len,
+            parent,
+            parent_idx,
+            None,
+            &mut self._buf,
+        );
+
+        left_sibling.write_len(left_sibling_len - 1);
+    }
+
+    fn steal_from_right_sibling_leaf(
+        &mut self,
+        leaf: &mut LeafBTreeNode<K, V, B>,
+        right_sibling: &mut LeafBTreeNode<K, V, B>,
+        right_sibling_len: usize,
+        parent: &mut InternalBTreeNode<K, B>,
+        parent_idx: usize,
+    ) {
+        leaf.steal_from_right(
+            Self::MIN_LEN_AFTER_SPLIT,
+            right_sibling,
+            right_sibling_len,
+            parent,
+            parent_idx,
+            None,
+            &mut self._buf,
+        );
+
+        right_sibling.write_len(right_sibling_len - 1);
+    }
+
+    fn handle_stack_after_merge(
+        &mut self,
+        mut merged_right: bool,
+        leaf: LeafBTreeNode<K, V, B>,
+        modified: &mut LeveledList,
+    ) {
+        let mut prev_node = BTreeNode::Leaf(leaf);
+
+        while let Some((mut node, node_len, remove_idx)) = self.pop_stack() {
+            let (idx_to_remove, child_idx_to_remove) = if merged_right {
+                (remove_idx, remove_idx + 1)
+            } else {
+                (remove_idx - 1, remove_idx)
+            };
+
+            // if the node has enough keys, return early
+            if node_len > Self::MIN_LEN_AFTER_SPLIT {
+                node.remove_key_buf(idx_to_remove, node_len, &mut self._buf);
+                node.remove_child_ptr_buf(child_idx_to_remove, node_len + 1, &mut self._buf);
+                node.write_len(node_len - 1);
+
+                modified.push(self.current_depth(), node.as_ptr());
+                self.clear_stack(modified, -1);
+
+                return;
+            }
+
+            let stack_top_frame = self.peek_stack();
+
+            // if there is no parent, return early
+            if stack_top_frame.is_none() {
+                // if the root has only one key, make child the new root
+                if node_len == 1 {
+                    modified.remove_root();
+
+                    node.destroy();
+                    self.root = Some(prev_node);
+
+                    return;
+                }
+
+                // otherwise simply remove and return
+                node.remove_key_buf(idx_to_remove, node_len, &mut self._buf);
+                node.remove_child_ptr_buf(child_idx_to_remove, node_len + 1, &mut self._buf);
+                node.write_len(node_len - 1);
+
+                modified.push(self.current_depth(), node.as_ptr());
+
+                return;
+            }
+
+            let (mut parent, parent_len, parent_idx) =
+                unsafe { stack_top_frame.unwrap_unchecked() };
+
+            if let Some(mut left_sibling) =
+                parent.read_left_sibling::<InternalBTreeNode<K, B>>(parent_idx)
+            {
+                let left_sibling_len = left_sibling.read_len();
+
+                // steal from left if it is possible
+                if left_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                    modified.push(self.current_depth(), node.as_ptr());
+                    modified.push(self.current_depth(), left_sibling.as_ptr());
+
+                    self.steal_from_left_sibling_internal(
+                        node,
+                        node_len,
+                        idx_to_remove,
+                        child_idx_to_remove,
+                        left_sibling,
+                        left_sibling_len,
+                        parent,
+                        parent_idx,
+                    );
+
+                    self.clear_stack(modified, -1);
+
+                    return;
+                }
+
+                if let Some(right_sibling) =
+                    parent.read_right_sibling::<InternalBTreeNode<K, B>>(parent_idx, parent_len)
+                {
+                    let right_sibling_len = right_sibling.read_len();
+
+                    // steal from right if it's possible
+                    if right_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                        modified.push(self.current_depth(), node.as_ptr());
+                        modified.push(self.current_depth(), right_sibling.as_ptr());
+
+                        self.steal_from_right_sibling_internal(
+                            node,
+                            node_len,
+                            idx_to_remove,
+                            child_idx_to_remove,
+                            right_sibling,
+                            right_sibling_len,
+                            parent,
+                            parent_idx,
+                        );
+
+                        self.clear_stack(modified, -1);
+
+                        return;
+                    }
+
+                    // otherwise merge with right
+                    self.merge_with_right_sibling_internal(
+                        &mut node,
+                        idx_to_remove,
+                        child_idx_to_remove,
+                        right_sibling,
+                        &mut parent,
+                        parent_idx,
+                        modified,
+                    );
+
+                    merged_right = true;
+                    prev_node = BTreeNode::Internal(node);
+
+                    continue;
+                }
+
+                // otherwise merge with left
+                self.merge_with_left_sibling_internal(
+                    node,
+                    idx_to_remove,
+                    child_idx_to_remove,
+                    &mut left_sibling,
+                    &mut parent,
+                    parent_idx,
+                    modified,
+                );
+
+                merged_right = false;
+                prev_node = BTreeNode::Internal(left_sibling);
+
+                continue;
+            }
+
+            if let Some(right_sibling) =
+                parent.read_right_sibling::<InternalBTreeNode<K, B>>(parent_idx, parent_len)
+            {
+                let right_sibling_len = right_sibling.read_len();
+
+                // steal from right if it's possible
+                if right_sibling_len > Self::MIN_LEN_AFTER_SPLIT {
+                    modified.push(self.current_depth(), node.as_ptr());
+                    modified.push(self.current_depth(), right_sibling.as_ptr());
+
+                    self.steal_from_right_sibling_internal(
+                        node,
+                        node_len,
+                        idx_to_remove,
+                        child_idx_to_remove,
+                        right_sibling,
+                        right_sibling_len,
+                        parent,
+                        parent_idx,
+                    );
+
+                    self.clear_stack(modified, -1);
+
+                    return;
+                }
+
+                // otherwise merge with right
+                self.merge_with_right_sibling_internal(
+                    &mut node,
+                    idx_to_remove,
+                    child_idx_to_remove,
+                    right_sibling,
+                    &mut parent,
+                    parent_idx,
+                    modified,
+                );
+
+                merged_right = true;
+                prev_node = BTreeNode::Internal(node);
+
+                continue;
+            }
+        }
+    }
+
+    fn steal_from_right_sibling_internal(
+        &mut self,
+        mut node: InternalBTreeNode<K, B>,
+        node_len: usize,
+        idx_to_remove: usize,
+        child_idx_to_remove: usize,
+        mut right_sibling: InternalBTreeNode<K, B>,
+        right_sibling_len: usize,
+        mut parent: InternalBTreeNode<K, B>,
+        parent_idx: usize,
+    ) {
+        node.steal_from_right(
+            node_len,
+            &mut right_sibling,
+            right_sibling_len,
+            &mut parent,
+            parent_idx,
+            None,
+            &mut self._buf,
+        );
+        right_sibling.write_len(right_sibling_len - 1);
+        node.remove_key_buf(idx_to_remove, B, &mut self._buf);
+        node.remove_child_ptr_buf(child_idx_to_remove, B + 1, &mut self._buf);
+    }
+
+    fn steal_from_left_sibling_internal(
+        &mut self,
+        mut node: InternalBTreeNode<K, B>,
+        node_len: usize,
+        idx_to_remove: usize,
+        child_idx_to_remove: usize,
+        mut left_sibling: InternalBTreeNode<K, B>,
+        left_sibling_len: usize,
+        mut parent: InternalBTreeNode<K, B>,
+        parent_idx: usize,
+    ) {
+        node.steal_from_left(
+            node_len,
+            &mut left_sibling,
+            left_sibling_len,
+            &mut parent,
+            parent_idx - 1,
+            None,
+            &mut self._buf,
+        );
+        left_sibling.write_len(left_sibling_len - 1);
+        node.remove_key_buf(idx_to_remove + 1, B, &mut self._buf);
+        node.remove_child_ptr_buf(child_idx_to_remove + 1, B + 1, &mut self._buf);
+    }
+
+    fn merge_with_right_sibling_internal(
+        &mut self,
+        node: &mut InternalBTreeNode<K, B>,
+        idx_to_remove: usize,
+        child_idx_to_remove: usize,
+        right_sibling: InternalBTreeNode<K, B>,
+        parent: &mut InternalBTreeNode<K, B>,
+        parent_idx: usize,
+        modified: &mut LeveledList,
+    ) {
+        modified.remove(self.current_depth(), right_sibling.as_ptr());
+        modified.push(self.current_depth(), node.as_ptr());
+
+        let mid_element = parent.read_key_buf(parent_idx);
+        node.merge_min_len(&mid_element, right_sibling, &mut self._buf);
+        node.remove_key_buf(idx_to_remove, Self::CAPACITY, &mut self._buf);
+        node.remove_child_ptr_buf(child_idx_to_remove, Self::CHILDREN_CAPACITY, &mut self._buf);
+        node.write_len(Self::CAPACITY - 1);
+    }
+
+    fn merge_with_left_sibling_internal(
+        &mut self,
+        node: InternalBTreeNode<K, B>,
+        idx_to_remove: usize,
+        child_idx_to_remove: usize,
+        left_sibling: &mut InternalBTreeNode<K, B>,
+        parent: &mut InternalBTreeNode<K, B>,
+        parent_idx: usize,
+        modified: &mut LeveledList,
+    ) {
+        modified.remove(self.current_depth(), node.as_ptr());
+        modified.push(self.current_depth(), left_sibling.as_ptr());
+
+        let mid_element = parent.read_key_buf(parent_idx - 1);
+        left_sibling.merge_min_len(&mid_element, node, &mut self._buf);
+        left_sibling.remove_key_buf(idx_to_remove + B, Self::CAPACITY, &mut self._buf);
+        left_sibling.remove_child_ptr_buf(
+            child_idx_to_remove + B,
+            Self::CHILDREN_CAPACITY,
+            &mut self._buf,
+        );
+        left_sibling.write_len(Self::CAPACITY - 1);
+    }
+
+    fn peek_stack(&self) -> Option<(InternalBTreeNode<K, B>, usize, usize)> {
+        self._stack
+            .last()
+            .map(|(n, l, i)| (unsafe { n.copy() }, *l, *i))
+    }
+
+    fn get_or_create_root(&mut self) -> Result<BTreeNode<K, V, B>, OutOfMemory> {
+        match &self.root {
+            Some(r) => unsafe { Ok(r.copy()) },
+            None => {
+                let new_root = BTreeNode::<K, V, B>::Leaf(LeafBTreeNode::create(self.certified)?);
+
+                self.root = Some(new_root);
+                unsafe { Ok(self.root.as_ref().unwrap_unchecked().copy()) }
+            }
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    StableType for SBTreeMap<K, V, B>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        if self.root.is_none() {
+            return;
+        }
+
+        let mut nodes = vec![unsafe { self.root.take().unwrap_unchecked() }];
+        let mut new_nodes = Vec::new();
+
+        loop {
+            if nodes.is_empty() {
+                return;
+            }
+
+            for _ in 0..nodes.len() {
+                match unsafe { nodes.pop().unwrap_unchecked() } {
+                    BTreeNode::Internal(internal) => {
+                        for j in 0..(internal.read_len() + 1) {
+                            let child_ptr_raw = internal.read_child_ptr_buf(j);
+                            let child_ptr = u64::from_fixed_size_bytes(&child_ptr_raw);
+                            let child = BTreeNode::<K, V, B>::from_ptr(child_ptr);
+
+                            new_nodes.push(child);
+                        }
+
+                        internal.destroy();
+                    }
+                    BTreeNode::Leaf(mut leaf) => {
+                        for j in 0..leaf.read_len() {
+                            leaf.read_and_disown_key(j);
+                            leaf.read_and_disown_value(j);
+                        }
+
+                        leaf.destroy();
+                    }
+                }
+            }
+
+            nodes = new_nodes;
+            new_nodes = Vec::new();
+        }
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize> Drop
+    for SBTreeMap<K, V, B>
+{
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            let root_ptr = self.root.as_ref().map(|it| it.as_ptr());
+
+            unsafe {
+                self.stable_drop();
+            }
+
+            if let Some(ptr) = root_ptr {
+                crate::mem::debug_assert_released(ptr, "SBTreeMap");
+            }
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+        const B: usize,
+    > SBTreeMap<K, V, B>
+{
+    pub fn debug_print_stack(&self) {
+        isoprint(&format!(
+            "STACK: {:?}",
+            self._stack
+                .iter()
+                .map(|(p, l, i)| (p.as_ptr(), *l, *i))
+                .collect::<Vec<_>>()
+        ));
+    }
+
+    pub fn debug_print(&self) {
+        if self.len == 0 {
+            isoprint("EMPTY BTREEMAP");
+            return;
+        }
+
+        let mut level = Vec::new();
+        level.push(unsafe { self.root.as_ref().unwrap_unchecked().copy() });
+
+        loop {
+            Self::print_level(&level);
+
+            let mut new_level = Vec::new();
+            for node in level {
+                if let BTreeNode::Internal(internal) = node {
+                    let c_len = internal.read_len() + 1;
+                    for i in 0..c_len {
+                        let c = BTreeNode::<K, V, B>::from_ptr(u64::from_fixed_size_bytes(
+                            &internal.read_child_ptr_buf(i),
+                        ));
+                        new_level.push(c);
+                    }
+                }
+            }
+
+            if new_level.is_empty() {
+                break;
+            } else {
+                level = new_level;
+            }
+        }
+    }
+
+    fn print_level(level: &Vec<BTreeNode<K, V, B>>) {
+        let mut result = String::new();
+
+        for node in level {
+            result += &match node {
+                BTreeNode::Internal(i) => i.to_string(),
+                BTreeNode::Leaf(l) => l.to_string(),
+            }
+        }
+
+        isoprint(&result);
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    Default for SBTreeMap<K, V, B>
+{
+    fn default() -> Self {
+        Self::with_branching_factor()
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    AsFixedSizeBytes for SBTreeMap<K, V, B>
+{
+    const SIZE: usize = u64::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 2];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let ptr = if let Some(root) = &self.root {
+            root.as_ptr() | Self::HEADER_B_TAG | Self::HEADER_LAYOUT_TAG
+        } else {
+            EMPTY_PTR
+        };
+
+        ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let mut ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let len = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+
+        if ptr != EMPTY_PTR {
+            // maps, written before the branching factor became configurable, have no tag
+            let stored_b = match (ptr >> 56) as usize {
+                0 => DEFAULT_B,
+                b => b,
+            };
+
+            assert_eq!(
+                stored_b, B,
+                "The SBTreeMap was written with the branching factor {}, but is read with {}",
+                stored_b, B
+            );
+
+            // maps, written before internal nodes started to store subtree counts, have no version
+            let stored_layout = ((ptr >> 48) & (u8::MAX as u64)) as u8;
+
+            assert_eq!(
+                stored_layout, NODE_LAYOUT_VERSION,
+                "The SBTreeMap was written with the node layout version {}, but is read with {} - see docs/migration.md",
+                stored_layout, NODE_LAYOUT_VERSION
+            );
+
+            ptr &= Self::HEADER_PTR_MASK;
+        }
+
+        Self {
+            root: if ptr == EMPTY_PTR {
+                None
+            } else {
+                Some(BTreeNode::from_ptr(ptr))
+            },
+            certified: false,
+            len,
+            stable_drop_flag: false,
+            _buf: Vec::default(),
+            _stack: Vec::default(),
+        }
+    }
+}
+
+pub(crate) enum LeveledList {
+    None,
+    Some((Vec<Vec<u64>>, usize)),
+    // only nodes, touched by splits, merges and rotations, are collected, so their child counts
+    // can be recalculated - counts of the rest of the path are adjusted in place
+    Counts((Vec<Vec<u64>>, usize)),
+}
+
+impl LeveledList {
+    pub(crate) fn new() -> Self {
+        Self::Some((vec![Vec::new()], 0))
+    }
+
+    pub(crate) fn counts() -> Self {
+        Self::Counts((vec![Vec::new()], 0))
+    }
+
+    fn is_some(&self) -> bool {
+        matches!(self, LeveledList::Some(_))
+    }
+
+    fn insert_root(&mut self, ptr: u64) {
+        match self {
+            LeveledList::None => {}
+            LeveledList::Some((v, max_level)) | LeveledList::Counts((v, max_level)) => {
+                let root = vec![ptr];
+                v.insert(0, root);
+                *max_level += 1;
+            }
+        }
+    }
+
+    fn remove_root(&mut self) {
+        match self {
+            LeveledList::None => {}
+            LeveledList::Some((v, max_level)) | LeveledList::Counts((v, max_level)) => {
+                v.remove(0);
+                *max_level -= 1;
+            }
+        }
+    }
+
+    fn push(&mut self, level: usize, ptr: u64) {
+        match self {
+            LeveledList::None => {}
+            LeveledList::Some((v, max_level)) | LeveledList::Counts((v, max_level)) => {
+                if level.gt(max_level) {
+                    *max_level = level;
+
+                    v.resize_with(level + 1, Vec::new);
+                }
+
+                match v[level].binary_search(&ptr) {
+                    Ok(_) => {}
+                    Err(idx) => v[level].insert(idx, ptr),
+                };
+            }
+        }
+    }
+
+    fn remove(&mut self, level: usize, ptr: u64) {
+        match self {
+            LeveledList::None => {}
+            LeveledList::Some((v, _)) | LeveledList::Counts((v, _)) => {
+                if let Some(level_list) = v.get_mut(level) {
+                    if let Ok(idx) = level_list.binary_search(&ptr) {
+                        level_list.remove(idx);
+                    }
+                }
+            }
+        }
+    }
+
+    // whether some node other than `ptr` was collected at this level
+    fn has_other_than(&self, level: usize, ptr: u64) -> bool {
+        match self {
+            LeveledList::None => false,
+            LeveledList::Some((v, _)) | LeveledList::Counts((v, _)) => v
+                .get(level)
+                .is_some_and(|level_list| level_list.iter().any(|it| *it != ptr)),
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u64> {
+        match self {
+            LeveledList::None => unreachable!(),
+            LeveledList::Some((v, max_level)) | LeveledList::Counts((v, max_level)) => {
+                let level_list = v.get_mut(*max_level)?;
+                let mut ptr = level_list.pop();
+
+                while ptr.is_none() {
+                    if *max_level == 0 {
+                        return None;
+                    }
+
+                    *max_level -= 1;
+
+                    ptr = v[*max_level].pop();
+                }
+
+                ptr
+            }
+        }
+    }
+
+    pub(crate) fn debug_print(&self) {
+        match self {
+            LeveledList::None => isoprint("LeveledList [Dummy]"),
+            LeveledList::Some((v, max_level)) | LeveledList::Counts((v, max_level)) => {
+                let mut str = String::from("LeveledList [");
+                for i in 0..(*max_level + 1) {
+                    str += format!("{} - ({:?})", i, v[i]).as_str();
+                    if i < *max_level {
+                        str += ", ";
+                    }
+                }
+                str += "]";
+
+                isoprint(&str);
+            }
+        }
+    }
+}
 
-        // if there is enough space - simply insert and return early
-        if leaf_node_len < CAPACITY {
-            leaf_node.insert_key_buf(insert_idx, &k, leaf_node_len, &mut self._buf);
-            leaf_node.insert_value_buf(insert_idx, &v, leaf_node_len, &mut self._buf);
+impl<
+        K: StableType + AsFixedSizeBytes + Ord + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+        const B: usize,
+    > Debug for SBTreeMap<K, V, B>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
 
-            leaf_node.write_len(leaf_node_len + 1);
+        for (idx, (k, v)) in self.iter().enumerate() {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
 
-            modified.push(self.current_depth(), leaf_node.as_ptr());
+            if (idx as u64) < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
 
-            unsafe { key.stable_drop_flag_off() };
-            unsafe { value.stable_drop_flag_off() };
+        f.write_str("}")
+    }
+}
 
-            return Ok(Err(None));
-        }
+pub(crate) trait IBTreeNode {
+    unsafe fn from_ptr(ptr: StablePtr) -> Self;
+    fn as_ptr(&self) -> StablePtr;
+    unsafe fn copy(&self) -> Self;
+}
 
-        // try passing an element to a neighbor, to make room for a new one
-        if self.pass_elem_to_sibling_leaf(leaf_node, &k, &v, insert_idx, modified) {
-            unsafe { key.stable_drop_flag_off() };
-            unsafe { value.stable_drop_flag_off() };
+pub(crate) enum BTreeNode<K, V, const B: usize = DEFAULT_B> {
+    Internal(InternalBTreeNode<K, B>),
+    Leaf(LeafBTreeNode<K, V, B>),
+}
 
-            return Ok(Err(None));
-        }
+impl<K, V, const B: usize> BTreeNode<K, V, B> {
+    pub(crate) fn from_ptr(ptr: StablePtr) -> Self {
+        let node_type: u8 =
+            unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(ptr, NODE_TYPE_OFFSET)) };
 
-        // cheking if it is possible to allocate worst-case scenario amount of memory
-        let memory_to_allocate = (self._stack.len() + 1) as u64
-            * FreeBlock::to_total_size(InternalBTreeNode::<K>::calc_byte_size(self.certified))
-            + FreeBlock::to_total_size(LeafBTreeNode::<K, V>::calc_size_bytes(self.certified));
+        unsafe {
+            match node_type {
+                NODE_TYPE_INTERNAL => Self::Internal(InternalBTreeNode::<K, B>::from_ptr(ptr)),
+                NODE_TYPE_LEAF => Self::Leaf(LeafBTreeNode::<K, V, B>::from_ptr(ptr)),
+                _ => unreachable!(),
+            }
+        }
+    }
 
-        // we can unwrap all OutOfMemory errors if this check passes, without any consequences
-        if !make_sure_can_allocate(memory_to_allocate) {
-            return Err((key, value));
+    pub(crate) fn as_ptr(&self) -> StablePtr {
+        match self {
+            Self::Internal(i) => i.as_ptr(),
+            Self::Leaf(l) => l.as_ptr(),
         }
+    }
 
-        unsafe { key.stable_drop_flag_off() };
-        unsafe { value.stable_drop_flag_off() };
+    pub(crate) unsafe fn copy(&self) -> Self {
+        match self {
+            Self::Internal(i) => Self::Internal(i.copy()),
+            Self::Leaf(l) => Self::Leaf(l.copy()),
+        }
+    }
+}
 
-        // split the leaf and insert so both leaves now have length of B
-        let mut right = if insert_idx < B {
-            let right = leaf_node
-                .split_max_len(true, &mut self._buf, self.certified)
-                .unwrap();
-            leaf_node.insert_key_buf(insert_idx, &k, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            leaf_node.insert_value_buf(insert_idx, &v, MIN_LEN_AFTER_SPLIT, &mut self._buf);
+#[cfg(test)]
+mod tests {
+    use crate::collections::btree_map::{SBTreeMap, DEFAULT_B};
+    use crate::collections::SVec;
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::primitive::StableType;
+    use crate::utils::test::generate_random_string;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
+        stable, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, SBox,
+    };
+    use rand::rngs::ThreadRng;
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
 
-            right
-        } else {
-            let mut right = leaf_node
-                .split_max_len(false, &mut self._buf, self.certified)
-                .unwrap();
-            right.insert_key_buf(insert_idx - B, &k, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            right.insert_value_buf(insert_idx - B, &v, MIN_LEN_AFTER_SPLIT, &mut self._buf);
+    #[test]
+    fn random_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            right
-        };
+        {
+            let iterations = 1000;
+            let mut map = SBTreeMap::<u64, u64>::default();
 
-        leaf_node.write_len(B);
-        right.write_len(B);
+            let mut example = Vec::new();
+            for i in 0..iterations {
+                example.push(i as u64);
+            }
+            example.shuffle(&mut thread_rng());
 
-        modified.push(self.current_depth(), leaf_node.as_ptr());
-        modified.push(self.current_depth(), right.as_ptr());
+            for i in 0..iterations {
+                map.debug_print_stack();
+                assert!(map._stack.is_empty());
+                assert!(map.insert(example[i], example[i]).unwrap().is_none());
+                map.verify().unwrap();
 
-        Ok(Err(Some(right)))
-    }
+                for j in 0..i {
+                    assert!(
+                        map.contains_key(&example[j]),
+                        "don't contain {}",
+                        example[j]
+                    );
+                    assert_eq!(
+                        *map.get(&example[j]).unwrap(),
+                        example[j],
+                        "unable to get {}",
+                        example[j]
+                    );
+                }
+            }
 
-    fn insert_internal(
-        &mut self,
-        internal_node: &mut InternalBTreeNode<K>,
-        len: usize,
-        idx: usize,
-        key: K::Buf,
-        child_ptr: StablePtrBuf,
-        modified: &mut LeveledList,
-    ) -> Option<(InternalBTreeNode<K>, K::Buf)> {
-        if len < CAPACITY {
-            internal_node.insert_key_buf(idx, &key, len, &mut self._buf);
-            internal_node.insert_child_ptr_buf(idx + 1, &child_ptr, len + 1, &mut self._buf);
+            assert_eq!(map.insert(0, 1).unwrap().unwrap(), 0);
+            assert_eq!(map.insert(0, 0).unwrap().unwrap(), 1);
 
-            internal_node.write_len(len + 1);
+            map.debug_print();
 
-            modified.push(self.current_depth(), internal_node.as_ptr());
+            example.shuffle(&mut thread_rng());
+            for i in 0..iterations {
+                assert!(map._stack.is_empty());
 
-            return None;
-        }
+                assert_eq!(map.remove(&example[i]), Some(example[i]));
+                map.verify().unwrap();
 
-        if self.pass_elem_to_sibling_internal(internal_node, idx, &key, &child_ptr, modified) {
-            return None;
+                for j in (i + 1)..iterations {
+                    assert!(
+                        map.contains_key(&example[j]),
+                        "don't contain {}",
+                        example[j]
+                    );
+                    assert_eq!(
+                        *map.get(&example[j]).unwrap(),
+                        example[j],
+                        "unable to get {}",
+                        example[j]
+                    );
+                }
+            }
         }
 
-        // TODO: possible to optimize when idx == MIN_LEN_AFTER_SPLIT
-        let (mut right, mid) = internal_node
-            .split_max_len(&mut self._buf, self.certified)
-            .unwrap();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-        if idx <= MIN_LEN_AFTER_SPLIT {
-            internal_node.insert_key_buf(idx, &key, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            internal_node.insert_child_ptr_buf(idx + 1, &child_ptr, B, &mut self._buf);
+    #[test]
+    fn iters_work_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            internal_node.write_len(B);
-            right.write_len(MIN_LEN_AFTER_SPLIT);
-        } else {
-            right.insert_key_buf(idx - B, &key, MIN_LEN_AFTER_SPLIT, &mut self._buf);
-            right.insert_child_ptr_buf(idx - B + 1, &child_ptr, B, &mut self._buf);
+        {
+            let mut map = SBTreeMap::<u64, u64>::default();
 
-            internal_node.write_len(MIN_LEN_AFTER_SPLIT);
-            right.write_len(B);
-        }
+            for i in 0..200 {
+                map.insert(i, i);
+            }
 
-        modified.push(self.current_depth(), internal_node.as_ptr());
-        modified.push(self.current_depth(), right.as_ptr());
+            let mut i = 0u64;
 
-        Some((right, mid))
-    }
+            for (mut k, mut v) in map.iter() {
+                assert_eq!(i, *k);
+                assert_eq!(i, *v);
 
-    fn pass_elem_to_sibling_leaf(
-        &mut self,
-        leaf_node: &mut LeafBTreeNode<K, V>,
-        key: &K::Buf,
-        value: &V::Buf,
-        insert_idx: usize,
-        modified: &mut LeveledList,
-    ) -> bool {
-        let stack_top_frame = self.peek_stack();
-        if stack_top_frame.is_none() {
-            return false;
-        }
+                print!("({:?}, {:?}), ", *k, *v);
 
-        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+                i += 1;
+            }
 
-        if let Some(mut left_sibling) = parent.read_left_sibling::<LeafBTreeNode<K, V>>(parent_idx)
-        {
-            let left_sibling_len = left_sibling.read_len();
+            println!();
 
-            // if it is possible to pass to the left sibling - do that
-            if left_sibling_len < CAPACITY {
-                self.pass_to_left_sibling_leaf(
-                    &mut parent,
-                    parent_idx,
-                    leaf_node,
-                    &mut left_sibling,
-                    left_sibling_len,
-                    insert_idx,
-                    key,
-                    value,
-                );
+            assert_eq!(i, 200);
+        }
 
-                modified.push(self.current_depth(), leaf_node.as_ptr());
-                modified.push(self.current_depth(), left_sibling.as_ptr());
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                return true;
-            }
-        }
+    #[test]
+    fn for_each_leaf_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        if let Some(mut right_sibling) =
-            parent.read_right_sibling::<LeafBTreeNode<K, V>>(parent_idx, parent_len)
         {
-            let right_sibling_len = right_sibling.read_len();
+            let mut map = SBTreeMap::<u64, u32>::default();
 
-            if right_sibling_len < CAPACITY {
-                self.pass_to_right_sibling_leaf(
-                    &mut parent,
-                    parent_idx,
-                    leaf_node,
-                    &mut right_sibling,
-                    right_sibling_len,
-                    insert_idx,
-                    key,
-                    value,
-                );
+            map.for_each_leaf(|_, _| unreachable!());
 
-                modified.push(self.current_depth(), leaf_node.as_ptr());
-                modified.push(self.current_depth(), right_sibling.as_ptr());
+            for i in 0..500 {
+                map.insert(i, i as u32 * 2).unwrap();
+            }
+
+            let mut entries = Vec::new();
+            map.for_each_leaf(|keys, values| {
+                assert_eq!(keys.len() / u64::SIZE, values.len() / u32::SIZE);
 
-                return true;
+                for (k, v) in keys.chunks(u64::SIZE).zip(values.chunks(u32::SIZE)) {
+                    entries.push((u64::from_fixed_size_bytes(k), u32::from_fixed_size_bytes(v)));
+                }
+            });
+
+            assert_eq!(entries.len(), 500);
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                assert_eq!(k, i as u64);
+                assert_eq!(v, i as u32 * 2);
             }
         }
 
-        false
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn pass_to_right_sibling_leaf(
-        &mut self,
-        p: &mut InternalBTreeNode<K>,
-        p_idx: usize,
-        leaf: &mut LeafBTreeNode<K, V>,
-        rs: &mut LeafBTreeNode<K, V>,
-        rs_len: usize,
-        i_idx: usize,
-        key: &K::Buf,
-        value: &V::Buf,
-    ) {
-        if i_idx != CAPACITY {
-            rs.steal_from_left(rs_len, leaf, CAPACITY, p, p_idx, None, &mut self._buf);
+    fn check_branching_factor<const B: usize>() {
+        let mut rng = thread_rng();
+        let mut map = SBTreeMap::<u32, u64, B>::with_branching_factor();
+        let mut example = BTreeMap::new();
 
-            leaf.insert_key_buf(i_idx, key, CAPACITY - 1, &mut self._buf);
-            leaf.insert_value_buf(i_idx, value, CAPACITY - 1, &mut self._buf);
+        for _ in 0..3000 {
+            let k = rng.gen_range(0..1000u32);
 
-            rs.write_len(rs_len + 1);
-            return;
+            if rng.gen_bool(0.6) {
+                assert_eq!(
+                    map.insert(k, k as u64).unwrap(),
+                    example.insert(k, k as u64)
+                );
+            } else {
+                assert_eq!(map.remove(&k), example.remove(&k));
+            }
         }
 
-        let last = Some((key, value));
-        rs.steal_from_left(rs_len, leaf, CAPACITY, p, p_idx, last, &mut self._buf);
-        rs.write_len(rs_len + 1);
+        assert!(map.verify().is_ok());
+        assert_eq!(map.len(), example.len() as u64);
+        assert!(map
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq(example.iter().map(|(k, v)| (*k, *v))));
+
+        let buf = map.as_new_fixed_size_bytes();
+        let restored = SBTreeMap::<u32, u64, B>::from_fixed_size_bytes(&buf);
+        assert_eq!(restored.len(), map.len());
+        assert!(restored.verify().is_ok());
     }
 
-    fn pass_to_left_sibling_leaf(
-        &mut self,
-        p: &mut InternalBTreeNode<K>,
-        p_idx: usize,
-        leaf: &mut LeafBTreeNode<K, V>,
-        ls: &mut LeafBTreeNode<K, V>,
-        ls_len: usize,
-        i_idx: usize,
-        key: &K::Buf,
-        value: &V::Buf,
-    ) {
-        if i_idx != 1 {
-            ls.steal_from_right(ls_len, leaf, CAPACITY, p, p_idx - 1, None, &mut self._buf);
-
-            leaf.insert_key_buf(i_idx - 1, key, CAPACITY - 1, &mut self._buf);
-            leaf.insert_value_buf(i_idx - 1, value, CAPACITY - 1, &mut self._buf);
+    fn check_from_sorted_iter<const B: usize>() {
+        let capacity = 2 * B - 1;
+        let min_len = B - 1;
+
+        // around the sizes, where the last leaves have to be rebalanced or a new level is needed
+        let sizes = [
+            0,
+            1,
+            capacity,
+            capacity + 1,
+            capacity + min_len,
+            capacity + min_len + 1,
+            capacity * 2 * B,
+            capacity * 2 * B + 1,
+            1000,
+        ];
+
+        for n in sizes {
+            let mut map =
+                SBTreeMap::<u32, u64, B>::from_sorted_iter((0..n as u32).map(|i| (i, i as u64)));
+
+            assert_eq!(map.len(), n as u64);
+            assert!(map.verify().is_ok(), "{:?}", map.verify());
+            assert!(map
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .eq((0..n as u32).map(|i| (i, i as u64))));
 
-            ls.write_len(ls_len + 1);
-            return;
-        };
+            // the tree keeps working as usual
+            for i in (0..n as u32).step_by(3) {
+                assert_eq!(map.remove(&i), Some(i as u64));
+            }
 
-        let first = Some((key, value));
-        ls.steal_from_right(ls_len, leaf, CAPACITY, p, p_idx - 1, first, &mut self._buf);
-        ls.write_len(ls_len + 1);
-    }
+            for i in n as u32..(n as u32 + 100) {
+                map.insert(i, i as u64).unwrap();
+            }
 
-    fn pass_elem_to_sibling_internal(
-        &mut self,
-        internal_node: &mut InternalBTreeNode<K>,
-        idx: usize,
-        key: &K::Buf,
-        child_ptr: &StablePtrBuf,
-        modified: &mut LeveledList,
-    ) -> bool {
-        let stack_top_frame = self.peek_stack();
-        if stack_top_frame.is_none() {
-            return false;
+            assert!(map.verify().is_ok(), "{:?}", map.verify());
         }
+    }
 
-        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+    #[test]
+    fn from_sorted_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        if let Some(mut left_sibling) = parent.read_left_sibling::<InternalBTreeNode<K>>(parent_idx)
         {
-            let left_sibling_len = left_sibling.read_len();
+            check_from_sorted_iter::<2>();
+            check_from_sorted_iter::<3>();
+            check_from_sorted_iter::<DEFAULT_B>();
 
-            if left_sibling_len < CAPACITY {
-                self.pass_to_left_sibling_internal(
-                    &mut parent,
-                    parent_idx,
-                    internal_node,
-                    &mut left_sibling,
-                    left_sibling_len,
-                    idx,
-                    key,
-                    child_ptr,
-                );
+            let mut rng = thread_rng();
+            let mut example = BTreeMap::new();
+            let mut pairs = SVec::new();
 
-                modified.push(self.current_depth(), internal_node.as_ptr());
-                modified.push(self.current_depth(), left_sibling.as_ptr());
+            for _ in 0..1000 {
+                let k = rng.gen_range(0..500u32);
+                let v = generate_random_string(&mut rng);
 
-                return true;
+                example.insert(k, v.clone());
+                pairs.push((k, SBox::new(v).unwrap())).unwrap();
+            }
+
+            let map = SBTreeMap::<_, _>::from_svec(pairs);
+
+            assert_eq!(map.len(), example.len() as u64);
+            assert!(map.verify().is_ok());
+
+            // the last pair with the same key wins
+            for ((k, v), (ek, ev)) in map.iter().zip(example.iter()) {
+                assert_eq!(*k, *ek);
+                assert_eq!(&**v, ev);
             }
         }
 
-        if let Some(mut right_sibling) =
-            parent.read_right_sibling::<InternalBTreeNode<K>>(parent_idx, parent_len)
-        {
-            let right_sibling_len = right_sibling.read_len();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-            if right_sibling_len < CAPACITY {
-                self.pass_to_right_sibling_internal(
-                    &mut parent,
-                    parent_idx,
-                    internal_node,
-                    &mut right_sibling,
-                    right_sibling_len,
-                    idx,
-                    key,
-                    child_ptr,
-                );
+    #[test]
+    fn custom_branching_factor_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                modified.push(self.current_depth(), internal_node.as_ptr());
-                modified.push(self.current_depth(), right_sibling.as_ptr());
+        check_branching_factor::<2>();
+        check_branching_factor::<3>();
+        check_branching_factor::<16>();
 
-                return true;
+        {
+            // maps written before the branching factor became configurable have no tag
+            let mut map = SBTreeMap::<u32, u64>::new();
+            for i in 0..100 {
+                map.insert(i, i as u64).unwrap();
             }
+
+            let mut buf = map.as_new_fixed_size_bytes();
+            buf[7] = 0;
+
+            let restored = SBTreeMap::<u32, u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(restored.len(), 100);
+            assert!(restored.verify().is_ok());
         }
 
-        false
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn pass_to_right_sibling_internal(
-        &mut self,
-        p: &mut InternalBTreeNode<K>,
-        p_idx: usize,
-        node: &mut InternalBTreeNode<K>,
-        rs: &mut InternalBTreeNode<K>,
-        rs_len: usize,
-        i_idx: usize,
-        key: &K::Buf,
-        child_ptr: &StablePtrBuf,
-    ) {
-        if i_idx != CAPACITY {
-            rs.steal_from_left(rs_len, node, CAPACITY, p, p_idx, None, &mut self._buf);
+    #[test]
+    #[should_panic(expected = "written with the branching factor 16, but is read with 8")]
+    fn branching_factor_mismatch_should_panic() {
+        stable::clear();
+        stable_memory_init();
 
-            node.insert_key_buf(i_idx, key, CAPACITY - 1, &mut self._buf);
-            node.insert_child_ptr_buf(i_idx + 1, child_ptr, CAPACITY, &mut self._buf);
+        let mut map = SBTreeMap::<u32, u64, 16>::with_branching_factor();
+        map.insert(1, 1).unwrap();
 
-            rs.write_len(rs_len + 1);
-            return;
+        let buf = map.as_new_fixed_size_bytes();
+        SBTreeMap::<u32, u64>::from_fixed_size_bytes(&buf);
+    }
+
+    #[test]
+    #[should_panic(expected = "written with the node layout version 0, but is read with 1")]
+    fn node_layout_mismatch_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut map = SBTreeMap::<u32, u64>::new();
+        map.insert(1, 1).unwrap();
+
+        // maps written before internal nodes started to store subtree counts have no version
+        let mut buf = map.as_new_fixed_size_bytes();
+        buf[6] = 0;
+
+        SBTreeMap::<u32, u64>::from_fixed_size_bytes(&buf);
+    }
+
+    #[test]
+    fn remove_missing_key_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::<u32, u32>::new();
+
+            // a failed removal should not leave anything behind, that would affect the next
+            // insertion - even if it splits the root
+            for i in 0..1000u32 {
+                assert!(map.remove(&(i * 2 + 1)).is_none());
+                map.insert(i * 2, i).unwrap();
+            }
+
+            assert_eq!(map.len(), 1000);
+            assert!(map.verify().is_ok());
         }
 
-        let last = Some((key, child_ptr));
-        rs.steal_from_left(rs_len, node, CAPACITY, p, p_idx, last, &mut self._buf);
-        rs.write_len(rs_len + 1);
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn pass_to_left_sibling_internal(
-        &mut self,
-        p: &mut InternalBTreeNode<K>,
-        p_idx: usize,
-        node: &mut InternalBTreeNode<K>,
-        ls: &mut InternalBTreeNode<K>,
-        ls_len: usize,
-        i_idx: usize,
-        key: &K::Buf,
-        child_ptr: &StablePtrBuf,
-    ) {
-        if i_idx != 0 {
-            ls.steal_from_right(ls_len, node, CAPACITY, p, p_idx - 1, None, &mut self._buf);
+    #[test]
+    fn iter_subtree_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            node.insert_key_buf(i_idx - 1, key, CAPACITY - 1, &mut self._buf);
-            node.insert_child_ptr_buf(i_idx, child_ptr, CAPACITY, &mut self._buf);
+        {
+            let mut map = SBTreeMap::<u64, u32>::default();
+            assert!(map.root_children().is_empty());
 
-            ls.write_len(ls_len + 1);
-            return;
+            for i in 0..5 {
+                map.insert(i, i as u32).unwrap();
+            }
+
+            // the root is a leaf
+            let subtrees = map.root_children();
+            assert_eq!(subtrees.len(), 1);
+            assert_eq!(unsafe { map.iter_subtree(subtrees[0]) }.count(), 5);
+
+            for i in 5..3000 {
+                map.insert(i, i as u32 * 2).unwrap();
+            }
+
+            let subtrees = map.root_children();
+            assert!(subtrees.len() > 1);
+
+            let mut entries = Vec::new();
+            for subtree in subtrees {
+                let chunk = unsafe { map.iter_subtree(subtree) }.collect::<Vec<_>>();
+                assert!(!chunk.is_empty());
+                assert!(chunk.len() < 3000);
+
+                entries.extend(chunk);
+            }
+
+            assert_eq!(
+                entries,
+                map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+            );
+            assert_eq!(entries.len(), 3000);
         }
 
-        let first = Some((key, child_ptr));
-        ls.steal_from_right(ls_len, node, CAPACITY, p, p_idx - 1, first, &mut self._buf);
-        ls.write_len(ls_len + 1);
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn steal_from_sibling_leaf_or_merge(
-        &mut self,
-        stack_top_frame: Option<(InternalBTreeNode<K>, usize, usize)>,
-        mut leaf: LeafBTreeNode<K, V>,
-        idx: usize,
-        found_internal_node: Option<(InternalBTreeNode<K>, usize)>,
-        modified: &mut LeveledList,
-    ) -> Option<V> {
-        let (mut parent, parent_len, parent_idx) = unsafe { stack_top_frame.unwrap_unchecked() };
+    #[test]
+    fn serialization_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        if let Some(mut left_sibling) = parent.read_left_sibling::<LeafBTreeNode<K, V>>(parent_idx)
         {
-            let left_sibling_len = left_sibling.read_len();
+            let map = SBTreeMap::<u32, u64>::new();
+            let buf = map.as_new_fixed_size_bytes();
+            let map1 = SBTreeMap::<u32, u64>::from_fixed_size_bytes(&buf);
 
-            // if possible to steal - return early
-            if left_sibling_len > MIN_LEN_AFTER_SPLIT {
-                self.steal_from_left_sibling_leaf(
-                    &mut leaf,
-                    &mut left_sibling,
-                    left_sibling_len,
-                    &mut parent,
-                    parent_idx - 1,
-                );
+            assert!(map1.is_empty());
+            assert!(map1.root.is_none());
 
-                // idx + 1, because after the rotation the leaf has one more key added before
-                let v = leaf.remove_and_disown_by_idx(idx + 1, B, &mut self._buf);
+            let mut map = SBTreeMap::new();
+            for i in 0..1000u32 {
+                map.insert(i, i as u64).unwrap();
+            }
 
-                if let Some((mut fin, i)) = found_internal_node {
-                    fin.write_key_buf(i, &leaf.read_key_buf(0));
-                }
+            let len = map.len();
+            let ptr = map.root.as_ref().unwrap().as_ptr();
 
-                modified.push(self.current_depth(), leaf.as_ptr());
-                modified.push(self.current_depth(), left_sibling.as_ptr());
-                self.clear_stack(modified);
+            let buf = map.as_new_fixed_size_bytes();
+            let map1 = SBTreeMap::<u32, u64>::from_fixed_size_bytes(&buf);
 
-                return Some(v);
+            assert_eq!(len, map1.len());
+            assert_eq!(ptr, map1.root.as_ref().unwrap().as_ptr());
+            assert!(map1.verify().is_ok());
+
+            for i in 0..1000u32 {
+                assert_eq!(*map1.get(&i).unwrap(), i as u64);
             }
 
-            if let Some(mut right_sibling) =
-                parent.read_right_sibling::<LeafBTreeNode<K, V>>(parent_idx, parent_len)
-            {
-                let right_sibling_len = right_sibling.read_len();
+            let boxed = SBox::new(map).unwrap();
+            let map = boxed.into_inner();
+            assert_eq!(map.len(), len);
+        }
 
-                // if possible to steal - return early
-                if right_sibling_len > MIN_LEN_AFTER_SPLIT {
-                    self.steal_from_right_sibling_leaf(
-                        &mut leaf,
-                        &mut right_sibling,
-                        right_sibling_len,
-                        &mut parent,
-                        parent_idx,
-                    );
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                    // just idx, because after rotation leaf has one more key added to the end
-                    let v = leaf.remove_and_disown_by_idx(idx, B, &mut self._buf);
+    #[test]
+    fn drop_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                    if let Some((mut fin, i)) = found_internal_node {
-                        fin.write_key_buf(i, &leaf.read_key_buf(0));
-                    }
+        {
+            let unrelated = SBox::new(10u64).unwrap();
+            let size_before = get_allocated_size();
 
-                    modified.push(self.current_depth(), leaf.as_ptr());
-                    modified.push(self.current_depth(), right_sibling.as_ptr());
-                    self.clear_stack(modified);
+            {
+                let mut map = SBTreeMap::new();
 
-                    return Some(v);
+                for i in 0..500 {
+                    map.insert(
+                        SBox::new(generate_random_string(&mut thread_rng())).unwrap(),
+                        SBox::new(i).unwrap(),
+                    )
+                    .unwrap();
                 }
 
-                return self.merge_with_right_sibling_leaf(
-                    leaf,
-                    right_sibling,
-                    idx,
-                    found_internal_node,
-                    modified,
-                );
+                assert!(get_allocated_size() > size_before);
             }
 
-            return self.merge_with_left_sibling_leaf(leaf, left_sibling, idx, modified);
+            assert_eq!(get_allocated_size(), size_before);
+            assert_eq!(*unrelated, 10);
         }
 
-        if let Some(mut right_sibling) =
-            parent.read_right_sibling::<LeafBTreeNode<K, V>>(parent_idx, parent_len)
-        {
-            let right_sibling_len = right_sibling.read_len();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-            // if possible to steal - return early
-            if right_sibling_len > MIN_LEN_AFTER_SPLIT {
-                self.steal_from_right_sibling_leaf(
-                    &mut leaf,
-                    &mut right_sibling,
-                    right_sibling_len,
-                    &mut parent,
-                    parent_idx,
-                );
+    #[test]
+    fn clear_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                // just idx, because after rotation leaf has one more key added to the end
-                let v = leaf.remove_and_disown_by_idx(idx, B, &mut self._buf);
+        {
+            let mut map = SBTreeMap::<SBox<u64>, SBox<u64>>::default();
+            let empty_size = get_allocated_size();
+
+            for i in 0..500 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                    .unwrap();
+            }
 
-                if let Some((mut fin, i)) = found_internal_node {
-                    fin.write_key_buf(i, &leaf.read_key_buf(0));
-                }
+            assert!(get_allocated_size() > empty_size);
 
-                modified.push(self.current_depth(), leaf.as_ptr());
-                modified.push(self.current_depth(), right_sibling.as_ptr());
-                self.clear_stack(modified);
+            map.clear();
 
-                return Some(v);
-            }
+            assert!(map.is_empty());
+            assert!(map.iter().next().is_none());
+            assert_eq!(get_allocated_size(), empty_size);
 
-            return self.merge_with_right_sibling_leaf(
-                leaf,
-                right_sibling,
-                idx,
-                found_internal_node,
-                modified,
-            );
+            map.insert(SBox::new(1).unwrap(), SBox::new(1).unwrap())
+                .unwrap();
+            assert_eq!(**map.get(&1).unwrap(), 1);
+            assert!(map.verify().is_ok());
         }
 
-        unreachable!();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn merge_with_right_sibling_leaf(
-        &mut self,
-        mut leaf: LeafBTreeNode<K, V>,
-        right_sibling: LeafBTreeNode<K, V>,
-        idx: usize,
-        found_internal_node: Option<(InternalBTreeNode<K>, usize)>,
-        modified: &mut LeveledList,
-    ) -> Option<V> {
-        modified.remove(self.current_depth(), right_sibling.as_ptr());
-        modified.push(self.current_depth(), leaf.as_ptr());
+    #[derive(Debug)]
+    enum Action {
+        Insert,
+        Remove,
+        Clear,
+        CanisterUpgrade,
+    }
 
-        // otherwise merge with right
-        leaf.merge_min_len(right_sibling, &mut self._buf);
+    struct Fuzzer {
+        map: Option<SBTreeMap<SBox<String>, SBox<String>>>,
+        example: BTreeMap<String, String>,
+        keys: Vec<String>,
+        rng: ThreadRng,
+        log: Vec<Action>,
+    }
 
-        // just idx, because leaf keys stay unchanged
-        let v = leaf.remove_and_disown_by_idx(idx, CAPACITY - 1, &mut self._buf);
-        leaf.write_len(CAPACITY - 2);
+    impl Fuzzer {
+        fn new() -> Fuzzer {
+            Fuzzer {
+                map: Some(SBTreeMap::new()),
+                example: BTreeMap::new(),
+                keys: Vec::new(),
+                rng: thread_rng(),
+                log: Vec::new(),
+            }
+        }
 
-        if let Some((mut fin, i)) = found_internal_node {
-            fin.write_key_buf(i, &leaf.read_key_buf(0));
+        fn map(&mut self) -> &mut SBTreeMap<SBox<String>, SBox<String>> {
+            self.map.as_mut().unwrap()
         }
 
-        self.handle_stack_after_merge(true, leaf, modified);
+        fn next(&mut self) {
+            let action = self.rng.gen_range(0..101);
 
-        Some(v)
-    }
+            match action {
+                // INSERT ~60%
+                0..=59 => {
+                    let key = generate_random_string(&mut self.rng);
+                    let value = generate_random_string(&mut self.rng);
 
-    fn merge_with_left_sibling_leaf(
-        &mut self,
-        leaf: LeafBTreeNode<K, V>,
-        mut left_sibling: LeafBTreeNode<K, V>,
-        idx: usize,
-        modified: &mut LeveledList,
-    ) -> Option<V> {
-        modified.remove(self.current_depth(), leaf.as_ptr());
-        modified.push(self.current_depth(), left_sibling.as_ptr());
+                    if let Ok(key_data) = SBox::new(key.clone()) {
+                        if let Ok(val_data) = SBox::new(value.clone()) {
+                            if self.map().insert(key_data, val_data).is_err() {
+                                return;
+                            }
+                            self.example.insert(key.clone(), value);
 
-        // if there is no right sibling - merge with left
-        left_sibling.merge_min_len(leaf, &mut self._buf);
-        // idx + MIN_LEN_AFTER_SPLIT, because all keys of leaf are added to the
-        // end of left_sibling
-        let v = left_sibling.remove_and_disown_by_idx(
-            idx + MIN_LEN_AFTER_SPLIT,
-            CAPACITY - 1,
-            &mut self._buf,
-        );
-        left_sibling.write_len(CAPACITY - 2);
+                            match self.keys.binary_search(&key) {
+                                Ok(idx) => {}
+                                Err(idx) => {
+                                    self.keys.insert(idx, key);
+                                }
+                            };
 
-        // no reason to handle 'found_internal_node', because the key is
-        // guaranteed to be in the nearest parent and left_sibling keys are all
-        // continue to present
+                            self.log.push(Action::Insert);
+                        }
+                    }
+                }
+                // REMOVE
+                60..=89 => {
+                    let len = self.map().len();
 
-        self.handle_stack_after_merge(false, left_sibling, modified);
+                    if len == 0 {
+                        return self.next();
+                    }
 
-        Some(v)
-    }
+                    let idx: u64 = self.rng.gen_range(0..len);
+                    let key = self.keys.remove(idx as usize);
 
-    fn steal_from_left_sibling_leaf(
-        &mut self,
-        leaf: &mut LeafBTreeNode<K, V>,
-        left_sibling: &mut LeafBTreeNode<K, V>,
-        left_sibling_len: usize,
-        parent: &mut InternalBTreeNode<K>,
-        parent_idx: usize,
-    ) {
-        leaf.steal_from_left(
-            MIN_LEN_AFTER_SPLIT,
-            left_sibling,
-            left_sibling_len,
-            parent,
-            parent_idx,
-            None,
-            &mut self._buf,
-        );
+                    self.map().remove(&key).unwrap();
+                    self.example.remove(&key).unwrap();
 
-        left_sibling.write_len(left_sibling_len - 1);
-    }
+                    self.log.push(Action::Remove);
+                }
+                // CLEAR
+                90..=91 => {
+                    self.map().clear();
+                    self.example.clear();
 
-    fn steal_from_right_sibling_leaf(
-        &mut self,
-        leaf: &mut LeafBTreeNode<K, V>,
-        right_sibling: &mut LeafBTreeNode<K, V>,
-        right_sibling_len: usize,
-        parent: &mut InternalBTreeNode<K>,
-        parent_idx: usize,
-    ) {
-        leaf.steal_from_right(
-            MIN_LEN_AFTER_SPLIT,
-            right_sibling,
-            right_sibling_len,
-            parent,
-            parent_idx,
-            None,
-            &mut self._buf,
-        );
+                    self.keys.clear();
 
-        right_sibling.write_len(right_sibling_len - 1);
-    }
+                    self.log.push(Action::Clear);
+                }
+                // CANISTER UPGRADE
+                _ => match SBox::new(self.map.take().unwrap()) {
+                    Ok(data) => {
+                        store_custom_data(1, data);
 
-    fn handle_stack_after_merge(
-        &mut self,
-        mut merged_right: bool,
-        leaf: LeafBTreeNode<K, V>,
-        modified: &mut LeveledList,
-    ) {
-        let mut prev_node = BTreeNode::Leaf(leaf);
+                        if stable_memory_pre_upgrade().is_ok() {
+                            stable_memory_post_upgrade();
+                        }
 
-        while let Some((mut node, node_len, remove_idx)) = self.pop_stack() {
-            let (idx_to_remove, child_idx_to_remove) = if merged_right {
-                (remove_idx, remove_idx + 1)
-            } else {
-                (remove_idx - 1, remove_idx)
-            };
+                        self.map = retrieve_custom_data::<SBTreeMap<SBox<String>, SBox<String>>>(1)
+                            .map(|it| it.into_inner());
 
-            // if the node has enough keys, return early
-            if node_len > MIN_LEN_AFTER_SPLIT {
-                node.remove_key_buf(idx_to_remove, node_len, &mut self._buf);
-                node.remove_child_ptr_buf(child_idx_to_remove, node_len + 1, &mut self._buf);
-                node.write_len(node_len - 1);
+                        self.log.push(Action::CanisterUpgrade);
+                    }
+                    Err(map) => {
+                        self.map = Some(map);
+                    }
+                },
+            }
 
-                modified.push(self.current_depth(), node.as_ptr());
-                self.clear_stack(modified);
+            _debug_validate_allocator();
+            assert_eq!(self.map().len() as usize, self.example.len());
 
-                return;
+            // check random key
+            let seed: u32 = self.rng.gen();
+            let rand_key = self.map.as_ref().unwrap().get_random_key(seed);
+            if self.map.as_ref().unwrap().is_empty() {
+                assert!(rand_key.is_none());
+            } else {
+                assert!(rand_key.is_some());
             }
 
-            let stack_top_frame = self.peek_stack();
-
-            // if there is no parent, return early
-            if stack_top_frame.is_none() {
-                // if the root has only one key, make child the new root
-                if node_len == 1 {
-                    modified.remove_root();
+            // check consistency
+            for key in self.keys.clone() {
+                let contains = self.map().contains_key(&key);
+                assert!(contains);
 
-                    node.destroy();
-                    self.root = Some(prev_node);
+                assert_eq!(
+                    self.map().get(&key).unwrap().clone(),
+                    self.example.get(&key).unwrap().clone()
+                );
+            }
+        }
+    }
 
-                    return;
-                }
+    #[test]
+    fn get_or_insert_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                // otherwise simply remove and return
-                node.remove_key_buf(idx_to_remove, node_len, &mut self._buf);
-                node.remove_child_ptr_buf(child_idx_to_remove, node_len + 1, &mut self._buf);
-                node.write_len(node_len - 1);
+        {
+            let mut map = SBTreeMap::new();
 
-                modified.push(self.current_depth(), node.as_ptr());
+            for i in 0..300u64 {
+                let (mut v, inserted) =
+                    map.get_or_insert(SBox::new(i % 100).unwrap(), SBox::new(0u64).unwrap());
+                assert_eq!(inserted, i < 100);
 
-                return;
+                let new_v = SBox::new(**v + 1).unwrap();
+                *v = new_v;
             }
 
-            let (mut parent, parent_len, parent_idx) =
-                unsafe { stack_top_frame.unwrap_unchecked() };
-
-            if let Some(mut left_sibling) =
-                parent.read_left_sibling::<InternalBTreeNode<K>>(parent_idx)
-            {
-                let left_sibling_len = left_sibling.read_len();
+            assert_eq!(map.len(), 100);
 
-                // steal from left if it is possible
-                if left_sibling_len > MIN_LEN_AFTER_SPLIT {
-                    modified.push(self.current_depth(), node.as_ptr());
-                    modified.push(self.current_depth(), left_sibling.as_ptr());
+            for i in 0..100u64 {
+                assert_eq!(**map.get(&i).unwrap(), 3);
+            }
+        }
 
-                    self.steal_from_left_sibling_internal(
-                        node,
-                        node_len,
-                        idx_to_remove,
-                        child_idx_to_remove,
-                        left_sibling,
-                        left_sibling_len,
-                        parent,
-                        parent_idx,
-                    );
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                    self.clear_stack(modified);
+    #[test]
+    fn clone_range_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                    return;
-                }
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
 
-                if let Some(right_sibling) =
-                    parent.read_right_sibling::<InternalBTreeNode<K>>(parent_idx, parent_len)
-                {
-                    let right_sibling_len = right_sibling.read_len();
+            for i in 0..300u64 {
+                map.insert(i, i * 10).unwrap();
+            }
 
-                    // steal from right if it's possible
-                    if right_sibling_len > MIN_LEN_AFTER_SPLIT {
-                        modified.push(self.current_depth(), node.as_ptr());
-                        modified.push(self.current_depth(), right_sibling.as_ptr());
+            let shard = map
+                .clone_range(Bound::Excluded(&99), Bound::Included(&199))
+                .unwrap();
+            shard.verify().unwrap();
 
-                        self.steal_from_right_sibling_internal(
-                            node,
-                            node_len,
-                            idx_to_remove,
-                            child_idx_to_remove,
-                            right_sibling,
-                            right_sibling_len,
-                            parent,
-                            parent_idx,
-                        );
+            assert_eq!(shard.len(), 100);
+            assert_eq!(
+                shard.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+                (100..200).map(|i| (i, i * 10)).collect::<Vec<_>>()
+            );
 
-                        self.clear_stack(modified);
+            // the clone doesn't share nodes with the source
+            *map.get_mut(&150).unwrap() = 0;
+            map.remove(&151);
+            assert_eq!(*shard.get(&150).unwrap(), 1500);
+            assert_eq!(*shard.get(&151).unwrap(), 1510);
 
-                        return;
-                    }
+            let empty = map
+                .clone_range(Bound::Included(&500), Bound::Unbounded)
+                .unwrap();
+            assert!(empty.is_empty());
 
-                    // otherwise merge with right
-                    self.merge_with_right_sibling_internal(
-                        &mut node,
-                        idx_to_remove,
-                        child_idx_to_remove,
-                        right_sibling,
-                        &mut parent,
-                        parent_idx,
-                        modified,
-                    );
+            let all = map.clone_range(Bound::Unbounded, Bound::Unbounded).unwrap();
+            all.verify().unwrap();
+            assert_eq!(all.len(), 299);
+            assert_eq!(*all.get(&150).unwrap(), 0);
+        }
 
-                    merged_right = true;
-                    prev_node = BTreeNode::Internal(node);
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                    continue;
-                }
+    #[test]
+    fn clone_range_out_of_memory_works_fine() {
+        stable::clear();
+        init_allocator(1);
 
-                // otherwise merge with left
-                self.merge_with_left_sibling_internal(
-                    node,
-                    idx_to_remove,
-                    child_idx_to_remove,
-                    &mut left_sibling,
-                    &mut parent,
-                    parent_idx,
-                    modified,
-                );
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
 
-                merged_right = false;
-                prev_node = BTreeNode::Internal(left_sibling);
+            let mut i = 0;
+            while map.insert(i, i).is_ok() {
+                i += 1;
+            }
 
-                continue;
+            for j in 0..(i / 10) {
+                map.remove(&j);
             }
 
-            if let Some(right_sibling) =
-                parent.read_right_sibling::<InternalBTreeNode<K>>(parent_idx, parent_len)
-            {
-                let right_sibling_len = right_sibling.read_len();
+            let allocated = get_allocated_size();
 
-                // steal from right if it's possible
-                if right_sibling_len > MIN_LEN_AFTER_SPLIT {
-                    modified.push(self.current_depth(), node.as_ptr());
-                    modified.push(self.current_depth(), right_sibling.as_ptr());
+            assert!(map.clone_range(Bound::Unbounded, Bound::Unbounded).is_err());
+            assert_eq!(get_allocated_size(), allocated);
+            map.verify().unwrap();
+        }
 
-                    self.steal_from_right_sibling_internal(
-                        node,
-                        node_len,
-                        idx_to_remove,
-                        child_idx_to_remove,
-                        right_sibling,
-                        right_sibling_len,
-                        parent,
-                        parent_idx,
-                    );
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                    self.clear_stack(modified);
+    fn check_rank_select<const B: usize>() {
+        let mut map = SBTreeMap::<u64, u64, B>::with_branching_factor();
+        let mut keys = (0..500u64).collect::<Vec<_>>();
+        keys.shuffle(&mut thread_rng());
 
-                    return;
-                }
+        for k in keys.iter() {
+            map.insert(*k, *k * 10).unwrap();
+        }
+        map.verify().unwrap();
 
-                // otherwise merge with right
-                self.merge_with_right_sibling_internal(
-                    &mut node,
-                    idx_to_remove,
-                    child_idx_to_remove,
-                    right_sibling,
-                    &mut parent,
-                    parent_idx,
-                    modified,
-                );
+        for i in 0..500u64 {
+            assert_eq!(map.rank(&i), i);
 
-                merged_right = true;
-                prev_node = BTreeNode::Internal(node);
+            assert_eq!(map.select(i), Some((i, i * 10)));
+        }
+        assert_eq!(map.rank(&1000), 500);
+        assert!(map.select(500).is_none());
 
-                continue;
-            }
+        // remove every key, which is not a multiple of 3, in random order
+        for k in keys.iter().filter(|k| **k % 3 != 0) {
+            map.remove(k).unwrap();
         }
-    }
+        map.verify().unwrap();
 
-    fn steal_from_right_sibling_internal(
-        &mut self,
-        mut node: InternalBTreeNode<K>,
-        node_len: usize,
-        idx_to_remove: usize,
-        child_idx_to_remove: usize,
-        mut right_sibling: InternalBTreeNode<K>,
-        right_sibling_len: usize,
-        mut parent: InternalBTreeNode<K>,
-        parent_idx: usize,
-    ) {
-        node.steal_from_right(
-            node_len,
-            &mut right_sibling,
-            right_sibling_len,
-            &mut parent,
-            parent_idx,
-            None,
-            &mut self._buf,
-        );
-        right_sibling.write_len(right_sibling_len - 1);
-        node.remove_key_buf(idx_to_remove, B, &mut self._buf);
-        node.remove_child_ptr_buf(child_idx_to_remove, B + 1, &mut self._buf);
-    }
+        let left = (0..500u64).filter(|k| k % 3 == 0).collect::<Vec<_>>();
+        for (i, k) in left.iter().enumerate() {
+            assert_eq!(map.rank(k), i as u64);
+            assert_eq!(map.rank(&(k + 1)), i as u64 + 1);
+            assert_eq!(map.select(i as u64).unwrap().0, *k);
+        }
 
-    fn steal_from_left_sibling_internal(
-        &mut self,
-        mut node: InternalBTreeNode<K>,
-        node_len: usize,
-        idx_to_remove: usize,
-        child_idx_to_remove: usize,
-        mut left_sibling: InternalBTreeNode<K>,
-        left_sibling_len: usize,
-        mut parent: InternalBTreeNode<K>,
-        parent_idx: usize,
-    ) {
-        node.steal_from_left(
-            node_len,
-            &mut left_sibling,
-            left_sibling_len,
-            &mut parent,
-            parent_idx - 1,
-            None,
-            &mut self._buf,
-        );
-        left_sibling.write_len(left_sibling_len - 1);
-        node.remove_key_buf(idx_to_remove + 1, B, &mut self._buf);
-        node.remove_child_ptr_buf(child_idx_to_remove + 1, B + 1, &mut self._buf);
-    }
+        map.retain(|k, _| k % 2 == 0);
+        map.verify().unwrap();
 
-    fn merge_with_right_sibling_internal(
-        &mut self,
-        node: &mut InternalBTreeNode<K>,
-        idx_to_remove: usize,
-        child_idx_to_remove: usize,
-        right_sibling: InternalBTreeNode<K>,
-        parent: &mut InternalBTreeNode<K>,
-        parent_idx: usize,
-        modified: &mut LeveledList,
-    ) {
-        modified.remove(self.current_depth(), right_sibling.as_ptr());
-        modified.push(self.current_depth(), node.as_ptr());
+        for i in 0..map.len() {
+            let k = map.select(i).unwrap().0;
+            assert_eq!(k % 6, 0);
+            assert_eq!(map.rank(&k), i);
+        }
 
-        let mid_element = parent.read_key_buf(parent_idx);
-        node.merge_min_len(&mid_element, right_sibling, &mut self._buf);
-        node.remove_key_buf(idx_to_remove, CAPACITY, &mut self._buf);
-        node.remove_child_ptr_buf(child_idx_to_remove, CHILDREN_CAPACITY, &mut self._buf);
-        node.write_len(CAPACITY - 1);
+        let bulk = SBTreeMap::<u64, u64, B>::from_sorted_iter((0..500u64).map(|i| (i * 2, i)));
+        bulk.verify().unwrap();
+        assert_eq!(bulk.rank(&501), 251);
+        assert_eq!(bulk.select(250), Some((500, 250)));
     }
 
-    fn merge_with_left_sibling_internal(
-        &mut self,
-        node: InternalBTreeNode<K>,
-        idx_to_remove: usize,
-        child_idx_to_remove: usize,
-        left_sibling: &mut InternalBTreeNode<K>,
-        parent: &mut InternalBTreeNode<K>,
-        parent_idx: usize,
-        modified: &mut LeveledList,
-    ) {
-        modified.remove(self.current_depth(), node.as_ptr());
-        modified.push(self.current_depth(), left_sibling.as_ptr());
+    #[test]
+    fn rank_select_work_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        let mid_element = parent.read_key_buf(parent_idx - 1);
-        left_sibling.merge_min_len(&mid_element, node, &mut self._buf);
-        left_sibling.remove_key_buf(idx_to_remove + B, CAPACITY, &mut self._buf);
-        left_sibling.remove_child_ptr_buf(
-            child_idx_to_remove + B,
-            CHILDREN_CAPACITY,
-            &mut self._buf,
-        );
-        left_sibling.write_len(CAPACITY - 1);
-    }
+        {
+            check_rank_select::<DEFAULT_B>();
+            check_rank_select::<2>();
 
-    fn peek_stack(&self) -> Option<(InternalBTreeNode<K>, usize, usize)> {
-        self._stack
-            .last()
-            .map(|(n, l, i)| (unsafe { n.copy() }, *l, *i))
+            let empty = SBTreeMap::<u64, u64>::new();
+            assert_eq!(empty.rank(&10), 0);
+            assert!(empty.select(0).is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn get_or_create_root(&mut self) -> Result<BTreeNode<K, V>, OutOfMemory> {
-        match &self.root {
-            Some(r) => unsafe { Ok(r.copy()) },
-            None => {
-                let new_root = BTreeNode::<K, V>::Leaf(LeafBTreeNode::create(self.certified)?);
+    #[test]
+    fn retain_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                self.root = Some(new_root);
-                unsafe { Ok(self.root.as_ref().unwrap_unchecked().copy()) }
+        {
+            let mut map = SBTreeMap::<SBox<u64>, SBox<u64>>::new();
+
+            for i in 0..400u64 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i * 10).unwrap())
+                    .unwrap();
+            }
+
+            map.retain(|k, _| **k % 2 == 0);
+            map.verify().unwrap();
+
+            assert_eq!(map.len(), 200);
+            for (i, (k, v)) in map.iter().enumerate() {
+                assert_eq!(**k, i as u64 * 2);
+                assert_eq!(**v, i as u64 * 20);
             }
+
+            // removes whole leaves, so siblings get merged
+            map.retain(|k, _| **k < 20 || **k >= 380);
+            map.verify().unwrap();
+
+            assert_eq!(
+                map.iter().map(|(k, _)| **k).collect::<Vec<_>>(),
+                (0..20).chain(380..400).step_by(2).collect::<Vec<_>>()
+            );
+
+            map.retain(|_, _| false);
+            map.verify().unwrap();
+            assert!(map.is_empty());
+
+            map.retain(|_, _| unreachable!());
         }
-    }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> StableType
-    for SBTreeMap<K, V>
-{
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.stable_drop_flag = false;
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.stable_drop_flag = true;
-    }
+    #[test]
+    fn drain_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    #[inline]
-    fn should_stable_drop(&self) -> bool {
-        self.stable_drop_flag
-    }
+        {
+            let mut map = SBTreeMap::<u64, SBox<u64>>::new();
+            assert_eq!(map.drain().count(), 0);
 
-    unsafe fn stable_drop(&mut self) {
-        if self.root.is_none() {
-            return;
-        }
+            for i in 0..300u64 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
+            }
 
-        let mut nodes = vec![unsafe { self.root.take().unwrap_unchecked() }];
-        let mut new_nodes = Vec::new();
+            let mut drained = Vec::new();
+            for (k, v) in map.drain() {
+                assert_eq!(k, *v);
+                drained.push(v);
+            }
 
-        loop {
-            if nodes.is_empty() {
-                return;
+            assert!(map.is_empty());
+            assert!(map.get(&0).is_none());
+            assert_eq!(drained.len(), 300);
+            assert!(drained.iter().enumerate().all(|(i, v)| **v == i as u64));
+
+            for i in 0..300u64 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
             }
 
-            for _ in 0..nodes.len() {
-                match unsafe { nodes.pop().unwrap_unchecked() } {
-                    BTreeNode::Internal(internal) => {
-                        for j in 0..(internal.read_len() + 1) {
-                            let child_ptr_raw = internal.read_child_ptr_buf(j);
-                            let child_ptr = u64::from_fixed_size_bytes(&child_ptr_raw);
-                            let child = BTreeNode::<K, V>::from_ptr(child_ptr);
+            let mut drain = map.drain();
+            assert_eq!(drain.next().map(|(k, _)| k), Some(0));
 
-                            new_nodes.push(child);
-                        }
+            map.insert(1000, SBox::new(1000).unwrap()).unwrap();
+            drop(drain);
 
-                        internal.destroy();
-                    }
-                    BTreeNode::Leaf(mut leaf) => {
-                        for j in 0..leaf.read_len() {
-                            leaf.read_and_disown_key(j);
-                            leaf.read_and_disown_value(j);
-                        }
+            assert_eq!(map.len(), 1);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn first_last_key_value_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                        leaf.destroy();
-                    }
-                }
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
+            assert!(map.first_key_value().is_none());
+            assert!(map.last_key_value().is_none());
+
+            // a single-leaf root
+            map.insert(1000, 1).unwrap();
+            let (first, v) = map.first_key_value().unwrap();
+            assert_eq!((*first, *v), (1000, 1));
+            let (last, v) = map.last_key_value().unwrap();
+            assert_eq!((*last, *v), (1000, 1));
+            map.remove(&1000).unwrap();
+
+            let mut keys = (0..500u64).collect::<Vec<_>>();
+            keys.shuffle(&mut thread_rng());
+
+            for k in keys {
+                map.insert(k, k * 2).unwrap();
+
+                let (first, _) = map.first_key_value().unwrap();
+                let (last, _) = map.last_key_value().unwrap();
+                assert_eq!(*first, *map.iter().next().unwrap().0);
+                assert_eq!(*last, *map.iter().next_back().unwrap().0);
             }
 
-            nodes = new_nodes;
-            new_nodes = Vec::new();
-        }
-    }
-}
+            let (k, v) = map.first_key_value().unwrap();
+            assert_eq!((*k, *v), (0, 0));
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Drop
-    for SBTreeMap<K, V>
-{
-    fn drop(&mut self) {
-        if self.should_stable_drop() {
-            unsafe {
-                self.stable_drop();
+            let (k, v) = map.last_key_value().unwrap();
+            assert_eq!((*k, *v), (499, 998));
+
+            for k in 0..500u64 {
+                map.remove(&k);
             }
+
+            assert!(map.first_key_value().is_none());
+            assert!(map.last_key_value().is_none());
         }
-    }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSizeBytes + Debug>
-    SBTreeMap<K, V>
-{
-    pub fn debug_print_stack(&self) {
-        isoprint(&format!(
-            "STACK: {:?}",
-            self._stack
-                .iter()
-                .map(|(p, l, i)| (p.as_ptr(), *l, *i))
-                .collect::<Vec<_>>()
-        ));
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    pub fn debug_print(&self) {
-        if self.len == 0 {
-            isoprint("EMPTY BTREEMAP");
-            return;
-        }
+    #[test]
+    fn first_last_entry_mut_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        let mut level = Vec::new();
-        level.push(unsafe { self.root.as_ref().unwrap_unchecked().copy() });
+        {
+            let mut map = SBTreeMap::<u64, SBox<u64>>::new();
+            assert!(map.first_entry_mut().is_none());
+            assert!(map.last_entry_mut().is_none());
 
-        loop {
-            Self::print_level(&level);
+            for i in 0..300u64 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
+            }
 
-            let mut new_level = Vec::new();
-            for node in level {
-                if let BTreeNode::Internal(internal) = node {
-                    let c_len = internal.read_len() + 1;
-                    for i in 0..c_len {
-                        let c = BTreeNode::<K, V>::from_ptr(u64::from_fixed_size_bytes(
-                            &internal.read_child_ptr_buf(i),
-                        ));
-                        new_level.push(c);
-                    }
+            for _ in 0..3 {
+                {
+                    let (k, mut v) = map.first_entry_mut().unwrap();
+                    assert_eq!(k, 0);
+                    let new_v = SBox::new(**v + 1).unwrap();
+                    *v = new_v;
                 }
+
+                let (k, mut v) = map.last_entry_mut().unwrap();
+                assert_eq!(k, 299);
+                let new_v = SBox::new(**v + 1).unwrap();
+                *v = new_v;
             }
 
-            if new_level.is_empty() {
-                break;
-            } else {
-                level = new_level;
+            assert_eq!(**map.get(&0).unwrap(), 3);
+            assert_eq!(**map.get(&299).unwrap(), 302);
+
+            for i in 0..300u64 {
+                map.remove(&i);
             }
+
+            assert!(map.first_entry_mut().is_none());
+            assert!(map.last_entry_mut().is_none());
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn print_level(level: &Vec<BTreeNode<K, V>>) {
-        let mut result = String::new();
+    #[test]
+    fn floor_ceiling_work_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        for node in level {
-            result += &match node {
-                BTreeNode::Internal(i) => i.to_string(),
-                BTreeNode::Leaf(l) => l.to_string(),
+        {
+            let mut map = SBTreeMap::new();
+            assert!(map.floor(&10).is_none());
+            assert!(map.ceiling(&10).is_none());
+
+            // enough keys for several levels of nodes
+            for i in 1..=1000u64 {
+                map.insert(i * 10, i).unwrap();
+            }
+
+            for x in 0..10020u64 {
+                let floor = map.floor(&x).map(|(k, v)| (*k, *v));
+                let expected_floor = (x >= 10).then(|| (x / 10 * 10, x / 10).min((10000, 1000)));
+                assert_eq!(floor, expected_floor, "floor of {}", x);
+
+                let ceiling = map.ceiling(&x).map(|(k, v)| (*k, *v));
+                let expected_ceiling = (x <= 10000).then(|| {
+                    let k = x.div_ceil(10).max(1) * 10;
+                    (k, k / 10)
+                });
+                assert_eq!(ceiling, expected_ceiling, "ceiling of {}", x);
             }
         }
 
-        isoprint(&result);
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> Default
-    for SBTreeMap<K, V>
-{
-    fn default() -> Self {
-        Self::new()
-    }
-}
+    #[test]
+    fn iter_from_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes> AsFixedSizeBytes
-    for SBTreeMap<K, V>
-{
-    const SIZE: usize = u64::SIZE * 2;
-    type Buf = [u8; u64::SIZE * 2];
+        {
+            let mut map = SBTreeMap::new();
+            assert!(map.iter_from(&150).next().is_none());
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        let ptr = if let Some(root) = &self.root {
-            root.as_ptr()
-        } else {
-            EMPTY_PTR
-        };
+            for i in 0..300u64 {
+                map.insert(i, i).unwrap();
+            }
 
-        ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.len
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
-    }
+            let keys = map.iter_from(&150).map(|(k, _)| *k).collect::<Vec<_>>();
+            assert_eq!(keys, (150..300).collect::<Vec<_>>());
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
-        let len = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+            for start in [0, 1, 63, 64, 299] {
+                assert!(map.iter_from(&start).map(|(k, _)| *k).eq(start..300));
+            }
 
-        Self {
-            root: if ptr == EMPTY_PTR {
-                None
-            } else {
-                Some(BTreeNode::from_ptr(ptr))
-            },
-            certified: false,
-            len,
-            stable_drop_flag: false,
-            _buf: Vec::default(),
-            _stack: Vec::default(),
-        }
-    }
-}
+            assert!(map.iter_from(&300).next().is_none());
 
-pub(crate) enum LeveledList {
-    None,
-    Some((Vec<Vec<u64>>, usize)),
-}
+            let keys = map
+                .iter_from(&150)
+                .rev()
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, (0..150).rev().collect::<Vec<_>>());
+        }
 
-impl LeveledList {
-    pub(crate) fn new() -> Self {
-        Self::Some((vec![Vec::new()], 0))
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn is_some(&self) -> bool {
-        match self {
-            LeveledList::None => false,
-            _ => true,
-        }
-    }
+    #[test]
+    fn page_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    fn insert_root(&mut self, ptr: u64) {
-        match self {
-            LeveledList::None => {}
-            LeveledList::Some((v, max_level)) => {
-                let root = vec![ptr];
-                v.insert(0, root);
-                *max_level += 1;
-            }
-        }
-    }
+        {
+            let mut map = SBTreeMap::new();
+            assert_eq!(map.page(Bound::Unbounded, 10), (vec![], None));
 
-    fn remove_root(&mut self) {
-        match self {
-            LeveledList::None => {}
-            LeveledList::Some((v, max_level)) => {
-                v.remove(0);
-                *max_level -= 1;
+            for i in 0..1000u64 {
+                map.insert(i * 2, i).unwrap();
             }
-        }
-    }
 
-    fn push(&mut self, level: usize, ptr: u64) {
-        match self {
-            LeveledList::None => {}
-            LeveledList::Some((v, max_level)) => {
-                if level.gt(max_level) {
-                    *max_level = level;
+            let mut cursor = Bound::Unbounded;
+            let mut next;
+            let mut result = Vec::new();
 
-                    v.resize_with(level + 1, Vec::new);
-                }
+            loop {
+                let (page, c) = map.page(cursor, 7);
+                assert!(page.len() <= 7);
 
-                match v[level].binary_search(&ptr) {
-                    Ok(_) => {}
-                    Err(idx) => v[level].insert(idx, ptr),
-                };
-            }
-        }
-    }
+                result.extend(page);
 
-    fn remove(&mut self, level: usize, ptr: u64) {
-        match self {
-            LeveledList::None => {}
-            LeveledList::Some((v, _)) => {
-                if let Some(level_list) = v.get_mut(level) {
-                    if let Ok(idx) = level_list.binary_search(&ptr) {
-                        level_list.remove(idx);
-                    }
+                next = c;
+                match &next {
+                    Some(k) => cursor = Bound::Included(k),
+                    None => break,
                 }
             }
-        }
-    }
 
-    pub(crate) fn pop(&mut self) -> Option<u64> {
-        match self {
-            LeveledList::None => unreachable!(),
-            LeveledList::Some((v, max_level)) => {
-                let level_list = v.get_mut(*max_level)?;
-                let mut ptr = level_list.pop();
+            assert_eq!(result, (0..1000).map(|i| (i * 2, i)).collect::<Vec<_>>());
 
-                while ptr.is_none() {
-                    if *max_level == 0 {
-                        return None;
-                    }
+            let (page, cursor) = map.page(Bound::Included(&11), 3);
+            assert_eq!(page, vec![(12, 6), (14, 7), (16, 8)]);
+            assert_eq!(cursor, Some(18));
 
-                    *max_level -= 1;
+            let (page, cursor) = map.page(Bound::Excluded(&12), 3);
+            assert_eq!(page, vec![(14, 7), (16, 8), (18, 9)]);
+            assert_eq!(cursor, Some(20));
 
-                    ptr = v[*max_level].pop();
-                }
+            let (page, cursor) = map.page(Bound::Included(&1996), 0);
+            assert!(page.is_empty());
+            assert_eq!(cursor, Some(1996));
 
-                ptr
-            }
+            assert_eq!(map.page(Bound::Excluded(&1998), 10), (vec![], None));
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    pub(crate) fn debug_print(&self) {
-        match self {
-            LeveledList::None => isoprint("LeveledList [Dummy]"),
-            LeveledList::Some((v, max_level)) => {
-                let mut str = String::from("LeveledList [");
-                for i in 0..(*max_level + 1) {
-                    str += format!("{} - ({:?})", i, v[i]).as_str();
-                    if i < *max_level {
-                        str += ", ";
+    #[test]
+    fn range_works_fine() {
+        use std::ops::RangeBounds;
+
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
+            assert!(map
+                .range(Bound::Unbounded, Bound::Unbounded)
+                .next()
+                .is_none());
+            assert!(map
+                .range(Bound::Unbounded, Bound::Unbounded)
+                .next_back()
+                .is_none());
+
+            for i in 0..500u64 {
+                map.insert(i * 2, i).unwrap();
+            }
+
+            let bounds = |k: Option<u64>, included: bool| match k {
+                None => Bound::Unbounded,
+                Some(k) if included => Bound::Included(k),
+                Some(k) => Bound::Excluded(k),
+            };
+
+            let points = [
+                None,
+                Some(0),
+                Some(1),
+                Some(31),
+                Some(32),
+                Some(501),
+                Some(998),
+                Some(1000),
+            ];
+
+            for from in points {
+                for to in points {
+                    for (from_incl, to_incl) in
+                        [(true, true), (true, false), (false, true), (false, false)]
+                    {
+                        let from = bounds(from, from_incl);
+                        let to = bounds(to, to_incl);
+
+                        let expected = (0..500u64)
+                            .map(|i| i * 2)
+                            .filter(|k| (from, to).contains(k))
+                            .collect::<Vec<_>>();
+
+                        let forward = map
+                            .range(from, to)
+                            .map(|(k, v)| {
+                                assert_eq!(*v, *k / 2);
+                                *k
+                            })
+                            .collect::<Vec<_>>();
+                        assert_eq!(forward, expected, "{:?} {:?}", from, to);
+
+                        let mut backward = map
+                            .range(from, to)
+                            .rev()
+                            .map(|(k, _)| *k)
+                            .collect::<Vec<_>>();
+                        backward.reverse();
+                        assert_eq!(backward, expected, "{:?} {:?}", from, to);
                     }
                 }
-                str += "]";
-
-                isoprint(&str);
             }
+
+            let mut iter = map.range(Bound::Included(3), Bound::Included(5));
+            assert_eq!(*iter.next().unwrap().0, 4);
+            assert!(iter.next().is_none());
+            assert!(iter.next().is_none());
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Ord + Debug, V: StableType + AsFixedSizeBytes + Debug> Debug
-    for SBTreeMap<K, V>
-{
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("{")?;
+    #[test]
+    fn range_inclusive_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        for (idx, (k, v)) in self.iter().enumerate() {
-            k.fmt(f)?;
-            f.write_str(": ")?;
-            v.fmt(f)?;
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
 
-            if (idx as u64) < self.len() - 1 {
-                f.write_str(", ")?;
+            for i in 0..1000u64 {
+                map.insert(i, i).unwrap();
             }
+
+            let forward = map
+                .range(Bound::Included(100), Bound::Included(200))
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>();
+            assert_eq!(forward.len(), 101);
+            assert_eq!(forward, (100..=200).map(|i| (i, i)).collect::<Vec<_>>());
+
+            let backward = map
+                .range(Bound::Included(100), Bound::Included(200))
+                .rev()
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>();
+            assert_eq!(backward.len(), 101);
+            assert_eq!(
+                backward,
+                (100..=200).rev().map(|i| (i, i)).collect::<Vec<_>>()
+            );
         }
 
-        f.write_str("}")
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-pub(crate) trait IBTreeNode {
-    unsafe fn from_ptr(ptr: StablePtr) -> Self;
-    fn as_ptr(&self) -> StablePtr;
-    unsafe fn copy(&self) -> Self;
-}
+    #[test]
+    fn get_mut_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-pub(crate) enum BTreeNode<K, V> {
-    Internal(InternalBTreeNode<K>),
-    Leaf(LeafBTreeNode<K, V>),
-}
+        {
+            let mut map = SBTreeMap::<u64, [u8; 256]>::new();
+            assert!(map.get_mut(&0).is_none());
 
-impl<K, V> BTreeNode<K, V> {
-    pub(crate) fn from_ptr(ptr: StablePtr) -> Self {
-        let node_type: u8 =
-            unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(ptr, NODE_TYPE_OFFSET)) };
+            for i in 0..300u64 {
+                map.insert(i, [i as u8; 256]).unwrap();
+            }
+
+            for i in (0..300u64).step_by(3) {
+                let mut v = map.get_mut(&i).unwrap();
+                v[255] = !(i as u8);
+            }
 
-        unsafe {
-            match node_type {
-                NODE_TYPE_INTERNAL => Self::Internal(InternalBTreeNode::<K>::from_ptr(ptr)),
-                NODE_TYPE_LEAF => Self::Leaf(LeafBTreeNode::<K, V>::from_ptr(ptr)),
-                _ => unreachable!(),
+            assert!(map.get_mut(&300).is_none());
+
+            for i in 0..300u64 {
+                let v = map.get(&i).unwrap();
+                assert_eq!(v[0], i as u8);
+
+                if i % 3 == 0 {
+                    assert_eq!(v[255], !(i as u8));
+                } else {
+                    assert_eq!(v[255], i as u8);
+                }
             }
+
+            assert!(map.verify().is_ok());
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    pub(crate) fn as_ptr(&self) -> StablePtr {
-        match self {
-            Self::Internal(i) => i.as_ptr(),
-            Self::Leaf(l) => l.as_ptr(),
-        }
+    thread_local! {
+        static DECODES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
     }
 
-    pub(crate) unsafe fn copy(&self) -> Self {
-        match self {
-            Self::Internal(i) => Self::Internal(i.copy()),
-            Self::Leaf(l) => Self::Leaf(l.copy()),
+    #[derive(Debug, PartialEq)]
+    struct Counted([u8; 64]);
+
+    impl AsFixedSizeBytes for Counted {
+        const SIZE: usize = 64;
+        type Buf = [u8; 64];
+
+        fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+            buf.copy_from_slice(&self.0);
+        }
+
+        fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+            DECODES.with(|it| it.set(it.get() + 1));
+
+            Self(<[u8; 64]>::from_fixed_size_bytes(buf))
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::collections::btree_map::SBTreeMap;
-    use crate::utils::test::generate_random_string;
-    use crate::{
-        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
-        stable, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
-        store_custom_data, SBox,
-    };
-    use rand::rngs::ThreadRng;
-    use rand::seq::SliceRandom;
-    use rand::{thread_rng, Rng};
-    use std::collections::BTreeMap;
+    impl StableType for Counted {}
 
     #[test]
-    fn random_works_fine() {
+    fn get_reads_value_once() {
         stable::clear();
         stable_memory_init();
 
         {
-            let iterations = 1000;
-            let mut map = SBTreeMap::<u64, u64>::default();
+            let mut map = SBTreeMap::new();
 
-            let mut example = Vec::new();
-            for i in 0..iterations {
-                example.push(i as u64);
+            for i in 0..100u64 {
+                map.insert(i, Counted([i as u8; 64])).unwrap();
             }
-            example.shuffle(&mut thread_rng());
 
-            for i in 0..iterations {
-                map.debug_print_stack();
-                assert!(map._stack.is_empty());
-                assert!(map.insert(example[i], example[i]).unwrap().is_none());
+            DECODES.with(|it| it.set(0));
 
-                for j in 0..i {
-                    assert!(
-                        map.contains_key(&example[j]),
-                        "don't contain {}",
-                        example[j]
-                    );
-                    assert_eq!(
-                        *map.get(&example[j]).unwrap(),
-                        example[j],
-                        "unable to get {}",
-                        example[j]
-                    );
-                }
-            }
+            let v = map.get(&42).unwrap();
+            assert_eq!(DECODES.with(|it| it.get()), 0);
 
-            assert_eq!(map.insert(0, 1).unwrap().unwrap(), 0);
-            assert_eq!(map.insert(0, 0).unwrap().unwrap(), 1);
+            assert_eq!(*v, Counted([42; 64]));
+            assert_eq!(v.0[63], 42);
+            assert_eq!(DECODES.with(|it| it.get()), 1);
 
-            map.debug_print();
+            assert!(map.get(&100).is_none());
+        }
 
-            example.shuffle(&mut thread_rng());
-            for i in 0..iterations {
-                assert!(map._stack.is_empty());
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                assert_eq!(map.remove(&example[i]), Some(example[i]));
+    #[test]
+    fn get_mut_increments_counters() {
+        stable::clear();
+        stable_memory_init();
 
-                for j in (i + 1)..iterations {
-                    assert!(
-                        map.contains_key(&example[j]),
-                        "don't contain {}",
-                        example[j]
-                    );
-                    assert_eq!(
-                        *map.get(&example[j]).unwrap(),
-                        example[j],
-                        "unable to get {}",
-                        example[j]
-                    );
+        {
+            let mut map = SBTreeMap::<u64, u64>::new();
+
+            for i in 0..100u64 {
+                map.insert(i, 0).unwrap();
+            }
+
+            for _ in 0..3 {
+                for i in 0..100u64 {
+                    *map.get_mut(&i).unwrap() += i;
                 }
             }
+
+            for i in 0..100u64 {
+                assert_eq!(*map.get(&i).unwrap(), i * 3);
+            }
+
+            assert_eq!(map.len(), 100);
         }
 
         _debug_validate_allocator();
@@ -1933,31 +5333,57 @@ mod tests {
     }
 
     #[test]
-    fn iters_work_fine() {
+    fn entry_works_fine() {
+        use crate::collections::btree_map::entry::Entry;
+
         stable::clear();
         stable_memory_init();
 
         {
-            let mut map = SBTreeMap::<u64, u64>::default();
+            let mut map = SBTreeMap::<u64, SBox<String>>::new();
 
-            for i in 0..200 {
-                map.insert(i, i);
+            for i in 0..200u64 {
+                let v = map
+                    .entry(i)
+                    .or_insert_with(|| SBox::new(i.to_string()).unwrap());
+                assert_eq!(**v, i.to_string());
             }
 
-            let mut i = 0u64;
+            for i in 0..300u64 {
+                map.entry(i)
+                    .and_modify(|mut it| {
+                        it.with(|s| s.push('!')).unwrap();
+                    })
+                    .or_insert_with(|| SBox::new(String::from("new")).unwrap());
+            }
 
-            for (mut k, mut v) in map.iter() {
-                assert_eq!(i, *k);
-                assert_eq!(i, *v);
+            assert_eq!(map.len(), 300);
+            for i in 0..200u64 {
+                assert_eq!(**map.get(&i).unwrap(), format!("{}!", i));
+            }
+            for i in 200..300u64 {
+                assert_eq!(**map.get(&i).unwrap(), "new");
+            }
 
-                print!("({:?}, {:?}), ", *k, *v);
+            match map.entry(10) {
+                Entry::Occupied(mut e) => {
+                    assert_eq!(*e.key(), 10);
+                    assert_eq!(**e.get(), "10!");
 
-                i += 1;
+                    let prev = e.insert(SBox::new(String::from("replaced")).unwrap());
+                    assert_eq!(*prev, "10!");
+                    assert_eq!(*e.remove(), "replaced");
+                }
+                Entry::Vacant(_) => unreachable!(),
             }
 
-            println!();
+            match map.entry(10) {
+                Entry::Vacant(e) => assert_eq!(e.into_key(), 10),
+                Entry::Occupied(_) => unreachable!(),
+            }
 
-            assert_eq!(i, 200);
+            assert_eq!(map.len(), 299);
+            assert!(map.verify().is_ok());
         }
 
         _debug_validate_allocator();
@@ -1965,151 +5391,161 @@ mod tests {
     }
 
     #[test]
-    fn clear_works_fine() {
+    fn range_copy_works_fine() {
         stable::clear();
         stable_memory_init();
 
         {
-            let mut map = SBTreeMap::<SBox<u64>, SBox<u64>>::default();
+            let mut map = SBTreeMap::<u64, u64>::new();
+            assert!(map
+                .range_copy(Bound::Unbounded, Bound::Unbounded)
+                .is_empty());
 
-            for i in 0..500 {
-                map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
-                    .unwrap();
+            for i in 0..300u64 {
+                map.insert(i * 2, i).unwrap();
             }
 
-            map.clear();
+            let all = map.range_copy(Bound::Unbounded, Bound::Unbounded);
+            assert_eq!(all, (0..300).map(|i| (i * 2, i)).collect::<Vec<_>>());
+
+            let r = map.range_copy(Bound::Included(&100), Bound::Included(&120));
+            assert_eq!(r, (50..=60).map(|i| (i * 2, i)).collect::<Vec<_>>());
+
+            let r = map.range_copy(Bound::Excluded(&100), Bound::Excluded(&120));
+            assert_eq!(r, (51..60).map(|i| (i * 2, i)).collect::<Vec<_>>());
+
+            let r = map.range_copy(Bound::Included(&101), Bound::Included(&119));
+            assert_eq!(r, (51..60).map(|i| (i * 2, i)).collect::<Vec<_>>());
+
+            assert!(map
+                .range_copy(Bound::Included(&120), Bound::Excluded(&120))
+                .is_empty());
+            assert!(map
+                .range_copy(Bound::Excluded(&598), Bound::Unbounded)
+                .is_empty());
         }
 
         _debug_validate_allocator();
         assert_eq!(get_allocated_size(), 0);
     }
 
-    #[derive(Debug)]
-    enum Action {
-        Insert,
-        Remove,
-        Clear,
-        CanisterUpgrade,
-    }
-
-    struct Fuzzer {
-        map: Option<SBTreeMap<SBox<String>, SBox<String>>>,
-        example: BTreeMap<String, String>,
-        keys: Vec<String>,
-        rng: ThreadRng,
-        log: Vec<Action>,
-    }
+    #[test]
+    fn migrate_values_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    impl Fuzzer {
-        fn new() -> Fuzzer {
-            Fuzzer {
-                map: Some(SBTreeMap::new()),
-                example: BTreeMap::new(),
-                keys: Vec::new(),
-                rng: thread_rng(),
-                log: Vec::new(),
+        {
+            let mut map = SBTreeMap::new();
+            for i in 0..1000u64 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
             }
-        }
 
-        fn map(&mut self) -> &mut SBTreeMap<SBox<String>, SBox<String>> {
-            self.map.as_mut().unwrap()
-        }
-
-        fn next(&mut self) {
-            let action = self.rng.gen_range(0..101);
+            let map = map.migrate_values(|v| (*v, SBox::new(v.into_inner() * 2).unwrap()));
 
-            match action {
-                // INSERT ~60%
-                0..=59 => {
-                    let key = generate_random_string(&mut self.rng);
-                    let value = generate_random_string(&mut self.rng);
+            assert_eq!(map.len(), 1000);
+            map.verify().unwrap();
 
-                    if let Ok(key_data) = SBox::new(key.clone()) {
-                        if let Ok(val_data) = SBox::new(value.clone()) {
-                            if self.map().insert(key_data, val_data).is_err() {
-                                return;
-                            }
-                            self.example.insert(key.clone(), value);
+            for (k, v) in map.iter() {
+                assert_eq!(v.0, *k);
+                assert_eq!(*v.1, *k * 2);
+            }
 
-                            match self.keys.binary_search(&key) {
-                                Ok(idx) => {}
-                                Err(idx) => {
-                                    self.keys.insert(idx, key);
-                                }
-                            };
+            let map = SBTreeMap::<u64, u64>::new().migrate_values(|v| v as u32);
+            assert!(map.is_empty());
+        }
 
-                            self.log.push(Action::Insert);
-                        }
-                    }
-                }
-                // REMOVE
-                60..=89 => {
-                    let len = self.map().len();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                    if len == 0 {
-                        return self.next();
-                    }
+    #[test]
+    fn merge_sorted_slice_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-                    let idx: u64 = self.rng.gen_range(0..len);
-                    let key = self.keys.remove(idx as usize);
+        {
+            let mut map = SBTreeMap::new();
+            let mut example = BTreeMap::new();
 
-                    self.map().remove(&key).unwrap();
-                    self.example.remove(&key).unwrap();
+            for i in (0..1000u64).step_by(3) {
+                map.insert(i, i).unwrap();
+                example.insert(i, i);
+            }
 
-                    self.log.push(Action::Remove);
-                }
-                // CLEAR
-                90..=91 => {
-                    self.map().clear();
-                    self.example.clear();
+            let mut batch = Vec::new();
+            for i in (500..2000u64).step_by(2) {
+                batch.push((i, i * 10));
+                example.insert(i, i * 10);
+            }
 
-                    self.keys.clear();
+            map.merge_sorted_slice(&batch).unwrap();
+            map.verify().unwrap();
 
-                    self.log.push(Action::Clear);
-                }
-                // CANISTER UPGRADE
-                _ => match SBox::new(self.map.take().unwrap()) {
-                    Ok(data) => {
-                        store_custom_data(1, data);
+            assert_eq!(map.len() as usize, example.len());
+            for ((k1, v1), (k2, v2)) in map.iter().zip(example.iter()) {
+                assert_eq!(*k1, *k2);
+                assert_eq!(*v1, *v2);
+            }
 
-                        if stable_memory_pre_upgrade().is_ok() {
-                            stable_memory_post_upgrade();
-                        }
+            map.merge_sorted_slice(&[]).unwrap();
+            assert_eq!(map.len() as usize, example.len());
 
-                        self.map = retrieve_custom_data::<SBTreeMap<SBox<String>, SBox<String>>>(1)
-                            .map(|it| it.into_inner());
+            let mut empty = SBTreeMap::new();
+            empty.merge_sorted_slice(&batch).unwrap();
+            empty.verify().unwrap();
+            assert_eq!(empty.len() as usize, batch.len());
+        }
 
-                        self.log.push(Action::CanisterUpgrade);
-                    }
-                    Err(map) => {
-                        self.map = Some(map);
-                    }
-                },
-            }
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-            _debug_validate_allocator();
-            assert_eq!(self.map().len() as usize, self.example.len());
+    #[test]
+    fn merge_sorted_slice_visits_each_node_once() {
+        stable::clear();
+        stable_memory_init();
 
-            // check random key
-            let seed: u32 = self.rng.gen();
-            let rand_key = self.map.as_ref().unwrap().get_random_key(seed);
-            if self.map.as_ref().unwrap().is_empty() {
-                assert!(rand_key.is_none());
-            } else {
-                assert!(rand_key.is_some());
+        {
+            let mut map = SBTreeMap::new();
+            for i in 0..10_000u64 {
+                map.insert(i, i).unwrap();
             }
 
-            // check consistency
-            for key in self.keys.clone() {
-                let contains = self.map().contains_key(&key);
-                assert!(contains);
-
-                assert_eq!(
-                    self.map().get(&key).unwrap().clone(),
-                    self.example.get(&key).unwrap().clone()
-                );
-            }
+            let mut leaves = 0;
+            map.for_each_leaf(|_, _| leaves += 1);
+
+            // a single pair takes a single descent
+            let height = map._merge_sorted_slice(&[(5, 5)]).unwrap();
+
+            // pairs far apart don't walk the leaves in between
+            let visited = map._merge_sorted_slice(&[(0, 1), (1_000_000, 1)]).unwrap();
+            assert!(visited <= height * 2, "{} > {}", visited, height * 2);
+
+            // replacing every value visits each leaf once (moving to the next leaf re-reads its
+            // parent), instead of descending from the root for each key
+            let batch = (0..10_000u64).map(|i| (i, i * 2)).collect::<Vec<_>>();
+            let visited = map._merge_sorted_slice(&batch).unwrap();
+            assert!(visited <= leaves * 3, "{} > {}", visited, leaves * 3);
+            assert!(visited * 4 < batch.len() * height);
+
+            // new keys only start from the root again, when a leaf is split
+            let batch = (20_000..30_000u64).map(|i| (i, i)).collect::<Vec<_>>();
+            let visited = map._merge_sorted_slice(&batch).unwrap();
+            assert!(visited < batch.len(), "{} >= {}", visited, batch.len());
+
+            map.verify().unwrap();
+            assert_eq!(map.len(), 20_001);
+            assert_eq!(*map.get(&0).unwrap(), 0);
+            assert_eq!(*map.get(&9_999).unwrap(), 19_998);
+            assert_eq!(*map.get(&1_000_000).unwrap(), 1);
+            assert!(map
+                .range(Bound::Included(20_000), Bound::Excluded(30_000))
+                .map(|(k, v)| (*k, *v))
+                .eq(batch.into_iter()));
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
     #[test]
@@ -367,7 +367,10 @@ impl<
         while let Some(ptr) = self.modified.pop() {
             let mut node = BTreeNode::<K, V>::from_ptr(ptr);
             match &mut node {
-                BTreeNode::Internal(n) => n.commit::<V>(),
+                BTreeNode::Internal(n) => {
+                    SBTreeMap::<K, V>::count_children(n);
+                    n.commit::<V>()
+                }
                 BTreeNode::Leaf(n) => n.commit(),
             };
         }
@@ -1074,6 +1077,7 @@ mod tests {
                 }
 
                 map.commit();
+                map.inner.verify().unwrap();
 
                 for j in 0..((i + 1) * 100) {
                     let wit = map.witness_with(&example[j], |it| leaf(it.as_hashable_bytes()));
@@ -1092,6 +1096,7 @@ mod tests {
                 }
 
                 map.commit();
+                map.inner.verify().unwrap();
 
                 for j in ((i + 1) * 100)..(iterations * 100) {
                     let wit = map.witness_with(&example[j], |it| leaf(it.as_hashable_bytes()));
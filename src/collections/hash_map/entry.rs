@@ -0,0 +1,214 @@
+use crate::collections::hash_map::hasher::{StableHasherFactory, ZwoHasherFactory};
+use crate::collections::hash_map::SHashMap;
+use crate::encoding::{AsFixedSizeBytes, Buffer};
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use std::hash::Hash;
+
+/// A view into a single entry of an [SHashMap], which may either be vacant or occupied
+///
+/// Constructed by [SHashMap::entry].
+pub enum Entry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
+    /// There is a value stored by the key
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// There is no value stored by the key
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Entry<'a, K, V, S>
+{
+    /// Returns the key of this entry
+    #[inline]
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value of this entry, inserting the provided
+    /// default value first, if the entry is vacant
+    ///
+    /// If the entry is occupied, the default value is dropped.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    #[inline]
+    pub fn or_insert(self, default: V) -> SRefMut<'a, V> {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    /// Same as [Entry::or_insert], but the default value is only constructed, if the entry is vacant
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    #[inline]
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> SRefMut<'a, V> {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    /// Modifies the value of an occupied entry with the provided lambda, does nothing for a vacant one
+    ///
+    /// The lambda receives an [SRefMut], which writes the value back to stable memory, when it is
+    /// dropped at the end of the lambda.
+    #[inline]
+    pub fn and_modify<F: FnOnce(SRefMut<'_, V>)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+}
+
+/// A view into an occupied entry of an [SHashMap], see [Entry]
+pub struct OccupiedEntry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
+    pub(crate) map: &'a mut SHashMap<K, V, S>,
+    pub(crate) key: K,
+    pub(crate) idx: usize,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > OccupiedEntry<'a, K, V, S>
+{
+    /// Returns the key, which was passed to [SHashMap::entry]
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns an immutable reference [SRef] to the value of this entry
+    #[inline]
+    pub fn get(&self) -> SRef<'_, V> {
+        self.map.get_val(self.idx)
+    }
+
+    /// Returns a mutable reference [SRefMut] to the value of this entry
+    #[inline]
+    pub fn get_mut(&mut self) -> SRefMut<'_, V> {
+        self.map.get_val_mut(self.idx)
+    }
+
+    /// Converts this entry into a mutable reference [SRefMut] to its value, bound to the map
+    #[inline]
+    pub fn into_mut(self) -> SRefMut<'a, V> {
+        self.map.get_val_mut(self.idx)
+    }
+
+    /// Replaces the value of this entry, returning the previous one
+    #[inline]
+    pub fn insert(&mut self, value: V) -> V {
+        let prev = self.map.read_and_disown_val(self.idx);
+        self.map.write_and_own_val(self.idx, value);
+
+        prev
+    }
+
+    /// Removes this entry from the map, returning its value
+    ///
+    /// Just like [SHashMap::remove], may shrink the table, if auto-shrink is enabled.
+    #[inline]
+    pub fn remove(self) -> V {
+        let value = self.map.remove_by_idx(self.idx);
+        self.map.auto_shrink_if_needed();
+
+        value
+    }
+}
+
+/// A view into a vacant entry of an [SHashMap], see [Entry]
+pub struct VacantEntry<
+    'a,
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
+    pub(crate) map: &'a mut SHashMap<K, V, S>,
+    pub(crate) key: K,
+    pub(crate) key_hash: usize,
+    // the slot, where the key should be inserted, or [None] if the table is not allocated yet
+    pub(crate) idx: Option<usize>,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > VacantEntry<'a, K, V, S>
+{
+    /// Returns the key, which was passed to [SHashMap::entry]
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the key back, without inserting anything
+    #[inline]
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts the value by the key of this entry, returning a mutable reference [SRefMut] to it
+    ///
+    /// If there is room in the table, the pair is written right into the slot found by
+    /// [SHashMap::entry]. Otherwise the table is allocated or grown, which moves all the entries, so
+    /// the key is probed again in the new table.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    pub fn insert(self, value: V) -> SRefMut<'a, V> {
+        match self.idx {
+            Some(idx) if !self.map.is_full() => {
+                self.map.occupy(idx, self.key, value);
+
+                self.map.get_val_mut(idx)
+            }
+            _ => {
+                let key_buf = self.key.as_new_fixed_size_bytes();
+                if self
+                    .map
+                    .insert_with_hash(self.key_hash, self.key, value)
+                    .is_err()
+                {
+                    panic!("Out of memory");
+                }
+
+                let mut key = K::from_fixed_size_bytes(key_buf._deref());
+                unsafe { key.stable_drop_flag_off() };
+
+                let idx = self.map.probe(self.key_hash, &key).unwrap();
+
+                self.map.get_val_mut(idx)
+            }
+        }
+    }
+}
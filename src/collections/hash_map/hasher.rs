@@ -0,0 +1,201 @@
+use crate::encoding::AsFixedSizeBytes;
+use std::hash::{BuildHasher, Hasher};
+use zwohash::ZwoHasher;
+
+/// Produces hashers, which an [SHashMap](crate::collections::SHashMap) uses to hash its keys
+///
+/// Positions of entries in the table depend on hashes of their keys, so hashers have to be
+/// deterministic - the same key should always produce the same hash, even after a canister upgrade.
+/// This is why the factory is persisted together with the header of the map, using
+/// [AsFixedSizeBytes]. Any state the factory has (e.g. a seed) should be stored there.
+///
+/// Implemented automatically for any [BuildHasher], which is also [AsFixedSizeBytes] and [Clone].
+pub trait StableHasherFactory: BuildHasher + AsFixedSizeBytes + Clone {}
+
+impl<T: BuildHasher + AsFixedSizeBytes + Clone> StableHasherFactory for T {}
+
+/// The default [StableHasherFactory], producing [zwohash](https://github.com/jix/zwohash) hashers
+///
+/// Fast and deterministic, but not resistant to hash flooding - use [SipHasherFactory] with a
+/// random seed, if keys can be chosen by an adversary. Has no state, so takes no space in the
+/// header of a map.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ZwoHasherFactory;
+
+impl BuildHasher for ZwoHasherFactory {
+    type Hasher = ZwoHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> Self::Hasher {
+        ZwoHasher::default()
+    }
+}
+
+impl AsFixedSizeBytes for ZwoHasherFactory {
+    const SIZE: usize = 0;
+    type Buf = [u8; 0];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, _: &mut [u8]) {}
+
+    #[inline]
+    fn from_fixed_size_bytes(_: &[u8]) -> Self {
+        Self
+    }
+}
+
+/// [StableHasherFactory], producing SipHash-2-4 hashers with a 128-bit key
+///
+/// Resistant to hash flooding, as long as the key is secret - generate it randomly once per
+/// canister (e.g. with `raw_rand`). The key is persisted together with the header of a map.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::hash_map::hasher::SipHasherFactory;
+/// # use ic_stable_memory::collections::SHashMap;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let seed = [42u8; 16]; // use a random one instead
+/// let mut map = SHashMap::<u64, u64, _>::with_hasher(SipHasherFactory::new(seed));
+///
+/// map.insert(1, 10).expect("Out of memory");
+/// assert_eq!(*map.get(&1).unwrap(), 10);
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SipHasherFactory {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipHasherFactory {
+    /// Creates a new factory, which uses the provided seed as the SipHash key
+    #[inline]
+    pub fn new(seed: [u8; 16]) -> Self {
+        Self {
+            k0: u64::from_le_bytes(seed[0..8].try_into().unwrap()),
+            k1: u64::from_le_bytes(seed[8..16].try_into().unwrap()),
+        }
+    }
+}
+
+impl BuildHasher for SipHasherFactory {
+    #[allow(deprecated)]
+    type Hasher = std::hash::SipHasher;
+
+    // std::hash::SipHasher is deprecated only in favor of hashers with randomized keys, but the key
+    // is exactly what has to stay the same here
+    #[allow(deprecated)]
+    #[inline]
+    fn build_hasher(&self) -> Self::Hasher {
+        std::hash::SipHasher::new_with_keys(self.k0, self.k1)
+    }
+}
+
+impl AsFixedSizeBytes for SipHasherFactory {
+    const SIZE: usize = u64::SIZE * 2;
+    type Buf = [u8; u64::SIZE * 2];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.k0.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.k1
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        Self {
+            k0: u64::from_fixed_size_bytes(&arr[0..u64::SIZE]),
+            k1: u64::from_fixed_size_bytes(&arr[u64::SIZE..(u64::SIZE * 2)]),
+        }
+    }
+}
+
+/// [StableHasherFactory], producing [IdentityHasher]s - for integer keys, which are already well
+/// distributed (e.g. random ids)
+///
+/// Hashing costs nothing, but sequential keys hash to sequential slots, so it is better used together
+/// with [IndexingScheme::Fibonacci](crate::collections::hash_map::IndexingScheme::Fibonacci), which
+/// mixes the bits of the hash.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct IdentityHasherFactory;
+
+impl BuildHasher for IdentityHasherFactory {
+    type Hasher = IdentityHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> Self::Hasher {
+        IdentityHasher::default()
+    }
+}
+
+impl AsFixedSizeBytes for IdentityHasherFactory {
+    const SIZE: usize = 0;
+    type Buf = [u8; 0];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, _: &mut [u8]) {}
+
+    #[inline]
+    fn from_fixed_size_bytes(_: &[u8]) -> Self {
+        Self
+    }
+}
+
+/// Hasher, which returns the integer written into it as is
+///
+/// If arbitrary bytes are written, they are folded into the hash 8 at a time (little-endian).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut buf = [0u8; 8];
+            buf[..chunk.len()].copy_from_slice(chunk);
+
+            self.0 = self.0.rotate_left(8) ^ u64::from_le_bytes(buf);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.0 = i as u64;
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.0 = i as u64;
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.0 = i as u64;
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.0 = i as u64;
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.0 = i as u64;
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        self.0 = i as u64;
+    }
+}
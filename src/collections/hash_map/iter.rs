@@ -1,3 +1,4 @@
+use crate::collections::hash_map::hasher::{StableHasherFactory, ZwoHasherFactory};
 use crate::collections::hash_map::SHashMap;
 use crate::encoding::AsFixedSizeBytes;
 use crate::primitive::s_ref::SRef;
@@ -8,21 +9,30 @@ pub struct SHashMapIter<
     'a,
     K: StableType + AsFixedSizeBytes + Hash + Eq,
     V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
 > {
-    map: &'a SHashMap<K, V>,
+    map: &'a SHashMap<K, V, S>,
     i: usize,
 }
 
-impl<'a, K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
-    SHashMapIter<'a, K, V>
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > SHashMapIter<'a, K, V, S>
 {
-    pub fn new(map: &'a SHashMap<K, V>) -> Self {
+    pub fn new(map: &'a SHashMap<K, V, S>) -> Self {
         Self { map, i: 0 }
     }
 }
 
-impl<'a, K: StableType + AsFixedSizeBytes + Eq + Hash, V: StableType + AsFixedSizeBytes> Iterator
-    for SHashMapIter<'a, K, V>
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Eq + Hash,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Iterator for SHashMapIter<'a, K, V, S>
 {
     type Item = (SRef<'a, K>, SRef<'a, V>);
 
@@ -48,3 +58,119 @@ impl<'a, K: StableType + AsFixedSizeBytes + Eq + Hash, V: StableType + AsFixedSi
         }
     }
 }
+
+pub struct SHashMapIntoIter<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
+    map: Option<SHashMap<K, V, S>>,
+    i: usize,
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > SHashMapIntoIter<K, V, S>
+{
+    pub fn new(map: SHashMap<K, V, S>) -> Self {
+        Self {
+            map: Some(map),
+            i: 0,
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Eq + Hash,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Iterator for SHashMapIntoIter<K, V, S>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.map.as_mut()?;
+
+        while !map.is_empty() {
+            if let Some(k) = map.take_by_idx(self.i) {
+                self.i += 1;
+
+                return Some(k);
+            }
+
+            self.i += 1;
+        }
+
+        // the table is released as soon as the last entry is taken out of it
+        self.map = None;
+
+        None
+    }
+}
+
+pub struct SHashMapDrain<
+    'a,
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
+    map: &'a mut SHashMap<K, V, S>,
+    i: usize,
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > SHashMapDrain<'a, K, V, S>
+{
+    pub fn new(map: &'a mut SHashMap<K, V, S>) -> Self {
+        Self { map, i: 0 }
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Eq + Hash,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Iterator for SHashMapDrain<'a, K, V, S>
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.map.is_empty() {
+            let entry = self.map.take_by_idx(self.i);
+            self.i += 1;
+
+            if entry.is_some() {
+                return entry;
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.map.len(), Some(self.map.len()))
+    }
+}
+
+impl<
+        'a,
+        K: StableType + AsFixedSizeBytes + Eq + Hash,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Drop for SHashMapDrain<'a, K, V, S>
+{
+    fn drop(&mut self) {
+        // entries, which were not taken, are dropped, releasing their stable memory
+        for _ in self.by_ref() {}
+
+        // gets rid of tombstones, if there are any
+        self.map.clear();
+    }
+}
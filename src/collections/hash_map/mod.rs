@@ -1,4 +1,6 @@
-use crate::collections::hash_map::iter::SHashMapIter;
+use crate::collections::hash_map::entry::{Entry, OccupiedEntry, VacantEntry};
+use crate::collections::hash_map::hasher::{StableHasherFactory, ZwoHasherFactory};
+use crate::collections::hash_map::iter::{SHashMapDrain, SHashMapIntoIter, SHashMapIter};
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::mem::allocator::EMPTY_PTR;
 use crate::mem::StablePtr;
@@ -9,10 +11,11 @@ use crate::utils::DebuglessUnwrap;
 use crate::{allocate, deallocate, OutOfMemory, SSlice};
 use std::borrow::Borrow;
 use std::fmt::{Debug, Formatter};
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
-use zwohash::ZwoHasher;
 
+pub mod entry;
+pub mod hasher;
 #[doc(hidden)]
 pub mod iter;
 
@@ -28,29 +31,114 @@ const fn values_offset<K: AsFixedSizeBytes>(capacity: usize) -> usize {
 }
 
 const DEFAULT_CAPACITY: usize = 7;
+const DEFAULT_FIBONACCI_CAPACITY: usize = 8;
+
+// capacities of tables with prime indexing and linear probing - the smallest primes, which are
+// greater than or equal to `2^(i/4)`, so keys which share a common factor (e.g. multiples of 5)
+// don't pile up in a fraction of slots, like they do with composite capacities, while a requested
+// capacity is never overshot by more than ~20%
+const PRIME_CAPACITIES: [u32; 111] = [
+    7, 11, 13, 17, 23, 29, 37, 41, 47, 59, 67, 79, 97, 109, 131, 157, 191, 223, 257, 307, 367, 431,
+    521, 613, 727, 863, 1031, 1223, 1451, 1723, 2053, 2437, 2897, 3449, 4099, 4871, 5801, 6899,
+    8209, 9743, 11587, 13781, 16411, 19489, 23173, 27581, 32771, 38971, 46349, 55109, 65537, 77951,
+    92683, 110221, 131101, 155887, 185369, 220447, 262147, 311747, 370759, 440893, 524309, 623521,
+    741457, 881779, 1048583, 1246997, 1482919, 1763491, 2097169, 2493949, 2965847, 3526987,
+    4194319, 4987901, 5931649, 7053971, 8388617, 9975803, 11863289, 14107921, 16777259, 19951597,
+    23726569, 28215809, 33554467, 39903197, 47453149, 56431657, 67108879, 79806341, 94906297,
+    112863217, 134217757, 159612679, 189812533, 225726419, 268435459, 319225391, 379625083,
+    451452839, 536870923, 638450719, 759250133, 902905657, 1073741827, 1276901429, 1518500279,
+    1805811341, 2147483659,
+];
+
+// 2^64 / golden ratio
+const FIBONACCI_MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
 
 const EMPTY: u8 = 0;
+const DELETED: u8 = 1;
 const OCCUPIED: u8 = 255;
 
+// the auto-shrink flag, the indexing scheme and the probe sequence are persisted in the four
+// highest bits of the encoded capacity, which are never used by the capacity itself
+const AUTO_SHRINK_BIT: usize = 1 << (usize::BITS - 1);
+const FIBONACCI_BIT: usize = 1 << (usize::BITS - 2);
+const QUADRATIC_BIT: usize = 1 << (usize::BITS - 3);
+const PRIME_BIT: usize = 1 << (usize::BITS - 4);
+
 type KeyHash = usize;
 
-/// Reallocating, open addressing, linear probing, eager removes hash map
+/// Defines how an [SHashMap] maps hashes of its keys to positions in its table
+///
+/// Entries are placed in the table according to the scheme, so it is persisted together with the
+/// map and can't be changed afterwards.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum IndexingScheme {
+    /// `hash % capacity`, with capacities `7, 13, 25, ...` (each next one is `2 * capacity - 1`)
+    #[default]
+    Modulo,
+    /// `hash % capacity`, with prime capacities `7, 13, 29, 59, ...` (each next one is the smallest
+    /// tabulated prime, which is greater than or equal to `2 * capacity - 1`)
+    ///
+    /// Keys, which hashes share a common factor with a composite capacity (e.g. multiples of `5` with
+    /// an identity hasher), only land in a fraction of slots of an [IndexingScheme::Modulo] table.
+    /// Prime capacities avoid that, at the cost of overshooting the requested capacity by up to ~20%.
+    Prime,
+    /// Fibonacci hashing - the hash is multiplied by `2^64 / φ` and the highest `log2(capacity)`
+    /// bits of the product are taken, with power-of-two capacities `8, 16, 32, ...`
+    ///
+    /// Mixes all the bits of the hash, so it spreads keys more evenly than [IndexingScheme::Modulo]
+    /// (especially for small capacities), resulting in shorter probe sequences.
+    Fibonacci,
+}
+
+/// Defines which slots an [SHashMap] checks, when the slot its key hash points to is already taken
+///
+/// Persisted together with the map. Can be changed with [SHashMap::set_probe], which rehashes
+/// the whole table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ProbeSequence {
+    /// Slots are checked one after another: `home, home + 1, home + 2, ...`
+    ///
+    /// Cache-friendly and allows eager removes, but long runs of occupied slots (primary
+    /// clustering) form at high load.
+    #[default]
+    Linear,
+    /// Slots are checked with triangular offsets: `home, home + 1, home + 3, home + 6, ...`
+    ///
+    /// Avoids primary clustering, but removed entries leave tombstones behind, which are only
+    /// cleaned up, when the table is rehashed. Forces power-of-two capacities `8, 16, 32, ...`,
+    /// so the sequence visits every slot of the table. The table is also 8 bytes bigger, since the
+    /// number of tombstones is stored right after the entries.
+    Quadratic,
+}
+
+/// Reallocating, open addressing, eager removes hash map
 ///
 /// Conceptually the same thing as [std::collections::HashMap], but with a couple of twists:
 /// 1. [zwohash](https://github.com/jix/zwohash) is used, instead of `SipHash`, to make hashes faster
 /// and deterministic between canister upgrades.
-/// 2. eager removes (no tombstones) are performed in order to prevent performance degradation.
+/// 2. with the default [ProbeSequence::Linear], eager removes (no tombstones) are performed in
+///    order to prevent performance degradation.
 ///
 /// This is a "finite" data structure - it can only handle up to [u32::MAX] / `(1 + K::SIZE + V::SIZE)`
 /// elements total. Putting more elements inside will panic.
 ///
 /// Both `K` and `V` have to implement [StableType] and [AsFixedSizeBytes] traits. [SHashMap] also
 /// implements these traits itself, so you can nest it inside other stable structures.
-pub struct SHashMap<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
-{
+///
+/// Keys are hashed with hashers produced by `S` (see [StableHasherFactory]), which is
+/// [ZwoHasherFactory] by default. Use [SHashMap::with_hasher] to create a map with another one.
+pub struct SHashMap<
+    K: StableType + AsFixedSizeBytes + Hash + Eq,
+    V: StableType + AsFixedSizeBytes,
+    S: StableHasherFactory = ZwoHasherFactory,
+> {
     table_ptr: u64,
     len: usize,
     cap: usize,
+    auto_shrink: bool,
+    scheme: IndexingScheme,
+    probe: ProbeSequence,
+    hasher: S,
     stable_drop_flag: bool,
     _marker_k: PhantomData<K>,
     _marker_v: PhantomData<V>,
@@ -74,21 +162,36 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     /// ```
     #[inline]
     pub fn new() -> Self {
-        Self {
-            table_ptr: EMPTY_PTR,
-            len: 0,
-            cap: DEFAULT_CAPACITY,
-            stable_drop_flag: true,
-            _marker_k: PhantomData::default(),
-            _marker_v: PhantomData::default(),
-        }
+        Self::new_with_scheme(IndexingScheme::Modulo)
+    }
+
+    /// Creates a new [SHashMap] of default capacity, which uses the provided [IndexingScheme]
+    ///
+    /// Does not allocate any heap or stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::hash_map::IndexingScheme;
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u64, u64>::new_with_scheme(IndexingScheme::Fibonacci);
+    ///
+    /// map.insert(1, 10).expect("Out of memory");
+    /// assert_eq!(map.capacity(), 8);
+    /// ```
+    #[inline]
+    pub fn new_with_scheme(scheme: IndexingScheme) -> Self {
+        Self::with_scheme_and_hasher(scheme, ZwoHasherFactory)
     }
 
-    /// Creates a [SHashMap] of requested capacity.
+    /// Creates a [SHashMap], which can hold the requested number of entries without reallocating.
     ///
     /// Does allocate stable memory, returning [OutOfMemory] if there is not enough of it.
     /// If this function returns [Ok], you are guaranteed to have enough stable memory to store at
-    /// least `capacity` entries in it.
+    /// least `capacity` entries in it. Since the table is never filled more than 3/4, the
+    /// resulting [SHashMap::capacity] (the number of slots in the table) is bigger than `capacity`.
     ///
     /// # Example
     /// ```rust
@@ -98,11 +201,199 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     /// # stable_memory_init();
     /// let mut at_least_10_number_pairs = SHashMap::<u64, u64>::new_with_capacity(10)
     ///     .expect("Out of memory");
+    ///
+    /// for i in 0..10 {
+    ///     at_least_10_number_pairs.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// // the table was not reallocated
+    /// assert_eq!(at_least_10_number_pairs.capacity(), 16);
     /// ```
+    #[inline]
     pub fn new_with_capacity(capacity: usize) -> Result<Self, OutOfMemory> {
+        Self::new_with_capacity_and_scheme(capacity, IndexingScheme::Modulo)
+    }
+
+    /// Creates a [SHashMap], which can hold the requested number of entries without reallocating and
+    /// which uses the provided [IndexingScheme]
+    ///
+    /// Same as [SHashMap::new_with_capacity], but for [IndexingScheme::Fibonacci] the capacity is
+    /// rounded up to the next power of two and for [IndexingScheme::Prime] - to the next prime.
+    #[inline]
+    pub fn new_with_capacity_and_scheme(
+        capacity: usize,
+        scheme: IndexingScheme,
+    ) -> Result<Self, OutOfMemory> {
+        Self::with_table_capacity(
+            Self::min_capacity_for(capacity).max(DEFAULT_CAPACITY),
+            scheme,
+            ProbeSequence::Linear,
+            ZwoHasherFactory,
+        )
+    }
+
+    /// Creates a [SHashMap] from its raw parts: a pointer to the table, the length and the capacity
+    ///
+    /// This is a low-level escape hatch, which allows passing the backing table of a map between
+    /// different versions of code without reserializing it. See also [SHashMap::into_raw_parts].
+    ///
+    /// The returned [SHashMap] owns the table and will stable-drop it automatically.
+    ///
+    /// # Safety
+    /// The following invariants must hold, otherwise the behavior is undefined:
+    /// 1. `table_ptr` is either [EMPTY_PTR](crate::mem::allocator::EMPTY_PTR) (for a map which
+    ///    has not allocated yet), or points to a valid allocated [SSlice] of at least
+    ///    `(1 + K::SIZE + V::SIZE) * cap` bytes, which was previously owned by an [SHashMap] with the
+    ///    same `K` and `V` types and the same `cap`;
+    /// 2. `len` is equal to the number of occupied entries in this table;
+    /// 3. there is no other owner of this table.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u64, u64>::new();
+    /// map.insert(1, 10).expect("Out of memory");
+    ///
+    /// let (table_ptr, len, cap) = map.into_raw_parts();
+    ///
+    /// let map = unsafe { SHashMap::<u64, u64>::from_raw_parts(table_ptr, len, cap) };
+    /// assert_eq!(*map.get(&1).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub unsafe fn from_raw_parts(table_ptr: u64, len: usize, cap: usize) -> Self {
+        Self {
+            table_ptr,
+            len,
+            cap,
+            auto_shrink: false,
+            scheme: IndexingScheme::Modulo,
+            probe: ProbeSequence::Linear,
+            hasher: ZwoHasherFactory,
+            stable_drop_flag: true,
+            _marker_k: PhantomData,
+            _marker_v: PhantomData,
+        }
+    }
+
+    /// Decomposes this [SHashMap] into its raw parts: a pointer to the table, the length and the capacity
+    ///
+    /// The table is not released - it is up to the caller to restore the map later using
+    /// [SHashMap::from_raw_parts], otherwise its stable memory will leak.
+    ///
+    /// # Panics
+    /// Panics if this map uses [IndexingScheme::Fibonacci] or [ProbeSequence::Quadratic], since raw
+    /// parts can only describe maps with the default indexing scheme and probe sequence.
+    #[inline]
+    pub fn into_raw_parts(mut self) -> (u64, usize, usize) {
+        assert_eq!(
+            self.scheme,
+            IndexingScheme::Modulo,
+            "Only maps with the default indexing scheme can be decomposed into raw parts"
+        );
+        assert_eq!(
+            self.probe,
+            ProbeSequence::Linear,
+            "Only maps with the default probe sequence can be decomposed into raw parts"
+        );
+
+        unsafe { self.stable_drop_flag_off() };
+
+        (self.table_ptr, self.len, self.cap)
+    }
+
+    /// Returns the hash of the key, the same way an [SHashMap] with the default hasher computes it
+    /// internally
+    ///
+    /// Hashes are deterministic, so they can be computed ahead of time and passed to
+    /// [SHashMap::insert_prehashed]. For maps with other hashers, see [SHashMap::hash_key].
+    #[inline]
+    pub fn key_hash<Q: Hash + ?Sized>(key: &Q) -> u64 {
+        ZwoHasherFactory.hash_one(key)
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > SHashMap<K, V, S>
+{
+    /// Creates a new [SHashMap] of default capacity, which hashes keys with the provided hasher
+    /// factory
+    ///
+    /// Does not allocate any heap or stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::hash_map::hasher::IdentityHasherFactory;
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u64, u64, _>::with_hasher(IdentityHasherFactory);
+    ///
+    /// map.insert(1, 10).expect("Out of memory");
+    /// assert_eq!(map.hash_key(&1), 1);
+    /// ```
+    #[inline]
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_scheme_and_hasher(IndexingScheme::Modulo, hasher)
+    }
+
+    /// Creates a new [SHashMap] of default capacity, which uses the provided [IndexingScheme] and
+    /// hashes keys with the provided hasher factory
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn with_scheme_and_hasher(scheme: IndexingScheme, hasher: S) -> Self {
+        let cap = match scheme {
+            IndexingScheme::Modulo | IndexingScheme::Prime => DEFAULT_CAPACITY,
+            IndexingScheme::Fibonacci => DEFAULT_FIBONACCI_CAPACITY,
+        };
+
+        Self {
+            table_ptr: EMPTY_PTR,
+            len: 0,
+            cap,
+            auto_shrink: false,
+            scheme,
+            probe: ProbeSequence::Linear,
+            hasher,
+            stable_drop_flag: true,
+            _marker_k: PhantomData::default(),
+            _marker_v: PhantomData::default(),
+        }
+    }
+
+    // allocates a table of at least `capacity` slots (rounded up to a prime or a power of two)
+    #[inline]
+    fn with_table_capacity(
+        capacity: usize,
+        scheme: IndexingScheme,
+        probe: ProbeSequence,
+        hasher: S,
+    ) -> Result<Self, OutOfMemory> {
+        Self::with_exact_table_capacity(
+            Self::round_capacity(capacity, scheme, probe),
+            scheme,
+            probe,
+            hasher,
+        )
+    }
+
+    // allocates a table of exactly `capacity` slots
+    pub(crate) fn with_exact_table_capacity(
+        capacity: usize,
+        scheme: IndexingScheme,
+        probe: ProbeSequence,
+        hasher: S,
+    ) -> Result<Self, OutOfMemory> {
         assert!(capacity <= Self::max_capacity());
 
-        let size = (1 + K::SIZE + V::SIZE) * capacity;
+        let size = Self::table_bytes(capacity, probe);
         let table = unsafe { allocate(size as u64)? };
 
         let zeroed = vec![0u8; size];
@@ -112,6 +403,10 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
             table_ptr: table.as_ptr(),
             len: 0,
             cap: capacity,
+            auto_shrink: false,
+            scheme,
+            probe,
+            hasher,
             stable_drop_flag: true,
             _marker_k: PhantomData::default(),
             _marker_v: PhantomData::default(),
@@ -142,9 +437,55 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     ///     Err((k, v)) => println!("Out of memory. Unable to insert: {k}, {v}"),
     /// };
     /// ```
+    #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        let key_hash = self.hash(&key);
+
+        self.insert_with_hash(key_hash, key, value)
+    }
+
+    /// Inserts a key-value pair in this [SHashMap], using a precomputed hash of the key
+    ///
+    /// Works the same way as [SHashMap::insert], but does not hash the key. Useful for bulk loading,
+    /// when hashes of keys are already known. The hash should be computed with [SHashMap::hash_key]
+    /// (or [SHashMap::key_hash] for the default hasher), which is checked in debug builds.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    /// let hash = SHashMap::<u64, u64>::key_hash(&1);
+    ///
+    /// map.insert_prehashed(hash, 1, 10).expect("Out of memory");
+    ///
+    /// assert_eq!(*map.get(&1).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn insert_prehashed(&mut self, hash: u64, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        debug_assert_eq!(hash, self.hash_key(&key), "Invalid key hash");
+
+        self.insert_with_hash(hash as KeyHash, key, value)
+    }
+
+    /// Returns the hash of the key, computed with the hasher of this [SHashMap]
+    ///
+    /// Can be passed to [SHashMap::insert_prehashed].
+    #[inline]
+    pub fn hash_key<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    pub(crate) fn insert_with_hash(
+        &mut self,
+        key_hash: KeyHash,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, (K, V)> {
         if self.table_ptr == EMPTY_PTR {
-            let size = (1 + K::SIZE + V::SIZE) * self.capacity();
+            let size = self.capacity_bytes();
             if let Ok(table) = unsafe { allocate(size as u64) } {
                 let zeroed = vec![0u8; size];
                 unsafe { crate::mem::write_bytes(table.offset(0), &zeroed) };
@@ -155,63 +496,55 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
             }
         }
 
-        let key_hash = Self::hash(&key);
-        let mut i = key_hash % self.capacity();
-
-        loop {
-            match self.get_key(i) {
-                // if there is already a key like that, don't even check for fullness - simply replace the value
-                Some(prev_key) => {
-                    if (*prev_key).eq(&key) {
-                        let prev_value = self.read_and_disown_val(i);
-                        self.write_and_own_val(i, value);
-
-                        return Ok(Some(prev_value));
-                    } else {
-                        i = (i + 1) % self.capacity();
+        match self.probe(key_hash, &key) {
+            // if there is already a key like that, don't even check for fullness - simply replace the value
+            Ok(i) => {
+                let prev_value = self.read_and_disown_val(i);
+                self.write_and_own_val(i, value);
 
-                        continue;
-                    }
-                }
-                None => {
-                    if self.is_full() {
-                        // since we're allocating a new map with "new_with_capacity()" method, it should have
-                        // enough space to fit all elements without throwing an OutOfMemory error
-                        if let Ok(mut new) =
-                            Self::new_with_capacity(self.capacity().checked_mul(2).unwrap() - 1)
-                        {
-                            for i in 0..self.cap {
-                                if let Some(k) = self.read_and_disown_key(i) {
-                                    let v = self.read_and_disown_val(i);
-
-                                    new.insert(k, v).debugless_unwrap();
-                                }
+                Ok(Some(prev_value))
+            }
+            Err(i) => {
+                if self.is_full() {
+                    // since we're allocating a new map with "with_table_capacity()" method, it should have
+                    // enough space to fit all elements without throwing an OutOfMemory error
+                    if let Ok(mut new) = Self::with_table_capacity(
+                        self.grown_capacity(),
+                        self.scheme,
+                        self.probe,
+                        self.hasher.clone(),
+                    ) {
+                        for i in 0..self.cap {
+                            if let Some(k) = self.read_and_disown_key(i) {
+                                let v = self.read_and_disown_val(i);
+
+                                new.insert(k, v).debugless_unwrap();
                             }
+                        }
 
-                            let res = new.insert(key, value).debugless_unwrap();
-                            let slice = unsafe { SSlice::from_ptr(self.table_ptr).unwrap() };
-                            deallocate(slice);
+                        let res = new
+                            .insert_with_hash(key_hash, key, value)
+                            .debugless_unwrap();
+                        let slice = unsafe { SSlice::from_ptr(self.table_ptr).unwrap() };
+                        deallocate(slice);
 
-                            // dirty hack to make it not call stable_drop() when it is dropped
-                            // it is safe to use, since we've moved all the data inside into the new map
-                            // and deallocated the underlying slice
-                            unsafe { self.stable_drop_flag_off() };
+                        // dirty hack to make it not call stable_drop() when it is dropped
+                        // it is safe to use, since we've moved all the data inside into the new map
+                        // and deallocated the underlying slice
+                        unsafe { self.stable_drop_flag_off() };
 
-                            *self = new;
+                        new.auto_shrink = self.auto_shrink;
+                        *self = new;
 
-                            return Ok(res);
-                        } else {
-                            return Err((key, value));
-                        }
+                        return Ok(res);
+                    } else {
+                        return Err((key, value));
                     }
+                }
 
-                    self.write_and_own_key(i, Some(key));
-                    self.write_and_own_val(i, value);
-
-                    self.len += 1;
+                self.occupy(i, key, value);
 
-                    return Ok(None);
-                }
+                Ok(None)
             }
         }
     }
@@ -220,6 +553,10 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
     ///
     /// Returns [None] if no pair was found by this key
     ///
+    /// If auto-shrink is enabled (see [SHashMap::set_auto_shrink]) and the length drops below 1/8 of
+    /// the capacity, the table gets reallocated into a smaller one. If there is not enough stable
+    /// memory for that, the table is simply left as is.
+    ///
     /// # Examples
     /// ```rust
     /// # use ic_stable_memory::collections::SHashMap;
@@ -253,7 +590,58 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        Some(self.remove_by_idx(self.find_inner_idx(key)?))
+        let value = self.remove_by_idx(self.find_inner_idx(key)?);
+        self.auto_shrink_if_needed();
+
+        Some(value)
+    }
+
+    /// Returns an [Entry] for the provided key, which allows to inspect, insert or modify its value
+    /// without looking the key up again
+    ///
+    /// The key is hashed and probed once. If inserting into a vacant entry makes the table grow,
+    /// the key is probed again in the new table.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut word_counts = SHashMap::<u64, u64>::new();
+    ///
+    /// for word in [1, 2, 1, 3, 1] {
+    ///     *word_counts.entry(word).or_insert(0) += 1;
+    /// }
+    ///
+    /// assert_eq!(*word_counts.get(&1).unwrap(), 3);
+    /// assert_eq!(*word_counts.get(&2).unwrap(), 1);
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        let key_hash = self.hash(&key);
+
+        if self.table_ptr == EMPTY_PTR {
+            return Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                key_hash,
+                idx: None,
+            });
+        }
+
+        match self.probe(key_hash, &key) {
+            Ok(idx) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                key,
+                idx,
+            }),
+            Err(idx) => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                key_hash,
+                idx: Some(idx),
+            }),
+        }
     }
 
     /// Returns an immutable reference [SRef] to a value stored by the key
@@ -306,6 +694,62 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         Some(self.get_val_mut(self.find_inner_idx(key)?))
     }
 
+    /// Returns mutable references [SRefMut] to values stored by two different keys at once
+    ///
+    /// See also [SHashMap::get_mut].
+    ///
+    /// Each of the references is [None], if there is no such key in this [SHashMap]. Changes are
+    /// written back independently, when each of the references is dropped.
+    ///
+    /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
+    /// then you can get the values by [String].
+    ///
+    /// # Panics
+    /// Panics if both keys are present and equal.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// map.insert(1u64, 10u64).expect("Out of memory");
+    /// map.insert(2u64, 20u64).expect("Out of memory");
+    ///
+    /// {
+    ///     let (a, b) = map.get_disjoint_mut(&1, &2);
+    ///     let (mut a, mut b) = (a.unwrap(), b.unwrap());
+    ///
+    ///     std::mem::swap(&mut *a, &mut *b);
+    /// }
+    ///
+    /// assert_eq!(*map.get(&1).unwrap(), 20);
+    /// assert_eq!(*map.get(&2).unwrap(), 10);
+    /// ```
+    pub fn get_disjoint_mut<Q>(
+        &mut self,
+        key1: &Q,
+        key2: &Q,
+    ) -> (Option<SRefMut<'_, V>>, Option<SRefMut<'_, V>>)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx1 = self.find_inner_idx(key1);
+        let idx2 = self.find_inner_idx(key2);
+
+        if idx1.is_some() {
+            assert_ne!(idx1, idx2, "Keys should be different");
+        }
+
+        (
+            idx1.map(|idx| self.get_val_mut(idx)),
+            idx2.map(|idx| self.get_val_mut(idx)),
+        )
+    }
+
     /// Returns true if there exists a key-value pair stored by the provided key
     ///
     /// Borrowed type is also accepted. If your key type is, for example, [SBox] of [String],
@@ -331,6 +775,32 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         self.cap
     }
 
+    /// Returns the size (in bytes) of the table, which an [SHashMap] of the requested capacity allocates
+    ///
+    /// Useful to estimate the cost of a map in stable memory before creating it. The allocator may
+    /// reserve slightly more, see [allocation_size_for](crate::allocation_size_for).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// // each entry takes 1 byte for the status flag, 8 bytes for the key and 4 bytes for the value
+    /// assert_eq!(SHashMap::<u64, u32>::bytes_for_capacity(10), 130);
+    /// ```
+    #[inline]
+    pub const fn bytes_for_capacity(capacity: usize) -> usize {
+        (1 + K::SIZE + V::SIZE) * capacity
+    }
+
+    /// Returns the size (in bytes) of the table of this [SHashMap]
+    ///
+    /// If nothing was inserted yet, returns the size of the table, which will be allocated on the
+    /// first insert. For [ProbeSequence::Quadratic] this also includes the 8 bytes of the tombstone
+    /// counter.
+    #[inline]
+    pub const fn capacity_bytes(&self) -> usize {
+        Self::table_bytes(self.capacity(), self.probe)
+    }
+
     /// Returns the maximum possible capacity of this [SHashMap]
     #[inline]
     pub const fn max_capacity() -> usize {
@@ -343,441 +813,1948 @@ impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBy
         self.len() == 0
     }
 
+    /// Writes the header of this [SHashMap] into `dst`
+    ///
+    /// The header is the pointer to the table, the length, the capacity (together with the
+    /// indexing, probing and auto-shrink settings) and the hasher factory. Entries are written to
+    /// stable memory as the map changes, so only the header has to be persisted - see
+    /// [persistence model](crate::mem#persistence-model). Restore the map with
+    /// [AsFixedSizeBytes::from_fixed_size_bytes].
+    ///
+    /// # Panics
+    /// Panics if `dst` is shorter than [AsFixedSizeBytes::SIZE] bytes.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u64, u64>::new();
+    /// map.insert(1, 10).expect("Out of memory");
+    ///
+    /// let mut header = vec![0u8; SHashMap::<u64, u64>::SIZE];
+    /// map.flush(&mut header);
+    ///
+    /// let restored = SHashMap::<u64, u64>::from_fixed_size_bytes(&header);
+    /// assert_eq!(*restored.get(&1).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn flush(&self, dst: &mut [u8]) {
+        self.as_fixed_size_bytes(&mut dst[0..Self::SIZE]);
+    }
+
     /// Returns true if the next unique key insert will trigger the reallocation and rehashing
+    ///
+    /// For [ProbeSequence::Quadratic] tombstones left by removed entries also take up space.
     #[inline]
-    pub const fn is_full(&self) -> bool {
-        self.len() == (self.capacity() >> 2) * 3
+    pub fn is_full(&self) -> bool {
+        self.len() + self.tombstones() == (self.capacity() >> 2) * 3
     }
 
-    /// Returns an iterator over entries of this [SHashMap]
+    /// Shrinks the capacity of this [SHashMap] as much as possible, but not below `min_capacity`
     ///
-    /// Elements of this iterator are presented in unpredictable and non-deterministic order.
+    /// The capacity never goes below the length of this [SHashMap] plus the free space needed to keep
+    /// the load factor, nor below the default capacity. Does nothing, if the capacity is already small
+    /// enough. Otherwise allocates a new table, rehashes all the keys into it and releases the old one.
+    /// If the canister is out of stable memory, returns [OutOfMemory] and leaves this map untouched.
     ///
     /// # Example
     /// ```rust
     /// # use ic_stable_memory::collections::SHashMap;
     /// # use ic_stable_memory::stable_memory_init;
-    /// # stable_memory_init();
     /// # unsafe { ic_stable_memory::mem::clear(); }
-    /// let mut map = SHashMap::new();
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new_with_capacity(1000).expect("Out of memory");
     ///
-    /// for i in 0..100 {
+    /// for i in 0..10u64 {
     ///     map.insert(i, i).expect("Out of memory");
     /// }
     ///
-    /// for (k, v) in map.iter() {
-    ///     println!("{}, {}", *k, *v);
-    /// }
+    /// map.shrink_to(0).expect("Out of memory");
+    ///
+    /// assert!(map.capacity() < 1000);
+    /// assert_eq!(*map.get(&5).unwrap(), 5);
     /// ```
-    #[inline]
-    pub fn iter(&self) -> SHashMapIter<K, V> {
-        SHashMapIter::new(self)
-    }
+    pub fn shrink_to(&mut self, min_capacity: usize) -> Result<(), OutOfMemory> {
+        let capacity = Self::round_capacity(
+            min_capacity
+                .max(Self::min_capacity_for(self.len()))
+                .max(DEFAULT_CAPACITY),
+            self.scheme,
+            self.probe,
+        );
 
-    /// Removes all elements from this [SHashMap]
-    pub fn clear(&mut self) {
-        if self.is_empty() {
-            return;
+        if capacity >= self.capacity() {
+            return Ok(());
         }
 
-        for i in 0..self.cap {
-            if let Some(k) = self.read_and_disown_key(i) {
+        if self.table_ptr == EMPTY_PTR {
+            self.cap = capacity;
+
+            return Ok(());
+        }
+
+        self.resize_table(capacity, self.probe)
+    }
+
+    /// Reserves space for at least `additional` more entries, so they can be inserted into this
+    /// [SHashMap] without reallocating the table
+    ///
+    /// Does nothing, if there is already enough space. Otherwise allocates a new table (even if
+    /// nothing was inserted yet), rehashes all the keys into it and releases the old one. If the
+    /// canister is out of stable memory, returns [OutOfMemory] and leaves this map untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    /// map.reserve(100).expect("Out of memory");
+    ///
+    /// let capacity = map.capacity();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(map.capacity(), capacity);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) -> Result<(), OutOfMemory> {
+        let capacity = Self::min_capacity_for(self.len().checked_add(additional).unwrap());
+
+        if capacity <= self.capacity() && self.table_ptr != EMPTY_PTR {
+            return Ok(());
+        }
+
+        self.resize_table(capacity.max(self.capacity()), self.probe)
+    }
+
+    // moves all the entries into a new table of the requested capacity and probe sequence
+    fn resize_table(&mut self, capacity: usize, probe: ProbeSequence) -> Result<(), OutOfMemory> {
+        let mut new = Self::with_table_capacity(capacity, self.scheme, probe, self.hasher.clone())?;
+
+        if self.table_ptr != EMPTY_PTR {
+            for i in 0..self.cap {
+                if let Some(k) = self.read_and_disown_key(i) {
+                    let v = self.read_and_disown_val(i);
+
+                    new.insert(k, v).debugless_unwrap();
+                }
+            }
+
+            let slice = unsafe { SSlice::from_ptr(self.table_ptr).unwrap() };
+            deallocate(slice);
+        }
+
+        // same as when growing - all the data was moved into the new map
+        unsafe { self.stable_drop_flag_off() };
+
+        new.auto_shrink = self.auto_shrink;
+        *self = new;
+
+        Ok(())
+    }
+
+    /// Enables or disables auto-shrink of this [SHashMap]
+    ///
+    /// When enabled, [SHashMap::remove] shrinks the table, once the length drops below 1/8 of the
+    /// capacity - useful for workloads with bursts of inserts followed by long quiet periods. The
+    /// flag is persisted together with the rest of the header of this map. Disabled by default.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    /// map.set_auto_shrink(true);
+    ///
+    /// for i in 0..1000u64 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let capacity = map.capacity();
+    ///
+    /// for i in 0..990u64 {
+    ///     map.remove(&i);
+    /// }
+    ///
+    /// assert!(map.capacity() < capacity);
+    /// ```
+    #[inline]
+    pub fn set_auto_shrink(&mut self, enabled: bool) {
+        self.auto_shrink = enabled;
+    }
+
+    /// Returns the [IndexingScheme] of this [SHashMap]
+    #[inline]
+    pub const fn indexing_scheme(&self) -> IndexingScheme {
+        self.scheme
+    }
+
+    /// Switches this [SHashMap] to the provided [ProbeSequence]
+    ///
+    /// Does nothing, if the map already uses it. Otherwise allocates a new table (unless nothing
+    /// was inserted yet), rehashes all the keys into it and releases the old one. If the canister is
+    /// out of stable memory, returns [OutOfMemory] and leaves this map untouched. The probe sequence
+    /// is persisted together with the rest of the header of this map.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::hash_map::ProbeSequence;
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u64, u64>::new();
+    /// map.set_probe(ProbeSequence::Quadratic).expect("Out of memory");
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(map.remove(&10), Some(10));
+    /// assert_eq!(*map.get(&20).unwrap(), 20);
+    /// assert_eq!(map.capacity(), 256);
+    /// ```
+    pub fn set_probe(&mut self, probe: ProbeSequence) -> Result<(), OutOfMemory> {
+        if self.probe == probe {
+            return Ok(());
+        }
+
+        if self.table_ptr == EMPTY_PTR {
+            self.probe = probe;
+            self.cap = Self::round_capacity(self.cap, self.scheme, probe);
+
+            return Ok(());
+        }
+
+        self.resize_table(self.cap, probe)
+    }
+
+    /// Returns the [ProbeSequence] of this [SHashMap]
+    ///
+    /// See [SHashMap::set_probe].
+    #[inline]
+    pub const fn probe_sequence(&self) -> ProbeSequence {
+        self.probe
+    }
+
+    /// Returns true if auto-shrink is enabled for this [SHashMap]
+    ///
+    /// See [SHashMap::set_auto_shrink].
+    #[inline]
+    pub const fn auto_shrink(&self) -> bool {
+        self.auto_shrink
+    }
+
+    /// Returns an iterator over entries of this [SHashMap]
+    ///
+    /// Elements of this iterator are presented in unpredictable and non-deterministic order.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # stable_memory_init();
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// for (k, v) in map.iter() {
+    ///     println!("{}, {}", *k, *v);
+    /// }
+    /// ```
+    #[inline]
+    pub fn iter(&self) -> SHashMapIter<K, V, S> {
+        SHashMapIter::new(self)
+    }
+
+    /// Returns an iterator over keys of this [SHashMap]
+    ///
+    /// Values are not read from stable memory at all. Just like with [SHashMap::iter], keys are
+    /// presented in unpredictable and non-deterministic order.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let mut keys = map.keys().map(|k| *k).collect::<Vec<_>>();
+    /// keys.sort();
+    ///
+    /// assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = SRef<'_, K>> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Consumes this [SHashMap], returning an iterator over its keys
+    ///
+    /// Values are dropped along the way. The table of this [SHashMap] is released as soon as the
+    /// last key is returned (or when the iterator is dropped, whichever happens first).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let mut keys = map.into_keys().collect::<Vec<_>>();
+    /// keys.sort();
+    ///
+    /// assert_eq!(keys, (0..100).collect::<Vec<_>>());
+    /// ```
+    #[inline]
+    pub fn into_keys(self) -> impl Iterator<Item = K> {
+        SHashMapIntoIter::new(self).map(|(k, _)| k)
+    }
+
+    /// Consumes this [SHashMap], returning an iterator over its values
+    ///
+    /// Keys are dropped along the way. The table of this [SHashMap] is released as soon as the
+    /// last value is returned (or when the iterator is dropped, whichever happens first).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(map.into_values().sum::<u64>(), 9900);
+    /// ```
+    #[inline]
+    pub fn into_values(self) -> impl Iterator<Item = V> {
+        SHashMapIntoIter::new(self).map(|(_, v)| v)
+    }
+
+    /// Removes all entries from this [SHashMap], returning them by value in an iterator
+    ///
+    /// Unlike [SHashMap::clear], keys and values are not dropped, but moved to the caller, so any
+    /// stable memory they own (e.g. the contents of an [SBox](crate::SBox)) is transferred as well.
+    /// The table itself is kept, so the map can be filled again without reallocating. Entries are
+    /// presented in unpredictable order, just like with [SHashMap::iter].
+    ///
+    /// If the iterator is dropped before it is exhausted, the remaining entries are dropped, so
+    /// the map is left empty and nothing leaks. Leaking the iterator itself (e.g. with
+    /// [std::mem::forget]) may leave the remaining entries unreachable.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::{SBTreeMap, SHashMap};
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// let capacity = map.capacity();
+    ///
+    /// let mut rehomed = SBTreeMap::new();
+    /// for (k, v) in map.drain() {
+    ///     rehomed.insert(k, v).expect("Out of memory");
+    /// }
+    ///
+    /// assert!(map.is_empty());
+    /// assert_eq!(map.capacity(), capacity);
+    /// assert_eq!(*rehomed.get(&10).unwrap(), 20);
+    /// ```
+    #[inline]
+    pub fn drain(&mut self) -> SHashMapDrain<'_, K, V, S> {
+        SHashMapDrain::new(self)
+    }
+
+    /// Returns an iterator over entries of this [SHashMap], sorted by key
+    ///
+    /// Unlike [SHashMap::iter], the order of elements only depends on the contents of this map, and
+    /// not on its capacity or on the history of insertions and removals. This makes it suitable for
+    /// deterministic exports - e.g. for hashing the state of a canister and comparing it across replicas.
+    ///
+    /// All entries are cloned to the heap and sorted before the first one is returned, so this costs
+    /// `O(n log n)` time and `O(n)` heap memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in (0..100u64).rev() {
+    ///     map.insert(i, i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// assert!(map.canonical_iter().eq((0..100).map(|i| (i, i * 2))));
+    /// ```
+    pub fn canonical_iter(&self) -> impl Iterator<Item = (K, V)>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        let mut entries: Vec<_> = self
+            .iter()
+            .map(|(k, v)| ((*k).clone(), (*v).clone()))
+            .collect();
+
+        entries.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+
+        entries.into_iter()
+    }
+
+    /// Removes all elements from this [SHashMap]
+    pub fn clear(&mut self) {
+        if self.is_empty() && self.tombstones() == 0 {
+            return;
+        }
+
+        for i in 0..self.cap {
+            if let Some(k) = self.read_and_disown_key(i) {
                 let v = self.read_and_disown_val(i);
 
-                self.write_and_own_key(i, None);
+                self.write_and_own_key(i, None);
+            } else if self.read_flag(i) == DELETED {
+                self.write_and_own_key(i, None);
+            }
+        }
+
+        self.len = 0;
+        self.set_tombstones(0);
+    }
+
+    /// Filters this [SHashMap], so only entries for which the provided lambda returns [true] are left
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.retain_mut(|k, v| f(k, v));
+    }
+
+    /// Filters this [SHashMap], so only entries for which the provided lambda returns [true] are left,
+    /// giving the lambda mutable access to each value
+    ///
+    /// Mutations made to retained values are written back to stable memory. Removed entries are not
+    /// written back - they are dropped, releasing any stable memory they own, and the probe sequences
+    /// of the remaining entries are repaired (or tombstones are left for
+    /// [ProbeSequence::Quadratic]), so lookups keep working afterwards.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::<u32, u32>::new();
+    ///
+    /// for i in 0..10 {
+    ///     map.insert(i, i % 3).expect("Out of memory");
+    /// }
+    ///
+    /// // decrement each counter, dropping the ones that were already zero
+    /// map.retain_mut(|_, counter| {
+    ///     if *counter == 0 {
+    ///         return false;
+    ///     }
+    ///
+    ///     *counter -= 1;
+    ///     true
+    /// });
+    ///
+    /// assert_eq!(map.len(), 6);
+    /// assert_eq!(*map.get(&2).unwrap(), 1);
+    /// assert!(map.get(&3).is_none());
+    /// ```
+    pub fn retain_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        if self.is_empty() {
+            return;
+        }
+
+        let cap = self.capacity();
+
+        // start right after an empty slot, so backward shifts never move an already visited
+        // entry in front of the cursor
+        let empty_idx = (0..cap)
+            .find(|&i| self.read_key_for_reference(i).is_none())
+            .unwrap();
+
+        let mut i = (empty_idx + 1) % cap;
+        let mut steps = 0;
+
+        while steps < cap {
+            if let Some(k) = self.read_key_for_reference(i) {
+                let mut v = self.read_and_disown_val(i);
+
+                if f(&k, &mut v) {
+                    self.write_and_own_val(i, v);
+                } else {
+                    // the value is owned here, so it is only dropped once - right away
+                    drop(v);
+                    self.vacate(i);
+
+                    // with linear probing the slot is now occupied by the next entry of the probe
+                    // sequence (if any), with quadratic probing it is a tombstone
+                    if self.probe == ProbeSequence::Linear {
+                        continue;
+                    }
+                }
+            }
+
+            i = (i + 1) % cap;
+            steps += 1;
+        }
+    }
+
+    /// Transforms each value of this [SHashMap] with the provided lambda, moving all entries into a new
+    /// [SHashMap]
+    ///
+    /// Useful for schema migrations, when the encoding of `V` changes between canister upgrades.
+    /// Keys and values are moved, not copied, so the lambda receives each value by value. The new
+    /// [SHashMap] is allocated with the same capacity upfront, so it never rehashes, and the table of
+    /// this [SHashMap] is released at the end.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SHashMap;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut map = SHashMap::new();
+    ///
+    /// for i in 0..100u32 {
+    ///     map.insert(i, i).expect("Out of memory");
+    /// }
+    ///
+    /// let map = map.migrate_values(|v| (v as u64, true));
+    ///
+    /// assert_eq!(*map.get(&10).unwrap(), (10, true));
+    /// ```
+    pub fn migrate_values<V2, F>(mut self, mut f: F) -> SHashMap<K, V2, S>
+    where
+        V2: StableType + AsFixedSizeBytes,
+        F: FnMut(V) -> V2,
+    {
+        if self.is_empty() {
+            let mut new_map = SHashMap::with_scheme_and_hasher(self.scheme, self.hasher.clone());
+            new_map.set_probe(self.probe).debugless_unwrap();
+
+            return new_map;
+        }
+
+        let mut new_map = SHashMap::<K, V2, S>::with_table_capacity(
+            self.cap,
+            self.scheme,
+            self.probe,
+            self.hasher.clone(),
+        )
+        .expect("Out of memory");
+        new_map.set_auto_shrink(self.auto_shrink);
+
+        for i in 0..self.cap {
+            if let Some(k) = self.read_and_disown_key(i) {
+                let v = self.read_and_disown_val(i);
+                self.write_and_own_key(i, None);
+
+                if new_map.insert(k, f(v)).is_err() {
+                    panic!("Out of memory");
+                }
+            }
+        }
+
+        self.len = 0;
+
+        new_map
+    }
+
+    // the smallest capacity, which can hold `len` entries without growing
+    #[inline]
+    const fn min_capacity_for(len: usize) -> usize {
+        len.div_ceil(3) * 4
+    }
+
+    #[inline]
+    fn home_idx(&self, key_hash: KeyHash) -> usize {
+        match self.scheme {
+            IndexingScheme::Modulo | IndexingScheme::Prime => key_hash % self.capacity(),
+            IndexingScheme::Fibonacci => {
+                let shift = u64::BITS - self.capacity().trailing_zeros();
+
+                ((key_hash as u64).wrapping_mul(FIBONACCI_MULTIPLIER) >> shift) as usize
+            }
+        }
+    }
+
+    // the index, which is checked after `idx` at the `step`-th (starting from 1) step of the probe sequence
+    #[inline]
+    fn next_probe_idx(&self, idx: usize, step: usize) -> usize {
+        match self.probe {
+            ProbeSequence::Linear => (idx + 1) % self.capacity(),
+            ProbeSequence::Quadratic => (idx + step) % self.capacity(),
+        }
+    }
+
+    #[inline]
+    const fn has_power_of_two_capacity(scheme: IndexingScheme, probe: ProbeSequence) -> bool {
+        matches!(scheme, IndexingScheme::Fibonacci) || matches!(probe, ProbeSequence::Quadratic)
+    }
+
+    #[inline]
+    fn round_capacity(capacity: usize, scheme: IndexingScheme, probe: ProbeSequence) -> usize {
+        if Self::has_power_of_two_capacity(scheme, probe) {
+            capacity.max(DEFAULT_FIBONACCI_CAPACITY).next_power_of_two()
+        } else if scheme == IndexingScheme::Prime {
+            Self::prime_capacity(capacity)
+        } else {
+            capacity
+        }
+    }
+
+    // the smallest prime capacity, which fits `capacity` slots - if there is no such one, `capacity`
+    // itself is used
+    fn prime_capacity(capacity: usize) -> usize {
+        PRIME_CAPACITIES
+            .iter()
+            .map(|p| *p as usize)
+            .find(|p| *p >= capacity)
+            .filter(|p| *p <= Self::max_capacity())
+            .unwrap_or(capacity)
+    }
+
+    #[inline]
+    fn next_capacity(&self) -> usize {
+        let doubled = self.capacity().checked_mul(2).unwrap();
+
+        if Self::has_power_of_two_capacity(self.scheme, self.probe) {
+            doubled
+        } else if self.scheme == IndexingScheme::Prime {
+            Self::prime_capacity(doubled - 1)
+        } else {
+            doubled - 1
+        }
+    }
+
+    // the capacity of the table, which replaces this one, once it is full - if most of the space is
+    // taken by tombstones, rehashing into a table of the same capacity is enough to get rid of them
+    #[inline]
+    fn grown_capacity(&self) -> usize {
+        if self.tombstones() > self.len() {
+            self.capacity()
+        } else {
+            self.next_capacity()
+        }
+    }
+
+    #[inline]
+    const fn table_bytes(capacity: usize, probe: ProbeSequence) -> usize {
+        match probe {
+            ProbeSequence::Linear => Self::bytes_for_capacity(capacity),
+            ProbeSequence::Quadratic => Self::bytes_for_capacity(capacity) + u64::SIZE,
+        }
+    }
+
+    // the number of tombstones is stored right after the values, only for quadratic probing
+    #[inline]
+    fn tombstones(&self) -> usize {
+        if self.probe == ProbeSequence::Linear || self.table_ptr == EMPTY_PTR {
+            return 0;
+        }
+
+        let n: u64 = unsafe { crate::mem::read_fixed_for_reference(self.get_tombstones_ptr()) };
+
+        n as usize
+    }
+
+    #[inline]
+    fn set_tombstones(&mut self, n: usize) {
+        if self.probe == ProbeSequence::Linear || self.table_ptr == EMPTY_PTR {
+            return;
+        }
+
+        unsafe { crate::mem::write_fixed(self.get_tombstones_ptr(), &mut (n as u64)) };
+    }
+
+    #[inline]
+    fn get_tombstones_ptr(&self) -> StablePtr {
+        SSlice::_offset(
+            self.table_ptr,
+            Self::bytes_for_capacity(self.capacity()) as u64,
+        )
+    }
+
+    // how many steps of the probe sequence each entry is away from the position its hash points to,
+    // used by benchmarks
+    #[cfg(test)]
+    pub(crate) fn probe_lengths(&self) -> Vec<usize> {
+        let mut result = Vec::with_capacity(self.len());
+
+        for i in 0..self.cap {
+            if let Some(k) = self.get_key(i) {
+                let mut j = self.home_idx(self.hash(&*k));
+                let mut steps = 0;
+
+                while j != i {
+                    steps += 1;
+                    j = self.next_probe_idx(j, steps);
+                }
+
+                result.push(steps);
+            }
+        }
+
+        result
+    }
+
+    #[inline]
+    fn hash<T: Hash + ?Sized>(&self, val: &T) -> KeyHash {
+        self.hash_key(val) as KeyHash
+    }
+
+    pub(crate) fn remove_by_idx(&mut self, idx: usize) -> V {
+        let prev_value = self.read_and_disown_val(idx);
+        self.vacate(idx);
+
+        prev_value
+    }
+
+    // drops the key of the entry and frees its slot, the value should already be moved out of it
+    fn vacate(&mut self, idx: usize) {
+        self.read_and_disown_key(idx).unwrap();
+
+        // entries can't be shifted back along a quadratic probe sequence, so a tombstone is left
+        if self.probe == ProbeSequence::Quadratic {
+            let mut flag = DELETED;
+            unsafe { crate::mem::write_fixed(self.get_key_flag_ptr(idx), &mut flag) };
+            self.set_tombstones(self.tombstones() + 1);
+            self.len -= 1;
+
+            return;
+        }
+
+        let mut i = idx;
+        let mut j = idx;
+
+        loop {
+            j = (j + 1) % self.capacity();
+            if j == idx {
+                break;
+            }
+
+            if let Some(next_key) = self.read_key_for_reference(j) {
+                let k = self.home_idx(self.hash(&next_key));
+
+                if (j < i) ^ (k <= i) ^ (k > j) {
+                    self.write_and_own_key(i, Some(next_key));
+                    self.write_and_own_val(i, self.read_and_disown_val(j));
+
+                    i = j;
+                }
+
+                continue;
+            }
+
+            break;
+        }
+
+        self.write_and_own_key(i, None);
+        self.len -= 1;
+    }
+
+    pub(crate) fn auto_shrink_if_needed(&mut self) {
+        if self.auto_shrink && self.len() < self.capacity() / 8 {
+            // leave enough room for the map to double, before it has to grow again
+            let _ = self.shrink_to(self.len() * 2);
+        }
+    }
+
+    // returns the index of the key, or the index of the slot, where the key should be inserted -
+    // the first tombstone of the probe sequence, or the empty slot, where the sequence ends
+    pub(crate) fn probe(&self, key_hash: KeyHash, key: &K) -> Result<usize, usize> {
+        let mut i = self.home_idx(key_hash);
+        let mut tombstone = None;
+        let mut step = 0;
+
+        loop {
+            match self.read_flag(i) {
+                EMPTY => return Err(tombstone.unwrap_or(i)),
+                DELETED => {
+                    tombstone.get_or_insert(i);
+                }
+                _ => {
+                    if (*self.get_key(i).unwrap()).eq(key) {
+                        return Ok(i);
+                    }
+                }
+            }
+
+            step += 1;
+            i = self.next_probe_idx(i, step);
+        }
+    }
+
+    fn find_inner_idx<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.is_empty() {
+            return None;
+        }
+
+        let key_hash = self.hash(key);
+        let mut i = self.home_idx(key_hash);
+        let mut step = 0;
+
+        loop {
+            match self.read_flag(i) {
+                EMPTY => return None,
+                DELETED => {}
+                _ => {
+                    if (*self.get_key(i).unwrap()).borrow().eq(key) {
+                        return Some(i);
+                    }
+                }
+            }
+
+            step += 1;
+            i = self.next_probe_idx(i, step);
+        }
+    }
+
+    // writes the entry into a vacant slot, found by [SHashMap::probe]
+    pub(crate) fn occupy(&mut self, idx: usize, key: K, value: V) {
+        if self.read_flag(idx) == DELETED {
+            self.set_tombstones(self.tombstones() - 1);
+        }
+
+        self.write_and_own_key(idx, Some(key));
+        self.write_and_own_val(idx, value);
+
+        self.len += 1;
+    }
+
+    // moves the entry out of the table, if the slot is occupied
+    pub(crate) fn take_by_idx(&mut self, idx: usize) -> Option<(K, V)> {
+        let k = self.read_and_disown_key(idx)?;
+        let v = self.read_and_disown_val(idx);
+
+        self.write_and_own_key(idx, None);
+        self.len -= 1;
+
+        Some((k, v))
+    }
+
+    #[inline]
+    fn read_flag(&self, idx: usize) -> u8 {
+        unsafe { crate::mem::read_fixed_for_reference(self.get_key_flag_ptr(idx)) }
+    }
+
+    fn get_key(&self, idx: usize) -> Option<SRef<K>> {
+        let ptr = self.get_key_flag_ptr(idx);
+        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+
+        match flag {
+            EMPTY | DELETED => None,
+            OCCUPIED => Some(unsafe { SRef::new(ptr + 1) }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn read_and_disown_key(&self, idx: usize) -> Option<K> {
+        let ptr = self.get_key_flag_ptr(idx);
+        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+
+        match flag {
+            EMPTY | DELETED => None,
+            OCCUPIED => Some(unsafe { crate::mem::read_fixed_for_move(ptr + 1) }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn read_key_for_reference(&self, idx: usize) -> Option<K> {
+        let ptr = self.get_key_flag_ptr(idx);
+        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+
+        match flag {
+            EMPTY | DELETED => None,
+            OCCUPIED => Some(unsafe { crate::mem::read_fixed_for_reference(ptr + 1) }),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn write_and_own_key(&mut self, idx: usize, key: Option<K>) {
+        let ptr = self.get_key_flag_ptr(idx);
+
+        if let Some(mut k) = key {
+            unsafe { crate::mem::write_fixed(ptr, &mut OCCUPIED) };
+            unsafe { crate::mem::write_fixed(ptr + 1, &mut k) };
+
+            return;
+        }
+
+        unsafe { crate::mem::write_fixed(ptr, &mut EMPTY) };
+    }
+
+    #[inline]
+    pub(crate) fn get_val(&self, idx: usize) -> SRef<V> {
+        unsafe { SRef::new(self.get_value_ptr(idx)) }
+    }
+
+    #[inline]
+    pub(crate) fn get_val_mut(&self, idx: usize) -> SRefMut<V> {
+        unsafe { SRefMut::new(self.get_value_ptr(idx)) }
+    }
+
+    #[inline]
+    pub(crate) fn read_and_disown_val(&self, idx: usize) -> V {
+        unsafe { crate::mem::read_fixed_for_move(self.get_value_ptr(idx)) }
+    }
+
+    #[inline]
+    pub(crate) fn write_and_own_val(&mut self, idx: usize, mut val: V) {
+        unsafe { crate::mem::write_fixed(self.get_value_ptr(idx), &mut val) }
+    }
+
+    #[inline]
+    fn get_value_ptr(&self, idx: usize) -> StablePtr {
+        SSlice::_offset(
+            self.table_ptr,
+            (values_offset::<K>(self.capacity()) + V::SIZE * idx) as u64,
+        )
+    }
+
+    #[inline]
+    fn get_key_flag_ptr(&self, idx: usize) -> StablePtr {
+        SSlice::_offset(self.table_ptr, (KEYS_OFFSET + (1 + K::SIZE) * idx) as u64)
+    }
+
+    #[inline]
+    fn get_key_data_ptr(&self, idx: usize) -> StablePtr {
+        SSlice::_offset(
+            self.table_ptr,
+            (KEYS_OFFSET + (1 + K::SIZE) * idx + 1) as u64,
+        )
+    }
+
+    /// Prints byte representation of this [SHashMap]
+    ///
+    /// Useful for tests
+    pub fn debug_print(&self) {
+        print!("Node({}, {})[", self.len(), self.capacity());
+        for i in 0..self.capacity() {
+            let k_flag: u8 =
+                unsafe { crate::mem::read_fixed_for_reference(self.get_key_flag_ptr(i)) };
+            let mut k_buf = K::Buf::new(K::SIZE);
+            let mut v_buf = V::Buf::new(V::SIZE);
+
+            unsafe { crate::mem::read_bytes(self.get_key_data_ptr(i), k_buf._deref_mut()) };
+            unsafe { crate::mem::read_bytes(self.get_value_ptr(i), v_buf._deref_mut()) };
+
+            print!("(");
+
+            match k_flag {
+                EMPTY => print!("<empty> = "),
+                DELETED => print!("<deleted> = "),
+                OCCUPIED => print!("<occupied> = "),
+                _ => unreachable!(),
+            };
+
+            print!("{:?}, {:?})", k_buf._deref(), v_buf._deref());
+
+            if i < self.capacity() - 1 {
+                print!(", ");
+            }
+        }
+        println!("]");
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
+        V: StableType + AsFixedSizeBytes + Debug,
+        S: StableHasherFactory,
+    > Debug for SHashMap<K, V, S>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("{")?;
+        for (idx, (k, v)) in self.iter().enumerate() {
+            k.fmt(f)?;
+            f.write_str(": ")?;
+            v.fmt(f)?;
+
+            if idx < self.len() - 1 {
+                f.write_str(", ")?;
+            }
+        }
+        f.write_str("}")
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
+    for SHashMap<K, V>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > AsFixedSizeBytes for SHashMap<K, V, S>
+{
+    const SIZE: usize = u64::SIZE + usize::SIZE * 2 + S::SIZE;
+    type Buf = Vec<u8>;
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.table_ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.len
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
+        let mut cap = self.cap;
+        if self.auto_shrink {
+            cap |= AUTO_SHRINK_BIT;
+        }
+        match self.scheme {
+            IndexingScheme::Modulo => {}
+            IndexingScheme::Fibonacci => cap |= FIBONACCI_BIT,
+            IndexingScheme::Prime => cap |= PRIME_BIT,
+        }
+        if self.probe == ProbeSequence::Quadratic {
+            cap |= QUADRATIC_BIT;
+        }
+
+        cap.as_fixed_size_bytes(&mut buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)]);
+        self.hasher
+            .as_fixed_size_bytes(&mut buf[(usize::SIZE * 2 + u64::SIZE)..Self::SIZE]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let table_ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let len = usize::from_fixed_size_bytes(&buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
+        let cap = usize::from_fixed_size_bytes(
+            &buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)],
+        );
+
+        Self {
+            table_ptr,
+            len,
+            cap: cap & !(AUTO_SHRINK_BIT | FIBONACCI_BIT | QUADRATIC_BIT | PRIME_BIT),
+            auto_shrink: cap & AUTO_SHRINK_BIT != 0,
+            scheme: if cap & FIBONACCI_BIT != 0 {
+                IndexingScheme::Fibonacci
+            } else if cap & PRIME_BIT != 0 {
+                IndexingScheme::Prime
+            } else {
+                IndexingScheme::Modulo
+            },
+            probe: if cap & QUADRATIC_BIT != 0 {
+                ProbeSequence::Quadratic
+            } else {
+                ProbeSequence::Linear
+            },
+            hasher: S::from_fixed_size_bytes(&buf[(usize::SIZE * 2 + u64::SIZE)..Self::SIZE]),
+            stable_drop_flag: false,
+            _marker_k: PhantomData::default(),
+            _marker_v: PhantomData::default(),
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > StableType for SHashMap<K, V, S>
+{
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        if self.table_ptr != EMPTY_PTR {
+            self.clear();
+
+            let slice = SSlice::from_ptr(self.table_ptr).unwrap();
+            deallocate(slice);
+        }
+    }
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > Drop for SHashMap<K, V, S>
+{
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            let ptr = self.table_ptr;
+
+            unsafe {
+                self.stable_drop();
+            }
+
+            if ptr != EMPTY_PTR {
+                crate::mem::debug_assert_released(ptr, "SHashMap");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::hash_map::entry::Entry;
+    use crate::collections::hash_map::hasher::{IdentityHasherFactory, SipHasherFactory};
+    use crate::collections::hash_map::{IndexingScheme, ProbeSequence, SHashMap};
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::mem::allocator::EMPTY_PTR;
+    use crate::primitive::s_box::SBox;
+    use crate::primitive::StableType;
+    use crate::utils::mem_context::stable;
+    use crate::utils::test::generate_random_string;
+    use crate::utils::DebuglessUnwrap;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data,
+    };
+    use rand::rngs::ThreadRng;
+    use rand::seq::SliceRandom;
+    use rand::{thread_rng, Rng};
+    use std::collections::HashMap;
+    use std::ops::Deref;
+
+    #[test]
+    fn simple_flow_works_well() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new_with_capacity(3).debugless_unwrap();
+
+            let k1 = 1;
+            let k2 = 2;
+            let k3 = 3;
+            let k4 = 4;
+            let k5 = 5;
+            let k6 = 6;
+            let k7 = 7;
+            let k8 = 8;
+
+            map.insert(k1, 1);
+            map.insert(k2, 2);
+            map.insert(k3, 3);
+            map.insert(k4, 4);
+            map.insert(k5, 5);
+            map.insert(k6, 6);
+            map.insert(k7, 7);
+            map.insert(k8, 8);
+
+            assert_eq!(*map.get(&k1).unwrap(), 1);
+            assert_eq!(*map.get(&k2).unwrap(), 2);
+            assert_eq!(*map.get(&k3).unwrap(), 3);
+            assert_eq!(*map.get(&k4).unwrap(), 4);
+            assert_eq!(*map.get(&k5).unwrap(), 5);
+            assert_eq!(*map.get(&k6).unwrap(), 6);
+            assert_eq!(*map.get(&k7).unwrap(), 7);
+            assert_eq!(*map.get(&k8).unwrap(), 8);
+
+            assert!(map.get(&9).is_none());
+            assert!(map.get(&0).is_none());
+
+            assert_eq!(map.remove(&k3).unwrap(), 3);
+            assert!(map.get(&k3).is_none());
+
+            assert_eq!(map.remove(&k1).unwrap(), 1);
+            assert!(map.get(&k1).is_none());
+
+            assert_eq!(map.remove(&k5).unwrap(), 5);
+            assert!(map.get(&k5).is_none());
+
+            assert_eq!(map.remove(&k7).unwrap(), 7);
+            assert!(map.get(&k7).is_none());
+
+            assert_eq!(*map.get(&k2).unwrap(), 2);
+            assert_eq!(*map.get(&k4).unwrap(), 4);
+            assert_eq!(*map.get(&k6).unwrap(), 6);
+            assert_eq!(*map.get(&k8).unwrap(), 8);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new_with_capacity(3).debugless_unwrap();
+
+            assert!(map.remove(&10).is_none());
+            assert!(map.get(&10).is_none());
+
+            let it = map.insert(1, 1).unwrap();
+            assert!(it.is_none());
+            assert!(map.insert(2, 2).unwrap().is_none());
+            assert!(map.insert(3, 3).unwrap().is_none());
+            assert_eq!(map.insert(1, 10).unwrap().unwrap(), 1);
+
+            assert!(map.remove(&5).is_none());
+            assert_eq!(map.remove(&1).unwrap(), 10);
+
+            assert!(map.contains_key(&2));
+            assert!(!map.contains_key(&5));
+
+            map.debug_print();
+
+            let mut map = SHashMap::default();
+            for i in 0..100 {
+                assert!(map.insert(i, i).unwrap().is_none());
+            }
+
+            for i in 0..100 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+
+            for i in 0..100 {
+                assert_eq!(map.remove(&(99 - i)).unwrap(), 99 - i);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn removes_work() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new();
+
+            for i in 0..500 {
+                map.insert(499 - i, i);
+            }
+
+            let mut vec = (200..300).collect::<Vec<_>>();
+            vec.shuffle(&mut thread_rng());
+
+            for i in vec {
+                map.remove(&i);
+            }
+
+            for i in 500..5000 {
+                map.insert(i, i);
+            }
+
+            for i in 200..300 {
+                map.insert(i, i);
+            }
+
+            let mut vec = (0..5000).collect::<Vec<_>>();
+            vec.shuffle(&mut thread_rng());
+
+            for i in vec {
+                map.remove(&i);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn capacity_bytes_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::<u64, u32>::new_with_capacity(100).unwrap();
+            assert_eq!(
+                map.capacity_bytes(),
+                SHashMap::<u64, u32>::bytes_for_capacity(136)
+            );
+            // 100 entries need 136 slots to stay under the load factor
+            assert_eq!(map.capacity_bytes(), 1768);
+
+            let allocated_size = get_allocated_size();
+            assert!(allocated_size >= map.capacity_bytes() as u64);
+
+            for i in 0..100 {
+                map.insert(i, i as u32).unwrap();
             }
+
+            assert_eq!(
+                map.capacity_bytes(),
+                SHashMap::<u64, u32>::bytes_for_capacity(map.capacity())
+            );
         }
 
-        self.len = 0;
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    /// Filters this [SHashMap], so only entries for which the provided lambda returns [true] are left
-    pub fn retain<F>(&mut self, mut f: F)
-    where
-        F: FnMut(&K, &V) -> bool,
-    {
-        if self.is_empty() {
-            return;
-        }
+    #[test]
+    fn reserve_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        for i in 0..self.cap {
-            if let Some(mut k) = self.read_and_disown_key(i) {
-                let mut v = self.read_and_disown_val(i);
-                if f(&k, &v) {
-                    unsafe {
-                        k.stable_drop_flag_off();
-                        v.stable_drop_flag_off();
+        {
+            for scheme in [
+                IndexingScheme::Modulo,
+                IndexingScheme::Fibonacci,
+                IndexingScheme::Prime,
+            ] {
+                for n in [0, 1, 2, 3, 10, 100, 1000] {
+                    let mut map = SHashMap::new_with_capacity_and_scheme(n, scheme).unwrap();
+                    let ptr = map.table_ptr;
+
+                    for i in 0..n {
+                        map.insert(i, i).unwrap();
+                    }
+
+                    assert_eq!(map.table_ptr, ptr);
+
+                    let mut map = SHashMap::new_with_scheme(scheme);
+                    map.reserve(n).unwrap();
+
+                    // the table is allocated right away
+                    assert_ne!(map.table_ptr, EMPTY_PTR);
+                    let ptr = map.table_ptr;
+
+                    for i in 0..n {
+                        map.insert(i, i).unwrap();
+                    }
+
+                    assert_eq!(map.table_ptr, ptr);
+
+                    map.reserve(n).unwrap();
+                    let ptr = map.table_ptr;
+
+                    for i in n..(n * 2) {
+                        map.insert(i, i).unwrap();
+                    }
+
+                    assert_eq!(map.table_ptr, ptr);
+                    assert_eq!(map.len(), n * 2);
+
+                    for i in 0..(n * 2) {
+                        assert_eq!(*map.get(&i).unwrap(), i);
                     }
 
-                    continue;
+                    // already enough space
+                    map.reserve(0).unwrap();
+                    assert_eq!(map.table_ptr, ptr);
                 }
+            }
+        }
 
-                self.write_and_own_key(i, None);
-                self.len -= 1;
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn auto_shrink_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new();
+            for i in 0..1000u64 {
+                map.insert(i, i).unwrap();
+            }
+
+            let capacity = map.capacity();
+            for i in 0..990u64 {
+                map.remove(&i);
             }
+            assert_eq!(map.capacity(), capacity);
 
-            continue;
+            map.set_auto_shrink(true);
+
+            let mut buf = vec![0u8; SHashMap::<u64, u64>::SIZE];
+            map.flush(&mut buf);
+            let restored = SHashMap::<u64, u64>::from_fixed_size_bytes(&buf);
+            assert!(restored.auto_shrink());
+            assert_eq!(restored.capacity(), capacity);
+
+            map.remove(&990);
+            assert!(map.capacity() < capacity);
+            assert!(map.auto_shrink());
+
+            for i in 991..1000u64 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+
+            for i in 0..1000u64 {
+                map.insert(i, i).unwrap();
+            }
+            for i in 0..1000u64 {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            assert!(map.is_empty());
+            assert!(map.capacity() < 16);
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn hash<T: Hash + ?Sized>(val: &T) -> KeyHash {
-        let mut hasher = ZwoHasher::default();
-        val.hash(&mut hasher);
+    #[test]
+    fn canonical_iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        hasher.finish() as KeyHash
-    }
+        {
+            let mut m1 = SHashMap::new();
+            let mut m2 = SHashMap::new_with_capacity(200).unwrap();
 
-    fn remove_by_idx(&mut self, idx: usize) -> V {
-        let prev_value = self.read_and_disown_val(idx);
-        self.read_and_disown_key(idx).unwrap();
+            for i in 0..100u64 {
+                m1.insert(i, i * 2).unwrap();
+            }
 
-        let mut i = idx;
-        let mut j = idx;
+            for i in (0..150u64).rev() {
+                m2.insert(i, 0).unwrap();
+            }
 
-        loop {
-            j = (j + 1) % self.capacity();
-            if j == idx {
-                break;
+            for i in 100..150u64 {
+                m2.remove(&i);
             }
 
-            if let Some(next_key) = self.read_key_for_reference(j) {
-                let k = Self::hash(&next_key) % self.capacity();
+            for i in 0..100u64 {
+                m2.insert(i, i * 2).unwrap();
+            }
 
-                if (j < i) ^ (k <= i) ^ (k > j) {
-                    self.write_and_own_key(i, Some(next_key));
-                    self.write_and_own_val(i, self.read_and_disown_val(j));
+            assert!(m1.iter().map(|(k, _)| *k).ne(m2.iter().map(|(k, _)| *k)));
+            assert!(m1.canonical_iter().eq(m2.canonical_iter()));
+            assert!(m1.canonical_iter().eq((0..100).map(|i| (i, i * 2))));
+        }
 
-                    i = j;
-                }
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-                continue;
+    #[test]
+    fn keys_and_into_keys_values_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::new();
+
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(i * 2).unwrap()).unwrap();
             }
 
-            break;
-        }
+            let mut keys = map.keys().map(|k| *k).collect::<Vec<_>>();
+            keys.sort();
+            assert_eq!(keys, (0..100).collect::<Vec<_>>());
 
-        self.write_and_own_key(i, None);
-        self.len -= 1;
+            let mut keys = map.into_keys().collect::<Vec<_>>();
+            keys.sort();
+            assert_eq!(keys, (0..100).collect::<Vec<_>>());
 
-        prev_value
-    }
+            let mut map = SHashMap::new();
 
-    fn find_inner_idx<Q>(&self, key: &Q) -> Option<usize>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized,
-    {
-        if self.is_empty() {
-            return None;
-        }
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(i * 2).unwrap()).unwrap();
+            }
 
-        let key_hash = Self::hash(key);
-        let mut i = key_hash % self.capacity();
+            let mut values = map.into_values().map(|v| *v).collect::<Vec<_>>();
+            values.sort();
+            assert_eq!(values, (0..100).map(|i| i * 2).collect::<Vec<_>>());
 
-        loop {
-            if (*self.get_key(i)?).borrow().eq(key) {
-                return Some(i);
-            } else {
-                i = (i + 1) % self.capacity();
+            let mut map = SHashMap::new();
+
+            for i in 0..100u64 {
+                map.insert(i, SBox::new(i * 2).unwrap()).unwrap();
             }
+
+            // partially consumed - the rest is released on drop
+            assert_eq!(map.into_values().take(10).count(), 10);
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn get_key(&self, idx: usize) -> Option<SRef<K>> {
-        let ptr = self.get_key_flag_ptr(idx);
-        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+    #[test]
+    fn fibonacci_scheme_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        match flag {
-            EMPTY => None,
-            OCCUPIED => Some(unsafe { SRef::new(ptr + 1) }),
-            _ => unreachable!(),
+        {
+            let mut map = SHashMap::new_with_scheme(IndexingScheme::Fibonacci);
+            map.set_auto_shrink(true);
+
+            for i in 0..1000u64 {
+                map.insert(SBox::new(i).unwrap(), i).unwrap();
+                assert!(map.capacity().is_power_of_two());
+            }
+
+            let mut buf = vec![0u8; SHashMap::<SBox<u64>, u64>::SIZE];
+            map.flush(&mut buf);
+            let restored = SHashMap::<SBox<u64>, u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(restored.indexing_scheme(), IndexingScheme::Fibonacci);
+            assert_eq!(restored.capacity(), map.capacity());
+            assert_eq!(*restored.get(&500).unwrap(), 500);
+
+            for i in 0..990u64 {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            assert!(map.capacity().is_power_of_two());
+            assert!(map.capacity() < 64);
+
+            for i in 990..1000u64 {
+                assert_eq!(*map.get(&i).unwrap(), i);
+            }
+
+            let map = map.migrate_values(|v| v as u32);
+            assert_eq!(map.indexing_scheme(), IndexingScheme::Fibonacci);
+            assert_eq!(*map.get(&995).unwrap(), 995);
+
+            let map =
+                SHashMap::<u64, u64>::new_with_capacity_and_scheme(100, IndexingScheme::Fibonacci)
+                    .unwrap();
+            assert_eq!(map.capacity(), 256);
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn read_and_disown_key(&self, idx: usize) -> Option<K> {
-        let ptr = self.get_key_flag_ptr(idx);
-        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+    #[test]
+    fn prime_scheme_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        match flag {
-            EMPTY => None,
-            OCCUPIED => Some(unsafe { crate::mem::read_fixed_for_move(ptr + 1) }),
-            _ => unreachable!(),
+        fn is_prime(n: usize) -> bool {
+            n > 1
+                && (2..)
+                    .take_while(|d| d * d <= n)
+                    .all(|d| !n.is_multiple_of(d))
         }
-    }
 
-    fn read_key_for_reference(&self, idx: usize) -> Option<K> {
-        let ptr = self.get_key_flag_ptr(idx);
-        let flag: u8 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+        {
+            let mut map = SHashMap::new_with_scheme(IndexingScheme::Prime);
 
-        match flag {
-            EMPTY => None,
-            OCCUPIED => Some(unsafe { crate::mem::read_fixed_for_reference(ptr + 1) }),
-            _ => unreachable!(),
+            for i in 0..1000u64 {
+                map.insert(i * 5, i).unwrap();
+                assert!(is_prime(map.capacity()));
+            }
+
+            let mut buf = vec![0u8; SHashMap::<u64, u64>::SIZE];
+            map.flush(&mut buf);
+            let restored = SHashMap::<u64, u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(restored.indexing_scheme(), IndexingScheme::Prime);
+            assert_eq!(restored.capacity(), map.capacity());
+            assert_eq!(*restored.get(&500).unwrap(), 100);
+
+            let map = SHashMap::<u64, u64>::new_with_capacity_and_scheme(10, IndexingScheme::Prime)
+                .unwrap();
+            assert_eq!(map.capacity(), 17);
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn write_and_own_key(&mut self, idx: usize, key: Option<K>) {
-        let ptr = self.get_key_flag_ptr(idx);
+    #[test]
+    fn quadratic_probe_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-        if let Some(mut k) = key {
-            unsafe { crate::mem::write_fixed(ptr, &mut OCCUPIED) };
-            unsafe { crate::mem::write_fixed(ptr + 1, &mut k) };
+        {
+            let mut map = SHashMap::new();
+            map.set_probe(ProbeSequence::Quadratic).unwrap();
+            assert_eq!(map.capacity(), 8);
+
+            let mut example = HashMap::new();
+            let mut rng = thread_rng();
+
+            for _ in 0..5000 {
+                let k = rng.gen_range(0..300u64);
+
+                if rng.gen_bool(0.6) {
+                    assert_eq!(
+                        map.insert(SBox::new(k).unwrap(), k * 2).unwrap(),
+                        example.insert(k, k * 2)
+                    );
+                } else {
+                    assert_eq!(map.remove(&k), example.remove(&k));
+                }
 
-            return;
+                assert!(map.capacity().is_power_of_two());
+                assert!(map.len() + map.tombstones() <= map.capacity() / 4 * 3);
+            }
+
+            assert_eq!(map.len(), example.len());
+            for (k, v) in example.iter() {
+                assert_eq!(*map.get(k).unwrap(), *v);
+            }
+
+            // removes only leave tombstones, so the capacity stays the same
+            let capacity = map.capacity();
+            for k in 0..300u64 {
+                map.remove(&k);
+            }
+            assert!(map.is_empty());
+            assert!(map.tombstones() > 0);
+            assert_eq!(map.capacity(), capacity);
+
+            // tombstones get reused or cleaned up by rehashing into a table of the same capacity
+            for k in 0..300u64 {
+                map.insert(SBox::new(k).unwrap(), k).unwrap();
+            }
+            assert_eq!(map.capacity(), capacity);
+            assert_eq!(map.len(), 300);
+
+            map.retain(|k, _| **k % 2 == 0);
+            assert_eq!(map.len(), 150);
+
+            *map.entry(SBox::new(1).unwrap()).or_insert(0) += 10;
+            *map.entry(SBox::new(2).unwrap()).or_insert(0) += 10;
+            assert_eq!(*map.get(&1).unwrap(), 10);
+            assert_eq!(*map.get(&2).unwrap(), 12);
+
+            let mut buf = vec![0u8; SHashMap::<SBox<u64>, u64>::SIZE];
+            map.flush(&mut buf);
+            let restored = SHashMap::<SBox<u64>, u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(restored.probe_sequence(), ProbeSequence::Quadratic);
+            assert_eq!(restored.capacity(), map.capacity());
+            assert_eq!(restored.tombstones(), map.tombstones());
+            assert_eq!(*restored.get(&100).unwrap(), 100);
+            assert!(restored.get(&101).is_none());
+
+            map.set_probe(ProbeSequence::Linear).unwrap();
+            assert_eq!(map.probe_sequence(), ProbeSequence::Linear);
+            assert_eq!(map.len(), 151);
+            assert_eq!(*map.get(&1).unwrap(), 10);
+            assert_eq!(*map.get(&100).unwrap(), 100);
+
+            map.clear();
+            map.set_probe(ProbeSequence::Quadratic).unwrap();
+            map.insert(SBox::new(1).unwrap(), 1).unwrap();
+            map.remove(&1);
+            map.clear();
+            assert_eq!(map.tombstones(), 0);
         }
 
-        unsafe { crate::mem::write_fixed(ptr, &mut EMPTY) };
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn get_val(&self, idx: usize) -> SRef<V> {
-        unsafe { SRef::new(self.get_value_ptr(idx)) }
-    }
+    #[test]
+    fn custom_hashers_work_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    #[inline]
-    fn get_val_mut(&self, idx: usize) -> SRefMut<V> {
-        unsafe { SRefMut::new(self.get_value_ptr(idx)) }
-    }
+        {
+            // the default hasher doesn't change the layout of the header
+            assert_eq!(SHashMap::<u64, u64>::SIZE, u64::SIZE + usize::SIZE * 2);
 
-    #[inline]
-    fn read_and_disown_val(&self, idx: usize) -> V {
-        unsafe { crate::mem::read_fixed_for_move(self.get_value_ptr(idx)) }
-    }
+            let mut map =
+                SHashMap::<SBox<u64>, u64, _>::with_hasher(SipHasherFactory::new([7; 16]));
+            let other = SHashMap::<SBox<u64>, u64, _>::with_hasher(SipHasherFactory::new([8; 16]));
+            assert_ne!(map.hash_key(&1u64), other.hash_key(&1u64));
 
-    #[inline]
-    fn write_and_own_val(&mut self, idx: usize, mut val: V) {
-        unsafe { crate::mem::write_fixed(self.get_value_ptr(idx), &mut val) }
-    }
+            for i in 0..1000u64 {
+                map.insert(SBox::new(i).unwrap(), i).unwrap();
+            }
 
-    #[inline]
-    fn get_value_ptr(&self, idx: usize) -> StablePtr {
-        SSlice::_offset(
-            self.table_ptr,
-            (values_offset::<K>(self.capacity()) + V::SIZE * idx) as u64,
-        )
-    }
+            let hash = map.hash_key(&1000u64);
+            map.insert_prehashed(hash, SBox::new(1000).unwrap(), 1000)
+                .unwrap();
 
-    #[inline]
-    fn get_key_flag_ptr(&self, idx: usize) -> StablePtr {
-        SSlice::_offset(self.table_ptr, (KEYS_OFFSET + (1 + K::SIZE) * idx) as u64)
-    }
+            let buf = map.as_new_fixed_size_bytes();
+            let restored =
+                SHashMap::<SBox<u64>, u64, SipHasherFactory>::from_fixed_size_bytes(buf._deref());
+            assert_eq!(restored.hash_key(&1u64), map.hash_key(&1u64));
+            for i in 0..=1000u64 {
+                assert_eq!(*restored.get(&i).unwrap(), i);
+            }
 
-    #[inline]
-    fn get_key_data_ptr(&self, idx: usize) -> StablePtr {
-        SSlice::_offset(
-            self.table_ptr,
-            (KEYS_OFFSET + (1 + K::SIZE) * idx + 1) as u64,
-        )
-    }
+            let mut map = SHashMap::<u64, u64, _>::with_scheme_and_hasher(
+                IndexingScheme::Fibonacci,
+                IdentityHasherFactory,
+            );
+            assert_eq!(map.hash_key(&12345u64), 12345);
 
-    /// Prints byte representation of this [SHashMap]
-    ///
-    /// Useful for tests
-    pub fn debug_print(&self) {
-        print!("Node({}, {})[", self.len(), self.capacity());
-        for i in 0..self.capacity() {
-            let k_flag: u8 =
-                unsafe { crate::mem::read_fixed_for_reference(self.get_key_flag_ptr(i)) };
-            let mut k_buf = K::Buf::new(K::SIZE);
-            let mut v_buf = V::Buf::new(V::SIZE);
+            for i in 0..1000u64 {
+                map.insert(i, i).unwrap();
+            }
+            for i in 0..500u64 {
+                assert_eq!(map.remove(&i), Some(i));
+            }
 
-            unsafe { crate::mem::read_bytes(self.get_key_data_ptr(i), k_buf._deref_mut()) };
-            unsafe { crate::mem::read_bytes(self.get_value_ptr(i), v_buf._deref_mut()) };
+            let map = map.migrate_values(|v| v as u32);
+            assert_eq!(map.hash_key(&12345u64), 12345);
+            for i in 500..1000u64 {
+                assert_eq!(*map.get(&i).unwrap(), i as u32);
+            }
+        }
 
-            print!("(");
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-            match k_flag {
-                EMPTY => print!("<empty> = "),
-                OCCUPIED => print!("<occupied> = "),
-                _ => unreachable!(),
-            };
+    #[test]
+    fn insert_prehashed_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::<u64, u64>::new();
+
+            for i in 0..1000 {
+                let hash = SHashMap::<u64, u64>::key_hash(&i);
+                assert!(map.insert_prehashed(hash, i, i).unwrap().is_none());
+            }
 
-            print!("{:?}, {:?})", k_buf._deref(), v_buf._deref());
+            let hash = SHashMap::<u64, u64>::key_hash(&10);
+            assert_eq!(map.insert_prehashed(hash, 10, 0).unwrap(), Some(10));
 
-            if i < self.capacity() - 1 {
-                print!(", ");
+            for i in 0..1000 {
+                let expected = if i == 10 { 0 } else { i };
+                assert_eq!(*map.get(&i).unwrap(), expected);
             }
         }
-        println!("]");
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-impl<
-        K: StableType + AsFixedSizeBytes + Hash + Eq + Debug,
-        V: StableType + AsFixedSizeBytes + Debug,
-    > Debug for SHashMap<K, V>
-{
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("{")?;
-        for (idx, (k, v)) in self.iter().enumerate() {
-            k.fmt(f)?;
-            f.write_str(": ")?;
-            v.fmt(f)?;
+    #[test]
+    fn migrate_values_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            if idx < self.len() - 1 {
-                f.write_str(", ")?;
+        {
+            let mut map = SHashMap::new();
+            for i in 0..1000u64 {
+                map.insert(i, SBox::new(i).unwrap()).unwrap();
+            }
+
+            let map = map.migrate_values(|v| (*v, SBox::new(v.into_inner() * 2).unwrap()));
+
+            assert_eq!(map.len(), 1000);
+
+            for i in 0..1000u64 {
+                let v = map.get(&i).unwrap();
+                assert_eq!(v.0, i);
+                assert_eq!(*v.1, i * 2);
             }
+
+            let map = SHashMap::<u64, u64>::new().migrate_values(|v| v as u32);
+            assert!(map.is_empty());
         }
-        f.write_str("}")
-    }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Default
-    for SHashMap<K, V>
-{
-    #[inline]
-    fn default() -> Self {
-        Self::new()
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes>
-    AsFixedSizeBytes for SHashMap<K, V>
-{
-    const SIZE: usize = u64::SIZE + usize::SIZE * 2;
-    type Buf = [u8; u64::SIZE + usize::SIZE * 2];
+    #[test]
+    fn entry_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.table_ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.len
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
-        self.cap.as_fixed_size_bytes(
-            &mut buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)],
-        );
-    }
+        {
+            let mut map = SHashMap::<u64, SBox<u64>>::new();
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let table_ptr = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
-        let len = usize::from_fixed_size_bytes(&buf[u64::SIZE..(usize::SIZE + u64::SIZE)]);
-        let cap = usize::from_fixed_size_bytes(
-            &buf[(usize::SIZE + u64::SIZE)..(usize::SIZE * 2 + u64::SIZE)],
-        );
+            // the table is not allocated yet
+            assert_eq!(**map.entry(0).or_insert(SBox::new(0).unwrap()), 0);
 
-        Self {
-            table_ptr,
-            len,
-            cap,
-            stable_drop_flag: false,
-            _marker_k: PhantomData::default(),
-            _marker_v: PhantomData::default(),
-        }
-    }
-}
+            let mut cap = map.capacity();
+            let mut resizes = 0;
 
-impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> StableType
-    for SHashMap<K, V>
-{
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.stable_drop_flag = false;
-    }
+            for i in 1..100u64 {
+                let was_full = map.is_full();
 
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.stable_drop_flag = true;
-    }
+                {
+                    let mut v = map.entry(i).or_insert_with(|| SBox::new(0).unwrap());
+                    assert_eq!(**v, 0);
 
-    #[inline]
-    fn should_stable_drop(&self) -> bool {
-        self.stable_drop_flag
-    }
+                    // the reference points to the new table after the resize
+                    v.with(|it| *it = i).unwrap();
+                }
 
-    unsafe fn stable_drop(&mut self) {
-        if self.table_ptr != EMPTY_PTR {
-            self.clear();
+                if was_full {
+                    assert!(map.capacity() > cap);
+                    cap = map.capacity();
+                    resizes += 1;
+                } else {
+                    assert_eq!(map.capacity(), cap);
+                }
 
-            let slice = SSlice::from_ptr(self.table_ptr).unwrap();
-            deallocate(slice);
-        }
-    }
-}
+                assert_eq!(map.len(), i as usize + 1);
+            }
 
-impl<K: StableType + AsFixedSizeBytes + Hash + Eq, V: StableType + AsFixedSizeBytes> Drop
-    for SHashMap<K, V>
-{
-    fn drop(&mut self) {
-        if self.should_stable_drop() {
-            unsafe {
-                self.stable_drop();
+            assert!(resizes > 0);
+
+            for i in 0..100u64 {
+                assert_eq!(**map.get(&i).unwrap(), i);
+
+                map.entry(i)
+                    .and_modify(|mut v| v.with(|it| *it *= 2).unwrap())
+                    .or_insert(SBox::new(0).unwrap());
+            }
+
+            assert_eq!(map.len(), 100);
+
+            for i in 0..100u64 {
+                assert_eq!(**map.get(&i).unwrap(), i * 2);
+            }
+
+            for i in 0..50u64 {
+                match map.entry(i) {
+                    Entry::Occupied(e) => assert_eq!(*e.remove(), i * 2),
+                    Entry::Vacant(_) => unreachable!(),
+                }
+            }
+
+            match map.entry(10) {
+                Entry::Occupied(_) => unreachable!(),
+                Entry::Vacant(e) => assert_eq!(*e.key(), 10),
+            }
+
+            for i in 50..100u64 {
+                match map.entry(i) {
+                    Entry::Occupied(mut e) => {
+                        assert_eq!(**e.get(), i * 2);
+                        assert_eq!(*e.insert(SBox::new(i).unwrap()), i * 2);
+                    }
+                    Entry::Vacant(_) => unreachable!(),
+                }
+            }
+
+            assert_eq!(map.len(), 50);
+
+            for i in 0..100u64 {
+                assert_eq!(map.get(&i).map(|v| **v), (i >= 50).then_some(i));
             }
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::collections::hash_map::SHashMap;
-    use crate::encoding::AsFixedSizeBytes;
-    use crate::primitive::s_box::SBox;
-    use crate::primitive::StableType;
-    use crate::utils::mem_context::stable;
-    use crate::utils::test::generate_random_string;
-    use crate::utils::DebuglessUnwrap;
-    use crate::{
-        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
-        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
-        store_custom_data,
-    };
-    use rand::rngs::ThreadRng;
-    use rand::seq::SliceRandom;
-    use rand::{thread_rng, Rng};
-    use std::collections::HashMap;
-    use std::ops::Deref;
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
     #[test]
-    fn simple_flow_works_well() {
+    fn retain_mut_works_fine() {
         stable::clear();
         stable_memory_init();
 
         {
-            let mut map = SHashMap::new_with_capacity(3).debugless_unwrap();
+            let mut map = SHashMap::new();
+            for i in 0..1000u64 {
+                map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                    .unwrap();
+            }
 
-            let k1 = 1;
-            let k2 = 2;
-            let k3 = 3;
-            let k4 = 4;
-            let k5 = 5;
-            let k6 = 6;
-            let k7 = 7;
-            let k8 = 8;
+            map.retain_mut(|k, v| {
+                if **k % 3 == 0 {
+                    return false;
+                }
 
-            map.insert(k1, 1);
-            map.insert(k2, 2);
-            map.insert(k3, 3);
-            map.insert(k4, 4);
-            map.insert(k5, 5);
-            map.insert(k6, 6);
-            map.insert(k7, 7);
-            map.insert(k8, 8);
+                v.with(|it| *it *= 2).unwrap();
+                true
+            });
 
-            assert_eq!(*map.get(&k1).unwrap(), 1);
-            assert_eq!(*map.get(&k2).unwrap(), 2);
-            assert_eq!(*map.get(&k3).unwrap(), 3);
-            assert_eq!(*map.get(&k4).unwrap(), 4);
-            assert_eq!(*map.get(&k5).unwrap(), 5);
-            assert_eq!(*map.get(&k6).unwrap(), 6);
-            assert_eq!(*map.get(&k7).unwrap(), 7);
-            assert_eq!(*map.get(&k8).unwrap(), 8);
+            assert_eq!(map.len(), 666);
 
-            assert!(map.get(&9).is_none());
-            assert!(map.get(&0).is_none());
+            for i in 0..1000u64 {
+                let k = SBox::new(i).unwrap();
 
-            assert_eq!(map.remove(&k3).unwrap(), 3);
-            assert!(map.get(&k3).is_none());
+                if i % 3 == 0 {
+                    assert!(map.get(&k).is_none());
+                } else {
+                    assert_eq!(**map.get(&k).unwrap(), i * 2);
+                }
+            }
 
-            assert_eq!(map.remove(&k1).unwrap(), 1);
-            assert!(map.get(&k1).is_none());
+            map.retain(|k, _| **k % 2 == 0);
+            assert_eq!(map.len(), 333);
 
-            assert_eq!(map.remove(&k5).unwrap(), 5);
-            assert!(map.get(&k5).is_none());
+            for i in 0..1000u64 {
+                let k = SBox::new(i).unwrap();
+                assert_eq!(map.contains_key(&k), i % 3 != 0 && i % 2 == 0);
+            }
 
-            assert_eq!(map.remove(&k7).unwrap(), 7);
-            assert!(map.get(&k7).is_none());
+            map.retain_mut(|_, _| false);
+            assert!(map.is_empty());
 
-            assert_eq!(*map.get(&k2).unwrap(), 2);
-            assert_eq!(*map.get(&k4).unwrap(), 4);
-            assert_eq!(*map.get(&k6).unwrap(), 6);
-            assert_eq!(*map.get(&k8).unwrap(), 8);
+            // values replaced by the lambda are released exactly once, whether retained or not
+            for probe in [ProbeSequence::Linear, ProbeSequence::Quadratic] {
+                map.set_probe(probe).unwrap();
+
+                for i in 0..100u64 {
+                    map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                        .unwrap();
+                }
+
+                map.retain_mut(|k, v| {
+                    *v = SBox::new(**k + 1).unwrap();
+                    **k % 4 == 0
+                });
+
+                assert_eq!(map.len(), 25);
+                for (k, v) in map.iter() {
+                    assert_eq!(**v, **k + 1);
+                }
+
+                map.clear();
+            }
         }
 
         _debug_validate_allocator();
@@ -785,42 +2762,89 @@ mod tests {
     }
 
     #[test]
-    fn basic_flow_works_fine() {
+    fn drain_works_fine() {
         stable::clear();
         stable_memory_init();
 
         {
-            let mut map = SHashMap::new_with_capacity(3).debugless_unwrap();
+            for probe in [ProbeSequence::Linear, ProbeSequence::Quadratic] {
+                let mut map = SHashMap::new();
+                map.set_probe(probe).unwrap();
 
-            assert!(map.remove(&10).is_none());
-            assert!(map.get(&10).is_none());
+                for i in 0..100u64 {
+                    map.insert(SBox::new(i).unwrap(), SBox::new(i * 2).unwrap())
+                        .unwrap();
+                }
+                map.remove(&0);
+
+                let capacity = map.capacity();
+
+                let mut drained = map
+                    .drain()
+                    .map(|(k, v)| {
+                        assert_eq!(*v, *k * 2);
+                        (k.into_inner(), v.into_inner())
+                    })
+                    .collect::<Vec<_>>();
+                drained.sort();
+
+                assert_eq!(drained, (1..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+                assert!(map.is_empty());
+                assert_eq!(map.capacity(), capacity);
+                assert!(map.get(&1).is_none());
+
+                for i in 0..100u64 {
+                    map.insert(SBox::new(i).unwrap(), SBox::new(i).unwrap())
+                        .unwrap();
+                }
 
-            let it = map.insert(1, 1).unwrap();
-            assert!(it.is_none());
-            assert!(map.insert(2, 2).unwrap().is_none());
-            assert!(map.insert(3, 3).unwrap().is_none());
-            assert_eq!(map.insert(1, 10).unwrap().unwrap(), 1);
+                // dropped early - the rest of the entries are released
+                let first = map.drain().take(10).count();
+                assert_eq!(first, 10);
+                assert!(map.is_empty());
+                assert_eq!(map.tombstones(), 0);
 
-            assert!(map.remove(&5).is_none());
-            assert_eq!(map.remove(&1).unwrap(), 10);
+                map.insert(SBox::new(1).unwrap(), SBox::new(1).unwrap())
+                    .unwrap();
+                assert_eq!(**map.get(&1).unwrap(), 1);
+            }
+        }
 
-            assert!(map.contains_key(&2));
-            assert!(!map.contains_key(&5));
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 
-            map.debug_print();
+    #[test]
+    fn get_disjoint_mut_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut map = SHashMap::<u64, u64>::new();
 
-            let mut map = SHashMap::default();
             for i in 0..100 {
-                assert!(map.insert(i, i).unwrap().is_none());
+                map.insert(i, i).unwrap();
             }
 
-            for i in 0..100 {
-                assert_eq!(*map.get(&i).unwrap(), i);
+            for i in 0..50 {
+                let (a, b) = map.get_disjoint_mut(&i, &(99 - i));
+                let (mut a, mut b) = (a.unwrap(), b.unwrap());
+
+                std::mem::swap(&mut *a, &mut *b);
             }
 
             for i in 0..100 {
-                assert_eq!(map.remove(&(99 - i)).unwrap(), 99 - i);
+                assert_eq!(*map.get(&i).unwrap(), 99 - i);
+            }
+
+            {
+                let (a, b) = map.get_disjoint_mut(&1000, &10);
+                assert!(a.is_none());
+                assert_eq!(*b.unwrap(), 89);
             }
+
+            let (a, b) = map.get_disjoint_mut(&1000, &1000);
+            assert!(a.is_none() && b.is_none());
         }
 
         _debug_validate_allocator();
@@ -828,37 +2852,38 @@ mod tests {
     }
 
     #[test]
-    fn removes_work() {
+    #[should_panic]
+    fn get_disjoint_mut_same_key_should_panic() {
         stable::clear();
         stable_memory_init();
 
-        {
-            let mut map = SHashMap::new();
+        let mut map = SHashMap::<u64, u64>::new();
+        map.insert(1, 1).unwrap();
 
-            for i in 0..500 {
-                map.insert(499 - i, i);
-            }
+        let _ = map.get_disjoint_mut(&1, &1);
+    }
 
-            let mut vec = (200..300).collect::<Vec<_>>();
-            vec.shuffle(&mut thread_rng());
+    #[test]
+    fn raw_parts_work_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            for i in vec {
-                map.remove(&i);
-            }
+        {
+            let map = SHashMap::<u64, u64>::new();
+            let (table_ptr, len, cap) = map.into_raw_parts();
+            let mut map = unsafe { SHashMap::<u64, u64>::from_raw_parts(table_ptr, len, cap) };
 
-            for i in 500..5000 {
-                map.insert(i, i);
+            for i in 0..100 {
+                map.insert(i, i * 2).unwrap();
             }
 
-            for i in 200..300 {
-                map.insert(i, i);
-            }
+            let (table_ptr, len, cap) = map.into_raw_parts();
+            assert_eq!(len, 100);
 
-            let mut vec = (0..5000).collect::<Vec<_>>();
-            vec.shuffle(&mut thread_rng());
+            let map = unsafe { SHashMap::<u64, u64>::from_raw_parts(table_ptr, len, cap) };
 
-            for i in vec {
-                map.remove(&i);
+            for i in 0..100 {
+                assert_eq!(*map.get(&i).unwrap(), i * 2);
             }
         }
 
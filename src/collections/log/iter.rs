@@ -14,6 +14,7 @@ struct CurSector {
 pub struct SLogIter<'a, T: StableType + AsFixedSizeBytes> {
     log: &'a SLog<T>,
     cur_sector: Option<CurSector>,
+    remaining: u64,
 }
 
 impl<'a, T: StableType + AsFixedSizeBytes> SLogIter<'a, T> {
@@ -21,6 +22,7 @@ impl<'a, T: StableType + AsFixedSizeBytes> SLogIter<'a, T> {
         Self {
             log,
             cur_sector: None,
+            remaining: log.len(),
         }
     }
 
@@ -46,15 +48,12 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SLogIter<'a, T> {
     type Item = SRef<'a, T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.log.is_empty() {
+        if self.remaining == 0 {
             return None;
         }
 
         self.init_from_back();
 
-        let p = self.log.cur_sector_ptr;
-        let c = self.log.cur_sector_capacity;
-
         let cur_sector = self.get_cur_sector_mut();
 
         if cur_sector.ptr == EMPTY_PTR {
@@ -65,17 +64,18 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SLogIter<'a, T> {
         let ptr = sector.get_element_ptr(cur_sector.idx * T::SIZE as u64);
 
         if cur_sector.idx == 0 {
-            cur_sector.len = if cur_sector.ptr == p {
-                c / 2
-            } else {
-                cur_sector.len / 2
-            };
             cur_sector.ptr = sector.read_prev_ptr();
-            cur_sector.idx = cur_sector.len - 1;
+
+            if cur_sector.ptr != EMPTY_PTR {
+                cur_sector.len = Sector::<T>::from_ptr(cur_sector.ptr).read_capacity();
+                cur_sector.idx = cur_sector.len - 1;
+            }
         } else {
             cur_sector.idx -= 1;
         }
 
+        self.remaining -= 1;
+
         unsafe { Some(SRef::new(ptr)) }
     }
 }
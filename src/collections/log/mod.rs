@@ -6,6 +6,7 @@ use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
 use crate::primitive::StableType;
 use crate::{allocate, deallocate, OutOfMemory, SSlice};
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 
@@ -35,10 +36,12 @@ pub(crate) const DEFAULT_CAPACITY: u64 = 2;
 pub struct SLog<T: StableType + AsFixedSizeBytes> {
     len: u64,
     first_sector_ptr: StablePtr,
+    first_sector_first_item_idx: u64,
     cur_sector_ptr: StablePtr,
     cur_sector_last_item_offset: u64,
     cur_sector_capacity: u64,
     cur_sector_len: u64,
+    max_sector_capacity: u64,
     stable_drop_flag: bool,
     _marker: PhantomData<T>,
 }
@@ -52,15 +55,95 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         Self {
             len: 0,
             first_sector_ptr: EMPTY_PTR,
+            first_sector_first_item_idx: 0,
             cur_sector_ptr: EMPTY_PTR,
             cur_sector_last_item_offset: 0,
             cur_sector_capacity: DEFAULT_CAPACITY,
             cur_sector_len: 0,
+            max_sector_capacity: 0,
             stable_drop_flag: true,
             _marker: PhantomData::default(),
         }
     }
 
+    /// Sets the capacity (in elements) of the first `Sector` of this [SLog]
+    ///
+    /// By default the first `Sector` holds 4 elements. `capacity` should be a power of two, bigger
+    /// than `2`, and the resulting `Sector` should fit into [u32::MAX] bytes. The setting is persisted
+    /// together with the [SLog] itself.
+    ///
+    /// # Panics
+    /// Panics if any of the conditions above is not met or if this [SLog] has already allocated
+    /// its first `Sector`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::<u64>::new().with_initial_capacity(64);
+    ///
+    /// log.push(10).expect("Out of memory");
+    /// ```
+    pub fn with_initial_capacity(mut self, capacity: u64) -> Self {
+        assert_eq!(
+            self.first_sector_ptr, EMPTY_PTR,
+            "The first sector is already allocated"
+        );
+        assert!(
+            capacity.is_power_of_two() && capacity > DEFAULT_CAPACITY,
+            "Initial capacity should be a power of two bigger than {}",
+            DEFAULT_CAPACITY
+        );
+        assert!(
+            capacity <= (u32::MAX as u64 - ELEMENTS_OFFSET) / T::SIZE as u64,
+            "Initial capacity is too big"
+        );
+
+        self.cur_sector_capacity = capacity / 2;
+
+        self
+    }
+
+    /// Limits the size (in bytes) of element data a single `Sector` of this [SLog] can hold
+    ///
+    /// By default each new `Sector` is twice as big as the previous one. Once a `Sector` reaches
+    /// `size` bytes, all the following ones will be of the same size. `size` should be a power of two,
+    /// not bigger than `2^31`, and should be enough to store more than `2` elements. The setting
+    /// is persisted together with the [SLog] itself.
+    ///
+    /// # Panics
+    /// Panics if any of the conditions above is not met.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // each sector will hold at most 128 u64-s
+    /// let mut log = SLog::<u64>::new().with_max_sector_size(1024);
+    ///
+    /// log.push(10).expect("Out of memory");
+    /// ```
+    pub fn with_max_sector_size(mut self, size: u64) -> Self {
+        assert!(
+            size.is_power_of_two() && size <= 1 << 31,
+            "Max sector size should be a power of two not bigger than 2^31"
+        );
+
+        let max_sector_capacity = size / T::SIZE as u64;
+        assert!(
+            max_sector_capacity > DEFAULT_CAPACITY,
+            "Max sector size is too small for this element type"
+        );
+
+        self.max_sector_capacity = max_sector_capacity;
+
+        self
+    }
+
     /// Inserts a new element at the end of the [SLog]
     ///
     /// May allocate a new `Sector`. If the canister is out of stable memory, will return [Err] with
@@ -93,6 +176,82 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         }
     }
 
+    /// Inserts a new element into a sorted [SLog], keeping it sorted according to the provided lambda
+    ///
+    /// Returns the index the element was inserted at. The position is found with
+    /// [SLog::binary_search_by]. If there are elements equal to the new one, it is inserted after
+    /// them. [SLog] is not designed for insertions into the middle - all elements after the found
+    /// position are moved one slot towards the end, which is `O(n)`. This is fine for rare
+    /// out-of-order entries in an append-mostly log, but consider [SBTreeMap](crate::collections::SBTreeMap)
+    /// if the order is random.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for timestamp in [10u64, 20, 40, 50] {
+    ///     log.push(timestamp).expect("Out of memory");
+    /// }
+    ///
+    /// let idx = log.insert_sorted_by(30, |a, b| a.cmp(b));
+    ///
+    /// assert_eq!(idx, 2);
+    /// for (i, timestamp) in [10, 20, 30, 40, 50].into_iter().enumerate() {
+    ///     assert_eq!(*log.get(i as u64).unwrap(), timestamp);
+    /// }
+    /// ```
+    pub fn insert_sorted_by<FN>(&mut self, it: T, cmp: FN) -> u64
+    where
+        FN: Fn(&T, &T) -> Ordering,
+    {
+        let idx = match self.binary_search_by(|elem| match cmp(elem, &it) {
+            Ordering::Equal => Ordering::Less,
+            ord => ord,
+        }) {
+            Ok(idx) | Err(idx) => idx,
+        };
+
+        if self.push(it).is_err() {
+            panic!("Out of memory");
+        }
+
+        // move the new element from the end to its position, shifting the ones in between
+        let mut sector_ptr = self.cur_sector_ptr;
+        let mut offset = self.cur_sector_last_item_offset - T::SIZE as u64;
+        let it = Sector::<T>::from_ptr(sector_ptr).read_and_disown_element(offset);
+
+        for _ in idx..(self.len - 1) {
+            let sector = Sector::<T>::from_ptr(sector_ptr);
+
+            let (prev_sector_ptr, prev_offset) = if offset == 0 {
+                let prev_sector_ptr = sector.read_prev_ptr();
+                let prev_sector_cap = Sector::<T>::from_ptr(prev_sector_ptr).read_capacity();
+
+                (prev_sector_ptr, (prev_sector_cap - 1) * T::SIZE as u64)
+            } else {
+                (sector_ptr, offset - T::SIZE as u64)
+            };
+
+            let prev_it =
+                Sector::<T>::from_ptr(prev_sector_ptr).read_and_disown_element(prev_offset);
+            sector.write_and_own_element(offset, prev_it);
+
+            sector_ptr = prev_sector_ptr;
+            offset = prev_offset;
+        }
+
+        Sector::<T>::from_ptr(sector_ptr).write_and_own_element(offset, it);
+
+        idx
+    }
+
     /// Removes an element from the end of the [SLog]
     ///
     /// If the [SLog] is empty, returns [None]. If it was the last element of the last `Sector` and
@@ -110,17 +269,299 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
 
         let it = sector.read_and_disown_element(self.cur_sector_last_item_offset);
 
-        self.move_to_prev_sector_if_needed(sector);
+        if self.len == 0 {
+            self.reset_first_sector();
+        } else {
+            self.move_to_prev_sector_if_needed(sector);
+        }
+
+        Some(it)
+    }
+
+    /// Removes an element at the requested index, replacing it with the last element of the [SLog]
+    ///
+    /// Works the same way as [Vec::swap_remove] - takes `O(1)` writes, but does not preserve the
+    /// order of elements. The returned element is disowned, the moved one is owned by its new slot.
+    ///
+    /// If the index is out of bounds, does nothing and returns [None].
+    ///
+    /// The closer the index to `0`, the worser the performance of this call.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..5u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(log.swap_remove(1).unwrap(), 1);
+    /// assert_eq!(*log.get(1).unwrap(), 4);
+    /// assert_eq!(log.len(), 4);
+    /// assert!(log.swap_remove(4).is_none());
+    /// ```
+    pub fn swap_remove(&mut self, idx: u64) -> Option<T> {
+        if idx >= self.len {
+            return None;
+        }
+
+        let last = self.pop()?;
+
+        if idx == self.len {
+            return Some(last);
+        }
+
+        let (sector, sector_idx) = self.find_sector_for_idx(idx).unwrap();
+        let offset = sector_idx * T::SIZE as u64;
+
+        let it = sector.read_and_disown_element(offset);
+        sector.write_and_own_element(offset, last);
+
+        Some(it)
+    }
+
+    /// Removes an element from the beginning of the [SLog]
+    ///
+    /// If the [SLog] is empty, returns [None]. If it was the last element of the first `Sector` and
+    /// there are more `Sectors` after it, the first `Sector` gets deallocated, freeing the memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// log.push(10u64).expect("Out of memory");
+    /// log.push(20u64).expect("Out of memory");
+    ///
+    /// assert_eq!(log.pop_front().unwrap(), 10);
+    /// assert_eq!(*log.first().unwrap(), 20);
+    /// ```
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let sector = self.get_first_sector()?;
+        let it = sector.read_and_disown_element(self.first_sector_first_item_idx * T::SIZE as u64);
+
+        self.first_sector_first_item_idx += 1;
+        self.len -= 1;
+
+        self.move_to_next_first_sector_if_needed(sector);
 
         Some(it)
     }
 
+    /// Removes up to `n` elements from the beginning of the [SLog], returning them in order
+    ///
+    /// Same as calling [SLog::pop_front] `n` times, but processes whole `Sectors` at once. Emptied
+    /// `Sectors` get deallocated, freeing the memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let batch = log.drain_front(10);
+    ///
+    /// assert_eq!(batch, (0..10).collect::<Vec<_>>());
+    /// assert_eq!(log.len(), 90);
+    /// ```
+    pub fn drain_front(&mut self, n: u64) -> Vec<T> {
+        let n = n.min(self.len);
+        let mut result = Vec::with_capacity(n as usize);
+
+        while (result.len() as u64) < n {
+            let sector = match self.get_first_sector() {
+                Some(s) => s,
+                None => break,
+            };
+
+            let sector_len = self.sector_len(&sector);
+
+            let count =
+                (sector_len - self.first_sector_first_item_idx).min(n - result.len() as u64);
+            let mut offset = self.first_sector_first_item_idx * T::SIZE as u64;
+
+            for _ in 0..count {
+                result.push(sector.read_and_disown_element(offset));
+                offset += T::SIZE as u64;
+            }
+
+            self.first_sector_first_item_idx += count;
+            self.len -= count;
+
+            self.move_to_next_first_sector_if_needed(sector);
+        }
+
+        result
+    }
+
+    /// Removes up to `n` elements from the end of the [SLog], returning them newest first
+    ///
+    /// Same as calling [SLog::pop] `n` times, but processes whole `Sectors` at once. Emptied
+    /// `Sectors` get deallocated, freeing the memory. If the [SLog] is shorter than `n`, returns
+    /// all of its elements.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let batch = log.pop_n(10);
+    ///
+    /// assert_eq!(batch, (90..100).rev().collect::<Vec<_>>());
+    /// assert_eq!(log.len(), 90);
+    /// ```
+    pub fn pop_n(&mut self, n: u64) -> Vec<T> {
+        let n = n.min(self.len);
+        let mut result = Vec::with_capacity(n as usize);
+
+        while (result.len() as u64) < n {
+            let sector = match self.get_current_sector() {
+                Some(s) => s,
+                None => break,
+            };
+
+            let sector_first_item_idx = if sector.as_ptr() == self.first_sector_ptr {
+                self.first_sector_first_item_idx
+            } else {
+                0
+            };
+
+            let count = (self.cur_sector_len - sector_first_item_idx).min(n - result.len() as u64);
+
+            for _ in 0..count {
+                self.cur_sector_last_item_offset -= T::SIZE as u64;
+                result.push(sector.read_and_disown_element(self.cur_sector_last_item_offset));
+            }
+
+            self.cur_sector_len -= count;
+            self.len -= count;
+
+            if self.len == 0 {
+                self.reset_first_sector();
+            } else {
+                self.move_to_prev_sector_if_needed(sector);
+            }
+        }
+
+        result
+    }
+
+    /// Shortens this [SLog], keeping the first `new_len` elements and dropping the rest
+    ///
+    /// If `new_len` is greater than or equal to the current length, does nothing. Works like calling
+    /// [SLog::pop] repeatedly, but processes whole `Sectors` at once, deallocating emptied `Sectors`
+    /// and freeing the memory. If `T` does not need to be dropped (see [std::mem::needs_drop]),
+    /// removed elements are not read at all. Truncating to `0` leaves the [SLog] in the same state as
+    /// [SLog::clear] does.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// // roll back a failed batch
+    /// log.truncate(90);
+    ///
+    /// assert_eq!(log.len(), 90);
+    /// assert_eq!(*log.last().unwrap(), 89);
+    /// ```
+    pub fn truncate(&mut self, new_len: u64) {
+        while self.len > new_len {
+            let sector = match self.get_current_sector() {
+                Some(s) => s,
+                None => break,
+            };
+
+            let sector_first_item_idx = if sector.as_ptr() == self.first_sector_ptr {
+                self.first_sector_first_item_idx
+            } else {
+                0
+            };
+
+            let count = (self.cur_sector_len - sector_first_item_idx).min(self.len - new_len);
+
+            if std::mem::needs_drop::<T>() {
+                for _ in 0..count {
+                    self.cur_sector_last_item_offset -= T::SIZE as u64;
+                    sector.read_and_disown_element(self.cur_sector_last_item_offset);
+                }
+            } else {
+                self.cur_sector_last_item_offset -= count * T::SIZE as u64;
+            }
+
+            self.cur_sector_len -= count;
+            self.len -= count;
+
+            if self.len == 0 {
+                self.reset_first_sector();
+            } else {
+                self.move_to_prev_sector_if_needed(sector);
+            }
+        }
+    }
+
     /// Removes all elements from this [SLog]
     ///
     /// Deallocates all `Sectors`, but the first one, freeing the memory.
-    #[inline]
+    ///
+    /// If `T` does not need to be dropped (see [std::mem::needs_drop]), elements are not read at all
+    /// and `Sectors` are released directly, so this call takes `O(sectors)` instead of `O(len)`.
     pub fn clear(&mut self) {
-        while self.pop().is_some() {}
+        if std::mem::needs_drop::<T>() {
+            while self.pop().is_some() {}
+
+            return;
+        }
+
+        if self.len == 0 {
+            return;
+        }
+
+        let mut sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+        while sector.as_ptr() != self.first_sector_ptr {
+            let prev_sector_ptr = sector.read_prev_ptr();
+            sector.destroy();
+
+            sector = Sector::<T>::from_ptr(prev_sector_ptr);
+        }
+
+        sector.write_next_ptr(EMPTY_PTR);
+
+        self.len = 0;
+        self.cur_sector_ptr = self.first_sector_ptr;
+        self.cur_sector_capacity = sector.read_capacity();
+        self.reset_first_sector();
     }
 
     /// Returns an immutable reference [SRef] to the last element of this [SLog]
@@ -172,7 +613,7 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
         }
 
         let sector = self.get_first_sector()?;
-        let ptr = sector.get_element_ptr(0);
+        let ptr = sector.get_element_ptr(self.first_sector_first_item_idx * T::SIZE as u64);
 
         unsafe { Some(SRef::new(ptr)) }
     }
@@ -186,8 +627,8 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     /// If the [SLog] is empty, returns [None]
     #[inline]
     pub fn get(&self, idx: u64) -> Option<SRef<T>> {
-        let (sector, dif) = self.find_sector_for_idx(idx)?;
-        let ptr = sector.get_element_ptr((idx - dif) * T::SIZE as u64);
+        let (sector, sector_idx) = self.find_sector_for_idx(idx)?;
+        let ptr = sector.get_element_ptr(sector_idx * T::SIZE as u64);
 
         unsafe { Some(SRef::new(ptr)) }
     }
@@ -201,27 +642,20 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     /// If the [SLog] is empty, returns [None]
     #[inline]
     pub fn get_mut(&mut self, idx: u64) -> Option<SRefMut<T>> {
-        let (sector, dif) = self.find_sector_for_idx(idx)?;
-        let ptr = sector.get_element_ptr((idx - dif) * T::SIZE as u64);
+        let (sector, sector_idx) = self.find_sector_for_idx(idx)?;
+        let ptr = sector.get_element_ptr(sector_idx * T::SIZE as u64);
 
         unsafe { Some(SRefMut::new(ptr)) }
     }
 
-    /// Returns the length of this [SLog]
-    #[inline]
-    pub fn len(&self) -> u64 {
-        self.len
-    }
-
-    /// Returns true if the length of this [SLog] is `0`
-    #[inline]
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
-    }
-
-    /// Returns a back-to-front iterator over this [SLog]
+    /// Applies `f` to elements at each of the requested indices, writing them back in place
     ///
-    /// This iterator contains elements from last to first.
+    /// Indices are sorted first, so all the `Sectors` are walked through only once, instead of once
+    /// per index, like with repeated [SLog::get_mut] calls. `f` receives the index and the element
+    /// and is called in ascending order of indices - once for each occurrence of an index.
+    ///
+    /// # Panics
+    /// Panics if any of the indices is out of bounds.
     ///
     /// # Example
     /// ```rust
@@ -231,443 +665,1701 @@ impl<T: StableType + AsFixedSizeBytes> SLog<T> {
     /// # stable_memory_init();
     /// let mut log = SLog::new();
     ///
-    /// for i in 0..100 {
+    /// for i in 0..100u64 {
     ///     log.push(i).expect("Out of memory");
     /// }
     ///
-    /// let mut i = 99;
-    /// for elem in log.rev_iter() {
-    ///     assert_eq!(*elem, i);
-    ///     i -= 1;
-    /// }
+    /// log.update_many(&[90, 5, 42], |idx, it| *it = idx * 10);
+    ///
+    /// assert_eq!(*log.get(5).unwrap(), 50);
+    /// assert_eq!(*log.get(42).unwrap(), 420);
+    /// assert_eq!(*log.get(90).unwrap(), 900);
+    /// assert_eq!(*log.get(6).unwrap(), 6);
     /// ```
-    #[inline]
-    pub fn rev_iter(&self) -> SLogIter<'_, T> {
-        SLogIter::new(self)
-    }
+    pub fn update_many(&mut self, indices: &[u64], mut f: impl FnMut(u64, &mut T)) {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
 
-    fn find_sector_for_idx(&self, idx: u64) -> Option<(Sector<T>, u64)> {
-        if idx >= self.len || self.len == 0 {
+        let (first, last) = match (indices.first(), indices.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return,
+        };
+
+        assert!(last < self.len, "Out of bounds");
+
+        let (mut sector, sector_idx) = self.find_sector_for_idx(first).unwrap();
+        let mut sector_len = self.sector_len(&sector);
+
+        // index of the element right after the last one of the current sector
+        let mut sector_end = first + sector_len - sector_idx;
+
+        for idx in indices {
+            while idx >= sector_end {
+                sector = Sector::<T>::from_ptr(sector.read_next_ptr());
+                sector_len = self.sector_len(&sector);
+                sector_end += sector_len;
+            }
+
+            let offset = (sector_len - (sector_end - idx)) * T::SIZE as u64;
+            let mut it = sector.get_element_mut(offset);
+
+            f(idx, &mut it);
+        }
+    }
+
+    /// Performs binary search on a sorted [SLog], using the provided lambda
+    ///
+    /// Works the same way as in [Vec]. Boundaries of `Sectors` are collected once, before the search,
+    /// so each of the `O(log n)` probes locates the `Sector` of the element in `O(log sectors)`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(log.binary_search_by(|it| it.cmp(&10)), Ok(5));
+    /// assert_eq!(log.binary_search_by(|it| it.cmp(&11)), Err(6));
+    /// ```
+    pub fn binary_search_by<FN>(&self, mut f: FN) -> Result<u64, u64>
+    where
+        FN: FnMut(&T) -> Ordering,
+    {
+        let boundaries = self.sector_boundaries();
+
+        let mut min = 0;
+        let mut max = self.len;
+
+        while min < max {
+            let mid = min + (max - min) / 2;
+
+            match f(&self.get_by_boundaries(&boundaries, mid)) {
+                Ordering::Equal => return Ok(mid),
+                Ordering::Less => min = mid + 1,
+                Ordering::Greater => max = mid,
+            }
+        }
+
+        Err(min)
+    }
+
+    /// Performs binary search for the element on a sorted [SLog]
+    ///
+    /// Works the same way as in [Vec]. See also [SLog::binary_search_by].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i * 2).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(log.binary_search(&10), Ok(5));
+    /// assert_eq!(log.binary_search(&11), Err(6));
+    /// ```
+    #[inline]
+    pub fn binary_search(&self, x: &T) -> Result<u64, u64>
+    where
+        T: Ord,
+    {
+        self.binary_search_by(|it| it.cmp(x))
+    }
+
+    /// Reads `len` consecutive elements, starting from the index `start`, into `out`
+    ///
+    /// Elements which lie in the same `Sector` are read from stable memory in a single bulk read and
+    /// then decoded in heap memory, so reading a range which fits into one `Sector` takes one read
+    /// instead of `len`. A range spanning several `Sector`s takes one read per `Sector`. Just like with
+    /// [SLog::get], the elements stay owned by this [SLog] - their stable drop flag is turned `off`.
+    /// Previous values of `out[0..len]` are dropped.
+    ///
+    /// # Panics
+    /// Panics if `out` is shorter than `len` or if `start + len` is bigger than the length of this [SLog].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut window = [0u64; 10];
+    /// log.read_contiguous(40, 10, &mut window);
+    ///
+    /// assert_eq!(window, [40, 41, 42, 43, 44, 45, 46, 47, 48, 49]);
+    /// ```
+    pub fn read_contiguous(&self, start: u64, len: usize, out: &mut [T]) {
+        assert!(len <= out.len(), "Destination is too short");
+        assert!(start + len as u64 <= self.len, "Out of bounds");
+
+        if len == 0 {
+            return;
+        }
+
+        let (mut sector, mut sector_idx) = self.find_sector_for_idx(start).unwrap();
+        let mut out_idx = 0;
+
+        loop {
+            let sector_len = self.sector_len(&sector);
+
+            let count = ((sector_len - sector_idx) as usize).min(len - out_idx);
+
+            let mut buf = vec![0u8; count * T::SIZE];
+            unsafe {
+                crate::mem::read_bytes(
+                    sector.get_element_ptr(sector_idx * T::SIZE as u64),
+                    &mut buf,
+                )
+            };
+
+            for chunk in buf.chunks_exact(T::SIZE) {
+                let mut it = T::from_fixed_size_bytes(chunk);
+                unsafe { it.stable_drop_flag_off() };
+
+                out[out_idx] = it;
+                out_idx += 1;
+            }
+
+            if out_idx == len {
+                break;
+            }
+
+            sector = Sector::<T>::from_ptr(sector.read_next_ptr());
+            sector_idx = 0;
+        }
+    }
+
+    /// Returns an iterator over raw bytes of elements of this [SLog], one chunk per `Sector`
+    ///
+    /// Each item is a pair of the index of the first element of the chunk and the bytes of all
+    /// elements stored in that `Sector`, read from stable memory at once. This way the whole [SLog]
+    /// can be fed into a hasher with O(sectors) reads, instead of one read per element.
+    ///
+    /// The bytes are the raw [AsFixedSizeBytes] layout of elements, laid out one after another
+    /// (`T::SIZE` bytes each). Only used slots are exposed: elements removed with [SLog::pop_front]
+    /// are skipped and the last `Sector` only contributes its `cur_sector_len` elements.
+    ///
+    /// Stable memory can't be borrowed directly, so each chunk is copied into a fresh [Vec].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut bytes = Vec::new();
+    /// for (_, chunk) in log.sector_bytes() {
+    ///     bytes.extend_from_slice(&chunk);
+    /// }
+    ///
+    /// assert_eq!(bytes.len(), 100 * 8);
+    /// assert_eq!(&bytes[8..16], &1u64.to_le_bytes());
+    /// ```
+    pub fn sector_bytes(&self) -> impl Iterator<Item = (u64, Vec<u8>)> + '_ {
+        let mut sector = if self.len == 0 {
+            None
+        } else {
+            self.get_first_sector()
+        };
+        let mut sector_idx = self.first_sector_first_item_idx;
+        let mut idx = 0u64;
+
+        std::iter::from_fn(move || loop {
+            let s = sector.take()?;
+
+            let is_last = s.as_ptr() == self.cur_sector_ptr;
+            let sector_len = if is_last {
+                self.cur_sector_len
+            } else {
+                s.read_capacity()
+            };
+
+            let count = sector_len - sector_idx;
+            let first_idx = idx;
+
+            let mut buf = vec![0u8; count as usize * T::SIZE];
+            unsafe {
+                crate::mem::read_bytes(s.get_element_ptr(sector_idx * T::SIZE as u64), &mut buf)
+            };
+
+            if !is_last {
+                sector = Some(Sector::<T>::from_ptr(s.read_next_ptr()));
+            }
+            sector_idx = 0;
+            idx += count;
+
+            if count > 0 {
+                return Some((first_idx, buf));
+            }
+        })
+    }
+
+    /// Returns the length of this [SLog]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the length of this [SLog] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writes the header of this [SLog] into `dst`
+    ///
+    /// The header is the length, the pointers to the first and to the current sectors and the
+    /// position inside them. Elements are written to stable memory as they are pushed, so only the
+    /// header has to be persisted - see [persistence model](crate::mem#persistence-model). Restore
+    /// the log with [AsFixedSizeBytes::from_fixed_size_bytes].
+    ///
+    /// # Panics
+    /// Panics if `dst` is shorter than [AsFixedSizeBytes::SIZE] bytes.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::<u64>::new();
+    /// log.push(10).expect("Out of memory");
+    ///
+    /// let mut header = [0u8; SLog::<u64>::SIZE];
+    /// log.flush(&mut header);
+    ///
+    /// let restored = SLog::<u64>::from_fixed_size_bytes(&header);
+    /// assert_eq!(*restored.last().unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn flush(&self, dst: &mut [u8]) {
+        self.as_fixed_size_bytes(&mut dst[0..Self::SIZE]);
+    }
+
+    /// Returns a back-to-front iterator over this [SLog]
+    ///
+    /// This iterator contains elements from last to first.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut i = 99;
+    /// for elem in log.rev_iter() {
+    ///     assert_eq!(*elem, i);
+    ///     i -= 1;
+    /// }
+    /// ```
+    #[inline]
+    pub fn rev_iter(&self) -> SLogIter<'_, T> {
+        SLogIter::new(self)
+    }
+
+    /// Checks internal invariants of this [SLog], returning a description of the first problem found
+    ///
+    /// Walks the chain of `Sectors` from the first one to the current one and checks that:
+    /// 1. each `Sector` is an allocated block of stable memory, big enough for its capacity;
+    /// 2. `Sectors` are linked with each other in both directions;
+    /// 3. each `Sector` is at most twice as big as the previous one and respects the max sector size;
+    /// 4. the stored state of the current `Sector` matches the actual one;
+    /// 5. the total number of elements in all `Sectors` is equal to [SLog::len].
+    ///
+    /// Useful to call in `#[post_upgrade]` in order to fail fast on a corrupted [SLog].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SLog;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SLog::new();
+    ///
+    /// for i in 0..100u64 {
+    ///     log.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// assert!(log.verify().is_ok());
+    /// ```
+    pub fn verify(&self) -> Result<(), String> {
+        if self.first_sector_ptr == EMPTY_PTR || self.cur_sector_ptr == EMPTY_PTR {
+            if self.first_sector_ptr != self.cur_sector_ptr {
+                return Err(String::from(
+                    "Only one of the first and current sectors is set",
+                ));
+            }
+
+            if self.len != 0 {
+                return Err(format!(
+                    "No sectors allocated, but the length is {}",
+                    self.len
+                ));
+            }
+
+            return Ok(());
+        }
+
+        if self.cur_sector_len > self.cur_sector_capacity {
+            return Err(format!(
+                "Current sector length {} is bigger than its capacity {}",
+                self.cur_sector_len, self.cur_sector_capacity
+            ));
+        }
+
+        if self.cur_sector_last_item_offset != self.cur_sector_len * T::SIZE as u64 {
+            return Err(format!(
+                "Current sector last item offset {} does not match its length {}",
+                self.cur_sector_last_item_offset, self.cur_sector_len
+            ));
+        }
+
+        let mut sector = Sector::<T>::from_ptr(self.first_sector_ptr);
+        let mut prev_sector_ptr = EMPTY_PTR;
+        let mut prev_sector_capacity = 0;
+        let mut total_len = 0u64;
+
+        loop {
+            let capacity = sector.read_capacity();
+
+            let slice = match unsafe { SSlice::from_ptr(sector.as_ptr()) } {
+                Some(s) => s,
+                None => return Err(format!("Sector {} is not allocated", sector.as_ptr())),
+            };
+
+            if slice.get_size_bytes() < ELEMENTS_OFFSET + capacity * T::SIZE as u64 {
+                return Err(format!(
+                    "Sector {} is too small for its capacity {}",
+                    sector.as_ptr(),
+                    capacity
+                ));
+            }
+
+            if sector.read_prev_ptr() != prev_sector_ptr {
+                return Err(format!(
+                    "Invalid prev pointer of sector {}",
+                    sector.as_ptr()
+                ));
+            }
+
+            if capacity <= DEFAULT_CAPACITY
+                || (prev_sector_capacity != 0 && capacity > prev_sector_capacity * 2)
+                || (self.max_sector_capacity != 0 && capacity > self.max_sector_capacity)
+            {
+                return Err(format!(
+                    "Invalid capacity {} of sector {}",
+                    capacity,
+                    sector.as_ptr()
+                ));
+            }
+
+            if sector.as_ptr() == self.cur_sector_ptr {
+                if capacity != self.cur_sector_capacity {
+                    return Err(format!(
+                        "Current sector capacity {} does not match the stored one {}",
+                        capacity, self.cur_sector_capacity
+                    ));
+                }
+
+                if sector.read_next_ptr() != EMPTY_PTR {
+                    return Err(String::from("Current sector is not the last one"));
+                }
+
+                total_len += self.cur_sector_len;
+                break;
+            }
+
+            total_len += capacity;
+
+            prev_sector_ptr = sector.as_ptr();
+            prev_sector_capacity = capacity;
+
+            let next_sector_ptr = sector.read_next_ptr();
+            if next_sector_ptr == EMPTY_PTR {
+                return Err(String::from(
+                    "Current sector is not reachable from the first one",
+                ));
+            }
+
+            sector = Sector::<T>::from_ptr(next_sector_ptr);
+        }
+
+        let first_sector_len = if self.first_sector_ptr == self.cur_sector_ptr {
+            self.cur_sector_len
+        } else {
+            Sector::<T>::from_ptr(self.first_sector_ptr).read_capacity()
+        };
+
+        if self.first_sector_first_item_idx > first_sector_len
+            || (self.first_sector_ptr != self.cur_sector_ptr
+                && self.first_sector_first_item_idx == first_sector_len)
+        {
+            return Err(format!(
+                "Invalid index {} of the first item in the first sector",
+                self.first_sector_first_item_idx
+            ));
+        }
+
+        if total_len - self.first_sector_first_item_idx != self.len {
+            return Err(format!(
+                "Sectors contain {} elements, but the length is {}",
+                total_len - self.first_sector_first_item_idx,
+                self.len
+            ));
+        }
+
+        Ok(())
+    }
+
+    // returns the sector and the index of the element inside of it
+    fn find_sector_for_idx(&self, idx: u64) -> Option<(Sector<T>, u64)> {
+        if idx >= self.len || self.len == 0 {
             return None;
         }
 
-        let mut sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
-        let mut sector_len = self.cur_sector_len;
+        // elements removed from the front still occupy their slots in the first sector
+        let idx = idx + self.first_sector_first_item_idx;
+
+        let mut sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+        let mut sector_len = self.cur_sector_len;
+
+        let mut len = self.len + self.first_sector_first_item_idx;
+
+        loop {
+            len -= sector_len;
+            if len <= idx {
+                break;
+            }
+
+            sector = Sector::<T>::from_ptr(sector.read_prev_ptr());
+            sector_len = sector.read_capacity();
+        }
+
+        Some((sector, idx - len))
+    }
+
+    // pointers to all sectors, from the first one to the current one, together with the index of the
+    // first slot of each of them (counting slots, freed by pop_front, in the first sector)
+    fn sector_boundaries(&self) -> Vec<(u64, StablePtr)> {
+        let mut result = Vec::new();
+
+        let mut sector = match self.get_current_sector() {
+            Some(s) if self.len > 0 => s,
+            _ => return result,
+        };
+        let mut start = self.len + self.first_sector_first_item_idx - self.cur_sector_len;
+
+        loop {
+            result.push((start, sector.as_ptr()));
+
+            if start == 0 {
+                break;
+            }
+
+            sector = Sector::<T>::from_ptr(sector.read_prev_ptr());
+            start -= sector.read_capacity();
+        }
+
+        result.reverse();
+        result
+    }
+
+    // same as SLog::get, but takes the sector from the result of SLog::sector_boundaries
+    fn get_by_boundaries(&self, boundaries: &[(u64, StablePtr)], idx: u64) -> SRef<'_, T> {
+        let idx = idx + self.first_sector_first_item_idx;
+        let (start, ptr) = boundaries[boundaries.partition_point(|(start, _)| *start <= idx) - 1];
+
+        let sector = Sector::<T>::from_ptr(ptr);
+        let ptr = sector.get_element_ptr((idx - start) * T::SIZE as u64);
+
+        unsafe { SRef::new(ptr) }
+    }
+
+    // number of used slots of the sector - only the current one can be partially filled
+    #[inline]
+    fn sector_len(&self, sector: &Sector<T>) -> u64 {
+        if sector.as_ptr() == self.cur_sector_ptr {
+            self.cur_sector_len
+        } else {
+            sector.read_capacity()
+        }
+    }
+
+    #[inline]
+    fn next_sector_capacity(&self) -> u64 {
+        let next_sector_capacity = self.cur_sector_capacity.checked_mul(2).unwrap();
+
+        if self.max_sector_capacity != 0 && next_sector_capacity > self.max_sector_capacity {
+            self.max_sector_capacity
+        } else {
+            next_sector_capacity
+        }
+    }
+
+    fn get_or_create_current_sector(&mut self) -> Result<Sector<T>, OutOfMemory> {
+        if self.cur_sector_ptr == EMPTY_PTR {
+            self.cur_sector_capacity = self.next_sector_capacity();
+
+            let it = Sector::<T>::new(self.cur_sector_capacity, EMPTY_PTR)?;
+
+            self.first_sector_ptr = it.as_ptr();
+            self.cur_sector_ptr = it.as_ptr();
+
+            Ok(it)
+        } else {
+            Ok(Sector::<T>::from_ptr(self.cur_sector_ptr))
+        }
+    }
+
+    #[inline]
+    fn get_current_sector(&self) -> Option<Sector<T>> {
+        if self.cur_sector_ptr == EMPTY_PTR {
+            None
+        } else {
+            Some(Sector::<T>::from_ptr(self.cur_sector_ptr))
+        }
+    }
+
+    #[inline]
+    fn get_first_sector(&self) -> Option<Sector<T>> {
+        if self.first_sector_ptr == EMPTY_PTR {
+            None
+        } else {
+            Some(Sector::<T>::from_ptr(self.first_sector_ptr))
+        }
+    }
+
+    fn move_to_prev_sector_if_needed(&mut self, sector: Sector<T>) {
+        if self.cur_sector_len > 0 {
+            return;
+        }
+
+        let prev_sector_ptr = sector.read_prev_ptr();
+        if prev_sector_ptr == EMPTY_PTR {
+            return;
+        }
+
+        let cur_sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+        cur_sector.destroy();
+
+        let mut prev_sector = Sector::<T>::from_ptr(prev_sector_ptr);
+        prev_sector.write_next_ptr(EMPTY_PTR);
+
+        self.cur_sector_capacity = prev_sector.read_capacity();
+        self.cur_sector_len = self.cur_sector_capacity;
+        self.cur_sector_ptr = prev_sector_ptr;
+        self.cur_sector_last_item_offset = self.cur_sector_capacity * T::SIZE as u64;
+    }
+
+    fn move_to_next_first_sector_if_needed(&mut self, sector: Sector<T>) {
+        if self.len == 0 {
+            self.reset_first_sector();
+            return;
+        }
+
+        if sector.as_ptr() == self.cur_sector_ptr
+            || self.first_sector_first_item_idx < sector.read_capacity()
+        {
+            return;
+        }
+
+        let next_sector_ptr = sector.read_next_ptr();
+        sector.destroy();
+
+        let mut next_sector = Sector::<T>::from_ptr(next_sector_ptr);
+        next_sector.write_prev_ptr(EMPTY_PTR);
+
+        self.first_sector_ptr = next_sector_ptr;
+        self.first_sector_first_item_idx = 0;
+    }
+
+    // when the log gets empty, only the current sector is left - reuse it from the beginning
+    fn reset_first_sector(&mut self) {
+        debug_assert_eq!(self.first_sector_ptr, self.cur_sector_ptr);
+
+        self.first_sector_first_item_idx = 0;
+        self.cur_sector_len = 0;
+        self.cur_sector_last_item_offset = 0;
+    }
+
+    fn move_to_next_sector_if_needed(&mut self, sector: &mut Sector<T>) -> Result<(), OutOfMemory> {
+        if self.cur_sector_len < self.cur_sector_capacity {
+            return Ok(());
+        }
+
+        let mut next_sector_capacity = self.next_sector_capacity();
+        let mut new_sector = loop {
+            if next_sector_capacity <= DEFAULT_CAPACITY {
+                return Err(OutOfMemory);
+            }
+
+            match Sector::<T>::new(next_sector_capacity, sector.as_ptr()) {
+                Ok(s) => break s,
+                Err(_) => {
+                    next_sector_capacity /= 2;
+                    continue;
+                }
+            };
+        };
+
+        sector.write_next_ptr(new_sector.as_ptr());
+        new_sector.write_prev_ptr(sector.as_ptr());
+
+        self.cur_sector_capacity = next_sector_capacity;
+        self.cur_sector_ptr = new_sector.as_ptr();
+        self.cur_sector_len = 0;
+        self.cur_sector_last_item_offset = 0;
+
+        *sector = new_sector;
+
+        Ok(())
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Default for SLog<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Extend<T> for SLog<T> {
+    /// Appends all elements of the iterator to the end of this [SLog]
+    ///
+    /// Elements are encoded in heap memory and written with a single stable memory write per
+    /// `Sector`, which is cheaper than calling [SLog::push] for each of them.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory. Elements appended before that stay in this [SLog].
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter().peekable();
+
+        while iter.peek().is_some() {
+            let mut sector = self.get_or_create_current_sector().expect("Out of memory");
+            self.move_to_next_sector_if_needed(&mut sector)
+                .expect("Out of memory");
+
+            let free = (self.cur_sector_capacity - self.cur_sector_len) as usize;
+            let mut buf = Vec::new();
+
+            for mut it in iter.by_ref().take(free) {
+                let from = buf.len();
+                buf.resize(from + T::SIZE, 0u8);
+
+                it.as_fixed_size_bytes(&mut buf[from..]);
+                unsafe { it.stable_drop_flag_off() };
+            }
+
+            let count = (buf.len() / T::SIZE) as u64;
+
+            unsafe {
+                crate::mem::write_bytes(
+                    sector.get_element_ptr(self.cur_sector_last_item_offset),
+                    &buf,
+                )
+            };
+
+            self.cur_sector_last_item_offset += count * T::SIZE as u64;
+            self.cur_sector_len += count;
+            self.len += count;
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> FromIterator<T> for SLog<T> {
+    /// Creates a new [SLog] from the elements of the iterator, see [SLog::extend]
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut log = Self::new();
+        log.extend(iter);
+
+        log
+    }
+}
+
+const PREV_OFFSET: u64 = 0;
+const NEXT_OFFSET: u64 = PREV_OFFSET + u64::SIZE as u64;
+const CAPACITY_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
+const ELEMENTS_OFFSET: u64 = CAPACITY_OFFSET + u64::SIZE as u64;
+
+struct Sector<T>(u64, PhantomData<T>);
+
+impl<T: StableType + AsFixedSizeBytes> Sector<T> {
+    fn new(cap: u64, prev: StablePtr) -> Result<Self, OutOfMemory> {
+        let slice = unsafe { allocate(u64::SIZE as u64 * 3 + cap * T::SIZE as u64)? };
+
+        let mut it = Self(slice.as_ptr(), PhantomData::default());
+        it.write_prev_ptr(prev);
+        it.write_next_ptr(EMPTY_PTR);
+        it.write_capacity(cap);
+
+        Ok(it)
+    }
+
+    fn destroy(self) {
+        let slice = unsafe { SSlice::from_ptr(self.0).unwrap() };
+        deallocate(slice);
+    }
+
+    #[inline]
+    fn as_ptr(&self) -> StablePtr {
+        self.0
+    }
+
+    #[inline]
+    fn from_ptr(ptr: u64) -> Self {
+        Self(ptr, PhantomData::default())
+    }
+
+    #[inline]
+    fn read_prev_ptr(&self) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, PREV_OFFSET)) }
+    }
+
+    #[inline]
+    fn write_prev_ptr(&mut self, mut ptr: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, PREV_OFFSET), &mut ptr) }
+    }
+
+    #[inline]
+    fn read_next_ptr(&self) -> StablePtr {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, NEXT_OFFSET)) }
+    }
+
+    #[inline]
+    fn write_next_ptr(&mut self, mut ptr: StablePtr) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, NEXT_OFFSET), &mut ptr) }
+    }
+
+    #[inline]
+    fn read_capacity(&self) -> u64 {
+        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, CAPACITY_OFFSET)) }
+    }
+
+    #[inline]
+    fn write_capacity(&mut self, mut cap: u64) {
+        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, CAPACITY_OFFSET), &mut cap) }
+    }
+
+    #[inline]
+    fn get_element_ptr(&self, offset: u64) -> u64 {
+        SSlice::_offset(self.0, ELEMENTS_OFFSET + offset)
+    }
+
+    #[inline]
+    fn read_and_disown_element(&self, offset: u64) -> T {
+        unsafe { crate::mem::read_fixed_for_move(self.get_element_ptr(offset)) }
+    }
+
+    #[inline]
+    fn get_element(&self, offset: u64) -> SRef<T> {
+        unsafe { SRef::new(self.get_element_ptr(offset)) }
+    }
+
+    #[inline]
+    fn get_element_mut(&mut self, offset: u64) -> SRefMut<T> {
+        unsafe { SRefMut::new(self.get_element_ptr(offset)) }
+    }
+
+    #[inline]
+    fn write_and_own_element(&self, offset: u64, mut element: T) {
+        unsafe { crate::mem::write_fixed(self.get_element_ptr(offset), &mut element) };
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes + Debug> SLog<T> {
+    /// Prints sectored representation of this [SLog]
+    ///
+    /// Useful for tests
+    pub fn debug_print(&self) {
+        let mut sector = if let Some(s) = self.get_first_sector() {
+            s
+        } else {
+            println!("SLog []");
+            return;
+        };
+
+        print!(
+            "SLog({}, {}, {}, {}, {}, {}, {})",
+            self.len,
+            self.first_sector_ptr,
+            self.first_sector_first_item_idx,
+            self.cur_sector_ptr,
+            self.cur_sector_len,
+            self.cur_sector_capacity,
+            self.cur_sector_last_item_offset
+        );
+
+        print!(" [");
+
+        loop {
+            print!("[");
+            let len = if sector.as_ptr() == self.cur_sector_ptr {
+                self.cur_sector_len
+            } else {
+                sector.read_capacity()
+            };
+
+            let start = if sector.as_ptr() == self.first_sector_ptr {
+                self.first_sector_first_item_idx
+            } else {
+                0
+            };
+
+            let mut offset = start * T::SIZE as u64;
+            for i in start..len {
+                let elem = sector.get_element(offset);
+                offset += T::SIZE as u64;
+
+                print!("{:?}", *elem);
+                if i < len - 1 {
+                    print!(", ");
+                }
+            }
+            print!("]");
+
+            if sector.as_ptr() == self.cur_sector_ptr {
+                break;
+            }
+
+            print!(", ");
+
+            let next_sector_ptr = sector.read_next_ptr();
+            assert_ne!(next_sector_ptr, EMPTY_PTR);
+
+            sector = Sector::<T>::from_ptr(next_sector_ptr);
+        }
+
+        println!("]");
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SLog<T> {
+    const SIZE: usize = u64::SIZE * 6 + usize::SIZE;
+    type Buf = [u8; u64::SIZE * 6 + usize::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.len.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.first_sector_ptr
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+        self.cur_sector_ptr
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 2)..(u64::SIZE * 3)]);
+        self.cur_sector_last_item_offset
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 3)..(u64::SIZE * 4)]);
+        // sector capacities always fit into 4 bytes, so the other 4 bytes of this slot (zeroed by
+        // older versions) store the number of elements, removed from the front of the first sector
+        debug_assert!(self.cur_sector_capacity <= u32::MAX as u64);
+        debug_assert!(self.first_sector_first_item_idx <= u32::MAX as u64);
+        (self.cur_sector_capacity | (self.first_sector_first_item_idx << u32::BITS))
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
+        self.cur_sector_len
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
+        (self.max_sector_capacity as usize)
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 6)..(u64::SIZE * 6 + usize::SIZE)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let len = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
+        let first_sector_ptr = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
+        let cur_sector_ptr = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 2)..(u64::SIZE * 3)]);
+        let cur_sector_last_item_offset =
+            u64::from_fixed_size_bytes(&buf[(u64::SIZE * 3)..(u64::SIZE * 4)]);
+        let packed_capacity = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
+        let cur_sector_capacity = packed_capacity & u32::MAX as u64;
+        let first_sector_first_item_idx = packed_capacity >> u32::BITS;
+        let cur_sector_len = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
+        let max_sector_capacity =
+            usize::from_fixed_size_bytes(&buf[(u64::SIZE * 6)..(u64::SIZE * 6 + usize::SIZE)])
+                as u64;
+
+        Self {
+            len,
+            first_sector_ptr,
+            first_sector_first_item_idx,
+            cur_sector_ptr,
+            cur_sector_len,
+            cur_sector_capacity,
+            cur_sector_last_item_offset,
+            max_sector_capacity,
+            stable_drop_flag: false,
+            _marker: PhantomData::default(),
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableType for SLog<T> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        self.clear();
+
+        if self.cur_sector_ptr != EMPTY_PTR {
+            let sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
+            sector.destroy();
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Drop for SLog<T> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            let ptr = self.cur_sector_ptr;
+
+            unsafe {
+                self.stable_drop();
+            }
+
+            if ptr != EMPTY_PTR {
+                crate::mem::debug_assert_released(ptr, "SLog");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::log::SLog;
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::utils::test::generate_random_string;
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
+        stable, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, SBox,
+    };
+    use rand::rngs::ThreadRng;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+
+            assert!(log.is_empty());
+
+            println!();
+            println!("PUSHES");
+
+            for i in 0..100 {
+                log.debug_print();
+
+                log.push(i);
+
+                for j in 0..i {
+                    assert_eq!(*log.get(j).unwrap(), j);
+                }
+            }
+
+            log.debug_print();
+
+            assert_eq!(log.len(), 100);
+            for i in 0..100 {
+                assert_eq!(*log.get(i).unwrap(), i);
+            }
+
+            println!();
+            println!("POPS");
+
+            for i in (20..100).rev() {
+                assert_eq!(log.pop().unwrap(), i);
+                log.debug_print();
+            }
+
+            println!();
+            println!("PUSHES again");
+
+            assert_eq!(log.len(), 20);
+            for i in 20..100 {
+                log.push(i);
+                log.debug_print();
+            }
+
+            for i in 0..100 {
+                assert_eq!(*log.get(i).unwrap(), i);
+            }
+
+            println!();
+            println!("POPS again");
+
+            for i in (0..100).rev() {
+                assert_eq!(log.pop().unwrap(), i);
+                log.debug_print();
+            }
+
+            assert!(log.pop().is_none());
+            assert!(log.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn iter_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::new();
+
+            for i in 0..100 {
+                log.push(i);
+            }
+
+            let mut j = 99;
 
-        let mut len = self.len;
+            log.debug_print();
 
-        loop {
-            len -= sector_len;
-            if len <= idx {
-                break;
+            for mut i in log.rev_iter() {
+                assert_eq!(*i, j);
+                j -= 1;
             }
 
-            sector = Sector::<T>::from_ptr(sector.read_prev_ptr());
-            sector_len = sector.read_capacity();
+            log.debug_print();
         }
 
-        Some((sector, len))
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    fn get_or_create_current_sector(&mut self) -> Result<Sector<T>, OutOfMemory> {
-        if self.cur_sector_ptr == EMPTY_PTR {
-            self.cur_sector_capacity *= 2;
+    #[test]
+    fn sector_bytes_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-            let it = Sector::<T>::new(self.cur_sector_capacity, EMPTY_PTR)?;
+        {
+            let mut log = SLog::<u64>::new();
+            assert_eq!(log.sector_bytes().count(), 0);
 
-            self.first_sector_ptr = it.as_ptr();
-            self.cur_sector_ptr = it.as_ptr();
+            for i in 0..100 {
+                log.push(i).unwrap();
+            }
 
-            Ok(it)
-        } else {
-            Ok(Sector::<T>::from_ptr(self.cur_sector_ptr))
+            for _ in 0..5 {
+                log.pop_front().unwrap();
+            }
+
+            let mut expected_idx = 0;
+            let mut elems = Vec::new();
+
+            for (idx, chunk) in log.sector_bytes() {
+                assert_eq!(idx, expected_idx);
+                assert!(!chunk.is_empty());
+                assert_eq!(chunk.len() % u64::SIZE, 0);
+
+                expected_idx += (chunk.len() / u64::SIZE) as u64;
+                elems.extend(
+                    chunk
+                        .chunks_exact(u64::SIZE)
+                        .map(u64::from_fixed_size_bytes),
+                );
+            }
+
+            assert_eq!(elems, (5..100).collect::<Vec<_>>());
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn get_current_sector(&self) -> Option<Sector<T>> {
-        if self.cur_sector_ptr == EMPTY_PTR {
-            None
-        } else {
-            Some(Sector::<T>::from_ptr(self.cur_sector_ptr))
+    #[test]
+    fn sector_geometry_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<u64>::new()
+                .with_initial_capacity(8)
+                .with_max_sector_size(128);
+
+            for i in 0..100 {
+                log.push(i).unwrap();
+            }
+
+            assert_eq!(log.cur_sector_capacity, 16);
+
+            let buf = log.as_new_fixed_size_bytes();
+            let log_copy = SLog::<u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(log_copy.max_sector_capacity, 16);
+
+            for (i, j) in log.rev_iter().zip((0..100).rev()) {
+                assert_eq!(*i, j);
+            }
+            assert_eq!(log.rev_iter().count(), 100);
+
+            for i in 0..100 {
+                assert_eq!(*log.get(i).unwrap(), i);
+            }
+
+            for i in (0..100).rev() {
+                assert_eq!(log.pop().unwrap(), i);
+            }
+
+            assert!(log.is_empty());
         }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn get_first_sector(&self) -> Option<Sector<T>> {
-        if self.first_sector_ptr == EMPTY_PTR {
-            None
-        } else {
-            Some(Sector::<T>::from_ptr(self.first_sector_ptr))
-        }
+    #[test]
+    #[should_panic(expected = "Initial capacity should be a power of two")]
+    fn initial_capacity_not_power_of_two_should_panic() {
+        let _ = SLog::<u64>::new().with_initial_capacity(10);
     }
 
-    fn move_to_prev_sector_if_needed(&mut self, sector: Sector<T>) {
-        if self.cur_sector_len > 0 {
-            return;
-        }
+    #[test]
+    #[should_panic(expected = "Initial capacity is too big")]
+    fn initial_capacity_too_big_should_panic() {
+        let _ = SLog::<u64>::new().with_initial_capacity(1 << 30);
+    }
 
-        let prev_sector_ptr = sector.read_prev_ptr();
-        if prev_sector_ptr == EMPTY_PTR {
-            return;
-        }
+    #[test]
+    #[should_panic(expected = "The first sector is already allocated")]
+    fn initial_capacity_after_allocation_should_panic() {
+        stable::clear();
+        stable_memory_init();
 
-        let cur_sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
-        cur_sector.destroy();
+        let mut log = SLog::<u64>::new();
+        log.push(1).unwrap();
 
-        let mut prev_sector = Sector::<T>::from_ptr(prev_sector_ptr);
-        prev_sector.write_next_ptr(EMPTY_PTR);
+        let _ = log.with_initial_capacity(8);
+    }
 
-        self.cur_sector_capacity = prev_sector.read_capacity();
-        self.cur_sector_len = self.cur_sector_capacity;
-        self.cur_sector_ptr = prev_sector_ptr;
-        self.cur_sector_last_item_offset = self.cur_sector_capacity * T::SIZE as u64;
+    #[test]
+    #[should_panic(expected = "Max sector size should be a power of two")]
+    fn max_sector_size_not_power_of_two_should_panic() {
+        let _ = SLog::<u64>::new().with_max_sector_size(1000);
     }
 
-    fn move_to_next_sector_if_needed(&mut self, sector: &mut Sector<T>) -> Result<(), OutOfMemory> {
-        if self.cur_sector_len < self.cur_sector_capacity {
-            return Ok(());
+    #[test]
+    #[should_panic(expected = "Max sector size should be a power of two")]
+    fn max_sector_size_too_big_should_panic() {
+        let _ = SLog::<u64>::new().with_max_sector_size(1 << 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "Max sector size is too small")]
+    fn max_sector_size_too_small_should_panic() {
+        let _ = SLog::<u64>::new().with_max_sector_size(16);
+    }
+
+    #[test]
+    fn header_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<u64>::new().with_max_sector_size(128);
+
+            for i in 0..100 {
+                log.push(i).unwrap();
+            }
+            for _ in 0..5 {
+                log.pop_front();
+            }
+
+            let buf = log.as_new_fixed_size_bytes();
+            assert_eq!(buf.len(), u64::SIZE * 6 + usize::SIZE);
+
+            let log_copy = SLog::<u64>::from_fixed_size_bytes(&buf);
+            assert!(log.first_sector_first_item_idx > 0);
+            assert_eq!(
+                log_copy.first_sector_first_item_idx,
+                log.first_sector_first_item_idx
+            );
+            assert_eq!(log_copy.cur_sector_capacity, log.cur_sector_capacity);
+            assert_eq!(log_copy.max_sector_capacity, 16);
+            assert!(log_copy.rev_iter().map(|it| *it).eq((5..100).rev()));
+
+            // headers, written by older versions, have zeroes in the upper half of the slot
+            let mut old_buf = buf;
+            log.cur_sector_capacity
+                .as_fixed_size_bytes(&mut old_buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
+            let old = SLog::<u64>::from_fixed_size_bytes(&old_buf);
+            assert_eq!(old.first_sector_first_item_idx, 0);
+            assert_eq!(old.cur_sector_capacity, log.cur_sector_capacity);
         }
 
-        let mut next_sector_capacity = self.cur_sector_capacity.checked_mul(2).unwrap();
-        let mut new_sector = loop {
-            if next_sector_capacity <= DEFAULT_CAPACITY {
-                return Err(OutOfMemory);
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn clear_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<u64>::new();
+
+            for i in 0..10_000 {
+                log.push(i).unwrap();
             }
 
-            match Sector::<T>::new(next_sector_capacity, sector.as_ptr()) {
-                Ok(s) => break s,
-                Err(_) => {
-                    next_sector_capacity /= 2;
-                    continue;
-                }
-            };
-        };
+            log.drain_front(10);
+            log.clear();
 
-        sector.write_next_ptr(new_sector.as_ptr());
-        new_sector.write_prev_ptr(sector.as_ptr());
+            assert!(log.is_empty());
+            assert!(log.first().is_none());
+            log.verify().unwrap();
 
-        self.cur_sector_capacity = next_sector_capacity;
-        self.cur_sector_ptr = new_sector.as_ptr();
-        self.cur_sector_len = 0;
-        self.cur_sector_last_item_offset = 0;
+            for i in 0..100 {
+                log.push(i).unwrap();
+            }
 
-        *sector = new_sector;
+            log.verify().unwrap();
+            assert_eq!(*log.first().unwrap(), 0);
+            assert_eq!(*log.get(50).unwrap(), 50);
+            assert_eq!(*log.last().unwrap(), 99);
 
-        Ok(())
-    }
-}
+            let mut boxes = SLog::new();
+            for i in 0..100 {
+                boxes.push(SBox::new(i).unwrap()).unwrap();
+            }
 
-impl<T: StableType + AsFixedSizeBytes> Default for SLog<T> {
-    fn default() -> Self {
-        Self::new()
+            boxes.clear();
+            boxes.verify().unwrap();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-const PREV_OFFSET: u64 = 0;
-const NEXT_OFFSET: u64 = PREV_OFFSET + u64::SIZE as u64;
-const CAPACITY_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
-const ELEMENTS_OFFSET: u64 = CAPACITY_OFFSET + u64::SIZE as u64;
+    #[test]
+    fn read_contiguous_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-struct Sector<T>(u64, PhantomData<T>);
+        {
+            let mut log = SLog::new();
 
-impl<T: StableType + AsFixedSizeBytes> Sector<T> {
-    fn new(cap: u64, prev: StablePtr) -> Result<Self, OutOfMemory> {
-        let slice = unsafe { allocate(u64::SIZE as u64 * 3 + cap * T::SIZE as u64)? };
+            for i in 0..300u64 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
 
-        let mut it = Self(slice.as_ptr(), PhantomData::default());
-        it.write_prev_ptr(prev);
-        it.write_next_ptr(EMPTY_PTR);
-        it.write_capacity(cap);
+            for _ in 0..10 {
+                log.pop_front();
+            }
 
-        Ok(it)
-    }
+            let mut out = (0..290)
+                .map(|_| SBox::new(0u64).unwrap())
+                .collect::<Vec<_>>();
 
-    fn destroy(self) {
-        let slice = unsafe { SSlice::from_ptr(self.0).unwrap() };
-        deallocate(slice);
-    }
+            for start in [0u64, 1, 5, 17, 100, 250, 289] {
+                for len in [0usize, 1, 2, 7, 40] {
+                    if start + len as u64 > log.len() {
+                        continue;
+                    }
 
-    #[inline]
-    fn as_ptr(&self) -> StablePtr {
-        self.0
-    }
+                    log.read_contiguous(start, len, &mut out);
 
-    #[inline]
-    fn from_ptr(ptr: u64) -> Self {
-        Self(ptr, PhantomData::default())
+                    for (i, it) in out.iter().take(len).enumerate() {
+                        assert_eq!(**it, start + i as u64 + 10);
+                    }
+                }
+            }
+
+            log.read_contiguous(0, 290, &mut out);
+            for (i, it) in out.iter().enumerate() {
+                assert_eq!(**it, i as u64 + 10);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn read_prev_ptr(&self) -> StablePtr {
-        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, PREV_OFFSET)) }
+    #[test]
+    fn extend_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = (0..10u64)
+                .map(|i| SBox::new(i).unwrap())
+                .collect::<SLog<_>>();
+            assert_eq!(log.len(), 10);
+
+            log.extend((10..1000u64).map(|i| SBox::new(i).unwrap()));
+            log.extend(std::iter::empty());
+            log.push(SBox::new(1000).unwrap()).unwrap();
+
+            assert_eq!(log.len(), 1001);
+            for i in 0..1001u64 {
+                assert_eq!(**log.get(i).unwrap(), i);
+            }
+
+            for i in (500..1001u64).rev() {
+                assert_eq!(log.pop().unwrap().into_inner(), i);
+            }
+
+            log.extend((500..600u64).map(|i| SBox::new(i).unwrap()));
+            assert_eq!(log.len(), 600);
+            assert_eq!(**log.last().unwrap(), 599);
+            assert!(log.verify().is_ok());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn write_prev_ptr(&mut self, mut ptr: StablePtr) {
-        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, PREV_OFFSET), &mut ptr) }
+    #[test]
+    fn pop_n_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<u64>::new();
+
+            for i in 0..1000 {
+                log.push(i).unwrap();
+            }
+
+            assert_eq!(log.pop_n(100), (900..1000).rev().collect::<Vec<_>>());
+            assert_eq!(log.len(), 900);
+            assert_eq!(*log.last().unwrap(), 899);
+            log.verify().unwrap();
+
+            assert_eq!(log.drain_front(10), (0..10).collect::<Vec<_>>());
+            assert_eq!(log.pop_n(880), (20..900).rev().collect::<Vec<_>>());
+            log.verify().unwrap();
+
+            log.push(1000).unwrap();
+            assert_eq!(
+                log.pop_n(100),
+                vec![1000, 19, 18, 17, 16, 15, 14, 13, 12, 11, 10]
+            );
+            assert!(log.is_empty());
+            assert!(log.pop_n(10).is_empty());
+            log.verify().unwrap();
+
+            log.push(1).unwrap();
+            assert_eq!(*log.first().unwrap(), 1);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn read_next_ptr(&self) -> StablePtr {
-        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, NEXT_OFFSET)) }
-    }
+    #[test]
+    fn truncate_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<SBox<u64>>::new().with_max_sector_size(64);
+
+            for i in 0..1000 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            log.truncate(2000);
+            assert_eq!(log.len(), 1000);
+
+            // spans several sectors
+            log.truncate(500);
+            assert_eq!(log.len(), 500);
+            assert_eq!(**log.last().unwrap(), 499);
+            log.verify().unwrap();
+
+            for i in 500..600 {
+                log.push(SBox::new(i).unwrap()).unwrap();
+            }
+            for i in 0..600 {
+                assert_eq!(**log.get(i).unwrap(), i);
+            }
+
+            // the first sector is partially drained
+            log.drain_front(10);
+            log.truncate(100);
+            assert_eq!(log.len(), 100);
+            assert_eq!(**log.first().unwrap(), 10);
+            assert_eq!(**log.last().unwrap(), 109);
+            log.verify().unwrap();
 
-    #[inline]
-    fn write_next_ptr(&mut self, mut ptr: StablePtr) {
-        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, NEXT_OFFSET), &mut ptr) }
-    }
+            log.truncate(0);
+            assert!(log.is_empty());
+            log.verify().unwrap();
 
-    #[inline]
-    fn read_capacity(&self) -> u64 {
-        unsafe { crate::mem::read_fixed_for_reference(SSlice::_offset(self.0, CAPACITY_OFFSET)) }
-    }
+            log.push(SBox::new(1).unwrap()).unwrap();
+            assert_eq!(**log.first().unwrap(), 1);
+        }
 
-    #[inline]
-    fn write_capacity(&mut self, mut cap: u64) {
-        unsafe { crate::mem::write_fixed(SSlice::_offset(self.0, CAPACITY_OFFSET), &mut cap) }
-    }
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
 
-    #[inline]
-    fn get_element_ptr(&self, offset: u64) -> u64 {
-        SSlice::_offset(self.0, ELEMENTS_OFFSET + offset)
-    }
+        {
+            let mut truncated = SLog::<u64>::new();
+            let mut cleared = SLog::<u64>::new();
 
-    #[inline]
-    fn read_and_disown_element(&self, offset: u64) -> T {
-        unsafe { crate::mem::read_fixed_for_move(self.get_element_ptr(offset)) }
-    }
+            for i in 0..1000 {
+                truncated.push(i).unwrap();
+                cleared.push(i).unwrap();
+            }
 
-    #[inline]
-    fn get_element(&self, offset: u64) -> SRef<T> {
-        unsafe { SRef::new(self.get_element_ptr(offset)) }
-    }
+            truncated.truncate(0);
+            cleared.clear();
+
+            truncated.verify().unwrap();
+            assert_eq!(truncated.first_sector_ptr, truncated.cur_sector_ptr);
+            assert_eq!(
+                (
+                    truncated.len,
+                    truncated.first_sector_first_item_idx,
+                    truncated.cur_sector_last_item_offset,
+                    truncated.cur_sector_capacity,
+                    truncated.cur_sector_len,
+                ),
+                (
+                    cleared.len,
+                    cleared.first_sector_first_item_idx,
+                    cleared.cur_sector_last_item_offset,
+                    cleared.cur_sector_capacity,
+                    cleared.cur_sector_len,
+                )
+            );
+        }
 
-    #[inline]
-    fn get_element_mut(&mut self, offset: u64) -> SRefMut<T> {
-        unsafe { SRefMut::new(self.get_element_ptr(offset)) }
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    fn write_and_own_element(&self, offset: u64, mut element: T) {
-        unsafe { crate::mem::write_fixed(self.get_element_ptr(offset), &mut element) };
-    }
-}
+    #[test]
+    fn swap_remove_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-impl<T: StableType + AsFixedSizeBytes + Debug> SLog<T> {
-    /// Prints sectored representation of this [SLog]
-    ///
-    /// Useful for tests
-    pub fn debug_print(&self) {
-        let mut sector = if let Some(s) = self.get_first_sector() {
-            s
-        } else {
-            println!("SLog []");
-            return;
-        };
+        {
+            let mut log = SLog::new().with_max_sector_size(64);
+            let mut expected = Vec::new();
 
-        let mut current_sector_len = DEFAULT_CAPACITY * 2;
+            assert!(log.swap_remove(0).is_none());
 
-        print!(
-            "SLog({}, {}, {}, {}, {}, {})",
-            self.len,
-            self.first_sector_ptr,
-            self.cur_sector_ptr,
-            self.cur_sector_len,
-            self.cur_sector_capacity,
-            self.cur_sector_last_item_offset
-        );
+            for i in 0..300u64 {
+                let s = SBox::new(format!("{}", i)).unwrap();
+                log.push(s).unwrap();
+                expected.push(format!("{}", i));
 
-        print!(" [");
+                if i % 3 == 2 {
+                    let idx = (i * 7) % log.len();
 
-        loop {
-            print!("[");
-            let len = if sector.as_ptr() == self.cur_sector_ptr {
-                self.cur_sector_len
-            } else {
-                current_sector_len
-            };
+                    let removed = log.swap_remove(idx).unwrap();
+                    assert_eq!(*removed, expected.swap_remove(idx as usize));
+                }
 
-            let mut offset = 0;
-            for i in 0..len {
-                let elem = sector.get_element(offset);
-                offset += T::SIZE as u64;
+                if i % 10 == 9 {
+                    let removed = log.swap_remove(log.len() - 1).unwrap();
+                    assert_eq!(*removed, expected.pop().unwrap());
+                }
 
-                print!("{:?}", *elem);
-                if i < len - 1 {
-                    print!(", ");
+                assert!(log.swap_remove(log.len()).is_none());
+                assert_eq!(log.len(), expected.len() as u64);
+
+                for (j, it) in expected.iter().enumerate() {
+                    assert_eq!(**log.get(j as u64).unwrap(), *it);
                 }
             }
-            print!("]");
 
-            if sector.as_ptr() == self.cur_sector_ptr {
-                break;
+            while !log.is_empty() {
+                let removed = log.swap_remove(0).unwrap();
+                assert_eq!(*removed, expected.swap_remove(0));
             }
 
-            print!(", ");
-
-            let next_sector_ptr = sector.read_next_ptr();
-            assert_ne!(next_sector_ptr, EMPTY_PTR);
-
-            sector = Sector::<T>::from_ptr(next_sector_ptr);
-            current_sector_len *= 2;
+            assert!(expected.is_empty());
+            log.verify().unwrap();
         }
 
-        println!("]");
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
-}
 
-impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SLog<T> {
-    const SIZE: usize = u64::SIZE * 6 + usize::SIZE;
-    type Buf = [u8; u64::SIZE * 6 + usize::SIZE];
+    #[test]
+    fn update_many_works_fine() {
+        stable::clear();
+        stable_memory_init();
 
-    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.len.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
-        self.first_sector_ptr
-            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
-        self.cur_sector_ptr
-            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 2)..(u64::SIZE * 3)]);
-        self.cur_sector_last_item_offset
-            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 3)..(u64::SIZE * 4)]);
-        self.cur_sector_capacity
-            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
-        self.cur_sector_len
-            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
-    }
+        {
+            let mut log = SLog::new().with_max_sector_size(64);
+            let mut expected = Vec::new();
 
-    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
-        let len = u64::from_fixed_size_bytes(&buf[0..u64::SIZE]);
-        let first_sector_ptr = u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]);
-        let cur_sector_ptr = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 2)..(u64::SIZE * 3)]);
-        let cur_sector_last_item_offset =
-            u64::from_fixed_size_bytes(&buf[(u64::SIZE * 3)..(u64::SIZE * 4)]);
-        let cur_sector_capacity =
-            u64::from_fixed_size_bytes(&buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
-        let cur_sector_len = u64::from_fixed_size_bytes(&buf[(u64::SIZE * 5)..(u64::SIZE * 6)]);
+            log.update_many(&[], |_, _| unreachable!());
 
-        Self {
-            len,
-            first_sector_ptr,
-            cur_sector_ptr,
-            cur_sector_len,
-            cur_sector_capacity,
-            cur_sector_last_item_offset,
-            stable_drop_flag: false,
-            _marker: PhantomData::default(),
-        }
-    }
-}
+            for i in 0..500u64 {
+                log.push(i).unwrap();
+                expected.push(i);
+            }
 
-impl<T: StableType + AsFixedSizeBytes> StableType for SLog<T> {
-    #[inline]
-    unsafe fn stable_drop_flag_off(&mut self) {
-        self.stable_drop_flag = false;
-    }
+            for _ in 0..20 {
+                log.pop_front();
+                expected.remove(0);
+            }
 
-    #[inline]
-    unsafe fn stable_drop_flag_on(&mut self) {
-        self.stable_drop_flag = true;
-    }
+            let mut rng = thread_rng();
+            for _ in 0..20 {
+                let indices = (0..rng.gen_range(1..50))
+                    .map(|_| rng.gen_range(0..log.len()))
+                    .collect::<Vec<_>>();
+
+                let mut visited = Vec::new();
+                log.update_many(&indices, |idx, it| {
+                    assert_eq!(*it, expected[idx as usize]);
+                    *it += 1000;
+                    expected[idx as usize] += 1000;
+                    visited.push(idx);
+                });
+
+                let mut sorted = indices.clone();
+                sorted.sort();
+                assert_eq!(visited, sorted);
+
+                for (j, it) in expected.iter().enumerate() {
+                    assert_eq!(*log.get(j as u64).unwrap(), *it);
+                }
+            }
+        }
 
-    #[inline]
-    fn should_stable_drop(&self) -> bool {
-        self.stable_drop_flag
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
     }
 
-    #[inline]
-    unsafe fn stable_drop(&mut self) {
-        self.clear();
+    #[test]
+    #[should_panic]
+    fn update_many_out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
 
-        if self.cur_sector_ptr != EMPTY_PTR {
-            let sector = Sector::<T>::from_ptr(self.cur_sector_ptr);
-            sector.destroy();
-        }
-    }
-}
+        let mut log = SLog::new();
+        log.push(10u64).unwrap();
 
-impl<T: StableType + AsFixedSizeBytes> Drop for SLog<T> {
-    fn drop(&mut self) {
-        if self.should_stable_drop() {
-            unsafe {
-                self.stable_drop();
-            }
-        }
+        log.update_many(&[0, 1], |_, it| *it += 1);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::collections::log::SLog;
-    use crate::utils::test::generate_random_string;
-    use crate::{
-        _debug_validate_allocator, get_allocated_size, init_allocator, retrieve_custom_data,
-        stable, stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
-        store_custom_data, SBox,
-    };
-    use rand::rngs::ThreadRng;
-    use rand::{thread_rng, Rng};
 
     #[test]
-    fn works_fine() {
+    fn binary_search_works_fine() {
         stable::clear();
         stable_memory_init();
 
         {
-            let mut log = SLog::new();
+            let mut log = SLog::new().with_max_sector_size(256);
 
-            assert!(log.is_empty());
+            assert_eq!(log.binary_search(&10), Err(0));
 
-            println!();
-            println!("PUSHES");
+            for i in 0..5000u64 {
+                log.push(i * 3).unwrap();
+            }
 
-            for i in 0..100 {
-                log.debug_print();
+            for i in 0..5000u64 {
+                assert_eq!(log.binary_search(&(i * 3)), Ok(i));
+                assert_eq!(log.binary_search(&(i * 3 + 1)), Err(i + 1));
+            }
 
-                log.push(i);
+            for _ in 0..1234 {
+                log.pop_front();
+            }
 
-                for j in 0..i {
-                    assert_eq!(*log.get(j).unwrap(), j);
-                }
+            for i in 1234..5000u64 {
+                assert_eq!(log.binary_search(&(i * 3)), Ok(i - 1234));
+                assert_eq!(log.binary_search(&(i * 3 - 1)), Err(i - 1234));
             }
+            assert_eq!(log.binary_search(&0), Err(0));
+            assert_eq!(log.binary_search(&15000), Err(log.len()));
+        }
 
-            log.debug_print();
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn insert_sorted_by_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut log = SLog::<SBox<u64>>::new().with_max_sector_size(64);
+            let mut example = Vec::new();
 
-            assert_eq!(log.len(), 100);
             for i in 0..100 {
-                assert_eq!(*log.get(i).unwrap(), i);
+                log.push(SBox::new(i * 2).unwrap()).unwrap();
+                example.push(i * 2);
             }
 
-            println!();
-            println!("POPS");
-
-            for i in (20..100).rev() {
-                assert_eq!(log.pop().unwrap(), i);
-                log.debug_print();
+            // make the first sector start in the middle
+            for _ in 0..5 {
+                log.pop_front().unwrap();
+                example.remove(0);
             }
 
-            println!();
-            println!("PUSHES again");
+            assert_eq!(log.binary_search_by(|it| (**it).cmp(&10)), Ok(0));
+            assert_eq!(log.binary_search_by(|it| (**it).cmp(&11)), Err(1));
+            assert_eq!(log.binary_search_by(|it| (**it).cmp(&1000)), Err(95));
 
-            assert_eq!(log.len(), 20);
-            for i in 20..100 {
-                log.push(i);
-                log.debug_print();
-            }
+            let mut rng = thread_rng();
+            for _ in 0..200 {
+                let it = rng.gen_range(0..250u64);
+                let expected_idx = example.partition_point(|x| *x <= it);
+                example.insert(expected_idx, it);
 
-            for i in 0..100 {
-                assert_eq!(*log.get(i).unwrap(), i);
+                let idx = log.insert_sorted_by(SBox::new(it).unwrap(), |a, b| a.cmp(b));
+                assert_eq!(idx, expected_idx as u64);
             }
 
-            println!();
-            println!("POPS again");
+            assert_eq!(log.len(), example.len() as u64);
+            assert!(log.verify().is_ok());
 
-            for i in (0..100).rev() {
-                assert_eq!(log.pop().unwrap(), i);
-                log.debug_print();
+            for (i, it) in example.iter().enumerate() {
+                assert_eq!(**log.get(i as u64).unwrap(), *it);
             }
 
-            assert!(log.pop().is_none());
-            assert!(log.is_empty());
+            let mut log = SLog::new();
+            assert_eq!(log.insert_sorted_by(1u64, |a, b| a.cmp(b)), 0);
+            assert_eq!(log.insert_sorted_by(0u64, |a, b| a.cmp(b)), 0);
+            assert_eq!(log.insert_sorted_by(2u64, |a, b| a.cmp(b)), 2);
         }
 
         _debug_validate_allocator();
@@ -675,27 +2367,50 @@ mod tests {
     }
 
     #[test]
-    fn iter_works_fine() {
+    fn pop_front_works_fine() {
         stable::clear();
         stable_memory_init();
 
         {
-            let mut log = SLog::new();
+            let mut log = SLog::<u64>::new();
 
             for i in 0..100 {
-                log.push(i);
+                log.push(i).unwrap();
             }
 
-            let mut j = 99;
+            for i in 0..10 {
+                assert_eq!(log.pop_front().unwrap(), i);
+                assert_eq!(*log.first().unwrap(), i + 1);
+            }
 
-            log.debug_print();
+            assert_eq!(log.drain_front(50), (10..60).collect::<Vec<_>>());
+            assert_eq!(log.len(), 40);
 
-            for mut i in log.rev_iter() {
+            for i in 0..40 {
+                assert_eq!(*log.get(i).unwrap(), i + 60);
+            }
+
+            for (i, j) in log.rev_iter().zip((60..100).rev()) {
                 assert_eq!(*i, j);
-                j -= 1;
             }
+            assert_eq!(log.rev_iter().count(), 40);
 
-            log.debug_print();
+            for i in 100..120 {
+                log.push(i).unwrap();
+            }
+
+            assert_eq!(log.pop().unwrap(), 119);
+            assert_eq!(log.drain_front(100), (60..119).collect::<Vec<_>>());
+            assert!(log.is_empty());
+            assert!(log.pop_front().is_none());
+            assert!(log.drain_front(10).is_empty());
+
+            log.push(1).unwrap();
+            log.push(2).unwrap();
+            assert_eq!(*log.first().unwrap(), 1);
+            assert_eq!(*log.last().unwrap(), 2);
+            assert_eq!(log.pop().unwrap(), 2);
+            assert_eq!(log.pop_front().unwrap(), 1);
         }
 
         _debug_validate_allocator();
@@ -705,6 +2420,8 @@ mod tests {
     enum Action {
         Push,
         Pop,
+        PopFront,
+        DrainFront,
         Clear,
         CanisterUpgrade,
     }
@@ -745,13 +2462,41 @@ mod tests {
                         self.log.push(Action::Push);
                     }
                 }
-                // POP ~30%
-                61..=90 => {
+                // POP ~20%
+                61..=80 => {
                     self.it().pop();
                     self.example.pop();
 
                     self.log.push(Action::Pop);
                 }
+                // POP FRONT ~6%
+                81..=86 => {
+                    let it = self.it().pop_front();
+
+                    if self.example.is_empty() {
+                        assert!(it.is_none());
+                    } else {
+                        assert_eq!(*it.unwrap(), self.example.remove(0));
+                    }
+
+                    self.log.push(Action::PopFront);
+                }
+                // DRAIN FRONT ~4%
+                87..=90 => {
+                    let n = self.rng.gen_range(0..10);
+                    let drained = self.it().drain_front(n);
+                    let expected = self
+                        .example
+                        .drain(0..(n as usize).min(self.example.len()))
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(
+                        drained.iter().map(|it| (**it).clone()).collect::<Vec<_>>(),
+                        expected
+                    );
+
+                    self.log.push(Action::DrainFront);
+                }
                 // CLEAR
                 91..=92 => {
                     self.it().clear();
@@ -780,7 +2525,9 @@ mod tests {
             }
 
             _debug_validate_allocator();
+            self.it().verify().unwrap();
             assert_eq!(self.it().len(), self.example.len() as u64);
+            assert_eq!(self.it().rev_iter().count(), self.example.len());
 
             for i in 0..self.it().len() {
                 assert_eq!(
@@ -0,0 +1,303 @@
+//! Traits, which allow writing code that is generic over the kind of a stable map.
+//!
+//! [StableMap] is implemented by both [SHashMap] and [SBTreeMap]. [StableOrderedMap] contains
+//! methods that only make sense for a map which keeps its entries sorted, so it is only implemented
+//! by [SBTreeMap].
+
+use crate::collections::btree_map::iter::{SBTreeMapIter, SBTreeMapRangeIter};
+use crate::collections::hash_map::hasher::StableHasherFactory;
+use crate::collections::hash_map::iter::SHashMapIter;
+use crate::collections::{SBTreeMap, SHashMap};
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::StableType;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::ops::Bound;
+
+/// Common interface of stable key-value maps
+///
+/// Lookups accept any borrowed form of the key, which implements both: hashing and ordering traits,
+/// so the same generic code works for [SHashMap] and [SBTreeMap].
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::{SBTreeMap, SHashMap, StableMap};
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// fn count_even<M: StableMap<u64, u64>>(map: &mut M) -> usize {
+///     for i in 0..10 {
+///         map.insert(i, i).expect("Out of memory");
+///     }
+///
+///     map.iter().filter(|(_, v)| **v % 2 == 0).count()
+/// }
+///
+/// assert_eq!(count_even(&mut SHashMap::new()), 5);
+/// assert_eq!(count_even(&mut SBTreeMap::new()), 5);
+/// ```
+pub trait StableMap<K: StableType + AsFixedSizeBytes, V: StableType + AsFixedSizeBytes> {
+    /// Iterator over entries of this map
+    type Iter<'a>: Iterator<Item = (SRef<'a, K>, SRef<'a, V>)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    /// Inserts a key-value pair, returning the previous value stored by this key
+    ///
+    /// If the canister is out of stable memory, returns [Err] with the key-value pair.
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)>;
+
+    /// Removes a key-value pair by the key, returning the value
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized;
+
+    /// Returns an immutable reference [SRef] to a value stored by the key
+    fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized;
+
+    /// Returns true if there exists a key-value pair stored by the key
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized;
+
+    /// Returns the number of entries in this map
+    fn len(&self) -> u64;
+
+    /// Returns true if the length of this map is `0`
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over entries of this map
+    fn iter(&self) -> Self::Iter<'_>;
+}
+
+/// Interface of stable key-value maps, which keep their entries sorted by key
+pub trait StableOrderedMap<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes>:
+    StableMap<K, V>
+{
+    /// Iterator over entries of this map, which keys are inside some range
+    type RangeIter<'a>: Iterator<Item = (SRef<'a, K>, SRef<'a, V>)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    /// Returns the entry with the smallest key, or [None] if the map is empty
+    fn first_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)>;
+
+    /// Returns the entry with the biggest key, or [None] if the map is empty
+    fn last_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)>;
+
+    /// Returns an iterator over entries of this map, which keys are between `from` and `to`, in
+    /// ascending order
+    fn range(&self, from: Bound<K>, to: Bound<K>) -> Self::RangeIter<'_>;
+}
+
+impl<
+        K: StableType + AsFixedSizeBytes + Hash + Eq,
+        V: StableType + AsFixedSizeBytes,
+        S: StableHasherFactory,
+    > StableMap<K, V> for SHashMap<K, V, S>
+{
+    type Iter<'a>
+        = SHashMapIter<'a, K, V, S>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a,
+        S: 'a;
+
+    #[inline]
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        SHashMap::insert(self, key, value)
+    }
+
+    #[inline]
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SHashMap::remove(self, key)
+    }
+
+    #[inline]
+    fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SHashMap::get(self, key)
+    }
+
+    #[inline]
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SHashMap::contains_key(self, key)
+    }
+
+    #[inline]
+    fn len(&self) -> u64 {
+        SHashMap::len(self) as u64
+    }
+
+    #[inline]
+    fn iter(&self) -> Self::Iter<'_> {
+        SHashMap::iter(self)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    StableMap<K, V> for SBTreeMap<K, V, B>
+{
+    type Iter<'a>
+        = SBTreeMapIter<'a, K, V, B>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    #[inline]
+    fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        SBTreeMap::insert(self, key, value)
+    }
+
+    #[inline]
+    fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SBTreeMap::remove(self, key)
+    }
+
+    #[inline]
+    fn get<Q>(&self, key: &Q) -> Option<SRef<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SBTreeMap::get(self, key)
+    }
+
+    #[inline]
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Ord + ?Sized,
+    {
+        SBTreeMap::contains_key(self, key)
+    }
+
+    #[inline]
+    fn len(&self) -> u64 {
+        SBTreeMap::len(self)
+    }
+
+    #[inline]
+    fn iter(&self) -> Self::Iter<'_> {
+        SBTreeMap::iter(self)
+    }
+}
+
+impl<K: StableType + AsFixedSizeBytes + Ord, V: StableType + AsFixedSizeBytes, const B: usize>
+    StableOrderedMap<K, V> for SBTreeMap<K, V, B>
+{
+    type RangeIter<'a>
+        = SBTreeMapRangeIter<'a, K, V, B>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    #[inline]
+    fn first_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        SBTreeMap::first_key_value(self)
+    }
+
+    #[inline]
+    fn last_key_value(&self) -> Option<(SRef<'_, K>, SRef<'_, V>)> {
+        SBTreeMap::last_key_value(self)
+    }
+
+    #[inline]
+    fn range(&self, from: Bound<K>, to: Bound<K>) -> Self::RangeIter<'_> {
+        SBTreeMap::range(self, from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::hash_map::hasher::IdentityHasherFactory;
+    use crate::collections::map::{StableMap, StableOrderedMap};
+    use crate::collections::{SBTreeMap, SHashMap};
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+    use std::ops::Bound;
+
+    fn exercise<M: StableMap<u64, u64>>(map: &mut M) {
+        assert!(map.is_empty());
+
+        for i in 0..100 {
+            assert!(map.insert(i, i * 2).unwrap().is_none());
+        }
+
+        assert_eq!(map.insert(10, 0).unwrap(), Some(20));
+        assert_eq!(map.len(), 100);
+
+        for i in 0..100 {
+            assert!(map.contains_key(&i));
+        }
+
+        assert_eq!(*map.get(&10).unwrap(), 0);
+        assert_eq!(map.remove(&10), Some(0));
+        assert!(map.get(&10).is_none());
+        assert!(!map.contains_key(&10));
+
+        assert_eq!(map.iter().count(), 99);
+    }
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut hash_map = SHashMap::new();
+            exercise(&mut hash_map);
+
+            let mut identity_hash_map = SHashMap::with_hasher(IdentityHasherFactory);
+            exercise(&mut identity_hash_map);
+
+            let mut btree_map = SBTreeMap::new();
+            exercise(&mut btree_map);
+
+            assert_eq!(*StableOrderedMap::first_key_value(&btree_map).unwrap().0, 0);
+            assert_eq!(*StableOrderedMap::last_key_value(&btree_map).unwrap().0, 99);
+
+            let keys = StableOrderedMap::range(&btree_map, Bound::Included(5), Bound::Excluded(15))
+                .map(|(k, _)| *k)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, vec![5, 6, 7, 8, 9, 11, 12, 13, 14]);
+
+            btree_map.clear();
+            assert!(StableOrderedMap::first_key_value(&btree_map).is_none());
+            assert!(StableOrderedMap::last_key_value(&btree_map).is_none());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -1,4 +1,6 @@
 #[doc(hidden)]
+pub mod boxed_hash_map;
+#[doc(hidden)]
 pub mod btree_map;
 #[doc(hidden)]
 pub mod btree_set;
@@ -13,8 +15,13 @@ pub mod hash_set;
 #[doc(hidden)]
 pub mod log;
 #[doc(hidden)]
+pub mod map;
+#[doc(hidden)]
+pub mod union_find;
+#[doc(hidden)]
 pub mod vec;
 
+pub use boxed_hash_map::SBoxedHashMap;
 pub use btree_map::SBTreeMap;
 pub use btree_set::SBTreeSet;
 pub use certified_btree_map::SCertifiedBTreeMap;
@@ -22,4 +29,6 @@ pub use certified_btree_set::SCertifiedBTreeSet;
 pub use hash_map::SHashMap;
 pub use hash_set::SHashSet;
 pub use log::SLog;
+pub use map::{StableMap, StableOrderedMap};
+pub use union_find::SUnionFind;
 pub use vec::SVec;
@@ -0,0 +1,419 @@
+use crate::collections::vec::SVec;
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use crate::utils::DebuglessUnwrap;
+use crate::OutOfMemory;
+use std::fmt::{Debug, Formatter};
+
+/// Union-find (disjoint set) structure, which lives in stable memory
+///
+/// Elements are numbered `0, 1, 2, ...` in the order they were added with [SUnionFind::make_set].
+/// Each element stores a pointer to its parent in an [SVec]`<u64>` - roots point to themselves.
+/// Another [SVec]`<u8>` stores ranks of roots, which are used to keep trees shallow on unions
+/// (union by rank). [SUnionFind::find] also compresses paths, writing the root straight into each
+/// visited element, so subsequent lookups are almost constant time.
+///
+/// Since both vectors use inline storage, each operation only touches a couple of fixed-size
+/// elements in stable memory. [SUnionFind] implements [StableType] and [AsFixedSizeBytes] itself,
+/// so it can be nested inside other stable structures.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SUnionFind;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut components = SUnionFind::new_with_len(5).expect("Out of memory");
+///
+/// components.union(0, 1);
+/// components.union(3, 4);
+/// components.union(1, 4);
+///
+/// assert!(components.connected(0, 3));
+/// assert!(!components.connected(0, 2));
+/// assert_eq!(components.set_count(), 2);
+/// ```
+pub struct SUnionFind {
+    parents: SVec<u64>,
+    ranks: SVec<u8>,
+    set_count: u64,
+}
+
+impl SUnionFind {
+    /// Creates an empty [SUnionFind]
+    ///
+    /// Does not allocate any heap or stable memory.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            parents: SVec::new(),
+            ranks: SVec::new(),
+            set_count: 0,
+        }
+    }
+
+    /// Creates an [SUnionFind] of `len` elements, each one in its own set
+    ///
+    /// Does allocate stable memory, returning [OutOfMemory] if there is not enough of it.
+    pub fn new_with_len(len: u64) -> Result<Self, OutOfMemory> {
+        let mut it = Self {
+            parents: SVec::new_with_capacity(len as usize)?,
+            ranks: SVec::new_with_capacity(len as usize)?,
+            set_count: 0,
+        };
+
+        for _ in 0..len {
+            it.make_set().debugless_unwrap();
+        }
+
+        Ok(it)
+    }
+
+    /// Adds a new element in its own set, returning the element
+    ///
+    /// If the canister is out of stable memory, returns [OutOfMemory] and leaves this [SUnionFind]
+    /// untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SUnionFind;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut sets = SUnionFind::new();
+    ///
+    /// assert_eq!(sets.make_set().expect("Out of memory"), 0);
+    /// assert_eq!(sets.make_set().expect("Out of memory"), 1);
+    /// ```
+    pub fn make_set(&mut self) -> Result<u64, OutOfMemory> {
+        let x = self.len();
+
+        self.parents.push(x).map_err(|_| OutOfMemory)?;
+
+        if self.ranks.push(0).is_err() {
+            self.parents.pop();
+
+            return Err(OutOfMemory);
+        }
+
+        self.set_count += 1;
+
+        Ok(x)
+    }
+
+    /// Returns the representative (the root) of the set, which contains `x`
+    ///
+    /// Compresses the path: each element visited on the way to the root is pointed straight to it.
+    ///
+    /// # Panics
+    /// Panics if `x` is out of bounds.
+    pub fn find(&mut self, x: u64) -> u64 {
+        let mut root = self.parent(x);
+        while self.parent(root) != root {
+            root = self.parent(root);
+        }
+
+        let mut i = x;
+        while i != root {
+            let mut parent = self.parents.get_mut(i as usize).unwrap();
+            i = *parent;
+            *parent = root;
+        }
+
+        root
+    }
+
+    /// Merges sets, which contain `a` and `b`
+    ///
+    /// Returns [false] if they were already in the same set.
+    ///
+    /// # Panics
+    /// Panics if either `a` or `b` is out of bounds.
+    pub fn union(&mut self, a: u64, b: u64) -> bool {
+        let a = self.find(a);
+        let b = self.find(b);
+
+        if a == b {
+            return false;
+        }
+
+        let rank_a = *self.ranks.get(a as usize).unwrap();
+        let rank_b = *self.ranks.get(b as usize).unwrap();
+
+        let (child, root) = if rank_a < rank_b { (a, b) } else { (b, a) };
+
+        *self.parents.get_mut(child as usize).unwrap() = root;
+
+        if rank_a == rank_b {
+            *self.ranks.get_mut(root as usize).unwrap() += 1;
+        }
+
+        self.set_count -= 1;
+
+        true
+    }
+
+    /// Returns [true] if `a` and `b` are in the same set
+    ///
+    /// Compresses paths of both elements, just like [SUnionFind::find].
+    ///
+    /// # Panics
+    /// Panics if either `a` or `b` is out of bounds.
+    #[inline]
+    pub fn connected(&mut self, a: u64, b: u64) -> bool {
+        self.find(a) == self.find(b)
+    }
+
+    /// Returns the number of elements in this [SUnionFind]
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.parents.len() as u64
+    }
+
+    /// Returns [true] if there are no elements in this [SUnionFind]
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// Returns the number of disjoint sets in this [SUnionFind]
+    #[inline]
+    pub fn set_count(&self) -> u64 {
+        self.set_count
+    }
+
+    /// Removes all elements from this [SUnionFind]
+    #[inline]
+    pub fn clear(&mut self) {
+        self.parents.clear();
+        self.ranks.clear();
+        self.set_count = 0;
+    }
+
+    #[inline]
+    fn parent(&self, x: u64) -> u64 {
+        *self.parents.get(x as usize).expect("Out of bounds")
+    }
+}
+
+impl Default for SUnionFind {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsFixedSizeBytes for SUnionFind {
+    const SIZE: usize = SVec::<u64>::SIZE + SVec::<u8>::SIZE + u64::SIZE;
+    type Buf = [u8; SVec::<u64>::SIZE + SVec::<u8>::SIZE + u64::SIZE];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        let mut from = 0;
+
+        self.parents
+            .as_fixed_size_bytes(&mut buf[from..(from + SVec::<u64>::SIZE)]);
+        from += SVec::<u64>::SIZE;
+
+        self.ranks
+            .as_fixed_size_bytes(&mut buf[from..(from + SVec::<u8>::SIZE)]);
+        from += SVec::<u8>::SIZE;
+
+        self.set_count
+            .as_fixed_size_bytes(&mut buf[from..(from + u64::SIZE)]);
+    }
+
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let mut from = 0;
+
+        let parents = SVec::<u64>::from_fixed_size_bytes(&arr[from..(from + SVec::<u64>::SIZE)]);
+        from += SVec::<u64>::SIZE;
+
+        let ranks = SVec::<u8>::from_fixed_size_bytes(&arr[from..(from + SVec::<u8>::SIZE)]);
+        from += SVec::<u8>::SIZE;
+
+        let set_count = u64::from_fixed_size_bytes(&arr[from..(from + u64::SIZE)]);
+
+        Self {
+            parents,
+            ranks,
+            set_count,
+        }
+    }
+}
+
+impl StableType for SUnionFind {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.parents.stable_drop_flag_off();
+        self.ranks.stable_drop_flag_off();
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.parents.stable_drop_flag_on();
+        self.ranks.stable_drop_flag_on();
+    }
+}
+
+impl Debug for SUnionFind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SUnionFind")
+            .field("parents", &self.parents)
+            .field("ranks", &self.ranks)
+            .field("set_count", &self.set_count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::union_find::SUnionFind;
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::{
+        _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable,
+        stable_memory_init, stable_memory_post_upgrade, stable_memory_pre_upgrade,
+        store_custom_data, SBox,
+    };
+    use rand::{thread_rng, Rng};
+
+    // naive reference implementation - each element stores the id of its component
+    fn naive_union(components: &mut [u64], a: u64, b: u64) {
+        let (from, to) = (components[a as usize], components[b as usize]);
+
+        for c in components.iter_mut() {
+            if *c == from {
+                *c = to;
+            }
+        }
+    }
+
+    #[test]
+    fn basic_flow_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut sets = SUnionFind::default();
+            assert!(sets.is_empty());
+
+            for i in 0..10 {
+                assert_eq!(sets.make_set().unwrap(), i);
+            }
+
+            assert_eq!(sets.len(), 10);
+            assert_eq!(sets.set_count(), 10);
+
+            assert!(sets.union(0, 1));
+            assert!(sets.union(2, 3));
+            assert!(sets.union(1, 3));
+            assert!(!sets.union(0, 2));
+
+            assert_eq!(sets.set_count(), 7);
+            assert!(sets.connected(0, 3));
+            assert!(!sets.connected(0, 4));
+            assert_eq!(sets.find(2), sets.find(1));
+
+            sets.clear();
+            assert!(sets.is_empty());
+            assert_eq!(sets.set_count(), 0);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn zero_len_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut sets = SUnionFind::new_with_len(0).unwrap();
+            assert!(sets.is_empty());
+
+            for i in 0..10 {
+                assert_eq!(sets.make_set().unwrap(), i);
+            }
+
+            assert!(sets.union(0, 9));
+            assert!(sets.connected(0, 9));
+            assert_eq!(sets.set_count(), 9);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn find_out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut sets = SUnionFind::new_with_len(10).unwrap();
+        sets.find(10);
+    }
+
+    #[test]
+    fn random_unions_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let n = 500u64;
+            let mut sets = SUnionFind::new_with_len(n).unwrap();
+            let mut components = (0..n).collect::<Vec<_>>();
+            let mut rng = thread_rng();
+
+            for _ in 0..400 {
+                let a = rng.gen_range(0..n);
+                let b = rng.gen_range(0..n);
+
+                let merged = components[a as usize] != components[b as usize];
+                assert_eq!(sets.union(a, b), merged);
+                naive_union(&mut components, a, b);
+
+                let a = rng.gen_range(0..n);
+                let b = rng.gen_range(0..n);
+                assert_eq!(
+                    sets.connected(a, b),
+                    components[a as usize] == components[b as usize]
+                );
+            }
+
+            let mut distinct = components.clone();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(sets.set_count(), distinct.len() as u64);
+
+            // after path compression every element points straight to its root
+            for x in 0..n {
+                let root = sets.find(x);
+                assert_eq!(sets.parent(x), root);
+            }
+
+            store_custom_data(1, SBox::new(sets).unwrap());
+            stable_memory_pre_upgrade().unwrap();
+            stable_memory_post_upgrade();
+
+            let mut sets = retrieve_custom_data::<SUnionFind>(1).unwrap().into_inner();
+            assert_eq!(sets.len(), n);
+            assert_eq!(sets.set_count(), distinct.len() as u64);
+
+            for a in 0..n {
+                let b = (a * 7) % n;
+                assert_eq!(
+                    sets.connected(a, b),
+                    components[a as usize] == components[b as usize]
+                );
+            }
+
+            let buf = sets.as_new_fixed_size_bytes();
+            let restored = SUnionFind::from_fixed_size_bytes(buf._deref());
+            assert_eq!(restored.len(), n);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -37,3 +37,16 @@ impl<'a, T: StableType + AsFixedSizeBytes> Iterator for SVecIter<'a, T> {
         unsafe { Some(SRef::new(ptr)) }
     }
 }
+
+impl<'a, T: StableType + AsFixedSizeBytes> DoubleEndedIterator for SVecIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.offset == self.max_offset {
+            return None;
+        }
+
+        self.max_offset -= T::SIZE;
+        let ptr = SSlice::_offset(self.svec.ptr, self.max_offset as u64);
+
+        unsafe { Some(SRef::new(ptr)) }
+    }
+}
@@ -6,6 +6,7 @@ use crate::mem::StablePtr;
 use crate::primitive::s_ref::SRef;
 use crate::primitive::s_ref_mut::SRefMut;
 use crate::primitive::StableType;
+use crate::utils::DebuglessUnwrap;
 use crate::{allocate, deallocate, reallocate, OutOfMemory};
 use std::cmp::Ordering;
 use std::fmt::{Debug, Formatter};
@@ -16,6 +17,10 @@ pub mod iter;
 
 const DEFAULT_CAPACITY: usize = 4;
 
+// stable memory pointers never use the highest byte, so the header stores `log2(max_size) + 1` there
+const PTR_BITS: u32 = 56;
+const PTR_MASK: u64 = (1 << PTR_BITS) - 1;
+
 /// Stable analog of [Vec]
 ///
 /// May reallocate on inserts. In this case will copy the underlying data to a new location.
@@ -31,6 +36,7 @@ pub struct SVec<T: StableType + AsFixedSizeBytes> {
     ptr: u64,
     len: usize,
     cap: usize,
+    max_size: u32,
     stable_drop_flag: bool,
     _marker_t: PhantomData<T>,
 }
@@ -55,6 +61,7 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
             len: 0,
             cap: DEFAULT_CAPACITY,
             ptr: EMPTY_PTR,
+            max_size: 0,
             stable_drop_flag: true,
             _marker_t: PhantomData::default(),
         }
@@ -83,11 +90,90 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
             len: 0,
             cap: capacity,
             ptr: unsafe { allocate((capacity * T::SIZE) as u64)?.as_ptr() },
+            max_size: 0,
             stable_drop_flag: true,
             _marker_t: PhantomData::default(),
         })
     }
 
+    /// Sets the capacity (in elements) of the memory block, which this [SVec] allocates on the first push
+    ///
+    /// By default it holds 4 elements. Unlike [SVec::new_with_capacity], does not allocate anything.
+    /// `capacity` should be a power of two and the memory block should fit into [u32::MAX] bytes (or
+    /// into the size set with [SVec::with_max_sector_size]). The setting is persisted together with
+    /// the [SVec] itself.
+    ///
+    /// # Panics
+    /// Panics if any of the conditions above is not met or if this [SVec] has already allocated
+    /// its memory block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new().with_initial_capacity(64);
+    /// assert_eq!(vec.capacity(), 64);
+    ///
+    /// vec.push(10).expect("Out of memory");
+    /// ```
+    pub fn with_initial_capacity(mut self, capacity: usize) -> Self {
+        assert_eq!(self.ptr, EMPTY_PTR, "The memory block is already allocated");
+        assert!(
+            capacity.is_power_of_two(),
+            "Initial capacity should be a power of two"
+        );
+        assert!(
+            capacity <= self.capacity_limit(),
+            "Initial capacity is too big"
+        );
+
+        self.cap = capacity;
+
+        self
+    }
+
+    /// Limits the size (in bytes) of the memory block, holding elements of this [SVec]
+    ///
+    /// By default the memory block doubles each time this [SVec] is full, until it reaches [u32::MAX]
+    /// bytes. Once it reaches `size` bytes, pushing more elements panics. `size` should be a power of
+    /// two, not bigger than `2^31`, and should be enough to store the current capacity. The setting is
+    /// persisted together with the [SVec] itself, without changing the size of its header.
+    ///
+    /// # Panics
+    /// Panics if any of the conditions above is not met.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// // will hold at most 128 u64-s
+    /// let mut vec = SVec::<u64>::new().with_max_sector_size(1024);
+    ///
+    /// for i in 0..128 {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(vec.capacity(), 128);
+    /// ```
+    pub fn with_max_sector_size(mut self, size: u64) -> Self {
+        assert!(
+            size.is_power_of_two() && size <= 1 << 31,
+            "Max sector size should be a power of two not bigger than 2^31"
+        );
+        assert!(
+            size / T::SIZE as u64 >= self.cap as u64,
+            "Max sector size is too small for the capacity of this SVec"
+        );
+
+        self.max_size = size as u32;
+
+        self
+    }
+
     /// Returns the capacity of this [SVec]
     #[inline]
     pub fn capacity(&self) -> usize {
@@ -106,6 +192,37 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         self.len == 0
     }
 
+    /// Writes the header of this [SVec] into `dst`
+    ///
+    /// The header is the pointer to the memory block with elements (tagged with the maximum size,
+    /// if there is one), the length and the capacity. Elements are written to stable memory as the
+    /// vector changes, so only the header has to be persisted - see
+    /// [persistence model](crate::mem#persistence-model). Restore the vector with
+    /// [AsFixedSizeBytes::from_fixed_size_bytes].
+    ///
+    /// # Panics
+    /// Panics if `dst` is shorter than [AsFixedSizeBytes::SIZE] bytes.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    /// vec.push(10).expect("Out of memory");
+    ///
+    /// let mut header = [0u8; SVec::<u64>::SIZE];
+    /// vec.flush(&mut header);
+    ///
+    /// let restored = SVec::<u64>::from_fixed_size_bytes(&header);
+    /// assert_eq!(*restored.get(0).unwrap(), 10);
+    /// ```
+    #[inline]
+    pub fn flush(&self, dst: &mut [u8]) {
+        self.as_fixed_size_bytes(&mut dst[0..Self::SIZE]);
+    }
+
     /// Returns the maximum possible capacity of this [SVec]
     #[inline]
     pub const fn max_capacity() -> usize {
@@ -196,6 +313,56 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         unsafe { Some(SRefMut::new(ptr)) }
     }
 
+    /// Reads `out.len()` elements, starting from `start`, into `out`
+    ///
+    /// Elements of an [SVec] are stored contiguously, so they are read from stable memory with a
+    /// single read and then decoded one by one, instead of performing a separate read per element,
+    /// like [SVec::get] does. Decoded elements are copies, read for reference - they do not own any
+    /// stable memory.
+    ///
+    /// # Panics
+    /// Panics if `start + out.len()` is out of bounds.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::<u64>::new();
+    ///
+    /// for i in 0..100 {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// let mut window = [0u64; 5];
+    /// vec.read_range(40, &mut window);
+    ///
+    /// assert_eq!(window, [40, 41, 42, 43, 44]);
+    /// ```
+    pub fn read_range(&self, start: usize, out: &mut [T]) {
+        assert!(start + out.len() <= self.len, "Out of bounds");
+
+        if out.is_empty() {
+            return;
+        }
+
+        let mut buf = vec![0u8; out.len() * T::SIZE];
+        unsafe {
+            crate::mem::read_bytes(
+                SSlice::_offset(self.ptr, (start * T::SIZE) as u64),
+                &mut buf,
+            )
+        };
+
+        for (chunk, elem) in buf.chunks_exact(T::SIZE).zip(out.iter_mut()) {
+            let mut it = T::from_fixed_size_bytes(chunk);
+            unsafe { it.stable_drop_flag_off() };
+
+            *elem = it;
+        }
+    }
+
     /// Replaces an element at requested index with a provided value
     ///
     /// # Panics
@@ -305,6 +472,58 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         unsafe { crate::mem::write_bytes(ptr1, buf_2._deref()) };
     }
 
+    /// Retains only the elements for which the provided lambda returns [true]
+    ///
+    /// Removed elements are stable-dropped, retained elements are shifted to the left, preserving
+    /// their order. Does not reallocate or shrink the underlying memory block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::new();
+    ///
+    /// for i in 0..10u64 {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// vec.retain(|it| *it % 2 == 0);
+    ///
+    /// assert_eq!(vec.len(), 5);
+    /// assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), vec![0, 2, 4, 6, 8]);
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut buf = T::Buf::new(T::SIZE);
+        let mut new_len = 0;
+
+        for idx in 0..self.len {
+            let elem_ptr = SSlice::_offset(self.ptr, (idx * T::SIZE) as u64);
+            let mut elem: T = unsafe { crate::mem::read_fixed_for_move(elem_ptr) };
+
+            if !f(&elem) {
+                continue;
+            }
+
+            unsafe { elem.stable_drop_flag_off() };
+
+            if new_len != idx {
+                let new_elem_ptr = SSlice::_offset(self.ptr, (new_len * T::SIZE) as u64);
+
+                unsafe { crate::mem::read_bytes(elem_ptr, buf._deref_mut()) };
+                unsafe { crate::mem::write_bytes(new_elem_ptr, buf._deref()) };
+            }
+
+            new_len += 1;
+        }
+
+        self.len = new_len;
+    }
+
     /// Clears the [SVec] from elements
     ///
     /// Does not reallocate or shrink the underlying memory block.
@@ -313,6 +532,254 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         while self.pop().is_some() {}
     }
 
+    /// Splits this [SVec] into two at the given index
+    ///
+    /// Returns a new [SVec] containing elements `[at, len)`, while this [SVec] keeps elements `[0, at)`.
+    /// The tail is moved with a single bulk copy of the underlying bytes, without decoding elements.
+    /// If `at == 0`, the underlying memory block itself is moved to the new [SVec] and nothing is
+    /// copied. Does not shrink the memory block of this [SVec].
+    ///
+    /// If the canister is out of stable memory, returns [OutOfMemory] leaving this [SVec] unchanged.
+    ///
+    /// # Panics
+    /// Panics if `at > len`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = (0..10u64).collect::<SVec<_>>();
+    ///
+    /// let tail = vec.split_off(6).expect("Out of memory");
+    ///
+    /// assert_eq!(vec.len(), 6);
+    /// assert_eq!(tail.len(), 4);
+    /// assert_eq!(*tail.get(0).unwrap(), 6);
+    /// ```
+    pub fn split_off(&mut self, at: usize) -> Result<Self, OutOfMemory> {
+        assert!(at <= self.len, "out of bounds");
+
+        let mut tail = Self::new();
+        tail.max_size = self.max_size;
+
+        if at == 0 {
+            std::mem::swap(&mut self.ptr, &mut tail.ptr);
+            std::mem::swap(&mut self.cap, &mut tail.cap);
+            std::mem::swap(&mut self.len, &mut tail.len);
+
+            return Ok(tail);
+        }
+
+        if at == self.len {
+            return Ok(tail);
+        }
+
+        let tail_len = self.len - at;
+        tail = Self::new_with_capacity(tail_len)?;
+        tail.max_size = self.max_size;
+
+        let mut buf = vec![0u8; tail_len * T::SIZE];
+        unsafe {
+            crate::mem::read_bytes(SSlice::_offset(self.ptr, (at * T::SIZE) as u64), &mut buf)
+        };
+        unsafe { crate::mem::write_bytes(SSlice::_offset(tail.ptr, 0), &buf) };
+
+        tail.len = tail_len;
+        self.len = at;
+
+        Ok(tail)
+    }
+
+    /// Moves all elements of `other` to the end of this [SVec], leaving `other` empty
+    ///
+    /// Elements are moved with a single bulk copy of the underlying bytes, without decoding them. If
+    /// this [SVec] is empty, memory blocks are simply swapped and nothing is copied.
+    ///
+    /// If the canister is out of stable memory, returns [OutOfMemory] leaving both [SVec]s unchanged.
+    ///
+    /// # Panics
+    /// Panics if the resulting length exceeds [SVec::max_capacity].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = (0..6u64).collect::<SVec<_>>();
+    /// let mut other = (6..10u64).collect::<SVec<_>>();
+    ///
+    /// vec.append(&mut other).expect("Out of memory");
+    ///
+    /// assert_eq!(vec.len(), 10);
+    /// assert!(other.is_empty());
+    /// assert_eq!(*vec.get(9).unwrap(), 9);
+    /// ```
+    pub fn append(&mut self, other: &mut Self) -> Result<(), OutOfMemory> {
+        if other.is_empty() {
+            return Ok(());
+        }
+
+        let new_len = self.len + other.len;
+        assert!(new_len <= self.capacity_limit());
+
+        if self.is_empty() && other.cap <= self.capacity_limit() {
+            std::mem::swap(&mut self.ptr, &mut other.ptr);
+            std::mem::swap(&mut self.cap, &mut other.cap);
+            std::mem::swap(&mut self.len, &mut other.len);
+
+            return Ok(());
+        }
+
+        if self.ptr == EMPTY_PTR {
+            self.ptr = unsafe { allocate((self.cap * T::SIZE) as u64)?.as_ptr() };
+        }
+
+        if new_len > self.cap {
+            let new_cap = new_len.max(self.cap * 2).min(self.capacity_limit());
+            let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+
+            self.ptr = unsafe { reallocate(slice, (new_cap * T::SIZE) as u64)?.as_ptr() };
+            self.cap = new_cap;
+        }
+
+        let mut buf = vec![0u8; other.len * T::SIZE];
+        unsafe { crate::mem::read_bytes(SSlice::_offset(other.ptr, 0), &mut buf) };
+        unsafe {
+            crate::mem::write_bytes(SSlice::_offset(self.ptr, (self.len * T::SIZE) as u64), &buf)
+        };
+
+        self.len = new_len;
+        other.len = 0;
+
+        Ok(())
+    }
+
+    /// Overwrites every element of this [SVec] with a clone of the provided value
+    ///
+    /// Replaced elements are dropped, releasing any stable memory they own.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = (0..10u64).collect::<SVec<_>>();
+    ///
+    /// vec.fill(&7);
+    ///
+    /// assert!(vec.iter().all(|it| *it == 7));
+    /// ```
+    pub fn fill(&mut self, value: &T)
+    where
+        T: Clone,
+    {
+        for idx in 0..self.len() {
+            self.replace(idx, value.clone());
+        }
+    }
+
+    /// Replaces the contents of this [SVec] with clones of elements of the provided slice
+    ///
+    /// The length of this [SVec] becomes equal to `src.len()`. Replaced and truncated elements are
+    /// dropped, releasing any stable memory they own. Does not shrink the underlying memory block.
+    ///
+    /// If the underlying memory block has to grow and the canister is out of stable memory, returns
+    /// [OutOfMemory] leaving this [SVec] unchanged.
+    ///
+    /// # Panics
+    /// Panics if `src.len()` exceeds [SVec::max_capacity].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = (0..3u64).collect::<SVec<_>>();
+    ///
+    /// vec.clone_from_slice(&[10, 20, 30, 40, 50]).expect("Out of memory");
+    /// assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), vec![10, 20, 30, 40, 50]);
+    ///
+    /// vec.clone_from_slice(&[1]).expect("Out of memory");
+    /// assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), vec![1]);
+    /// ```
+    pub fn clone_from_slice(&mut self, src: &[T]) -> Result<(), OutOfMemory>
+    where
+        T: Clone,
+    {
+        assert!(src.len() <= self.capacity_limit());
+
+        // allocate everything upfront, so pushes below can't fail
+        if self.ptr == EMPTY_PTR {
+            let cap = src.len().max(self.cap);
+
+            self.ptr = unsafe { allocate((cap * T::SIZE) as u64)?.as_ptr() };
+            self.cap = cap;
+        } else if src.len() > self.cap {
+            let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
+
+            self.ptr = unsafe { reallocate(slice, (src.len() * T::SIZE) as u64)?.as_ptr() };
+            self.cap = src.len();
+        }
+
+        while self.len() > src.len() {
+            self.pop();
+        }
+
+        for (idx, it) in src.iter().enumerate() {
+            if idx < self.len() {
+                self.replace(idx, it.clone());
+            } else {
+                self.push(it.clone()).debugless_unwrap();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns [true] if this [SVec] contains an element equal to the provided one
+    ///
+    /// Performs a linear scan, see [SVec::index_of].
+    #[inline]
+    pub fn contains(&self, x: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.index_of(x).is_some()
+    }
+
+    /// Returns the index of the first element equal to the provided one
+    ///
+    /// Performs a linear scan, stopping at the first match. If no such element is found, returns
+    /// [None]. For sorted [SVec]s consider using [SVec::binary_search_by] instead.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::new();
+    ///
+    /// for i in [3u64, 1, 4, 1, 5] {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(vec.index_of(&1), Some(1));
+    /// assert!(vec.contains(&5));
+    /// assert!(!vec.contains(&2));
+    /// ```
+    pub fn index_of(&self, x: &T) -> Option<usize>
+    where
+        T: PartialEq,
+    {
+        self.iter().position(|it| (*it).eq(x))
+    }
+
     /// Performs binary search on a sorted [SVec], using the provided lambda
     ///
     /// Works the same way as in [Vec].
@@ -379,8 +846,54 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         }
     }
 
+    /// Sorts this [SVec] in place, using the provided comparator lambda
+    ///
+    /// Elements are sorted right in stable memory with heapsort, so no additional stable or heap
+    /// memory is used, and it takes `O(n * log(n))` comparisons even in the worst case. Each
+    /// comparison reads both elements from stable memory, while moving elements only copies their
+    /// bytes. Like [slice::sort_unstable_by], this sort is not stable - equal elements may be
+    /// reordered.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::collections::SVec;
+    /// # use ic_stable_memory::stable_memory_init;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut vec = SVec::new();
+    ///
+    /// for i in [5, 1, 4, 2, 3] {
+    ///     vec.push(i).expect("Out of memory");
+    /// }
+    ///
+    /// vec.sort_by(|a, b| b.cmp(a));
+    ///
+    /// assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), vec![5, 4, 3, 2, 1]);
+    /// ```
+    pub fn sort_by<FN>(&mut self, mut f: FN)
+    where
+        FN: FnMut(&T, &T) -> Ordering,
+    {
+        let len = self.len();
+        if len < 2 {
+            return;
+        }
+
+        for root in (0..(len / 2)).rev() {
+            self.sift_down(root, len, &mut f);
+        }
+
+        for end in (1..len).rev() {
+            self.swap(0, end);
+            self.sift_down(0, end, &mut f);
+        }
+    }
+
     /// Returns an immutable iterator over this collection
     ///
+    /// The iterator is double-ended, so it can be reversed, in order to iterate from the last element
+    /// to the first one.
+    ///
     /// # Example
     /// ```rust
     /// # use ic_stable_memory::collections::SVec;
@@ -396,6 +909,10 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
     /// for elem in vec.iter() {
     ///     println!("{}", *elem); // will print '0, 1, 2, 3, 4, ...'
     /// }
+    ///
+    /// for elem in vec.iter().rev() {
+    ///     println!("{}", *elem); // will print '99, 98, 97, 96, ...'
+    /// }
     /// ```
     #[inline]
     pub fn iter(&self) -> SVecIter<T> {
@@ -426,6 +943,16 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         println!("]");
     }
 
+    // the maximum capacity, taking the size set with SVec::with_max_sector_size into account
+    #[inline]
+    fn capacity_limit(&self) -> usize {
+        if self.max_size == 0 {
+            Self::max_capacity()
+        } else {
+            self.max_size as usize / T::SIZE
+        }
+    }
+
     fn maybe_reallocate(&mut self) -> Result<(), OutOfMemory> {
         if self.ptr == EMPTY_PTR {
             self.ptr = unsafe { allocate((self.capacity() * T::SIZE) as u64)?.as_ptr() };
@@ -433,7 +960,18 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         }
 
         if self.len() == self.capacity() {
-            self.cap = self.cap.checked_mul(2).unwrap();
+            // an SVec created with zero capacity should still be able to grow
+            if self.max_size == 0 {
+                self.cap = self.cap.checked_mul(2).unwrap().max(1);
+            } else {
+                self.cap = self
+                    .cap
+                    .checked_mul(2)
+                    .unwrap()
+                    .max(1)
+                    .min(self.capacity_limit());
+                assert!(self.cap > self.len, "The SVec is full");
+            }
             assert!(self.cap <= Self::max_capacity());
 
             let slice = unsafe { SSlice::from_ptr(self.ptr).unwrap() };
@@ -444,6 +982,46 @@ impl<T: StableType + AsFixedSizeBytes> SVec<T> {
         Ok(())
     }
 
+    // restores the max-heap property of `self[root..end]`, assuming both subtrees of `root` are heaps
+    fn sift_down<FN>(&mut self, mut root: usize, end: usize, f: &mut FN)
+    where
+        FN: FnMut(&T, &T) -> Ordering,
+    {
+        loop {
+            let mut child = 2 * root + 1;
+            if child >= end {
+                return;
+            }
+
+            if child + 1 < end
+                && f(
+                    &self.read_for_reference(child),
+                    &self.read_for_reference(child + 1),
+                ) == Ordering::Less
+            {
+                child += 1;
+            }
+
+            if f(
+                &self.read_for_reference(root),
+                &self.read_for_reference(child),
+            ) != Ordering::Less
+            {
+                return;
+            }
+
+            self.swap(root, child);
+            root = child;
+        }
+    }
+
+    #[inline]
+    fn read_for_reference(&self, idx: usize) -> T {
+        unsafe {
+            crate::mem::read_fixed_for_reference(SSlice::_offset(self.ptr, (idx * T::SIZE) as u64))
+        }
+    }
+
     pub(crate) fn get_element_ptr(&self, idx: usize) -> Option<StablePtr> {
         if idx < self.len() {
             Some(SSlice::_offset(self.ptr, (idx * T::SIZE) as u64))
@@ -460,6 +1038,40 @@ impl<T: StableType + AsFixedSizeBytes> Default for SVec<T> {
     }
 }
 
+impl<T: StableType + AsFixedSizeBytes> Extend<T> for SVec<T> {
+    /// Appends all elements of the iterator to the end of this [SVec], see [SVec::push]
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory. Elements appended before that stay in this [SVec].
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for it in iter {
+            if self.push(it).is_err() {
+                panic!("Out of memory");
+            }
+        }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> FromIterator<T> for SVec<T> {
+    /// Creates a new [SVec] from the elements of the iterator
+    ///
+    /// Allocates the capacity of [Iterator::size_hint] upfront, if there is a lower bound.
+    ///
+    /// # Panics
+    /// Panics if the canister is out of stable memory.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+
+        let mut vec = match iter.size_hint().0 {
+            0 => Self::new(),
+            n => Self::new_with_capacity(n).expect("Out of memory"),
+        };
+        vec.extend(iter);
+
+        vec
+    }
+}
+
 impl<T: StableType + AsFixedSizeBytes + Debug> Debug for SVec<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("[")?;
@@ -479,7 +1091,18 @@ impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SVec<T> {
     type Buf = [u8; u64::SIZE + usize::SIZE * 2];
 
     fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
-        self.ptr.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        let ptr = if self.ptr == EMPTY_PTR {
+            PTR_MASK
+        } else {
+            self.ptr
+        };
+        let max_size_tag = if self.max_size == 0 {
+            0
+        } else {
+            self.max_size.trailing_zeros() as u64 + 1
+        };
+
+        (ptr | (max_size_tag << PTR_BITS)).as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
         self.len
             .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE + usize::SIZE)]);
         self.cap.as_fixed_size_bytes(
@@ -488,16 +1111,27 @@ impl<T: StableType + AsFixedSizeBytes> AsFixedSizeBytes for SVec<T> {
     }
 
     fn from_fixed_size_bytes(arr: &[u8]) -> Self {
-        let ptr = u64::from_fixed_size_bytes(&arr[0..u64::SIZE]);
+        let raw_ptr = u64::from_fixed_size_bytes(&arr[0..u64::SIZE]);
         let len = usize::from_fixed_size_bytes(&arr[u64::SIZE..(u64::SIZE + usize::SIZE)]);
         let cap = usize::from_fixed_size_bytes(
             &arr[(u64::SIZE + usize::SIZE)..(u64::SIZE + usize::SIZE * 2)],
         );
 
+        let ptr = match raw_ptr & PTR_MASK {
+            PTR_MASK => EMPTY_PTR,
+            p => p,
+        };
+        // older versions store EMPTY_PTR as is, which gives the tag of 0xFF
+        let max_size = match raw_ptr >> PTR_BITS {
+            tag @ 1..=32 => 1u32 << (tag - 1),
+            _ => 0,
+        };
+
         Self {
             ptr,
             len,
             cap,
+            max_size,
             stable_drop_flag: false,
             _marker_t: PhantomData::default(),
         }
@@ -534,9 +1168,15 @@ impl<T: StableType + AsFixedSizeBytes> StableType for SVec<T> {
 impl<T: StableType + AsFixedSizeBytes> Drop for SVec<T> {
     fn drop(&mut self) {
         if self.should_stable_drop() {
+            let ptr = self.ptr;
+
             unsafe {
                 self.stable_drop();
             }
+
+            if ptr != EMPTY_PTR {
+                crate::mem::debug_assert_released(ptr, "SVec");
+            }
         }
     }
 }
@@ -545,6 +1185,7 @@ impl<T: StableType + AsFixedSizeBytes> Drop for SVec<T> {
 mod tests {
     use crate::collections::vec::{SVec, DEFAULT_CAPACITY};
     use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::mem::allocator::EMPTY_PTR;
     use crate::primitive::s_box::SBox;
     use crate::primitive::StableType;
     use crate::utils::mem_context::stable;
@@ -587,6 +1228,161 @@ mod tests {
 
     impl StableType for Test {}
 
+    #[test]
+    fn read_range_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+
+            let mut out = [0u64; 0];
+            vec.read_range(0, &mut out);
+
+            for i in 0..1000 {
+                vec.push(i).unwrap();
+            }
+
+            let mut out = vec![0u64; 1000];
+            vec.read_range(0, &mut out);
+            assert_eq!(out, (0..1000).collect::<Vec<_>>());
+
+            let mut out = [0u64; 3];
+            vec.read_range(997, &mut out);
+            assert_eq!(out, [997, 998, 999]);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Out of bounds")]
+    fn read_range_out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut vec = SVec::<u64>::new();
+        vec.push(1).unwrap();
+
+        let mut out = [0u64; 2];
+        vec.read_range(0, &mut out);
+    }
+
+    #[test]
+    fn geometry_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new()
+                .with_initial_capacity(8)
+                .with_max_sector_size(256);
+            assert_eq!(vec.capacity(), 8);
+
+            for i in 0..32 {
+                vec.push(i).unwrap();
+            }
+            assert_eq!(vec.capacity(), 32);
+
+            let buf = vec.as_new_fixed_size_bytes();
+            let vec_copy = SVec::<u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(vec_copy.max_size, 256);
+            assert_eq!(vec_copy.ptr, vec.ptr);
+            assert_eq!(vec_copy.capacity(), 32);
+
+            let mut tail = vec.split_off(16).unwrap();
+            assert_eq!(tail.max_size, 256);
+
+            vec.append(&mut tail).unwrap();
+            assert_eq!(vec.len(), 32);
+            assert!(vec.iter().map(|it| *it).eq(0..32));
+
+            // an empty vec keeps the setting too
+            let empty = SVec::<u64>::new().with_max_sector_size(1 << 31);
+            let buf = empty.as_new_fixed_size_bytes();
+            let empty_copy = SVec::<u64>::from_fixed_size_bytes(&buf);
+            assert_eq!(empty_copy.max_size, 1 << 31);
+            assert_eq!(empty_copy.ptr, EMPTY_PTR);
+
+            // headers, written by older versions, have no max sector size
+            let mut old_buf = SVec::<u64>::new().as_new_fixed_size_bytes();
+            u64::MAX.as_fixed_size_bytes(&mut old_buf[0..u64::SIZE]);
+            let old = SVec::<u64>::from_fixed_size_bytes(&old_buf);
+            assert_eq!(old.max_size, 0);
+            assert_eq!(old.ptr, EMPTY_PTR);
+
+            10u64.as_fixed_size_bytes(&mut old_buf[0..u64::SIZE]);
+            let old = SVec::<u64>::from_fixed_size_bytes(&old_buf);
+            assert_eq!(old.max_size, 0);
+            assert_eq!(old.ptr, 10);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The SVec is full")]
+    fn push_over_max_sector_size_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let mut vec = SVec::<u64>::new().with_max_sector_size(64);
+        for i in 0..9 {
+            vec.push(i).unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Initial capacity should be a power of two")]
+    fn initial_capacity_not_power_of_two_should_panic() {
+        let _ = SVec::<u64>::new().with_initial_capacity(10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Initial capacity is too big")]
+    fn initial_capacity_too_big_should_panic() {
+        let _ = SVec::<u64>::new().with_initial_capacity(1 << 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "Initial capacity is too big")]
+    fn initial_capacity_over_max_sector_size_should_panic() {
+        let _ = SVec::<u64>::new()
+            .with_max_sector_size(64)
+            .with_initial_capacity(16);
+    }
+
+    #[test]
+    #[should_panic(expected = "The memory block is already allocated")]
+    fn initial_capacity_after_allocation_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let _ = SVec::<u64>::new_with_capacity(4)
+            .unwrap()
+            .with_initial_capacity(8);
+    }
+
+    #[test]
+    #[should_panic(expected = "Max sector size should be a power of two")]
+    fn max_sector_size_not_power_of_two_should_panic() {
+        let _ = SVec::<u64>::new().with_max_sector_size(1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Max sector size should be a power of two")]
+    fn max_sector_size_too_big_should_panic() {
+        let _ = SVec::<u64>::new().with_max_sector_size(1 << 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "Max sector size is too small")]
+    fn max_sector_size_too_small_should_panic() {
+        let _ = SVec::<u64>::new().with_max_sector_size(16);
+    }
+
     #[test]
     fn create_destroy_work_fine() {
         stable::clear();
@@ -760,6 +1556,49 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn sort_by_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut rng = thread_rng();
+
+            for len in [0, 1, 2, 3, 10, 100, 1000] {
+                let mut example = (0..len)
+                    .map(|_| rng.gen_range(0..100u64))
+                    .collect::<Vec<_>>();
+                let mut vec = SVec::new();
+
+                for it in &example {
+                    vec.push(*it).unwrap();
+                }
+
+                vec.sort_by(|a, b| a.cmp(b));
+                example.sort();
+
+                assert!(vec.iter().map(|it| *it).eq(example.iter().copied()));
+            }
+
+            let mut example = (0..100)
+                .map(|_| generate_random_string(&mut rng))
+                .collect::<Vec<_>>();
+            let mut vec = SVec::new();
+
+            for it in &example {
+                vec.push(SBox::new(it.clone()).unwrap()).unwrap();
+            }
+
+            vec.sort_by(|a, b| b.cmp(a));
+            example.sort_by(|a, b| b.cmp(a));
+
+            assert!(vec.iter().map(|it| (**it).clone()).eq(example.into_iter()));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn remove_works_fine() {
         stable::clear();
@@ -788,6 +1627,169 @@ mod tests {
         assert_eq!(get_allocated_size(), 0);
     }
 
+    #[test]
+    fn retain_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..100u64 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            vec.retain(|it| **it % 3 == 0);
+
+            assert_eq!(vec.len(), 34);
+            assert_eq!(
+                vec.iter().map(|it| **it).collect::<Vec<_>>(),
+                (0..100).filter(|it| it % 3 == 0).collect::<Vec<_>>()
+            );
+
+            vec.push(SBox::new(1000).unwrap()).unwrap();
+            assert_eq!(*vec.pop().unwrap(), 1000);
+
+            vec.retain(|_| false);
+            assert!(vec.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn index_of_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            assert!(!vec.contains(&0));
+
+            for i in 0..100u64 {
+                vec.push(i % 50).unwrap();
+            }
+
+            for i in 0..50u64 {
+                assert_eq!(vec.index_of(&i), Some(i as usize));
+                assert!(vec.contains(&i));
+            }
+
+            assert_eq!(vec.index_of(&50), None);
+            assert!(!vec.contains(&100));
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn extend_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = (0..100u64)
+                .map(|i| SBox::new(i).unwrap())
+                .collect::<SVec<_>>();
+            assert_eq!(vec.len(), 100);
+            assert_eq!(vec.capacity(), 100);
+
+            vec.extend((100..300u64).map(|i| SBox::new(i).unwrap()));
+            assert_eq!(vec.len(), 300);
+
+            for i in 0..300u64 {
+                assert_eq!(**vec.get(i as usize).unwrap(), i);
+            }
+
+            let empty = std::iter::empty::<u64>().collect::<SVec<_>>();
+            assert!(empty.is_empty());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn split_off_append_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = (0..100u64)
+                .map(|i| SBox::new(i).unwrap())
+                .collect::<SVec<_>>();
+
+            let mut tail = vec.split_off(70).unwrap();
+            assert_eq!(vec.len(), 70);
+            assert_eq!(tail.len(), 30);
+
+            let mut middle = vec.split_off(30).unwrap();
+            let mut head = vec.split_off(0).unwrap();
+            assert!(vec.is_empty());
+            assert!(vec.split_off(0).unwrap().is_empty());
+
+            assert!(tail.split_off(30).unwrap().is_empty());
+
+            for (i, it) in middle.iter().enumerate() {
+                assert_eq!(**it, i as u64 + 30);
+            }
+
+            vec.append(&mut head).unwrap();
+            vec.append(&mut middle).unwrap();
+            vec.append(&mut tail).unwrap();
+            vec.append(&mut SVec::new()).unwrap();
+
+            assert!(head.is_empty() && middle.is_empty() && tail.is_empty());
+            assert_eq!(vec.len(), 100);
+
+            for (i, it) in vec.iter().enumerate() {
+                assert_eq!(**it, i as u64);
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn fill_clone_from_slice_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            vec.fill(&0);
+            assert!(vec.is_empty());
+
+            let src = (0..10u64).collect::<Vec<_>>();
+            vec.clone_from_slice(&src).unwrap();
+            assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), src);
+
+            vec.fill(&7);
+            assert_eq!(vec.len(), 10);
+            assert!(vec.iter().all(|it| *it == 7));
+
+            vec.clone_from_slice(&src[..3]).unwrap();
+            assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+            let src = (0..100u64).rev().collect::<Vec<_>>();
+            vec.clone_from_slice(&src).unwrap();
+            assert_eq!(vec.iter().map(|it| *it).collect::<Vec<_>>(), src);
+
+            vec.clone_from_slice(&[]).unwrap();
+            assert!(vec.is_empty());
+
+            let mut vec = SVec::<u64>::new();
+            vec.clone_from_slice(&src).unwrap();
+            assert_eq!(vec.len(), 100);
+            assert_eq!(vec.capacity(), 100);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
     #[test]
     fn serialization_works_fine() {
         stable::clear();
@@ -849,6 +1851,20 @@ mod tests {
             }
 
             assert_eq!(c, 100);
+
+            let mut c = 100;
+            for i in vec.iter().rev() {
+                c -= 1;
+
+                assert_eq!(c, *i);
+            }
+
+            assert_eq!(c, 0);
+
+            let mut iter = vec.iter();
+            assert_eq!(*iter.next().unwrap(), 0);
+            assert_eq!(*iter.next_back().unwrap(), 99);
+            assert_eq!(iter.count(), 98);
         }
 
         _debug_validate_allocator();
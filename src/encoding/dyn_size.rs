@@ -51,9 +51,12 @@ impl<T: AsFixedSizeBytes> AsDynSizeBytes for T {
         v
     }
 
+    // shorter buffers are passed as is, so extensible types could decode data of their previous
+    // versions (which fields are present, they read from their own length prefix, since the buffer
+    // may also be longer, than the encoded value)
     #[inline]
     fn from_dyn_size_bytes(buf: &[u8]) -> Self {
-        Self::from_fixed_size_bytes(&buf[0..T::SIZE])
+        Self::from_fixed_size_bytes(&buf[0..T::SIZE.min(buf.len())])
     }
 }
 
@@ -64,6 +64,7 @@ macro_rules! impl_for_number {
                 buf.copy_from_slice(&self.to_le_bytes())
             }
 
+            // `buf` may start at any (unaligned) offset, so it is copied into an owned array first
             #[inline]
             fn from_fixed_size_bytes(buf: &[u8]) -> Self {
                 let mut b = Self::Buf::new(Self::SIZE);
@@ -205,7 +206,30 @@ macro_rules! impl_for_single_byte_type_arr {
 
 impl_for_single_byte_type_arr!(bool, false);
 impl_for_single_byte_type_arr!(i8, 0);
-impl_for_single_byte_type_arr!(u8, 0);
+
+// byte arrays are often used as keys (hashes, account ids), so they are copied as a whole
+impl<const N: usize> AsFixedSizeBytes for [u8; N] {
+    const SIZE: usize = N;
+    type Buf = [u8; N];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        buf[0..N].copy_from_slice(self);
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        let mut it = [0u8; N];
+        it.copy_from_slice(&buf[0..N]);
+
+        it
+    }
+
+    #[inline]
+    fn as_new_fixed_size_bytes(&self) -> Self::Buf {
+        *self
+    }
+}
 
 macro_rules! impl_for_number_arr {
     ($ty:ty, $zero:expr) => {
@@ -545,12 +569,12 @@ impl<const N: usize> Buffer for [u8; N] {
 
     #[inline]
     fn _deref(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self as *const u8, self.len()) }
+        self.as_slice()
     }
 
     #[inline]
     fn _deref_mut(&mut self) -> &mut [u8] {
-        unsafe { std::slice::from_raw_parts_mut(self as *mut u8, self.len()) }
+        self.as_mut_slice()
     }
 }
 
@@ -562,12 +586,12 @@ impl Buffer for Vec<u8> {
 
     #[inline]
     fn _deref(&self) -> &[u8] {
-        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len()) }
+        self.as_slice()
     }
 
     #[inline]
     fn _deref_mut(&mut self) -> &mut [u8] {
-        unsafe { std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len()) }
+        self.as_mut_slice()
     }
 }
 
@@ -586,4 +610,19 @@ fn subaccount_test() {
   let acc_copy = Subaccount::from_fixed_size_bytes(&buf);
 
   assert_eq!(acc, acc_copy);
-}
\ No newline at end of file
+}
+
+#[test]
+fn u8_array_test() {
+  assert_eq!(<[u8; 32]>::SIZE, 32);
+  let arr = [7u8; 32];
+  let buf = arr.as_new_fixed_size_bytes();
+  let arr_copy = <[u8; 32]>::from_fixed_size_bytes(&buf);
+
+  assert_eq!(arr, arr_copy);
+
+  let mut buf = [0u8; 40];
+  arr.as_fixed_size_bytes(&mut buf);
+  assert_eq!(&buf[0..32], &arr);
+  assert_eq!(<[u8; 32]>::from_fixed_size_bytes(&buf), arr);
+}
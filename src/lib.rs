@@ -39,6 +39,7 @@ use crate::utils::isoprint;
 pub use crate::utils::mem_context::{stable, OutOfMemory, PAGE_SIZE_BYTES};
 pub use encoding::{AsDynSizeBytes, AsFixedSizeBytes, Buffer};
 pub use primitive::s_box::SBox;
+pub use primitive::s_bytes::SBytes;
 pub use primitive::StableType;
 pub use utils::certification::{
     empty, empty_hash, fork, fork_hash, labeled, labeled_hash, leaf, leaf_hash, AsHashTree,
@@ -193,6 +194,9 @@ pub fn init_allocator(max_pages: u64) {
             let allocator = StableMemoryAllocator::init(max_pages);
 
             *it.borrow_mut() = Some(allocator);
+            #[cfg(debug_assertions)]
+            mem::reset_allocation_tracking(std::iter::empty());
+            mem::bump_allocator_generation();
         } else {
             unreachable!("StableMemoryAllocator can only be initialized once");
         }
@@ -231,7 +235,11 @@ pub fn deinit_allocator() -> Result<(), OutOfMemory> {
 pub fn reinit_allocator() {
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if it.borrow().is_none() {
-            let allocator = StableMemoryAllocator::retrieve();
+            let (allocator, zero_on_free) = StableMemoryAllocator::retrieve_with_zero_on_free();
+            mem::set_zero_on_free(zero_on_free);
+
+            #[cfg(debug_assertions)]
+            mem::reset_allocation_tracking(allocator.debug_allocated_blocks().into_iter());
 
             *it.borrow_mut() = Some(allocator);
         } else {
@@ -323,6 +331,8 @@ pub fn reinit_allocator() {
 /// Panics if there is no initialized stable memory allocator.
 #[inline]
 pub fn store_custom_data<T: StableType + AsDynSizeBytes>(idx: usize, data: SBox<T>) {
+    let _lock = mem::lock_allocator();
+
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.store_custom_data(idx, data)
@@ -352,6 +362,8 @@ pub fn store_custom_data<T: StableType + AsDynSizeBytes>(idx: usize, data: SBox<
 /// Panics if there is no initialized stable memory allocator.
 #[inline]
 pub fn retrieve_custom_data<T: StableType + AsDynSizeBytes>(idx: usize) -> Option<SBox<T>> {
+    let _lock = mem::lock_allocator();
+
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.retrieve_custom_data(idx)
@@ -395,18 +407,25 @@ pub fn retrieve_custom_data<T: StableType + AsDynSizeBytes>(idx: usize) -> Optio
 ///
 /// # Panics
 /// Panics if there is no initialized stable memory allocator.
+/// In debug builds also panics, if invoked while another allocator operation is still in progress.
 ///
 /// # Safety
 /// Don't forget to [deallocate] the memory block, when you're done!
 #[inline]
 pub unsafe fn allocate(size: u64) -> Result<SSlice, OutOfMemory> {
-    STABLE_MEMORY_ALLOCATOR.with(|it| {
+    let _lock = mem::lock_allocator();
+
+    let slice = STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.allocate(size)
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    })?;
+
+    mem::track_allocation(slice.as_ptr());
+
+    Ok(slice)
 }
 
 /// Deallocates an already allocated [SSlice] freeing it's memory.
@@ -429,15 +448,20 @@ pub unsafe fn allocate(size: u64) -> Result<SSlice, OutOfMemory> {
 ///
 /// # Panics
 /// Panics if there is no initialized stable memory allocator.
+/// In debug builds also panics, if invoked while another allocator operation is still in progress.
 #[inline]
 pub fn deallocate(slice: SSlice) {
+    let _lock = mem::lock_allocator();
+
+    mem::track_deallocation(slice.as_ptr());
+
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.deallocate(slice)
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    });
 }
 
 /// Attempts to reallocate a memory block growing its size and possibly moving its content to a new
@@ -474,19 +498,26 @@ pub fn deallocate(slice: SSlice) {
 ///
 /// # Panics
 /// Panics if there is no initialized stable memory allocator.
+/// In debug builds also panics, if invoked while another allocator operation is still in progress.
 /// Reallocating [SSlice]s bigger than [u32::MAX] bytes will also panic.
 ///
 /// # Safety
 /// Don't forget to [deallocate] the memory block, when you're done!
 #[inline]
 pub unsafe fn reallocate(slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMemory> {
-    STABLE_MEMORY_ALLOCATOR.with(|it| {
+    let _lock = mem::lock_allocator();
+
+    let new_slice = STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.reallocate(slice, new_size)
         } else {
             unreachable!("StableMemoryAllocator is not initialized");
         }
-    })
+    })?;
+
+    mem::track_reallocation(slice.as_ptr(), new_slice.as_ptr());
+
+    Ok(new_slice)
 }
 
 /// Checks if it would be possible to allocate a block of stable memory of the provided size right now.
@@ -513,6 +544,8 @@ pub unsafe fn reallocate(slice: SSlice, new_size: u64) -> Result<SSlice, OutOfMe
 /// Panics if there is no initialized stable memory allocator.
 #[inline]
 pub fn make_sure_can_allocate(size: u64) -> bool {
+    let _lock = mem::lock_allocator();
+
     STABLE_MEMORY_ALLOCATOR.with(|it| {
         if let Some(alloc) = &mut *it.borrow_mut() {
             alloc.make_sure_can_allocate(size)
@@ -558,6 +591,76 @@ pub fn get_free_size() -> u64 {
     })
 }
 
+/// Returns the size of a memory block in bytes, which the allocator would reserve for the requested size.
+///
+/// Useful to size your data structures in a way that avoids internal fragmentation. This is a pure
+/// function, so it does not require an initialized stable memory allocator.
+///
+/// Internally calls [StableMemoryAllocator::allocation_size_for](mem::allocator::StableMemoryAllocator::allocation_size_for).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::allocation_size_for;
+/// assert_eq!(allocation_size_for(1), 16);
+/// assert_eq!(allocation_size_for(100), 104);
+/// ```
+#[inline]
+pub fn allocation_size_for(size: u64) -> u64 {
+    StableMemoryAllocator::allocation_size_for(size)
+}
+
+/// Returns the amount of free stable memory in bytes, grouped by segregation classes.
+///
+/// Class `i` contains free blocks which size is in `[2^i, 2^(i+1))`. Useful for tracking
+/// fragmentation of stable memory.
+///
+/// Internally calls [StableMemoryAllocator::free_size_histogram](mem::allocator::StableMemoryAllocator::free_size_histogram).
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn get_free_size_histogram() -> [u64; mem::allocator::SEG_CLASS_PTRS_COUNT as usize] {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.free_size_histogram()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Returns a snapshot of the state of the stable memory allocator, for debugging purposes.
+///
+/// See [AllocatorHeaderDump](mem::allocator::AllocatorHeaderDump) for the list of fields. The dump
+/// implements [CandidType](candid::CandidType), so it can be returned from a query method, in order
+/// to inspect a canister in a bad state (e.g. after a failed upgrade).
+///
+/// Internally calls [StableMemoryAllocator::dump_header](mem::allocator::StableMemoryAllocator::dump_header).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{dump_allocator_header, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let dump = dump_allocator_header();
+///
+/// assert_eq!(dump.allocated_size, 0);
+/// println!("{dump:?}");
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn dump_allocator_header() -> mem::allocator::AllocatorHeaderDump {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.dump_header()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
 /// Returns the amount of allocated stable memory in bytes.
 ///
 /// Always equal to [get_available_size()] - [get_free_size()].
@@ -594,6 +697,93 @@ pub fn get_max_pages() -> u64 {
     })
 }
 
+/// Limits the amount of stable memory the allocator is allowed to grow to, in bytes.
+///
+/// The byte budget is converted to stable memory pages (rounding down, but to at least one page) and
+/// set as the new `max_pages` parameter. Passing `0` removes the limit. If more pages are already
+/// grown, the actual number of grown pages is used as a limit instead. See [init_allocator] for more details.
+///
+/// Internally calls [StableMemoryAllocator::set_max_pages](mem::allocator::StableMemoryAllocator::set_max_pages).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{remaining_growable_bytes, set_grow_ceiling_bytes, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// // never grow more than 1 GiB of stable memory
+/// set_grow_ceiling_bytes(1024 * 1024 * 1024);
+///
+/// assert!(remaining_growable_bytes().unwrap() <= 1024 * 1024 * 1024);
+/// ```
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn set_grow_ceiling_bytes(bytes: u64) {
+    let _lock = mem::lock_allocator();
+
+    let max_pages = if bytes == 0 {
+        0
+    } else {
+        (bytes / PAGE_SIZE_BYTES).max(1)
+    };
+
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &mut *it.borrow_mut() {
+            alloc.set_max_pages(max_pages)
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
+/// Enables or disables zeroing of memory blocks, when they are freed.
+///
+/// When enabled, the data of each memory block is overwritten with zeroes right before the block is
+/// returned to the free-list - both by [deallocate] and by [reallocate], when it has to move the data.
+/// This way sensitive data (e.g. keys or secrets) can't be read from a reused block later. Disabled
+/// by default, since it makes freeing memory more expensive. See also [SSlice::zeroize].
+///
+/// The setting is persisted together with the allocator by [stable_memory_pre_upgrade] and is
+/// restored by [stable_memory_post_upgrade], so it only has to be enabled once.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{set_zero_on_free, stable_memory_init, SBox};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// set_zero_on_free(true);
+///
+/// let secret = SBox::new(String::from("secret key")).expect("Out of memory");
+///
+/// // the underlying memory block is zeroed, before it is freed
+/// drop(secret);
+/// ```
+#[inline]
+pub fn set_zero_on_free(enabled: bool) {
+    mem::set_zero_on_free(enabled);
+}
+
+/// Returns the amount of stable memory in bytes, which the allocator can still grow, before reaching
+/// the `max_pages` limit.
+///
+/// Returns [None] if there is no limit. See [init_allocator] and [set_grow_ceiling_bytes] for more details.
+///
+/// Internally calls [StableMemoryAllocator::remaining_growable_bytes](mem::allocator::StableMemoryAllocator::remaining_growable_bytes).
+///
+/// # Panics
+/// Panics if there is no initialized stable memory allocator.
+#[inline]
+pub fn remaining_growable_bytes() -> Option<u64> {
+    STABLE_MEMORY_ALLOCATOR.with(|it| {
+        if let Some(alloc) = &*it.borrow() {
+            alloc.remaining_growable_bytes()
+        } else {
+            unreachable!("StableMemoryAllocator is not initialized");
+        }
+    })
+}
+
 #[inline]
 pub fn _debug_validate_allocator() {
     STABLE_MEMORY_ALLOCATOR.with(|it: &RefCell<Option<StableMemoryAllocator>>| {
@@ -618,10 +808,16 @@ pub fn _debug_print_allocator() {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(debug_assertions)]
+    use crate::collections::SVec;
+    #[cfg(debug_assertions)]
+    use crate::mem::live_allocation_count;
     use crate::{
         _debug_print_allocator, allocate, deallocate, get_allocated_size, get_free_size,
-        init_allocator, reallocate, retrieve_custom_data, stable_memory_init,
+        get_max_pages, init_allocator, reallocate, remaining_growable_bytes, retrieve_custom_data,
+        set_grow_ceiling_bytes, set_zero_on_free, stable, stable_memory_init,
         stable_memory_post_upgrade, stable_memory_pre_upgrade, store_custom_data, SBox,
+        PAGE_SIZE_BYTES,
     };
     use crate::{deinit_allocator, reinit_allocator, SSlice};
 
@@ -647,6 +843,166 @@ mod tests {
         _debug_print_allocator();
     }
 
+    #[test]
+    fn grow_ceiling_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        assert_eq!(remaining_growable_bytes(), None);
+
+        set_grow_ceiling_bytes(PAGE_SIZE_BYTES * 3 + 100);
+        assert_eq!(get_max_pages(), 3);
+        assert_eq!(remaining_growable_bytes(), Some(PAGE_SIZE_BYTES * 3));
+
+        let b = unsafe { allocate(PAGE_SIZE_BYTES + 100).unwrap() };
+        assert_eq!(remaining_growable_bytes(), Some(PAGE_SIZE_BYTES));
+
+        assert!(unsafe { allocate(PAGE_SIZE_BYTES * 2) }.is_err());
+        deallocate(b);
+
+        set_grow_ceiling_bytes(1);
+        assert_eq!(get_max_pages(), stable::size_pages());
+        assert_eq!(remaining_growable_bytes(), Some(0));
+
+        set_grow_ceiling_bytes(0);
+        assert_eq!(remaining_growable_bytes(), None);
+    }
+
+    #[test]
+    fn zero_on_free_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        let read_at = |ptr| unsafe { crate::mem::read_fixed_for_reference::<u64>(ptr) };
+
+        let a = unsafe { allocate(100).unwrap() };
+        let _guard = unsafe { allocate(100).unwrap() };
+        unsafe { crate::mem::write_fixed(a.offset(16), &mut 42u64) };
+        let ptr = a.offset(16);
+
+        deallocate(a);
+        assert_eq!(read_at(ptr), 42);
+
+        set_zero_on_free(true);
+        assert!(crate::mem::zero_on_free());
+
+        let a = unsafe { allocate(100).unwrap() };
+        unsafe { crate::mem::write_fixed(a.offset(16), &mut 42u64) };
+        let ptr = a.offset(16);
+
+        deallocate(a);
+        assert_eq!(read_at(ptr), 0);
+
+        let a = unsafe { allocate(100).unwrap() };
+        unsafe { crate::mem::write_fixed(a.offset(16), &mut 42u64) };
+        let ptr = a.offset(16);
+        let _guard = unsafe { allocate(100).unwrap() };
+
+        let a = unsafe { reallocate(a, 1000).unwrap() };
+        assert_ne!(a.offset(16), ptr);
+        assert_eq!(read_at(ptr), 0);
+        assert_eq!(read_at(a.offset(16)), 42);
+    }
+
+    #[test]
+    fn zero_on_free_survives_upgrades() {
+        stable::clear();
+        stable_memory_init();
+
+        set_zero_on_free(true);
+
+        deinit_allocator().unwrap();
+        // the heap is wiped out during an upgrade
+        crate::mem::set_zero_on_free(false);
+        reinit_allocator();
+
+        assert!(crate::mem::zero_on_free());
+
+        let a = unsafe { allocate(100).unwrap() };
+        let _guard = unsafe { allocate(100).unwrap() };
+        unsafe { crate::mem::write_fixed(a.offset(16), &mut 42u64) };
+        let ptr = a.offset(16);
+
+        deallocate(a);
+        assert_eq!(
+            unsafe { crate::mem::read_fixed_for_reference::<u64>(ptr) },
+            0
+        );
+
+        set_zero_on_free(false);
+
+        deinit_allocator().unwrap();
+        reinit_allocator();
+
+        assert!(!crate::mem::zero_on_free());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn live_allocation_count_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        assert_eq!(live_allocation_count(), 0);
+
+        {
+            let b = unsafe { allocate(100).unwrap() };
+            let b = unsafe { reallocate(b, 10_000).unwrap() };
+            assert_eq!(live_allocation_count(), 1);
+
+            let mut vec = SVec::new();
+            for i in 0..100u64 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+            assert_eq!(live_allocation_count(), 102);
+
+            deallocate(b);
+
+            // live blocks are recollected from stable memory after an upgrade
+            deinit_allocator().unwrap();
+            reinit_allocator();
+            assert_eq!(live_allocation_count(), 101);
+        }
+
+        assert_eq!(live_allocation_count(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "double free")]
+    fn double_free_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+        let _b = unsafe { allocate(100).unwrap() };
+
+        deallocate(a);
+        deallocate(a);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "SVec did not release its memory block")]
+    fn leak_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let a = unsafe { allocate(100).unwrap() };
+        crate::mem::debug_assert_released(a.as_ptr(), "SVec");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "allocator re-entered")]
+    fn reentrant_allocation_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let _lock = crate::mem::lock_allocator();
+        let _ = unsafe { allocate(10) };
+    }
+
     #[test]
     #[should_panic]
     fn init_allocator_twice_should_panic() {
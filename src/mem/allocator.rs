@@ -6,6 +6,9 @@
 //!
 //! Persisted between canister upgrades by serializing itself with [CandidType](candid::CandidType),
 //! putting itself in an [SBox] and writing a pointer to that [SBox] into stable memory at location (0..8).
+//! The encoded allocator is prefixed with a magic byte and a one-byte format version
+//! ([ALLOCATOR_FORMAT_VERSION]), so the persisted layout can evolve - older formats are migrated to
+//! the current one on retrieval.
 //!
 //! This allocator shouldn't be used directly - instead use top-level functions exposed by this crate.
 
@@ -13,6 +16,8 @@ use crate::encoding::dyn_size::candid_decode_one_allow_trailing;
 use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes, Buffer};
 use crate::mem::free_block::FreeBlock;
 use crate::mem::s_slice::SSlice;
+#[cfg(debug_assertions)]
+use crate::mem::s_slice::{ALLOCATED, FREE};
 use crate::mem::StablePtr;
 use crate::primitive::s_box::SBox;
 use crate::primitive::StableType;
@@ -25,6 +30,62 @@ pub(crate) const ALLOCATOR_PTR: StablePtr = 0;
 pub(crate) const MIN_PTR: StablePtr = u64::SIZE as u64;
 pub(crate) const EMPTY_PTR: StablePtr = u64::MAX;
 
+/// Version of the format in which [StableMemoryAllocator] is persisted between upgrades
+///
+/// Written as the second byte of the stored allocator, right after a non-ASCII magic byte. Allocators
+/// stored by older versions of this crate have neither - their bytes start with the Candid magic
+/// `DIDL` and are treated as version `0`. Since the magic byte can never be the first byte of Candid,
+/// any version number can be used without being confused with such allocators.
+///
+/// Since version `2` the version byte is followed by a byte of flags, which persists settings of the
+/// allocator (e.g. [set_zero_on_free](crate::set_zero_on_free)) between upgrades.
+pub const ALLOCATOR_FORMAT_VERSION: u8 = 2;
+
+const ALLOCATOR_FORMAT_MAGIC: u8 = 0xA7;
+const CANDID_MAGIC: &[u8] = b"DIDL";
+
+const FLAG_ZERO_ON_FREE: u8 = 1;
+
+/// Number of segregation classes reported by [StableMemoryAllocator::free_size_histogram]
+///
+/// Class `i` contains free blocks which size (in bytes) is in `[2^i, 2^(i+1))`.
+pub const SEG_CLASS_PTRS_COUNT: u32 = u64::BITS;
+
+/// A snapshot of the state of the [StableMemoryAllocator], returned by [StableMemoryAllocator::dump_header]
+///
+/// Contains plain copies of all the fields the allocator persists between upgrades, plus some values
+/// read directly from stable memory. Implements [Debug] and [CandidType], so it can be printed or
+/// returned from a query method, when investigating a canister in a bad state.
+///
+/// There are no segregation class pointers or flags in the header of this allocator - its whole
+/// state is a free-list of blocks and a map of custom data pointers, which are encoded with
+/// [CandidType] (prefixed with a magic byte and [ALLOCATOR_FORMAT_VERSION]) into a memory block,
+/// pointed to by bytes `0..8` of stable memory.
+#[derive(Debug, Clone, CandidType, Deserialize, Eq, PartialEq)]
+pub struct AllocatorHeaderDump {
+    /// Raw pointer stored in bytes `0..8` of stable memory
+    ///
+    /// Points to the memory block where the allocator was stored during the last upgrade. After
+    /// the allocator is retrieved, this block is released, so the pointer may point to a free block.
+    pub stored_header_ptr: StablePtr,
+    /// Total size of free blocks in bytes
+    pub free_size: u64,
+    /// Total size of all blocks (free and allocated) in bytes
+    pub available_size: u64,
+    /// Total size of allocated blocks in bytes
+    pub allocated_size: u64,
+    /// Pointer to the end of the memory managed by the allocator
+    pub max_ptr: StablePtr,
+    /// Maximum number of pages the allocator is allowed to grow to (`0` means infinite)
+    pub max_pages: u64,
+    /// Number of stable memory pages which are actually grown
+    pub grown_pages: u64,
+    /// Pointers and sizes (in bytes, not including metadata) of all free blocks, sorted by pointer
+    pub free_blocks: Vec<(StablePtr, u64)>,
+    /// Indices and pointers of all custom data entries, sorted by index
+    pub custom_data_pointers: Vec<(usize, StablePtr)>,
+}
+
 #[doc(hidden)]
 #[derive(Debug, CandidType, Deserialize, Eq, PartialEq)]
 pub struct StableMemoryAllocator {
@@ -145,6 +206,10 @@ impl StableMemoryAllocator {
 
     #[inline]
     pub fn deallocate(&mut self, slice: SSlice) {
+        if crate::mem::zero_on_free() {
+            slice.zeroize();
+        }
+
         let free_block = slice.to_free_block();
 
         self.more_free_size(free_block.get_total_size_bytes());
@@ -175,6 +240,10 @@ impl StableMemoryAllocator {
         unsafe { crate::mem::read_bytes(slice.offset(0), &mut b) };
 
         // deallocate the slice
+        if crate::mem::zero_on_free() {
+            slice.zeroize();
+        }
+
         self.more_free_size(free_block.get_total_size_bytes());
         self.push_free_block(free_block);
 
@@ -192,27 +261,72 @@ impl StableMemoryAllocator {
         let buf = self.as_dyn_size_bytes();
 
         // reserving 100 extra bytes in order for the allocator to grow while allocating memory for itself
-        let slice = self.allocate(buf.len() as u64 + 100)?;
+        // (+3 bytes for the magic byte, the format version and the flags)
+        let slice = self.allocate(buf.len() as u64 + 103)?;
 
         let buf = self.as_dyn_size_bytes();
 
-        unsafe { crate::mem::write_bytes(slice.offset(0), &buf) };
+        let flags = if crate::mem::zero_on_free() {
+            FLAG_ZERO_ON_FREE
+        } else {
+            0
+        };
+
+        unsafe {
+            crate::mem::write_bytes(
+                slice.offset(0),
+                &[ALLOCATOR_FORMAT_MAGIC, ALLOCATOR_FORMAT_VERSION, flags],
+            )
+        };
+        unsafe { crate::mem::write_bytes(slice.offset(3), &buf) };
         unsafe { crate::mem::write_fixed(0, &mut slice.as_ptr()) };
 
         Ok(())
     }
 
+    #[inline]
     pub fn retrieve() -> Self {
+        Self::retrieve_with_zero_on_free().0
+    }
+
+    // also returns the zero-on-free setting, which was active, when the allocator was stored
+    pub fn retrieve_with_zero_on_free() -> (Self, bool) {
         let slice_ptr = unsafe { crate::mem::read_fixed_for_reference(0) };
         let slice = unsafe { SSlice::from_ptr(slice_ptr).unwrap() };
 
         let mut buf = vec![0u8; slice.get_size_bytes() as usize];
         unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
 
-        let mut it = Self::from_dyn_size_bytes(&buf);
+        let (mut it, flags) = Self::migrate(&buf);
         it.deallocate(slice);
 
-        it
+        (it, flags & FLAG_ZERO_ON_FREE != 0)
+    }
+
+    // decodes an allocator stored in any known format, converting it to the current one
+    //
+    // when the persisted layout changes, bump ALLOCATOR_FORMAT_VERSION and add an arm here, which
+    // decodes the previous layout and converts it into the current one
+    fn migrate(buf: &[u8]) -> (Self, u8) {
+        if buf.starts_with(CANDID_MAGIC) {
+            // version 0 - no magic and version bytes, the same layout as version 1
+            return (Self::from_dyn_size_bytes(buf), 0);
+        }
+
+        assert_eq!(
+            buf[0], ALLOCATOR_FORMAT_MAGIC,
+            "The stored stable memory allocator is corrupted"
+        );
+
+        match buf[1] {
+            // version 1 - no flags
+            1 => (Self::from_dyn_size_bytes(&buf[2..]), 0),
+            ALLOCATOR_FORMAT_VERSION => (Self::from_dyn_size_bytes(&buf[3..]), buf[2]),
+            v => panic!(
+                "Unsupported stable memory allocator format version {} (the latest known is {})",
+                v, ALLOCATOR_FORMAT_VERSION
+            ),
+        }
     }
 
     #[inline]
@@ -230,6 +344,72 @@ impl StableMemoryAllocator {
         self.free_size
     }
 
+    /// Returns the size (in bytes) of a memory block, which would be reserved for the requested size
+    ///
+    /// The requested size is padded to at least `16` bytes and then ceiled to the nearest multiple
+    /// of `8`. The allocator may still return a slightly bigger block, if the remainder of a free
+    /// block it was taken from is too small to exist on its own.
+    #[inline]
+    pub fn allocation_size_for(size: u64) -> u64 {
+        Self::pad_size(size)
+    }
+
+    /// Returns the total size (in bytes) of free blocks, for each segregation class
+    ///
+    /// Class `i` contains free blocks which size is in `[2^i, 2^(i+1))`. The sum of all classes is
+    /// always equal to [StableMemoryAllocator::get_free_size].
+    pub fn free_size_histogram(&self) -> [u64; SEG_CLASS_PTRS_COUNT as usize] {
+        let mut histogram = [0u64; SEG_CLASS_PTRS_COUNT as usize];
+
+        for blocks in self.free_blocks.values() {
+            for free_block in blocks {
+                let size = free_block.get_total_size_bytes();
+                let class = (u64::BITS - 1 - size.leading_zeros()) as usize;
+
+                histogram[class] += size;
+            }
+        }
+
+        histogram
+    }
+
+    /// Returns a snapshot of the state of this allocator, see [AllocatorHeaderDump]
+    pub fn dump_header(&self) -> AllocatorHeaderDump {
+        let grown_pages = stable::size_pages();
+        let stored_header_ptr = if grown_pages > 0 {
+            unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) }
+        } else {
+            0
+        };
+
+        let mut free_blocks = self
+            .free_blocks
+            .values()
+            .flatten()
+            .map(|it| (it.as_ptr(), it.get_size_bytes()))
+            .collect::<Vec<_>>();
+        free_blocks.sort();
+
+        let mut custom_data_pointers = self
+            .custom_data_pointers
+            .iter()
+            .map(|(idx, ptr)| (*idx, *ptr))
+            .collect::<Vec<_>>();
+        custom_data_pointers.sort();
+
+        AllocatorHeaderDump {
+            stored_header_ptr,
+            free_size: self.free_size,
+            available_size: self.available_size,
+            allocated_size: self.get_allocated_size(),
+            max_ptr: self.max_ptr,
+            max_pages: self.max_pages,
+            grown_pages,
+            free_blocks,
+            custom_data_pointers,
+        }
+    }
+
     #[inline]
     fn more_available_size(&mut self, additional: u64) {
         self.available_size += additional;
@@ -272,6 +452,31 @@ impl StableMemoryAllocator {
         self.max_pages
     }
 
+    /// Sets the maximum number of stable memory pages this allocator is allowed to grow to
+    ///
+    /// Same rules as for [StableMemoryAllocator::init] apply: `0` means "infinite", and if more
+    /// pages are already grown, the actual number of grown pages is used instead.
+    pub fn set_max_pages(&mut self, max_pages: u64) {
+        let available_pages = stable::size_pages();
+
+        self.max_pages = if max_pages != 0 && available_pages > max_pages {
+            available_pages
+        } else {
+            max_pages
+        };
+    }
+
+    /// Returns how many bytes of stable memory can still be grown, before reaching `max_pages`
+    ///
+    /// Returns [None] if `max_pages` is `0` (infinite).
+    pub fn remaining_growable_bytes(&self) -> Option<u64> {
+        if self.max_pages == 0 {
+            return None;
+        }
+
+        Some(self.max_pages.saturating_sub(stable::size_pages()) * PAGE_SIZE_BYTES)
+    }
+
     fn try_reallocate_in_place(
         &mut self,
         mut free_block: FreeBlock,
@@ -418,6 +623,26 @@ impl StableMemoryAllocator {
         assert_eq!(total_free_size, self.free_size);
     }
 
+    // pointers to all allocated memory blocks, collected by walking the whole stable memory
+    #[cfg(debug_assertions)]
+    pub(crate) fn debug_allocated_blocks(&self) -> Vec<StablePtr> {
+        let mut result = Vec::new();
+        let mut ptr = MIN_PTR;
+
+        while ptr < self.max_ptr {
+            let encoded_size: u64 = unsafe { crate::mem::read_fixed_for_reference(ptr) };
+            let size = encoded_size & FREE;
+
+            if encoded_size & ALLOCATED == ALLOCATED {
+                result.push(ptr);
+            }
+
+            ptr += size + StablePtr::SIZE as u64 * 2;
+        }
+
+        result
+    }
+
     pub fn _free_blocks_count(&self) -> usize {
         let mut count = 0;
 
@@ -457,7 +682,9 @@ impl AsDynSizeBytes for StableMemoryAllocator {
 #[cfg(test)]
 mod tests {
     use crate::encoding::AsDynSizeBytes;
-    use crate::mem::allocator::StableMemoryAllocator;
+    use crate::mem::allocator::{
+        StableMemoryAllocator, ALLOCATOR_FORMAT_MAGIC, ALLOCATOR_FORMAT_VERSION, ALLOCATOR_PTR,
+    };
     use crate::primitive::s_box::SBox;
     use crate::utils::mem_context::stable;
     use crate::SSlice;
@@ -724,6 +951,167 @@ mod tests {
         sma.debug_validate_free_blocks();
     }
 
+    #[test]
+    fn free_size_histogram_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+
+        let mut slices = vec![];
+        for _ in 0..50 {
+            // 256 bytes total
+            slices.push(sma.allocate(240).unwrap());
+            sma.allocate(1000).unwrap();
+        }
+
+        for slice in slices {
+            sma.deallocate(slice);
+        }
+
+        let histogram = sma.free_size_histogram();
+
+        assert_eq!(histogram.iter().sum::<u64>(), sma.get_free_size());
+        assert_eq!(histogram[8], 50 * 256);
+        assert_eq!(histogram[..8].iter().sum::<u64>(), 0);
+    }
+
+    #[test]
+    fn format_version_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        let a = sma.allocate(100).unwrap();
+        sma.custom_data_pointers.insert(1, a.as_ptr());
+
+        sma.store().unwrap();
+
+        let ptr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+        let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+
+        let mut prefix = [0u8; 2];
+        unsafe { crate::mem::read_bytes(slice.offset(0), &mut prefix) };
+        assert_eq!(prefix, [ALLOCATOR_FORMAT_MAGIC, ALLOCATOR_FORMAT_VERSION]);
+
+        // rewrite it the way older versions did - without the version byte
+        let buf = sma.as_dyn_size_bytes();
+        unsafe { crate::mem::write_bytes(slice.offset(0), &buf) };
+
+        let sma = StableMemoryAllocator::retrieve();
+        assert_eq!(sma.custom_data_pointers.get(&1), Some(&a.as_ptr()));
+
+        sma.debug_validate_free_blocks();
+    }
+
+    #[test]
+    fn format_version_1_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        let a = sma.allocate(100).unwrap();
+        sma.custom_data_pointers.insert(1, a.as_ptr());
+
+        sma.store().unwrap();
+
+        let ptr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+        let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+
+        // rewrite it the way version 1 did - without the flags byte
+        let buf = sma.as_dyn_size_bytes();
+        unsafe { crate::mem::write_bytes(slice.offset(0), &[ALLOCATOR_FORMAT_MAGIC, 1]) };
+        unsafe { crate::mem::write_bytes(slice.offset(2), &buf) };
+
+        let (sma, zero_on_free) = StableMemoryAllocator::retrieve_with_zero_on_free();
+        assert_eq!(sma.custom_data_pointers.get(&1), Some(&a.as_ptr()));
+        assert!(!zero_on_free);
+
+        sma.debug_validate_free_blocks();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported stable memory allocator format version 255")]
+    fn unknown_format_version_should_panic() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        sma.store().unwrap();
+
+        let ptr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+        let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+        unsafe { crate::mem::write_bytes(slice.offset(1), &[u8::MAX]) };
+
+        StableMemoryAllocator::retrieve();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported stable memory allocator format version 68")]
+    fn ascii_format_version_should_panic() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        sma.store().unwrap();
+
+        // version 68 is the ASCII code of 'D' - it should not be mistaken for the legacy Candid format
+        let ptr = unsafe { crate::mem::read_fixed_for_reference(ALLOCATOR_PTR) };
+        let slice = unsafe { SSlice::from_ptr(ptr).unwrap() };
+        unsafe { crate::mem::write_bytes(slice.offset(1), b"DIDL") };
+
+        StableMemoryAllocator::retrieve();
+    }
+
+    #[test]
+    fn dump_header_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+        let dump = sma.dump_header();
+
+        assert_eq!(dump.stored_header_ptr, 0);
+        assert_eq!(dump.grown_pages, 0);
+        assert!(dump.free_blocks.is_empty());
+
+        let a = sma.allocate(100).unwrap();
+        let b = sma.allocate(100).unwrap();
+        sma.allocate(100).unwrap();
+        sma.deallocate(b);
+        sma.custom_data_pointers.insert(1, a.as_ptr());
+
+        let dump = sma.dump_header();
+
+        assert_eq!(dump.free_size, sma.get_free_size());
+        assert_eq!(dump.allocated_size, sma.get_allocated_size());
+        assert_eq!(dump.grown_pages, stable::size_pages());
+        assert_eq!(dump.custom_data_pointers, vec![(1, a.as_ptr())]);
+        assert_eq!(dump.free_blocks.len(), 2);
+        assert_eq!(dump.free_blocks[0], (b.as_ptr(), b.get_size_bytes()));
+        assert_eq!(
+            dump.free_blocks
+                .iter()
+                .map(|(_, size)| size + 16)
+                .sum::<u64>(),
+            dump.free_size
+        );
+
+        sma.store().unwrap();
+        assert_ne!(sma.dump_header().stored_header_ptr, 0);
+    }
+
+    #[test]
+    fn allocation_size_for_works_fine() {
+        stable::clear();
+
+        let mut sma = StableMemoryAllocator::init(0);
+
+        for size in [0, 1, 15, 16, 17, 100, 255, 256, 1000, 4097] {
+            let slice = sma.allocate(size).unwrap();
+
+            assert_eq!(
+                slice.get_size_bytes(),
+                StableMemoryAllocator::allocation_size_for(size)
+            );
+            assert!(StableMemoryAllocator::allocation_size_for(size) >= size);
+        }
+    }
+
     #[test]
     fn basic_flow_works_fine() {
         unsafe {
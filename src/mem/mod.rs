@@ -21,14 +21,43 @@
 //!
 //! If you're thinking of implementing your own data structure using this crate, check [this](https://github.com/seniorjoinu/ic-stable-memory/docs/user-defined-data-structures.md)
 //! document for more info on this topic.
+//!
+//! # Persistence model
+//! Every stable data structure consists of two parts:
+//! 1. The data itself (elements, nodes, tables, `Sectors`), which is stored in stable memory. It is
+//! written to stable memory immediately, during each operation.
+//! 2. A small fixed-size header (lengths, capacities, pointers to the data), which is stored in the
+//! data structure value itself, on the heap.
+//!
+//! The header only gets to stable memory, when it is encoded with [AsFixedSizeBytes] - when the data
+//! structure is stored inside another stable data structure (in this case the header is rewritten
+//! automatically on each update made through [SRefMut](crate::primitive::s_ref_mut::SRefMut)), inside
+//! an [SBox](crate::SBox) or with [store_custom_data](crate::store_custom_data). Collections also provide
+//! a `flush()` method (e.g. [SVec::flush](crate::collections::SVec::flush)), which writes the header
+//! into a buffer.
+//!
+//! On the IC a trap rolls back both heap and stable memory changes made during the message, so the
+//! header can't get out of sync because of a trap. It only has to be persisted before the heap is
+//! lost - during `#[pre_upgrade]`.
 
 use crate::encoding::{AsFixedSizeBytes, Buffer};
 use crate::primitive::StableType;
 use crate::stable;
+use std::cell::Cell;
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::BTreeSet;
 
 pub mod allocator;
 pub mod free_block;
+pub mod node;
 pub mod s_slice;
+pub mod slab;
+pub mod slice_writer;
+pub mod stable_ref;
+
+pub use stable_ref::StableRef;
 
 /// A pointer to something is stable memory.
 ///
@@ -36,6 +65,165 @@ pub mod s_slice;
 pub type StablePtr = u64;
 pub(crate) type StablePtrBuf = <u64 as AsFixedSizeBytes>::Buf;
 
+#[cfg(debug_assertions)]
+thread_local! {
+    static LIVE_ALLOCATIONS: RefCell<BTreeSet<StablePtr>> = const { RefCell::new(BTreeSet::new()) };
+    static ALLOCATOR_LOCKED: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATOR_GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+thread_local! {
+    static ZERO_ON_FREE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns the number of [SSlice](s_slice::SSlice)s, which were allocated and not yet deallocated.
+///
+/// Only available in debug builds. Counts blocks allocated with [allocate](crate::allocate) and released
+/// with [deallocate](crate::deallocate) since the allocator was initialized with [init_allocator](crate::init_allocator).
+/// After [reinit_allocator](crate::reinit_allocator) all blocks, which are allocated in stable memory,
+/// are counted. Useful in tests, to check that nothing leaks after a scope ends.
+///
+/// Each allocated block is tracked by its pointer, so deallocating a block twice panics in debug
+/// builds.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{stable_memory_init, SBox};
+/// # use ic_stable_memory::mem::live_allocation_count;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// {
+///     let b = SBox::new(10u64).expect("Out of memory");
+///     assert_eq!(live_allocation_count(), 1);
+/// }
+///
+/// assert_eq!(live_allocation_count(), 0);
+/// ```
+#[cfg(debug_assertions)]
+#[inline]
+pub fn live_allocation_count() -> u64 {
+    LIVE_ALLOCATIONS.with(|it| it.borrow().len() as u64)
+}
+
+#[inline]
+pub(crate) fn track_allocation(_ptr: StablePtr) {
+    #[cfg(debug_assertions)]
+    LIVE_ALLOCATIONS.with(|it| {
+        assert!(
+            it.borrow_mut().insert(_ptr),
+            "Memory block {} is allocated twice",
+            _ptr
+        )
+    });
+}
+
+// panics on double free, instead of letting the allocator corrupt its free-list
+#[inline]
+pub(crate) fn track_deallocation(_ptr: StablePtr) {
+    #[cfg(debug_assertions)]
+    LIVE_ALLOCATIONS.with(|it| {
+        assert!(
+            it.borrow_mut().remove(&_ptr),
+            "Memory block {} is not allocated (double free?)",
+            _ptr
+        )
+    });
+}
+
+#[inline]
+pub(crate) fn track_reallocation(_old_ptr: StablePtr, _new_ptr: StablePtr) {
+    track_deallocation(_old_ptr);
+    track_allocation(_new_ptr);
+}
+
+// after an upgrade the heap is empty, so live blocks are collected from stable memory itself
+#[cfg(debug_assertions)]
+#[inline]
+pub(crate) fn reset_allocation_tracking(live: impl Iterator<Item = StablePtr>) {
+    LIVE_ALLOCATIONS.with(|it| *it.borrow_mut() = live.collect());
+}
+
+/// Panics, if the memory block `ptr` was not deallocated
+///
+/// Only does something in debug builds. Called by data structures after they are stable-dropped,
+/// to make sure they did not leak the memory block they own.
+#[inline]
+pub(crate) fn debug_assert_released(_ptr: StablePtr, _owner: &str) {
+    #[cfg(debug_assertions)]
+    LIVE_ALLOCATIONS.with(|it| {
+        assert!(
+            !it.borrow().contains(&_ptr),
+            "{} did not release its memory block {}",
+            _owner,
+            _ptr
+        )
+    });
+}
+
+/// Returns the current generation of the global stable memory allocator.
+///
+/// Only available in debug builds. Each [SSlice](s_slice::SSlice) is stamped with the generation it
+/// was created in, so it can be told apart from slices, that outlived a reset of the allocator.
+#[cfg(debug_assertions)]
+#[inline]
+pub(crate) fn allocator_generation() -> u64 {
+    ALLOCATOR_GENERATION.with(|it| it.get())
+}
+
+/// Starts a new generation of the global stable memory allocator.
+///
+/// Should be called each time memory blocks could have been moved or wiped out (on allocator reset or
+/// on compaction), so that any [SSlice](s_slice::SSlice) created before that becomes stale. Only does
+/// something in debug builds.
+#[inline]
+pub(crate) fn bump_allocator_generation() {
+    #[cfg(debug_assertions)]
+    ALLOCATOR_GENERATION.with(|it| it.set(it.get() + 1));
+}
+
+/// Returns `true` if the data of memory blocks should be overwritten with zeroes, when they are freed.
+///
+/// See [set_zero_on_free](crate::set_zero_on_free).
+#[inline]
+pub fn zero_on_free() -> bool {
+    ZERO_ON_FREE.with(|it| it.get())
+}
+
+#[inline]
+pub(crate) fn set_zero_on_free(enabled: bool) {
+    ZERO_ON_FREE.with(|it| it.set(enabled));
+}
+
+/// Re-entrancy guard of the global stable memory allocator.
+///
+/// Returned by [lock_allocator] and released when dropped. Only does something in debug builds.
+pub(crate) struct AllocatorLock(());
+
+impl Drop for AllocatorLock {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        ALLOCATOR_LOCKED.with(|it| it.set(false));
+    }
+}
+
+/// Marks the global allocator as busy, until the returned guard is dropped.
+///
+/// In debug builds panics (traps), if the allocator is already busy - this means that some mutating
+/// operation of the allocator was re-entered, while another one was still in progress, which would
+/// otherwise silently corrupt the allocator's state.
+#[inline]
+pub(crate) fn lock_allocator() -> AllocatorLock {
+    #[cfg(debug_assertions)]
+    ALLOCATOR_LOCKED.with(|it| {
+        if it.replace(true) {
+            panic!("Stable memory allocator re-entered: a mutating allocator operation was invoked, while another one is still in progress");
+        }
+    });
+
+    AllocatorLock(())
+}
+
 #[inline]
 pub(crate) fn stable_ptr_buf() -> StablePtrBuf {
     StablePtrBuf::new(<StablePtr as AsFixedSizeBytes>::SIZE)
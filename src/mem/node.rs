@@ -0,0 +1,311 @@
+//! Building blocks for user-defined stable data structures.
+//!
+//! Stable collections of this crate are built from `nodes` - memory blocks with a fixed layout, where
+//! each field lives at a constant offset (e.g. a pointer to the next node, a length, an array of
+//! elements). [RawNode] is a reusable version of this pattern: it wraps an allocated [SSlice] and
+//! provides bounds-checked typed access to its fields, so custom data structures (skip lists, graphs,
+//! tries) can share the allocator and the encoding of this crate without copy-pasting unsafe code.
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::{InvalidStablePtr, SSlice};
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, OutOfMemory};
+
+/// A memory block of stable memory with a fixed layout of fields
+///
+/// Fields are addressed by their byte offsets from the beginning of the data of the node. Each access
+/// is checked against the size of the node and panics, if the field does not fit into it. Fields
+/// are encoded with [AsFixedSizeBytes], so any type which can be stored inside [SVec](crate::collections::SVec)
+/// can also be stored inside a [RawNode].
+///
+/// Just like [SSlice], [RawNode] implements [Copy] and does not own the memory block - call
+/// [RawNode::destroy], when the node is no longer needed. Values written into a node with
+/// [RawNode::write_field] are owned by the node, so they have to be taken back with
+/// [RawNode::read_and_disown_field] before destroying it, if they own some stable memory themselves.
+///
+/// # Example
+/// A node of a singly-linked list:
+/// ```rust
+/// # use ic_stable_memory::mem::node::RawNode;
+/// # use ic_stable_memory::{stable_memory_init, AsFixedSizeBytes};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// const NEXT_OFFSET: u64 = 0;
+/// const VALUE_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
+/// const NODE_SIZE: u64 = VALUE_OFFSET + u64::SIZE as u64;
+///
+/// let mut first = RawNode::new(NODE_SIZE).expect("Out of memory");
+/// let mut second = RawNode::new(NODE_SIZE).expect("Out of memory");
+///
+/// first.write_ptr(NEXT_OFFSET, second.as_ptr());
+/// first.write_field(VALUE_OFFSET, 10u64);
+///
+/// second.write_ptr(NEXT_OFFSET, RawNode::null_ptr());
+/// second.write_field(VALUE_OFFSET, 20u64);
+///
+/// let next = unsafe { RawNode::from_ptr(first.read_ptr(NEXT_OFFSET)) };
+/// assert_eq!(next.read_field::<u64>(VALUE_OFFSET), 20);
+/// assert_eq!(next.read_ptr(NEXT_OFFSET), RawNode::null_ptr());
+///
+/// first.destroy();
+/// second.destroy();
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct RawNode {
+    slice: SSlice,
+}
+
+impl RawNode {
+    /// Allocates a new node of `size` bytes, filled with zeroes
+    ///
+    /// Returns [OutOfMemory] if there is not enough stable memory.
+    pub fn new(size: u64) -> Result<Self, OutOfMemory> {
+        let slice = unsafe { allocate(size)? };
+
+        let zeroed = vec![0u8; slice.get_size_bytes() as usize];
+        unsafe { crate::mem::write_bytes(slice.offset(0), &zeroed) };
+
+        Ok(Self { slice })
+    }
+
+    /// Recreates a node from a pointer, previously returned by [RawNode::as_ptr]
+    ///
+    /// # Panics
+    /// Panics if the pointer does not point to a valid memory block.
+    ///
+    /// # Safety
+    /// This method creates a copy of the node, make sure the node is not used after it was destroyed.
+    #[inline]
+    pub unsafe fn from_ptr(ptr: StablePtr) -> Self {
+        Self {
+            slice: SSlice::from_ptr_checked(ptr).unwrap(),
+        }
+    }
+
+    /// Same as [RawNode::from_ptr], but returns [InvalidStablePtr] instead of panicking
+    ///
+    /// # Safety
+    /// Same as for [RawNode::from_ptr].
+    #[inline]
+    pub unsafe fn try_from_ptr(ptr: StablePtr) -> Result<Self, InvalidStablePtr> {
+        Ok(Self {
+            slice: SSlice::from_ptr_checked(ptr)?,
+        })
+    }
+
+    /// A pointer value, which never points to a valid node
+    ///
+    /// Use it to mark empty pointer slots.
+    #[inline]
+    pub const fn null_ptr() -> StablePtr {
+        EMPTY_PTR
+    }
+
+    /// Returns a pointer to this node, which can be stored inside other nodes
+    #[inline]
+    pub fn as_ptr(&self) -> StablePtr {
+        self.slice.as_ptr()
+    }
+
+    /// Returns the size of this node in bytes
+    ///
+    /// May be bigger than the size requested in [RawNode::new].
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.slice.get_size_bytes()
+    }
+
+    /// Returns the underlying [SSlice]
+    #[inline]
+    pub fn as_slice(&self) -> &SSlice {
+        &self.slice
+    }
+
+    /// Reads a copy of the field at `offset`, leaving the value owned by this node
+    ///
+    /// The returned value won't be stable-dropped. See also [RawNode::read_and_disown_field].
+    ///
+    /// # Panics
+    /// Panics if the field does not fit into this node.
+    #[inline]
+    pub fn read_field<T: StableType + AsFixedSizeBytes>(&self, offset: u64) -> T {
+        unsafe { crate::mem::read_fixed_for_reference(self.field_ptr::<T>(offset)) }
+    }
+
+    /// Writes the field at `offset`, passing the ownership over the value to this node
+    ///
+    /// The previous value of the field is simply overwritten, without being stable-dropped.
+    ///
+    /// # Panics
+    /// Panics if the field does not fit into this node.
+    #[inline]
+    pub fn write_field<T: StableType + AsFixedSizeBytes>(&mut self, offset: u64, mut it: T) {
+        unsafe { crate::mem::write_fixed(self.field_ptr::<T>(offset), &mut it) };
+    }
+
+    /// Reads the field at `offset`, taking the ownership over the value from this node
+    ///
+    /// # Panics
+    /// Panics if the field does not fit into this node.
+    ///
+    /// # Safety
+    /// The field should contain a value of type `T`, which is owned by this node. Taking the same
+    /// value twice leads to a double stable-drop.
+    #[inline]
+    pub unsafe fn read_and_disown_field<T: StableType + AsFixedSizeBytes>(
+        &mut self,
+        offset: u64,
+    ) -> T {
+        crate::mem::read_fixed_for_move(self.field_ptr::<T>(offset))
+    }
+
+    /// Returns an [SRef] to the field at `offset`
+    ///
+    /// # Panics
+    /// Panics if the field does not fit into this node.
+    #[inline]
+    pub fn get_field<T: StableType + AsFixedSizeBytes>(&self, offset: u64) -> SRef<'_, T> {
+        unsafe { SRef::new(self.field_ptr::<T>(offset)) }
+    }
+
+    /// Returns an [SRefMut] to the field at `offset`
+    ///
+    /// # Panics
+    /// Panics if the field does not fit into this node.
+    #[inline]
+    pub fn get_field_mut<T: StableType + AsFixedSizeBytes>(
+        &mut self,
+        offset: u64,
+    ) -> SRefMut<'_, T> {
+        unsafe { SRefMut::new(self.field_ptr::<T>(offset)) }
+    }
+
+    /// Reads a pointer slot at `offset`
+    ///
+    /// # Panics
+    /// Panics if the slot does not fit into this node.
+    #[inline]
+    pub fn read_ptr(&self, offset: u64) -> StablePtr {
+        self.read_field(offset)
+    }
+
+    /// Writes a pointer slot at `offset`
+    ///
+    /// # Panics
+    /// Panics if the slot does not fit into this node.
+    #[inline]
+    pub fn write_ptr(&mut self, offset: u64, ptr: StablePtr) {
+        self.write_field(offset, ptr)
+    }
+
+    /// Reads raw bytes starting from `offset` into the provided buffer
+    ///
+    /// # Panics
+    /// Panics if `offset + buf.len()` is bigger than the size of this node.
+    #[inline]
+    pub fn read_bytes(&self, offset: u64, buf: &mut [u8]) {
+        unsafe { crate::mem::read_bytes(self.bytes_ptr(offset, buf.len() as u64), buf) };
+    }
+
+    /// Writes raw bytes starting from `offset`
+    ///
+    /// # Panics
+    /// Panics if `offset + buf.len()` is bigger than the size of this node.
+    #[inline]
+    pub fn write_bytes(&mut self, offset: u64, buf: &[u8]) {
+        unsafe { crate::mem::write_bytes(self.bytes_ptr(offset, buf.len() as u64), buf) };
+    }
+
+    /// Deallocates this node
+    ///
+    /// Values stored inside the node are not stable-dropped.
+    #[inline]
+    pub fn destroy(self) {
+        deallocate(self.slice);
+    }
+
+    #[inline]
+    fn field_ptr<T: AsFixedSizeBytes>(&self, offset: u64) -> StablePtr {
+        self.bytes_ptr(offset, T::SIZE as u64)
+    }
+
+    #[inline]
+    fn bytes_ptr(&self, offset: u64, len: u64) -> StablePtr {
+        assert!(offset + len <= self.size(), "Out of bounds");
+
+        self.slice.offset(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::mem::node::RawNode;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init, SBox};
+
+    const NEXT_OFFSET: u64 = 0;
+    const BOX_OFFSET: u64 = NEXT_OFFSET + u64::SIZE as u64;
+    const BYTES_OFFSET: u64 = BOX_OFFSET + SBox::<String>::SIZE as u64;
+    const NODE_SIZE: u64 = BYTES_OFFSET + 4;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut head = RawNode::null_ptr();
+
+            for i in 0..100u64 {
+                let mut node = RawNode::new(NODE_SIZE).unwrap();
+                assert_eq!(node.read_ptr(NEXT_OFFSET), 0);
+
+                node.write_ptr(NEXT_OFFSET, head);
+                node.write_field(BOX_OFFSET, SBox::new(i.to_string()).unwrap());
+                node.write_bytes(BYTES_OFFSET, &(i as u32).to_le_bytes());
+
+                head = node.as_ptr();
+            }
+
+            let mut i = 100;
+            let mut ptr = head;
+
+            while ptr != RawNode::null_ptr() {
+                i -= 1;
+
+                let mut node = unsafe { RawNode::from_ptr(ptr) };
+                assert_eq!(*node.get_field::<SBox<String>>(BOX_OFFSET), i.to_string());
+
+                let mut buf = [0u8; 4];
+                node.read_bytes(BYTES_OFFSET, &mut buf);
+                assert_eq!(u32::from_le_bytes(buf), i as u32);
+
+                let b: SBox<String> = unsafe { node.read_and_disown_field(BOX_OFFSET) };
+                assert_eq!(*b, i.to_string());
+
+                ptr = node.read_ptr(NEXT_OFFSET);
+                node.destroy();
+            }
+
+            assert_eq!(i, 0);
+            assert!(unsafe { RawNode::try_from_ptr(12345) }.is_err());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_bounds_should_panic() {
+        stable::clear();
+        stable_memory_init();
+
+        let node = RawNode::new(8).unwrap();
+        node.read_field::<u64>(node.size() - 4);
+    }
+}
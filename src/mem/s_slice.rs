@@ -6,14 +6,46 @@
 //! memory blocks, when you don't longer need them.
 
 use crate::encoding::{AsFixedSizeBytes, Buffer};
-use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::allocator::{EMPTY_PTR, MIN_PTR};
 use crate::mem::free_block::FreeBlock;
 use crate::mem::{StablePtr, StablePtrBuf};
-use crate::utils::mem_context::stable;
+use crate::primitive::StableType;
+use crate::utils::mem_context::{stable, PAGE_SIZE_BYTES};
 
 pub(crate) const ALLOCATED: u64 = 2u64.pow(u64::BITS - 1); // first biggest bit set to 1, other set to 0
 pub(crate) const FREE: u64 = ALLOCATED - 1; // first biggest bit set to 0, other set to 1
 
+// lookup table of the reflected CRC-32 (IEEE 802.3) polynomial
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Indicates that a pointer does not point to an allocated memory block.
+///
+/// Contains the pointer itself. Returned by [SSlice::from_ptr_checked].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidStablePtr(pub StablePtr);
+
 /// An allocated block of stable memory.
 ///
 /// Represented by a pointer to the first byte of the memory block and a [u64] size of this block in
@@ -25,10 +57,16 @@ pub(crate) const FREE: u64 = ALLOCATED - 1; // first biggest bit set to 0, other
 /// - bytes `(size + 8)..(size + 16)` - another `size` + `allocated bit flag`
 /// So, a memory block is simply `size` bytes of data wrapped with some metadata from both sides.
 /// [FreeBlock](mem::free_block::FreeBlock) is stored exactly in a same way.
+///
+/// In debug builds each [SSlice] is also stamped with the generation of the allocator it was created
+/// in. Once the allocator is reset, all previously created [SSlice]s become stale and any attempt to
+/// read or write through them panics, instead of silently corrupting the memory.
 #[derive(Debug, Copy, Clone)]
 pub struct SSlice {
     ptr: StablePtr,
     size: u64,
+    #[cfg(debug_assertions)]
+    generation: u64,
 }
 
 impl SSlice {
@@ -37,7 +75,12 @@ impl SSlice {
             Self::write_size(ptr, size);
         }
 
-        Self { ptr, size }
+        Self {
+            ptr,
+            size,
+            #[cfg(debug_assertions)]
+            generation: crate::mem::allocator_generation(),
+        }
     }
 
     /// Recreate an [SSlice] from a pointer to the front of the memory block.
@@ -59,6 +102,62 @@ impl SSlice {
         Some(Self::new(ptr, size, false))
     }
 
+    /// Recreate an [SSlice] from a pointer to the front of the memory block, validating it first.
+    ///
+    /// See also [SSlice::from_ptr].
+    ///
+    /// Unlike [SSlice::from_ptr], which only checks the `allocated bit flag`, this function also
+    /// checks that the memory block lies inside the grown stable memory and that its metadata on both
+    /// sides matches. Returns [InvalidStablePtr] if any of these checks fail, so it never panics or
+    /// reads outside of stable memory, even for garbage pointers.
+    ///
+    /// This still can't tell a valid memory block from random data that happens to look like one, so
+    /// it is only a sanity check - e.g. for pointers restored after an upgrade.
+    ///
+    /// # Safety
+    /// By calling this function, you're basically create a copy of an [SSlice], be careful
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{allocate, deallocate, stable_memory_init};
+    /// # use ic_stable_memory::mem::s_slice::{InvalidStablePtr, SSlice};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let slice = unsafe { allocate(100).expect("Out of memory") };
+    ///
+    /// let same_slice = unsafe { SSlice::from_ptr_checked(slice.as_ptr()).unwrap() };
+    /// assert_eq!(same_slice.get_size_bytes(), slice.get_size_bytes());
+    ///
+    /// let invalid_ptr = slice.offset(10);
+    /// assert_eq!(
+    ///     unsafe { SSlice::from_ptr_checked(invalid_ptr) }.unwrap_err(),
+    ///     InvalidStablePtr(invalid_ptr)
+    /// );
+    ///
+    /// deallocate(slice);
+    /// ```
+    pub unsafe fn from_ptr_checked(ptr: StablePtr) -> Result<Self, InvalidStablePtr> {
+        let max_ptr = stable::size_pages() * PAGE_SIZE_BYTES;
+        let meta_size = StablePtr::SIZE as u64;
+
+        if ptr < MIN_PTR || ptr == EMPTY_PTR || ptr.saturating_add(meta_size * 2) > max_ptr {
+            return Err(InvalidStablePtr(ptr));
+        }
+
+        let size = Self::read_size(ptr).ok_or(InvalidStablePtr(ptr))?;
+
+        let rear_ptr = ptr
+            .checked_add(meta_size + size)
+            .filter(|it| it.saturating_add(meta_size) <= max_ptr)
+            .ok_or(InvalidStablePtr(ptr))?;
+
+        if Self::read_size(rear_ptr) != Some(size) {
+            return Err(InvalidStablePtr(ptr));
+        }
+
+        Ok(Self::new(ptr, size, false))
+    }
+
     /// Recreate an [SSlice] from a pointer to the back of the memory block.
     ///
     /// See also [SSlice::from_ptr].
@@ -117,6 +216,7 @@ impl SSlice {
     ///
     /// # Panics
     /// Panics if boundary check fails (if the offset is outside the memory block).
+    /// In debug builds also panics, if this [SSlice] was created before the allocator was reset.
     ///
     /// # Example
     /// ```rust
@@ -132,12 +232,230 @@ impl SSlice {
     /// ```
     #[inline]
     pub fn offset(&self, offset: u64) -> StablePtr {
+        self.debug_check_generation();
+
         let ptr = Self::_offset(self.as_ptr(), offset);
         assert!(ptr <= self.as_ptr() + StablePtr::SIZE as u64 + self.get_size_bytes());
 
         ptr
     }
 
+    /// Reads `count` consecutive values of type `T`, starting from `offset`, into `dst`
+    ///
+    /// Performs a single bulk read of `count * T::SIZE` bytes and decodes the values in heap memory,
+    /// which is much cheaper than reading them one by one. Just like with
+    /// [read_fixed_for_reference](crate::mem::read_fixed_for_reference), the values stay owned by
+    /// stable memory - their stable drop flag is turned `off`. Previous values of `dst[0..count]` are
+    /// dropped.
+    ///
+    /// # Panics
+    /// Panics if `dst` is shorter than `count` or if the values do not fit into this memory block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{allocate, deallocate, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let slice = unsafe { allocate(100).expect("Out of memory") };
+    ///
+    /// slice.write_many(8, &mut [1u64, 2, 3]);
+    ///
+    /// let mut buf = [0u64; 3];
+    /// slice.read_many(8, 3, &mut buf);
+    ///
+    /// assert_eq!(buf, [1, 2, 3]);
+    ///
+    /// deallocate(slice);
+    /// ```
+    pub fn read_many<T: StableType + AsFixedSizeBytes>(
+        &self,
+        offset: u64,
+        count: usize,
+        dst: &mut [T],
+    ) {
+        assert!(count <= dst.len(), "Destination is too short");
+
+        let mut buf = vec![0u8; T::SIZE * count];
+        unsafe { crate::mem::read_bytes(self.bulk_ptr(offset, buf.len() as u64), &mut buf) };
+
+        for (it, chunk) in dst[0..count].iter_mut().zip(buf.chunks_exact(T::SIZE)) {
+            let mut v = T::from_fixed_size_bytes(chunk);
+            unsafe { v.stable_drop_flag_off() };
+
+            *it = v;
+        }
+    }
+
+    /// Writes all values of `src` consecutively, starting from `offset`
+    ///
+    /// Encodes the values in heap memory and then performs a single bulk write. Just like with
+    /// [write_fixed](crate::mem::write_fixed), the ownership over the values is passed to stable
+    /// memory - their stable drop flag is turned `off`.
+    ///
+    /// # Panics
+    /// Panics if the values do not fit into this memory block.
+    pub fn write_many<T: StableType + AsFixedSizeBytes>(&self, offset: u64, src: &mut [T]) {
+        let mut buf = vec![0u8; T::SIZE * src.len()];
+
+        for (it, chunk) in src.iter_mut().zip(buf.chunks_exact_mut(T::SIZE)) {
+            it.as_fixed_size_bytes(chunk);
+            unsafe { it.stable_drop_flag_off() };
+        }
+
+        unsafe { crate::mem::write_bytes(self.bulk_ptr(offset, buf.len() as u64), &buf) };
+    }
+
+    /// Overwrites the data of this memory block with zeroes
+    ///
+    /// Useful to scrub sensitive data (e.g. keys or secrets) before deallocating the block, so it
+    /// can't be read by whoever reuses this memory later. See also
+    /// [set_zero_on_free](crate::set_zero_on_free), which does that for every deallocated block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{allocate, deallocate, mem, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let slice = unsafe { allocate(100).expect("Out of memory") };
+    /// unsafe { mem::write_fixed(slice.offset(0), &mut 42u64) };
+    ///
+    /// slice.zeroize();
+    ///
+    /// assert_eq!(unsafe { mem::read_fixed_for_reference::<u64>(slice.offset(0)) }, 0);
+    /// deallocate(slice);
+    /// ```
+    pub fn zeroize(&self) {
+        let zeroes = vec![0u8; self.get_size_bytes().min(PAGE_SIZE_BYTES) as usize];
+
+        let mut offset = 0;
+        while offset < self.get_size_bytes() {
+            let len = (self.get_size_bytes() - offset).min(zeroes.len() as u64);
+            unsafe { crate::mem::write_bytes(self.offset(offset), &zeroes[..len as usize]) };
+
+            offset += len;
+        }
+    }
+
+    /// Computes the CRC-32 (IEEE 802.3, the same one as used by zlib) checksum of `len` bytes of this
+    /// memory block, starting from `offset`
+    ///
+    /// Bytes are read from stable memory in chunks of at most one page, so the heap usage does not
+    /// depend on `len`. Useful to detect silent corruption of critical data: store the checksum
+    /// next to it and compare them later, e.g. in `post_upgrade`. This is not a cryptographic hash -
+    /// it only protects against accidental changes.
+    ///
+    /// # Panics
+    /// Panics if the range does not fit into this memory block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{allocate, deallocate, mem, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let slice = unsafe { allocate(100).expect("Out of memory") };
+    /// unsafe { mem::write_bytes(slice.offset(0), b"123456789") };
+    ///
+    /// assert_eq!(slice.crc32(0, 9), 0xCBF4_3926);
+    /// deallocate(slice);
+    /// ```
+    pub fn crc32(&self, offset: u64, len: u64) -> u32 {
+        let ptr = self.bulk_ptr(offset, len);
+        let mut buf = vec![0u8; len.min(PAGE_SIZE_BYTES) as usize];
+        let mut crc = u32::MAX;
+
+        let mut read = 0;
+        while read < len {
+            let chunk_len = (len - read).min(buf.len() as u64) as usize;
+            let chunk = &mut buf[..chunk_len];
+            unsafe { crate::mem::read_bytes(ptr + read, chunk) };
+
+            for b in chunk.iter() {
+                crc = CRC32_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8);
+            }
+
+            read += chunk_len as u64;
+        }
+
+        !crc
+    }
+
+    /// Copies `len` bytes of this memory block, starting from `src_offset`, into `dst`, starting
+    /// from `dst_offset`
+    ///
+    /// Bytes are streamed from one memory block to another in chunks of at most one page, so the
+    /// heap usage does not depend on `len`. `dst` can be the same memory block - overlapping ranges
+    /// are copied correctly, like with [std::ptr::copy].
+    ///
+    /// # Panics
+    /// Panics if any of the ranges does not fit into its memory block.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{allocate, deallocate, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let src = unsafe { allocate(100).expect("Out of memory") };
+    /// let dst = unsafe { allocate(100).expect("Out of memory") };
+    ///
+    /// src.write_many(0, &mut [1u64, 2, 3]);
+    /// src.copy_to(8, &dst, 16, 16);
+    ///
+    /// let mut buf = [0u64; 2];
+    /// dst.read_many(16, 2, &mut buf);
+    ///
+    /// assert_eq!(buf, [2, 3]);
+    ///
+    /// deallocate(src);
+    /// deallocate(dst);
+    /// ```
+    pub fn copy_to(&self, src_offset: u64, dst: &SSlice, dst_offset: u64, len: u64) {
+        let src_ptr = self.bulk_ptr(src_offset, len);
+        let dst_ptr = dst.bulk_ptr(dst_offset, len);
+
+        if len == 0 || src_ptr == dst_ptr {
+            return;
+        }
+
+        let mut buf = vec![0u8; len.min(PAGE_SIZE_BYTES) as usize];
+
+        // if the destination overlaps the tail of the source, copy from the end
+        let backwards = dst_ptr > src_ptr && dst_ptr < src_ptr + len;
+
+        let mut copied = 0;
+        while copied < len {
+            let chunk_len = (len - copied).min(buf.len() as u64);
+            let chunk_offset = if backwards {
+                len - copied - chunk_len
+            } else {
+                copied
+            };
+
+            let chunk = &mut buf[..chunk_len as usize];
+            unsafe { crate::mem::read_bytes(src_ptr + chunk_offset, chunk) };
+            unsafe { crate::mem::write_bytes(dst_ptr + chunk_offset, chunk) };
+
+            copied += chunk_len;
+        }
+    }
+
+    #[inline]
+    fn bulk_ptr(&self, offset: u64, len: u64) -> StablePtr {
+        self.debug_check_generation();
+        assert!(offset + len <= self.get_size_bytes(), "Out of bounds");
+
+        Self::_offset(self.as_ptr(), offset)
+    }
+
+    #[inline]
+    fn debug_check_generation(&self) {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.generation,
+            crate::mem::allocator_generation(),
+            "Stale SSlice: the allocator was reset after this memory block was created"
+        );
+    }
+
     #[inline]
     pub(crate) fn to_free_block(self) -> FreeBlock {
         FreeBlock::new(self.ptr, self.size)
@@ -178,9 +496,9 @@ impl SSlice {
 mod tests {
     use crate::encoding::AsFixedSizeBytes;
     use crate::mem::allocator::MIN_PTR;
-    use crate::mem::s_slice::SSlice;
+    use crate::mem::s_slice::{SSlice, ALLOCATED};
     use crate::mem::StablePtr;
-    use crate::utils::mem_context::stable;
+    use crate::utils::mem_context::{stable, PAGE_SIZE_BYTES};
 
     #[test]
     fn read_write_work_fine() {
@@ -215,4 +533,198 @@ mod tests {
         assert_eq!(&b, &b1);
         assert_eq!(&c, &c1);
     }
+
+    #[test]
+    fn from_ptr_checked_works_fine() {
+        stable::clear();
+        stable::grow(1).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, 100, true);
+
+        unsafe {
+            assert_eq!(
+                SSlice::from_ptr_checked(MIN_PTR).unwrap().get_size_bytes(),
+                100
+            );
+            assert!(SSlice::from_ptr_checked(0).is_err());
+            assert!(SSlice::from_ptr_checked(m1.offset(8)).is_err());
+            assert!(SSlice::from_ptr_checked(PAGE_SIZE_BYTES - 4).is_err());
+            assert!(SSlice::from_ptr_checked(u64::MAX - 4).is_err());
+
+            // broken rear metadata
+            crate::mem::write_fixed(m1.offset(100), &mut 0u64);
+            assert!(SSlice::from_ptr_checked(MIN_PTR).is_err());
+
+            // size pointing outside of stable memory
+            crate::mem::write_fixed(MIN_PTR, &mut (PAGE_SIZE_BYTES | ALLOCATED));
+            assert!(SSlice::from_ptr_checked(MIN_PTR).is_err());
+        }
+    }
+
+    #[test]
+    fn read_write_many_work_fine() {
+        stable::clear();
+        stable::grow(1).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, 100, true);
+
+        let mut a = [(1u32, 2u8), (3, 4), (5, 6)];
+        m1.write_many(10, &mut a);
+
+        let mut a1 = [(0u32, 0u8); 4];
+        m1.read_many(10, 3, &mut a1);
+        assert_eq!(a1, [(1, 2), (3, 4), (5, 6), (0, 0)]);
+
+        for (i, it) in a.iter().enumerate() {
+            let v: (u32, u8) = unsafe {
+                crate::mem::read_fixed_for_reference(m1.offset(10 + (i * <(u32, u8)>::SIZE) as u64))
+            };
+            assert_eq!(v, *it);
+        }
+
+        let mut b = [u64::MAX; 12];
+        m1.write_many(4, &mut b);
+
+        let mut b1 = [0u64; 12];
+        m1.read_many(4, 12, &mut b1);
+        assert_eq!(b, b1);
+    }
+
+    #[test]
+    fn unaligned_reads_work_fine() {
+        stable::clear();
+        stable::grow(1).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, 100, true);
+
+        for offset in [1u64, 3, 5, 7, 9] {
+            unsafe { crate::mem::write_fixed(m1.offset(offset), &mut u64::MAX.rotate_left(3)) };
+            let v: u64 = unsafe { crate::mem::read_fixed_for_reference(m1.offset(offset)) };
+            assert_eq!(v, u64::MAX.rotate_left(3));
+
+            unsafe { crate::mem::write_fixed(m1.offset(offset + 16), &mut (u128::MAX - 1)) };
+            let v: u128 = unsafe { crate::mem::read_fixed_for_reference(m1.offset(offset + 16)) };
+            assert_eq!(v, u128::MAX - 1);
+        }
+
+        let buf = (0u8..32).collect::<Vec<_>>();
+        for offset in 1..8 {
+            let expected = u64::from_le_bytes(buf[offset..(offset + 8)].try_into().unwrap());
+            assert_eq!(
+                u64::from_fixed_size_bytes(&buf[offset..(offset + 8)]),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn crc32_works_fine() {
+        stable::clear();
+        stable::grow(2).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, PAGE_SIZE_BYTES + 100, true);
+        assert_eq!(m1.crc32(0, 0), 0);
+
+        unsafe { crate::mem::write_bytes(m1.offset(10), b"123456789") };
+        assert_eq!(m1.crc32(10, 9), 0xCBF4_3926);
+
+        // multiple chunks
+        let data = (0..(PAGE_SIZE_BYTES + 100))
+            .map(|i| (i * 7) as u8)
+            .collect::<Vec<_>>();
+        unsafe { crate::mem::write_bytes(m1.offset(0), &data) };
+
+        let crc = m1.crc32(0, data.len() as u64);
+        assert_eq!(crc, m1.crc32(0, data.len() as u64));
+
+        unsafe { crate::mem::write_bytes(m1.offset(PAGE_SIZE_BYTES + 50), &[1]) };
+        assert_ne!(crc, m1.crc32(0, data.len() as u64));
+    }
+
+    #[test]
+    fn copy_to_works_fine() {
+        stable::clear();
+        stable::grow(5).expect("Unable to grow");
+
+        let len = PAGE_SIZE_BYTES * 2 + 100;
+        let m1 = SSlice::new(MIN_PTR, len, true);
+        let m2 = SSlice::new(m1.as_ptr() + m1.get_total_size_bytes(), len, true);
+
+        let data = (0..len)
+            .map(|i| (i * 7 + i / 256) as u8)
+            .collect::<Vec<_>>();
+        unsafe { crate::mem::write_bytes(m1.offset(0), &data) };
+
+        m1.copy_to(0, &m2, 0, len);
+        m1.copy_to(10, &m2, 0, 0);
+
+        let mut buf = vec![0u8; len as usize];
+        unsafe { crate::mem::read_bytes(m2.offset(0), &mut buf) };
+        assert_eq!(buf, data);
+
+        m1.copy_to(50, &m2, 3, PAGE_SIZE_BYTES + 7);
+        unsafe { crate::mem::read_bytes(m2.offset(0), &mut buf) };
+        assert_eq!(&buf[..3], &data[..3]);
+        assert_eq!(
+            &buf[3..(PAGE_SIZE_BYTES as usize + 10)],
+            &data[50..(PAGE_SIZE_BYTES as usize + 57)]
+        );
+        assert_eq!(
+            &buf[(PAGE_SIZE_BYTES as usize + 10)..],
+            &data[(PAGE_SIZE_BYTES as usize + 10)..]
+        );
+
+        // overlapping ranges of the same memory block
+        let mut expected = data.clone();
+        expected.copy_within(0..(PAGE_SIZE_BYTES as usize + 20), 30);
+        m1.copy_to(0, &m1, 30, PAGE_SIZE_BYTES + 20);
+        unsafe { crate::mem::read_bytes(m1.offset(0), &mut buf) };
+        assert_eq!(buf, expected);
+
+        expected.copy_within(40..(PAGE_SIZE_BYTES as usize + 60), 5);
+        m1.copy_to(40, &m1, 5, PAGE_SIZE_BYTES + 20);
+        unsafe { crate::mem::read_bytes(m1.offset(0), &mut buf) };
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    #[should_panic]
+    fn copy_to_out_of_bounds_should_panic() {
+        stable::clear();
+        stable::grow(1).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, 100, true);
+        let m2 = SSlice::new(m1.as_ptr() + m1.get_total_size_bytes(), 50, true);
+
+        m1.copy_to(0, &m2, 0, 60);
+    }
+
+    #[test]
+    #[should_panic]
+    fn read_many_out_of_bounds_should_panic() {
+        stable::clear();
+        stable::grow(1).expect("Unable to grow");
+
+        let m1 = SSlice::new(MIN_PTR, 100, true);
+
+        let mut b = [0u64; 13];
+        m1.read_many(0, 13, &mut b);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Stale SSlice")]
+    fn stale_slice_should_panic() {
+        stable::clear();
+        crate::stable_memory_init();
+
+        let slice = unsafe { crate::allocate(100).unwrap() };
+        slice.offset(0);
+
+        crate::deinit_allocator().unwrap();
+        stable::clear();
+        crate::stable_memory_init();
+
+        slice.offset(0);
+    }
 }
@@ -0,0 +1,332 @@
+//! An arena of fixed-size slots of stable memory.
+//!
+//! [SSlab] is useful for pools of same-size objects (e.g. nodes of a graph). Unlike the general
+//! [allocator](crate::mem::allocator::StableMemoryAllocator), it does not store any metadata next to
+//! each slot - slots are carved out of big `Regions` of stable memory, and freed slots are linked into
+//! an intrusive free list. Both allocation and deallocation are `O(1)`.
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::allocator::EMPTY_PTR;
+use crate::mem::s_slice::SSlice;
+use crate::mem::StablePtr;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, OutOfMemory};
+
+const DEFAULT_REGION_CAPACITY: u64 = 16;
+const MAX_REGION_SIZE: u64 = 1 << 20;
+
+// Region layout:
+// NEXT_REGION_PTR: u64
+// SLOTS: [[u8; SLOT_SIZE]; CAPACITY]
+const NEXT_REGION_PTR_OFFSET: u64 = 0;
+const SLOTS_OFFSET: u64 = NEXT_REGION_PTR_OFFSET + u64::SIZE as u64;
+
+/// Arena allocator of fixed-size slots of stable memory
+///
+/// Slots are allocated from `Regions` - big blocks of stable memory, requested from the global allocator.
+/// Each new `Region` is twice as big as the previous one, until it reaches 1 MiB. The first `Region`
+/// always has 16 slots, so for slots bigger than 64 KiB all `Regions` are of that same size.
+/// `Regions` are only released, when the [SSlab] itself is stable-dropped.
+///
+/// Pointers returned by [SSlab::allocate] point directly to the first byte of a slot, so they can be
+/// used with [read_bytes](crate::mem::read_bytes), [write_fixed](crate::mem::write_fixed) and other
+/// functions of the [mem](crate::mem) module, the same way as pointers returned by [SSlice::offset].
+///
+/// `SLOT_SIZE` should be at least `8` bytes, since a free slot stores a pointer to the next free slot.
+///
+/// [SSlab] implements both [StableType] and [AsFixedSizeBytes], so it can be stored inside other
+/// stable data structures.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::mem::slab::SSlab;
+/// # use ic_stable_memory::stable_memory_init;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut slab = SSlab::<32>::new();
+///
+/// unsafe {
+///     let ptr = slab.allocate().expect("Out of memory");
+///     ic_stable_memory::mem::write_bytes(ptr, &[1u8; 32]);
+///
+///     slab.deallocate(ptr);
+/// }
+/// ```
+pub struct SSlab<const SLOT_SIZE: usize> {
+    len: u64,
+    free_list_ptr: StablePtr,
+    region_ptr: StablePtr,
+    region_capacity: u64,
+    region_len: u64,
+    stable_drop_flag: bool,
+}
+
+impl<const SLOT_SIZE: usize> SSlab<SLOT_SIZE> {
+    /// Creates a new [SSlab]
+    ///
+    /// Does not allocate any heap or stable memory.
+    ///
+    /// # Panics
+    /// Panics if `SLOT_SIZE` is less than `8` or bigger than 1 MiB.
+    #[inline]
+    pub fn new() -> Self {
+        assert!(
+            SLOT_SIZE >= u64::SIZE && SLOT_SIZE as u64 <= MAX_REGION_SIZE,
+            "Invalid slot size"
+        );
+
+        Self {
+            len: 0,
+            free_list_ptr: EMPTY_PTR,
+            region_ptr: EMPTY_PTR,
+            region_capacity: 0,
+            region_len: 0,
+            stable_drop_flag: true,
+        }
+    }
+
+    /// Returns the number of currently allocated slots
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if there are no allocated slots
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Allocates a slot of `SLOT_SIZE` bytes, returning a pointer to its first byte
+    ///
+    /// Reuses previously deallocated slots first. If there are none, takes a new slot from the current
+    /// `Region`, allocating a new `Region` if needed. Returns [OutOfMemory] if that was impossible.
+    ///
+    /// The content of the slot is undefined.
+    ///
+    /// # Safety
+    /// Don't forget to [deallocate](SSlab::deallocate) the slot, when you're done!
+    pub unsafe fn allocate(&mut self) -> Result<StablePtr, OutOfMemory> {
+        if self.free_list_ptr != EMPTY_PTR {
+            let ptr = self.free_list_ptr;
+
+            self.free_list_ptr = crate::mem::read_fixed_for_reference(ptr);
+            self.len += 1;
+
+            return Ok(ptr);
+        }
+
+        if self.region_ptr == EMPTY_PTR || self.region_len == self.region_capacity {
+            self.allocate_region()?;
+        }
+
+        let ptr = SSlice::_offset(
+            self.region_ptr,
+            SLOTS_OFFSET + self.region_len * SLOT_SIZE as u64,
+        );
+
+        self.region_len += 1;
+        self.len += 1;
+
+        Ok(ptr)
+    }
+
+    /// Returns a slot back to this [SSlab], so it can be reused by the next [SSlab::allocate] call
+    ///
+    /// # Safety
+    /// `ptr` should be a pointer, previously returned by [SSlab::allocate] of this same [SSlab], which
+    /// was not yet deallocated. This is not checked, so passing any other pointer will corrupt stable memory.
+    pub unsafe fn deallocate(&mut self, ptr: StablePtr) {
+        let mut next_free_ptr = self.free_list_ptr;
+        crate::mem::write_fixed(ptr, &mut next_free_ptr);
+
+        self.free_list_ptr = ptr;
+        self.len -= 1;
+    }
+
+    fn allocate_region(&mut self) -> Result<(), OutOfMemory> {
+        let capacity = if self.region_ptr == EMPTY_PTR {
+            DEFAULT_REGION_CAPACITY
+        } else {
+            // regions of big slots may already be bigger than MAX_REGION_SIZE - they never shrink
+            (self.region_capacity * 2)
+                .min(MAX_REGION_SIZE / SLOT_SIZE as u64)
+                .max(self.region_capacity)
+        };
+
+        let slice = unsafe { allocate(SLOTS_OFFSET + capacity * SLOT_SIZE as u64)? };
+
+        let mut next_region_ptr = self.region_ptr;
+        unsafe {
+            crate::mem::write_fixed(slice.offset(NEXT_REGION_PTR_OFFSET), &mut next_region_ptr)
+        };
+
+        self.region_ptr = slice.as_ptr();
+        self.region_capacity = capacity;
+        self.region_len = 0;
+
+        Ok(())
+    }
+}
+
+impl<const SLOT_SIZE: usize> Default for SSlab<SLOT_SIZE> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SLOT_SIZE: usize> AsFixedSizeBytes for SSlab<SLOT_SIZE> {
+    const SIZE: usize = u64::SIZE * 5;
+    type Buf = [u8; u64::SIZE * 5];
+
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.len.as_fixed_size_bytes(&mut buf[0..u64::SIZE]);
+        self.free_list_ptr
+            .as_fixed_size_bytes(&mut buf[u64::SIZE..(u64::SIZE * 2)]);
+        self.region_ptr
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 2)..(u64::SIZE * 3)]);
+        self.region_capacity
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 3)..(u64::SIZE * 4)]);
+        self.region_len
+            .as_fixed_size_bytes(&mut buf[(u64::SIZE * 4)..(u64::SIZE * 5)]);
+    }
+
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        Self {
+            len: u64::from_fixed_size_bytes(&buf[0..u64::SIZE]),
+            free_list_ptr: u64::from_fixed_size_bytes(&buf[u64::SIZE..(u64::SIZE * 2)]),
+            region_ptr: u64::from_fixed_size_bytes(&buf[(u64::SIZE * 2)..(u64::SIZE * 3)]),
+            region_capacity: u64::from_fixed_size_bytes(&buf[(u64::SIZE * 3)..(u64::SIZE * 4)]),
+            region_len: u64::from_fixed_size_bytes(&buf[(u64::SIZE * 4)..(u64::SIZE * 5)]),
+            stable_drop_flag: false,
+        }
+    }
+}
+
+impl<const SLOT_SIZE: usize> StableType for SSlab<SLOT_SIZE> {
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    unsafe fn stable_drop(&mut self) {
+        let mut region_ptr = self.region_ptr;
+
+        while region_ptr != EMPTY_PTR {
+            let slice = SSlice::from_ptr(region_ptr).unwrap();
+            region_ptr = crate::mem::read_fixed_for_reference(slice.offset(NEXT_REGION_PTR_OFFSET));
+
+            deallocate(slice);
+        }
+    }
+}
+
+impl<const SLOT_SIZE: usize> Drop for SSlab<SLOT_SIZE> {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe {
+                self.stable_drop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::mem::slab::SSlab;
+    use crate::primitive::StableType;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+    use std::collections::HashSet;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut slab = SSlab::<16>::new();
+            let mut ptrs = Vec::new();
+
+            for i in 0..1000u64 {
+                let ptr = unsafe { slab.allocate().unwrap() };
+                unsafe { crate::mem::write_fixed(ptr, &mut (i, i * 2)) };
+
+                ptrs.push(ptr);
+            }
+
+            assert_eq!(slab.len(), 1000);
+            assert_eq!(ptrs.iter().collect::<HashSet<_>>().len(), 1000);
+
+            let mut freed = HashSet::new();
+            for ptr in ptrs.iter().step_by(2) {
+                unsafe { slab.deallocate(*ptr) };
+                freed.insert(*ptr);
+            }
+
+            assert_eq!(slab.len(), 500);
+
+            for (i, ptr) in ptrs.iter().enumerate().skip(1).step_by(2) {
+                let it: (u64, u64) = unsafe { crate::mem::read_fixed_for_reference(*ptr) };
+                assert_eq!(it, (i as u64, i as u64 * 2));
+            }
+
+            let allocated_size = get_allocated_size();
+
+            for _ in 0..500 {
+                let ptr = unsafe { slab.allocate().unwrap() };
+                assert!(freed.remove(&ptr));
+            }
+
+            assert!(freed.is_empty());
+            assert_eq!(get_allocated_size(), allocated_size);
+
+            let mut buf = <SSlab<16> as AsFixedSizeBytes>::Buf::new(SSlab::<16>::SIZE);
+            slab.as_fixed_size_bytes(&mut buf);
+            unsafe { slab.stable_drop_flag_off() };
+
+            let mut slab = SSlab::<16>::from_fixed_size_bytes(&buf);
+            unsafe { slab.stable_drop_flag_on() };
+
+            assert_eq!(slab.len(), 1000);
+            unsafe { slab.allocate().unwrap() };
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn big_slots_work_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut slab = SSlab::<{ 128 * 1024 }>::new();
+            let mut capacity = 0;
+
+            for _ in 0..50 {
+                unsafe { slab.allocate().unwrap() };
+
+                assert!(slab.region_capacity >= capacity);
+                capacity = slab.region_capacity;
+            }
+
+            assert_eq!(capacity, 16);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
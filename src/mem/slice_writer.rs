@@ -0,0 +1,168 @@
+//! [std::io::Write] implementation, which writes directly to stable memory.
+//!
+//! Allows serializing big values (e.g. with `serde` or `candid`) straight into an [SSlice], without
+//! building an intermediate [Vec] on the heap first.
+
+use crate::mem::s_slice::SSlice;
+use crate::{allocate, deallocate, reallocate, OutOfMemory};
+use std::io;
+
+/// A growable [SSlice], which implements [std::io::Write]
+///
+/// Bytes are written one after another, starting from the beginning of the [SSlice]. When there is
+/// not enough space left, the [SSlice] is reallocated, at least doubling its size. If the canister is
+/// out of stable memory, [std::io::Write::write] returns an error of [io::ErrorKind::OutOfMemory] kind.
+///
+/// When done, call [SliceWriter::into_slice] to take the resulting [SSlice]. If the [SliceWriter]
+/// is dropped instead, the [SSlice] gets deallocated.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::mem::slice_writer::SliceWriter;
+/// # use ic_stable_memory::{deallocate, stable_memory_init};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// use std::io::Write;
+///
+/// let mut writer = SliceWriter::new(16).expect("Out of memory");
+///
+/// write!(writer, "Hello, {}!", "world").unwrap();
+/// assert_eq!(writer.len(), 13);
+///
+/// let slice = writer.into_slice();
+///
+/// let mut buf = [0u8; 13];
+/// unsafe { ic_stable_memory::mem::read_bytes(slice.offset(0), &mut buf) };
+/// assert_eq!(&buf, b"Hello, world!");
+///
+/// deallocate(slice);
+/// ```
+pub struct SliceWriter {
+    slice: Option<SSlice>,
+    len: u64,
+}
+
+impl SliceWriter {
+    /// Allocates an [SSlice] of at least `capacity` bytes to write into
+    ///
+    /// Returns [OutOfMemory] if there is not enough stable memory.
+    #[inline]
+    pub fn new(capacity: u64) -> Result<Self, OutOfMemory> {
+        let slice = unsafe { allocate(capacity)? };
+
+        Ok(Self {
+            slice: Some(slice),
+            len: 0,
+        })
+    }
+
+    /// Returns the number of bytes written so far
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if nothing was written yet
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the resulting [SSlice]
+    ///
+    /// The [SSlice] may be bigger than the number of written bytes, so remember [SliceWriter::len]
+    /// before calling this method, if you need it. Don't forget to [deallocate] the [SSlice], when you're done!
+    #[inline]
+    pub fn into_slice(mut self) -> SSlice {
+        self.slice.take().unwrap()
+    }
+}
+
+impl io::Write for SliceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let new_len = self.len + buf.len() as u64;
+        let slice = self.slice.take().unwrap();
+
+        let slice = if new_len > slice.get_size_bytes() {
+            let new_size = new_len.max(slice.get_size_bytes() * 2);
+
+            match unsafe { reallocate(slice, new_size) } {
+                Ok(s) => s,
+                Err(_) => {
+                    self.slice = Some(slice);
+
+                    return Err(io::Error::new(
+                        io::ErrorKind::OutOfMemory,
+                        "Out of stable memory",
+                    ));
+                }
+            }
+        } else {
+            slice
+        };
+
+        unsafe { crate::mem::write_bytes(slice.offset(self.len), buf) };
+
+        self.slice = Some(slice);
+        self.len = new_len;
+
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SliceWriter {
+    fn drop(&mut self) {
+        if let Some(slice) = self.slice.take() {
+            deallocate(slice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::slice_writer::SliceWriter;
+    use crate::{
+        _debug_validate_allocator, deallocate, get_allocated_size, stable, stable_memory_init,
+    };
+    use std::io::Write;
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut writer = SliceWriter::new(0).unwrap();
+            let mut expected = Vec::new();
+
+            for i in 0..1000u32 {
+                let chunk = i.to_le_bytes();
+
+                writer.write_all(&chunk).unwrap();
+                expected.extend_from_slice(&chunk);
+            }
+
+            assert_eq!(writer.len(), expected.len() as u64);
+
+            let slice = writer.into_slice();
+            assert!(slice.get_size_bytes() >= expected.len() as u64);
+
+            let mut buf = vec![0u8; expected.len()];
+            unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
+            assert_eq!(buf, expected);
+
+            deallocate(slice);
+
+            let mut writer = SliceWriter::new(100).unwrap();
+            writer.write_all(b"dropped").unwrap();
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,167 @@
+//! Typed pointers into stable memory.
+
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::StablePtr;
+use crate::primitive::s_ref::SRef;
+use crate::primitive::s_ref_mut::SRefMut;
+use crate::primitive::StableType;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+/// A [StablePtr] which remembers the type of the value it points to
+///
+/// Useful when building your own layouts in stable memory (graphs, intrusive lists, etc.) - instead of
+/// storing bare [u64] pointers and reading them with [read_fixed_for_reference](crate::mem::read_fixed_for_reference),
+/// one can store [StableRef]s and access the value through [SRef] and [SRefMut].
+///
+/// [StableRef] does not own the value it points to - it is never stable-dropped and it implements
+/// [Copy]. It implements [AsFixedSizeBytes] by encoding just the pointer, so it can be stored inside
+/// other stable data structures.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::mem::StableRef;
+/// # use ic_stable_memory::{allocate, deallocate, stable_memory_init, AsFixedSizeBytes};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let slice = unsafe { allocate(u64::SIZE as u64).expect("Out of memory") };
+/// unsafe { ic_stable_memory::mem::write_fixed(slice.offset(0), &mut 10u64) };
+///
+/// let mut r = unsafe { StableRef::<u64>::new(slice.offset(0)) };
+/// *r.deref_mut() += 10;
+///
+/// assert_eq!(*r.deref(), 20);
+///
+/// deallocate(slice);
+/// ```
+pub struct StableRef<T> {
+    ptr: StablePtr,
+    _marker: PhantomData<T>,
+}
+
+impl<T: StableType + AsFixedSizeBytes> StableRef<T> {
+    /// Creates a [StableRef] from a raw pointer
+    ///
+    /// # Safety
+    /// Make sure the pointer points to a valid location, where a `T` encoded with [AsFixedSizeBytes]
+    /// is stored, for as long as this [StableRef] (or any of its copies) is used.
+    #[inline]
+    pub unsafe fn new(ptr: StablePtr) -> Self {
+        Self {
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying raw pointer
+    #[inline]
+    pub fn as_ptr(&self) -> StablePtr {
+        self.ptr
+    }
+
+    /// Returns an [SRef] to the value this [StableRef] points to
+    #[allow(clippy::should_implement_trait)]
+    #[inline]
+    pub fn deref(&self) -> SRef<'_, T> {
+        unsafe { SRef::new(self.ptr) }
+    }
+
+    /// Returns an [SRefMut] to the value this [StableRef] points to
+    ///
+    /// Changes are written back to stable memory, when the [SRefMut] is dropped.
+    #[allow(clippy::should_implement_trait)]
+    #[inline]
+    pub fn deref_mut(&mut self) -> SRefMut<'_, T> {
+        unsafe { SRefMut::new(self.ptr) }
+    }
+}
+
+impl<T> Clone for StableRef<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for StableRef<T> {}
+
+impl<T> PartialEq for StableRef<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T> Eq for StableRef<T> {}
+
+impl<T> Debug for StableRef<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("StableRef").field(&self.ptr).finish()
+    }
+}
+
+impl<T> AsFixedSizeBytes for StableRef<T> {
+    const SIZE: usize = StablePtr::SIZE;
+    type Buf = <StablePtr as AsFixedSizeBytes>::Buf;
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.ptr.as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        Self {
+            ptr: StablePtr::from_fixed_size_bytes(buf),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> StableType for StableRef<T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::encoding::AsFixedSizeBytes;
+    use crate::mem::StableRef;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, stable,
+        stable_memory_init,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let slice = unsafe { allocate((<(u64, u64)>::SIZE * 10) as u64).unwrap() };
+            let mut refs = SVec::new();
+
+            for i in 0..10u64 {
+                let ptr = slice.offset((<(u64, u64)>::SIZE as u64) * i);
+                unsafe { crate::mem::write_fixed(ptr, &mut (i, 0u64)) };
+
+                refs.push(unsafe { StableRef::<(u64, u64)>::new(ptr) })
+                    .unwrap();
+            }
+
+            for r in refs.iter() {
+                let mut r = *r;
+                let mut it = r.deref_mut();
+                it.1 = it.0 * 2;
+            }
+
+            for (i, r) in refs.iter().enumerate() {
+                assert_eq!(r.as_ptr(), slice.offset((<(u64, u64)>::SIZE * i) as u64));
+                assert_eq!(*r.deref(), (i as u64, i as u64 * 2));
+            }
+
+            deallocate(slice);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::encoding::AsFixedSizeBytes;
+use crate::primitive::StableType;
+use std::fmt::{Debug, Formatter};
+use std::ops::Deref;
+
+/// Non-owning read-only view of a stable data structure
+///
+/// Stable data structures release their stable memory, when they are dropped with the stable drop
+/// flag set to `on`. When a data structure is temporarily reconstructed (e.g. from its header stored
+/// at some pointer) only to be read, the data is still owned by someone else - so stable-dropping it
+/// would free live data. [Borrowed] makes this explicit: it keeps the stable drop flag of the wrapped
+/// value `off` for its whole life and only provides immutable access to it, so the underlying stable
+/// memory is never released by a [Borrowed] value.
+///
+/// [Borrowed] is not bound to the lifetime of the owner - make sure the owner does not release or
+/// move the data, while the view is in use.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::SLog;
+/// # use ic_stable_memory::primitive::borrowed::Borrowed;
+/// # use ic_stable_memory::{get_allocated_size, stable_memory_init, AsFixedSizeBytes};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut log = SLog::<u64>::new();
+/// log.push(10).expect("Out of memory");
+///
+/// let mut header = [0u8; SLog::<u64>::SIZE];
+/// log.flush(&mut header);
+///
+/// let allocated = get_allocated_size();
+/// {
+///     let view = Borrowed::<SLog<u64>>::from_fixed_size_bytes(&header);
+///     assert_eq!(*view.last().unwrap(), 10);
+/// }
+///
+/// // the view did not release anything
+/// assert_eq!(get_allocated_size(), allocated);
+/// ```
+pub struct Borrowed<T: StableType> {
+    inner: T,
+}
+
+impl<T: StableType> Borrowed<T> {
+    /// Wraps a value into a non-owning view
+    ///
+    /// The stable drop flag of the value is turned `off`.
+    ///
+    /// # Safety
+    /// If the value owns its stable memory (e.g. it was just created), nobody will ever release
+    /// this memory, which leads to a memory leak. Only wrap values which are owned by someone else.
+    #[inline]
+    pub unsafe fn new(mut it: T) -> Self {
+        it.stable_drop_flag_off();
+
+        Self { inner: it }
+    }
+}
+
+impl<T: StableType + AsFixedSizeBytes> Borrowed<T> {
+    /// Reconstructs a non-owning view from bytes produced by [AsFixedSizeBytes::as_fixed_size_bytes]
+    /// (or by the `flush()` method of a collection)
+    #[inline]
+    pub fn from_fixed_size_bytes(buf: &[u8]) -> Self {
+        unsafe { Self::new(T::from_fixed_size_bytes(buf)) }
+    }
+
+    /// Reconstructs a non-owning view from a pointer to a value, stored somewhere in stable memory
+    ///
+    /// # Safety
+    /// Make sure the pointer points to a valid location, where a `T` encoded with [AsFixedSizeBytes]
+    /// is stored.
+    #[inline]
+    pub unsafe fn from_ptr(ptr: u64) -> Self {
+        Self::new(crate::mem::read_fixed_for_reference(ptr))
+    }
+}
+
+impl<T: StableType> Deref for Borrowed<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T: StableType + Debug> Debug for Borrowed<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Borrowed(")?;
+        self.inner.fmt(f)?;
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::encoding::{AsFixedSizeBytes, Buffer};
+    use crate::primitive::borrowed::Borrowed;
+    use crate::{
+        _debug_validate_allocator, allocate, deallocate, get_allocated_size, stable,
+        stable_memory_init, SBox,
+    };
+
+    #[test]
+    fn works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            for i in 0..100u64 {
+                vec.push(SBox::new(i).unwrap()).unwrap();
+            }
+
+            let mut header =
+                <SVec<SBox<u64>> as AsFixedSizeBytes>::Buf::new(SVec::<SBox<u64>>::SIZE);
+            vec.flush(&mut header);
+
+            let slice = unsafe { allocate(SVec::<SBox<u64>>::SIZE as u64).unwrap() };
+            unsafe { crate::mem::write_bytes(slice.offset(0), &header) };
+
+            let allocated = get_allocated_size();
+
+            for _ in 0..10 {
+                let view = unsafe { Borrowed::<SVec<SBox<u64>>>::from_ptr(slice.offset(0)) };
+                assert_eq!(view.len(), 100);
+
+                let view = Borrowed::<SVec<SBox<u64>>>::from_fixed_size_bytes(&header);
+                assert_eq!(**view.get(50).unwrap(), 50);
+            }
+
+            assert_eq!(get_allocated_size(), allocated);
+
+            for i in 0..100u64 {
+                assert_eq!(**vec.get(i as usize).unwrap(), i);
+            }
+
+            deallocate(slice);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -5,9 +5,15 @@ use serde_bytes::ByteBuf;
 use std::collections::{BTreeSet, HashSet};
 use ic_ledger_types::Subaccount;
 
+/// [Borrowed](borrowed::Borrowed) non-owning read-only view of a stable data structure
+pub mod borrowed;
+
 /// [SBox] smart-pointer that allows storing dynamically-sized data to stable memory
 pub mod s_box;
 
+/// [SBytes](s_bytes::SBytes) growable byte buffer stored on stable memory
+pub mod s_bytes;
+
 /// Immutable reference to fixed size data on stable memory
 pub mod s_ref;
 
@@ -1,5 +1,5 @@
 use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
-use crate::mem::s_slice::SSlice;
+use crate::mem::s_slice::{InvalidStablePtr, SSlice};
 use crate::primitive::StableType;
 use crate::utils::certification::{AsHashTree, AsHashableBytes, HashTree};
 use crate::{allocate, deallocate, reallocate, OutOfMemory};
@@ -83,6 +83,32 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         self.slice.unwrap().as_ptr()
     }
 
+    /// Returns [true] if both [SBox]es point to the same [SSlice] of stable memory
+    ///
+    /// Works like [Rc::ptr_eq](std::rc::Rc::ptr_eq) - compares the location of the boxed values,
+    /// not the values themselves. Two [SBox]es, created with [SBox::new] from equal values, are
+    /// equal, but are not the same [SBox].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let a = SBox::new(10u64).expect("Out of memory");
+    /// let b = SBox::new(10u64).expect("Out of memory");
+    ///
+    /// assert_eq!(a, b);
+    /// assert!(!a.ptr_eq(&b));
+    ///
+    /// let a_copy = unsafe { SBox::<u64>::from_ptr(a.as_ptr()) };
+    ///
+    /// assert!(a.ptr_eq(&a_copy));
+    /// ```
+    #[inline]
+    pub fn ptr_eq(&self, other: &SBox<T>) -> bool {
+        self.as_ptr() == other.as_ptr()
+    }
+
     /// Returns the underlying data, releasing occupied stable memory.
     #[inline]
     pub fn into_inner(mut self) -> T {
@@ -132,6 +158,37 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         }
     }
 
+    /// Same as [SBox::from_ptr], but returns [InvalidStablePtr] instead of panicking, if the
+    /// pointer does not point to a valid memory block
+    ///
+    /// The memory block is validated with [SSlice::from_ptr_checked]. Useful, when restoring pointers
+    /// which come from outside (e.g. after an upgrade).
+    ///
+    /// # Safety
+    /// Same as for [SBox::from_ptr].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init, StableType};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut b = SBox::new(10u64).expect("Out of memory");
+    /// unsafe { b.stable_drop_flag_off() };
+    ///
+    /// b = unsafe { SBox::from_ptr_checked(b.as_ptr()).unwrap() };
+    ///
+    /// assert!(unsafe { SBox::<u64>::from_ptr_checked(12345) }.is_err());
+    /// ```
+    pub unsafe fn from_ptr_checked(ptr: u64) -> Result<Self, InvalidStablePtr> {
+        let slice = SSlice::from_ptr_checked(ptr)?;
+
+        Ok(Self {
+            stable_drop_flag: false,
+            slice: Some(slice),
+            inner: UnsafeCell::default(),
+        })
+    }
+
     /// Provides mutable access to the underlying data, by accepting a lambda function.
     ///
     /// Returns [OutOfMemory] error if it was impossible to reallocate the underlying [SSlice] to
@@ -168,6 +225,112 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
         }
     }
 
+    /// Replaces the underlying data with the new one, returning the previous value.
+    ///
+    /// Similar to [std::mem::replace]. If the canister is out of stable memory and it was impossible
+    /// to reallocate the underlying [SSlice] to fit the new value, returns [OutOfMemory], leaving
+    /// the [SBox] unchanged. The new value is dropped in that case.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut b = SBox::new(String::from("old")).expect("Out of memory");
+    ///
+    /// let prev = b.replace(String::from("new")).expect("Out of memory");
+    ///
+    /// assert_eq!(prev, "old");
+    /// assert_eq!(&*b, "new");
+    /// ```
+    pub fn replace(&mut self, it: T) -> Result<T, OutOfMemory> {
+        unsafe { self.lazy_read(true) };
+
+        let mut prev = self.inner.get_mut().replace(it).unwrap();
+
+        if self.repersist().is_ok() {
+            Ok(prev)
+        } else {
+            unsafe { prev.stable_drop_flag_off() };
+
+            let mut it = self.inner.get_mut().replace(prev).unwrap();
+            unsafe { it.stable_drop_flag_on() };
+
+            Err(OutOfMemory)
+        }
+    }
+
+    /// Provides fallible transactional mutable access to the underlying data, by accepting a lambda
+    /// function.
+    ///
+    /// The changes are only persisted if the lambda returns [Ok]. If it returns [Err], the changes
+    /// are discarded, the original value is restored and [UpdateError::Func] with that error is
+    /// returned. If the changed value doesn't fit into the underlying [SSlice] anymore and the canister
+    /// is out of stable memory, the original value is also restored and [UpdateError::OutOfMemory] is
+    /// returned.
+    ///
+    /// Only the value itself is restored. Stable structures nested inside it (e.g. other [SBox]es)
+    /// persist their changes immediately, so they should not be modified by a lambda that can fail.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{SBox, stable_memory_init};
+    /// # use ic_stable_memory::primitive::s_box::UpdateError;
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut name = SBox::new(String::from("Sasha")).expect("Out of memory");
+    ///
+    /// let append = |it: &mut String, suffix: &str| {
+    ///     it.push_str(suffix);
+    ///
+    ///     if it.len() > 10 {
+    ///         Err("Too long")
+    ///     } else {
+    ///         Ok(())
+    ///     }
+    /// };
+    ///
+    /// name.try_update_with(|it| append(it, "!")).expect("Out of memory");
+    /// assert_eq!(&*name, "Sasha!");
+    ///
+    /// let res = name.try_update_with(|it| append(it, " the Great"));
+    /// assert!(matches!(res, Err(UpdateError::Func("Too long"))));
+    /// assert_eq!(&*name, "Sasha!");
+    /// ```
+    pub fn try_update_with<E, F: FnOnce(&mut T) -> Result<(), E>>(
+        &mut self,
+        func: F,
+    ) -> Result<(), UpdateError<E>> {
+        unsafe { self.lazy_read(true) };
+
+        let res = match func(self.inner.get_mut().as_mut().unwrap()) {
+            Ok(_) => match self.repersist() {
+                Ok(_) => return Ok(()),
+                Err(e) => UpdateError::OutOfMemory(e),
+            },
+            Err(e) => UpdateError::Func(e),
+        };
+
+        // stable memory still holds the original value - discard the changed one and re-read it lazily
+        let mut discarded = self.inner.get_mut().take().unwrap();
+        unsafe { discarded.stable_drop_flag_off() };
+
+        Err(res)
+    }
+
+    // decodes a copy of the underlying data, which stays owned by this box - stable memory is always
+    // up to date, since every mutation is repersisted right away
+    pub(crate) fn read_for_reference(&self) -> T {
+        let slice = self.slice.as_ref().unwrap();
+        let mut buf = vec![0u8; slice.get_size_bytes() as usize];
+        unsafe { crate::mem::read_bytes(slice.offset(0), &mut buf) };
+
+        let mut it = T::from_dyn_size_bytes(&buf);
+        unsafe { it.stable_drop_flag_off() };
+
+        it
+    }
+
     unsafe fn lazy_read(&self, drop_flag: bool) {
         if let Some(it) = (*self.inner.get()).as_mut() {
             if drop_flag {
@@ -219,6 +382,15 @@ impl<T: AsDynSizeBytes + StableType> SBox<T> {
     }
 }
 
+/// Error of [SBox::try_update_with]
+#[derive(Debug, Copy, Clone)]
+pub enum UpdateError<E> {
+    /// The lambda function has returned an error
+    Func(E),
+    /// The canister is out of stable memory and the changed value can't be persisted
+    OutOfMemory(OutOfMemory),
+}
+
 impl<T: AsDynSizeBytes + StableType> AsFixedSizeBytes for SBox<T> {
     const SIZE: usize = u64::SIZE;
     type Buf = [u8; u64::SIZE];
@@ -254,7 +426,10 @@ impl<T: AsDynSizeBytes + StableType> StableType for SBox<T> {
 
     #[inline]
     unsafe fn stable_drop(&mut self) {
-        deallocate(self.slice.take().unwrap());
+        let slice = self.slice.take().unwrap();
+
+        deallocate(slice);
+        crate::mem::debug_assert_released(slice.as_ptr(), "SBox");
     }
 }
 
@@ -345,6 +520,28 @@ impl<T: PartialOrd + AsDynSizeBytes + StableType> PartialOrd for SBox<T> {
     }
 }
 
+impl<T: PartialEq + AsDynSizeBytes + StableType> PartialEq<T> for SBox<T> {
+    #[inline]
+    fn eq(&self, other: &T) -> bool {
+        unsafe {
+            self.lazy_read(false);
+
+            (*self.inner.get()).as_ref().unwrap().eq(other)
+        }
+    }
+}
+
+impl<T: PartialOrd + AsDynSizeBytes + StableType> PartialOrd<T> for SBox<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &T) -> Option<Ordering> {
+        unsafe {
+            self.lazy_read(false);
+
+            (*self.inner.get()).as_ref().unwrap().partial_cmp(other)
+        }
+    }
+}
+
 impl<T: Eq + PartialEq + AsDynSizeBytes + StableType> Eq for SBox<T> {}
 
 impl<T: Ord + PartialOrd + AsDynSizeBytes + StableType> Ord for SBox<T> {
@@ -411,10 +608,11 @@ impl<T: AsDynSizeBytes + StableType> Deref for SBox<T> {
 #[cfg(test)]
 mod tests {
     use crate::collections::SVec;
-    use crate::primitive::s_box::SBox;
+    use crate::primitive::s_box::{SBox, UpdateError};
     use crate::{
-        _debug_validate_allocator, get_allocated_size, retrieve_custom_data, stable,
-        stable_memory_init, store_custom_data,
+        _debug_validate_allocator, get_allocated_size, retrieve_custom_data,
+        set_grow_ceiling_bytes, stable, stable_memory_init, store_custom_data, OutOfMemory,
+        PAGE_SIZE_BYTES,
     };
     use candid::encode_one;
     use std::cmp::Ordering;
@@ -480,6 +678,36 @@ mod tests {
 
             let sbox = SBox::<i32>::new(i32::default()).unwrap();
             assert!(matches!(sbox1.cmp(&sbox), Ordering::Greater));
+
+            assert_eq!(sbox1, 10);
+            assert_ne!(sbox1, 20);
+            assert!(sbox1 < 20);
+            assert!(sbox2 > 10);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn ptr_eq_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let a = SBox::new(10u64).unwrap();
+            let b = SBox::new(10u64).unwrap();
+
+            assert_eq!(a, b);
+            assert!(a.ptr_eq(&a));
+            assert!(!a.ptr_eq(&b));
+
+            // not owned, so dropping it doesn't release the memory of `a`
+            let a_copy = unsafe { SBox::<u64>::from_ptr(a.as_ptr()) };
+
+            assert!(a.ptr_eq(&a_copy));
+            assert!(a_copy.ptr_eq(&a));
+            assert!(!a_copy.ptr_eq(&b));
         }
 
         _debug_validate_allocator();
@@ -529,4 +757,75 @@ mod tests {
             assert_eq!(bytes.len(), 17);
         }
     }
+
+    #[test]
+    fn replace_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut b = SBox::new(String::from("short")).unwrap();
+
+            let prev = b.replace(String::from("much much longer string")).unwrap();
+            assert_eq!(prev, "short");
+            assert_eq!(&*b, "much much longer string");
+
+            let mut nested = SBox::new(SBox::new(10u64).unwrap()).unwrap();
+
+            let prev = nested.replace(SBox::new(20u64).unwrap()).unwrap();
+            assert_eq!(*prev, 10);
+            assert_eq!(**nested, 20);
+
+            let mut b = SBox::new(String::from("short")).unwrap();
+            set_grow_ceiling_bytes(1);
+
+            let res = b.replace("long".repeat(PAGE_SIZE_BYTES as usize));
+            assert!(matches!(res, Err(OutOfMemory)));
+            assert_eq!(&*b, "short");
+
+            set_grow_ceiling_bytes(0);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn try_update_with_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut b = SBox::new(String::from("short")).unwrap();
+
+            b.try_update_with(|it| {
+                it.push_str(" and longer");
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+            assert_eq!(&*b, "short and longer");
+
+            let res = b.try_update_with(|it| {
+                it.push_str(" and even longer");
+                Err(10)
+            });
+            assert!(matches!(res, Err(UpdateError::Func(10))));
+            assert_eq!(&*b, "short and longer");
+
+            let mut nested = SBox::new(SBox::new(10u64).unwrap()).unwrap();
+
+            nested
+                .try_update_with(|it| {
+                    it.try_update_with(|it| {
+                        *it += 1;
+                        Ok::<(), ()>(())
+                    })
+                })
+                .unwrap();
+            assert_eq!(**nested, 11);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
 }
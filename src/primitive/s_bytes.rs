@@ -0,0 +1,283 @@
+use crate::encoding::AsFixedSizeBytes;
+use crate::mem::s_slice::SSlice;
+use crate::primitive::StableType;
+use crate::{allocate, deallocate, reallocate, OutOfMemory};
+use std::fmt::{Debug, Formatter};
+
+/// Growable byte buffer stored on stable memory.
+///
+/// Unlike `SBox<Vec<u8>>`, appending to [SBytes] does not deserialize and reserialize the whole buffer -
+/// only the new bytes get written. When there is not enough capacity left, the underlying [SSlice]
+/// is reallocated, at least doubling its size.
+///
+/// The length of the buffer is stored inside the [SSlice] itself, in its first `8` bytes. [SBytes]
+/// implements [AsFixedSizeBytes] as a pointer to this [SSlice], so it can be put inside any other
+/// stable data structure, just like [SBox](crate::SBox).
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::{stable_memory_init, SBytes};
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut bytes = SBytes::new(b"Hello").expect("Out of memory");
+///
+/// bytes.append(b", world!").expect("Out of memory");
+///
+/// assert_eq!(bytes.len(), 13);
+/// assert_eq!(bytes.to_vec(), b"Hello, world!".to_vec());
+/// ```
+pub struct SBytes {
+    slice: Option<SSlice>,
+    len: u64,
+    stable_drop_flag: bool,
+}
+
+impl SBytes {
+    /// Allocates a new [SBytes] buffer and writes the provided bytes into it.
+    ///
+    /// Returns [OutOfMemory] if there is not enough stable memory.
+    pub fn new(data: &[u8]) -> Result<Self, OutOfMemory> {
+        let slice = unsafe { allocate(u64::SIZE as u64 + data.len() as u64)? };
+
+        let mut it = Self {
+            slice: Some(slice),
+            len: data.len() as u64,
+            stable_drop_flag: true,
+        };
+
+        unsafe { crate::mem::write_bytes(slice.offset(u64::SIZE as u64), data) };
+        it.write_len();
+
+        Ok(it)
+    }
+
+    /// Returns the length of this [SBytes] in bytes
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns [true] if the length of this [SBytes] is `0`
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes this [SBytes] can hold without reallocating
+    #[inline]
+    pub fn capacity(&self) -> u64 {
+        self.slice.as_ref().unwrap().get_size_bytes() - u64::SIZE as u64
+    }
+
+    /// Appends bytes to the end of this [SBytes]
+    ///
+    /// Only the new bytes are written to stable memory. If there is not enough capacity left, tries
+    /// to reallocate the underlying [SSlice], returning [OutOfMemory] if that was impossible. In that
+    /// case this [SBytes] stays unchanged.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::{stable_memory_init, SBytes};
+    /// # unsafe { ic_stable_memory::mem::clear(); }
+    /// # stable_memory_init();
+    /// let mut log = SBytes::new(&[]).expect("Out of memory");
+    ///
+    /// for i in 0..100u8 {
+    ///     log.append(&[i]).expect("Out of memory");
+    /// }
+    ///
+    /// assert_eq!(log.len(), 100);
+    /// ```
+    pub fn append(&mut self, data: &[u8]) -> Result<(), OutOfMemory> {
+        let new_len = self.len + data.len() as u64;
+
+        if new_len > self.capacity() {
+            let new_capacity = new_len.max(self.capacity() * 2);
+            let slice = self.slice.take().unwrap();
+
+            match unsafe { reallocate(slice, u64::SIZE as u64 + new_capacity) } {
+                Ok(s) => self.slice = Some(s),
+                Err(e) => {
+                    self.slice = Some(slice);
+                    return Err(e);
+                }
+            }
+        }
+
+        let slice = self.slice.as_ref().unwrap();
+        unsafe { crate::mem::write_bytes(slice.offset(u64::SIZE as u64 + self.len), data) };
+
+        self.len = new_len;
+        self.write_len();
+
+        Ok(())
+    }
+
+    /// Reads bytes starting from `offset` into the provided buffer
+    ///
+    /// # Panics
+    /// Panics if `offset + buf.len()` is bigger than the length of this [SBytes].
+    pub fn read(&self, offset: u64, buf: &mut [u8]) {
+        assert!(offset + buf.len() as u64 <= self.len, "Out of bounds");
+
+        let slice = self.slice.as_ref().unwrap();
+        unsafe { crate::mem::read_bytes(slice.offset(u64::SIZE as u64 + offset), buf) };
+    }
+
+    /// Copies the content of this [SBytes] to the heap
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.len as usize];
+        self.read(0, &mut buf);
+
+        buf
+    }
+
+    /// Sets the length of this [SBytes] to `0`
+    ///
+    /// Does not release occupied stable memory.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.write_len();
+    }
+
+    /// Returns a pointer to the underlying [SSlice] of stable memory.
+    ///
+    /// See also [SBytes::from_ptr].
+    #[inline]
+    pub fn as_ptr(&self) -> u64 {
+        self.slice.unwrap().as_ptr()
+    }
+
+    /// Creates [SBytes] from a pointer to the underlying [SSlice] of stable memory.
+    ///
+    /// See also [SBytes::as_ptr].
+    ///
+    /// # Panics
+    /// Panics if the pointer points to an invalid (or free) block of stable memory.
+    ///
+    /// # Safety
+    /// This method basically allows you to clone the smart-pointer, which breaks ownership and
+    /// stable-drop rules. Always make sure you restore stable-drop rules manually.
+    pub unsafe fn from_ptr(ptr: u64) -> Self {
+        let slice = SSlice::from_ptr(ptr).unwrap();
+        let len = crate::mem::read_fixed_for_reference(slice.offset(0));
+
+        Self {
+            slice: Some(slice),
+            len,
+            stable_drop_flag: false,
+        }
+    }
+
+    #[inline]
+    fn write_len(&mut self) {
+        let slice = self.slice.as_ref().unwrap();
+        unsafe { crate::mem::write_fixed(slice.offset(0), &mut self.len) };
+    }
+}
+
+impl AsFixedSizeBytes for SBytes {
+    const SIZE: usize = u64::SIZE;
+    type Buf = [u8; u64::SIZE];
+
+    #[inline]
+    fn as_fixed_size_bytes(&self, buf: &mut [u8]) {
+        self.as_ptr().as_fixed_size_bytes(buf)
+    }
+
+    #[inline]
+    fn from_fixed_size_bytes(arr: &[u8]) -> Self {
+        let ptr = u64::from_fixed_size_bytes(arr);
+
+        unsafe { Self::from_ptr(ptr) }
+    }
+}
+
+impl StableType for SBytes {
+    #[inline]
+    fn should_stable_drop(&self) -> bool {
+        self.stable_drop_flag
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_off(&mut self) {
+        self.stable_drop_flag = false;
+    }
+
+    #[inline]
+    unsafe fn stable_drop_flag_on(&mut self) {
+        self.stable_drop_flag = true;
+    }
+
+    #[inline]
+    unsafe fn stable_drop(&mut self) {
+        deallocate(self.slice.take().unwrap());
+    }
+}
+
+impl Drop for SBytes {
+    fn drop(&mut self) {
+        if self.should_stable_drop() {
+            unsafe { self.stable_drop() };
+        }
+    }
+}
+
+impl Debug for SBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SBytes(")?;
+        self.to_vec().fmt(f)?;
+        f.write_str(")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::primitive::s_bytes::SBytes;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+
+    #[test]
+    fn append_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut bytes = SBytes::new(&[]).unwrap();
+            assert!(bytes.is_empty());
+
+            let mut expected = Vec::new();
+            for i in 0..1000u64 {
+                let chunk = vec![i as u8; (i % 13) as usize];
+
+                bytes.append(&chunk).unwrap();
+                expected.extend_from_slice(&chunk);
+
+                assert_eq!(bytes.len(), expected.len() as u64);
+                assert!(bytes.capacity() >= bytes.len());
+            }
+
+            assert_eq!(bytes.to_vec(), expected);
+
+            let mut buf = [0u8; 5];
+            bytes.read(10, &mut buf);
+            assert_eq!(buf, expected[10..15]);
+
+            let mut vec = SVec::new();
+            vec.push(bytes).unwrap();
+
+            let mut bytes = vec.pop().unwrap();
+            assert_eq!(bytes.to_vec(), expected);
+
+            bytes.clear();
+            assert!(bytes.is_empty());
+
+            bytes.append(b"test").unwrap();
+            assert_eq!(bytes.to_vec(), b"test".to_vec());
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
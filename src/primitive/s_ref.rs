@@ -3,6 +3,8 @@ use crate::primitive::StableType;
 use candid::types::{Serializer, Type, TypeId};
 use candid::CandidType;
 use std::cell::UnsafeCell;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::Deref;
 
@@ -13,6 +15,10 @@ use std::ops::Deref;
 /// Lazy - only loads and deserializes the data, when it gets accessed. Useful when building your
 /// own stable data structure. Immutable access is provided by dereferencing.
 ///
+/// Comparisons and hashing are delegated to `T`. Each [SRef] reads its value from stable memory at
+/// most once and then caches it, so comparing two fresh references costs two stable reads and any
+/// further comparison of the same references costs none.
+///
 /// `T` has to implement [StableType] and [AsFixedSizeBytes].
 pub struct SRef<'o, T> {
     ptr: u64,
@@ -78,3 +84,76 @@ impl<'o, T: StableType + AsFixedSizeBytes + CandidType> CandidType for SRef<'o,
         }
     }
 }
+
+impl<'o, T: StableType + AsFixedSizeBytes + PartialEq> PartialEq for SRef<'o, T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        (**self).eq(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Eq> Eq for SRef<'o, T> {}
+
+impl<'o, T: StableType + AsFixedSizeBytes + PartialOrd> PartialOrd for SRef<'o, T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Ord> Ord for SRef<'o, T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Hash> Hash for SRef<'o, T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::SVec;
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    #[test]
+    fn comparison_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::<u64>::new();
+            vec.push(10).unwrap();
+            vec.push(20).unwrap();
+            vec.push(10).unwrap();
+
+            assert!(vec.get(0) == vec.get(2));
+            assert!(vec.get(0) != vec.get(1));
+            assert!(vec.get(0).unwrap() < vec.get(1).unwrap());
+            assert_eq!(
+                vec.get(1).unwrap().cmp(&vec.get(2).unwrap()),
+                std::cmp::Ordering::Greater
+            );
+
+            let hash = |it: &dyn Fn(&mut DefaultHasher)| {
+                let mut hasher = DefaultHasher::new();
+                it(&mut hasher);
+                hasher.finish()
+            };
+
+            assert_eq!(
+                hash(&|h| vec.get(0).unwrap().hash(h)),
+                hash(&|h| 10u64.hash(h))
+            );
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
@@ -3,6 +3,8 @@ use crate::primitive::StableType;
 use candid::types::{Serializer, Type};
 use candid::CandidType;
 use std::cell::UnsafeCell;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
@@ -14,6 +16,9 @@ use std::ops::{Deref, DerefMut};
 /// only performs actual underlying data updates when [Drop]-ped. Useful when building your
 /// own stable data structure. Immutable and mutable access is provided by dereferencing.
 ///
+/// Comparisons and hashing are delegated to `T`, the same way as for [SRef](crate::primitive::s_ref::SRef) -
+/// each side is read from stable memory at most once.
+///
 /// `T` has to implement [StableType] and [AsFixedSizeBytes].
 pub struct SRefMut<'o, T: StableType + AsFixedSizeBytes> {
     ptr: u64,
@@ -101,3 +106,33 @@ impl<'o, T: StableType + AsFixedSizeBytes + CandidType> CandidType for SRefMut<'
         }
     }
 }
+
+impl<'o, T: StableType + AsFixedSizeBytes + PartialEq> PartialEq for SRefMut<'o, T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        (**self).eq(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Eq> Eq for SRefMut<'o, T> {}
+
+impl<'o, T: StableType + AsFixedSizeBytes + PartialOrd> PartialOrd for SRefMut<'o, T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Ord> Ord for SRefMut<'o, T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<'o, T: StableType + AsFixedSizeBytes + Hash> Hash for SRefMut<'o, T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
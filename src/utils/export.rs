@@ -0,0 +1,558 @@
+//! Canonical export of stable collections into a single byte stream
+//!
+//! See [StableExport].
+
+use crate::collections::hash_map::hasher::StableHasherFactory;
+use crate::collections::{SBTreeMap, SBTreeSet, SHashMap, SHashSet, SLog, SVec};
+use crate::encoding::{AsDynSizeBytes, AsFixedSizeBytes};
+use crate::primitive::s_box::SBox;
+use crate::primitive::StableType;
+use candid::{Int, Nat, Principal};
+use ic_ledger_types::Subaccount;
+use std::hash::Hash;
+
+/// Magic bytes, which every export starts with
+pub const EXPORT_MAGIC: [u8; 4] = *b"SMEX";
+
+/// Version of the export format, written right after [EXPORT_MAGIC]
+pub const EXPORT_FORMAT_VERSION: u8 = 2;
+
+/// Value of [AsExportBytes::EXPORT_SIZE] for types, whose exported size depends on the value
+pub const VARIABLE_EXPORT_SIZE: u32 = u32::MAX;
+
+/// Type of a collection, written in the header of each section of a [StableExport]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ExportedKind {
+    /// [SVec] - elements in index order
+    Vec = 0,
+    /// [SLog] - elements from first to last
+    Log = 1,
+    /// [SBTreeMap] - entries in ascending order of keys
+    BTreeMap = 2,
+    /// [SBTreeSet] - elements in ascending order
+    BTreeSet = 3,
+    /// [SHashMap] - entries sorted by key
+    HashMap = 4,
+    /// [SHashSet] - elements sorted in ascending order
+    HashSet = 5,
+}
+
+/// Types, whose values can be written into a [StableExport]
+///
+/// This trait is only implemented for types, which don't hold stable memory pointers, so an export
+/// never depends on where the allocator has put the data:
+/// * numbers, `bool`, `char`, `()`, [Principal], [Subaccount], [Nat] and [Int] are written in their
+///   raw [AsFixedSizeBytes] layout;
+/// * arrays and tuples are written element by element;
+/// * an [Option] is written as a `0` byte, or as a `1` byte followed by the value;
+/// * a [String] or a [Vec] is written as its length (u32) followed by its bytes or elements;
+/// * an [SBox] is written as the value it points to.
+///
+/// Stable collections don't implement this trait, so they can't be nested into exported ones. For
+/// your own types, write their fields one after another.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::utils::export::AsExportBytes;
+/// # use ic_stable_memory::derive::{AsFixedSizeBytes, StableType};
+/// #[derive(AsFixedSizeBytes, StableType)]
+/// struct Account {
+///     id: u64,
+///     balance: u128,
+/// }
+///
+/// impl AsExportBytes for Account {
+///     const EXPORT_SIZE: u32 = u64::EXPORT_SIZE + u128::EXPORT_SIZE;
+///
+///     fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+///         self.id.write_export_bytes(buf);
+///         self.balance.write_export_bytes(buf);
+///     }
+/// }
+/// ```
+pub trait AsExportBytes {
+    /// Size of an exported value in bytes, or [VARIABLE_EXPORT_SIZE] if it depends on the value
+    const EXPORT_SIZE: u32;
+
+    /// Appends the exported representation of this value to the buffer
+    fn write_export_bytes(&self, buf: &mut Vec<u8>);
+}
+
+macro_rules! impl_for_plain {
+    ($($ty:ty),+) => {
+        $(
+            impl AsExportBytes for $ty {
+                const EXPORT_SIZE: u32 = <$ty as AsFixedSizeBytes>::SIZE as u32;
+
+                #[inline]
+                fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+                    push_fixed(buf, self);
+                }
+            }
+        )+
+    };
+}
+
+impl_for_plain!(i8, u8, i16, u16, i32, u32, i64, u64, i128, u128, isize, usize, f32, f64);
+impl_for_plain!(char, bool, (), Principal, Subaccount, Nat, Int);
+
+impl<T: AsExportBytes, const N: usize> AsExportBytes for [T; N] {
+    const EXPORT_SIZE: u32 = if T::EXPORT_SIZE == VARIABLE_EXPORT_SIZE {
+        VARIABLE_EXPORT_SIZE
+    } else {
+        T::EXPORT_SIZE * N as u32
+    };
+
+    fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+        for it in self {
+            it.write_export_bytes(buf);
+        }
+    }
+}
+
+macro_rules! impl_for_tuple {
+    ($($ty:ident $idx:tt),+) => {
+        impl<$($ty: AsExportBytes),+> AsExportBytes for ($($ty,)+) {
+            const EXPORT_SIZE: u32 = sum_export_sizes(&[$($ty::EXPORT_SIZE),+]);
+
+            fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+                $(self.$idx.write_export_bytes(buf);)+
+            }
+        }
+    };
+}
+
+impl_for_tuple!(A 0);
+impl_for_tuple!(A 0, B 1);
+impl_for_tuple!(A 0, B 1, C 2);
+impl_for_tuple!(A 0, B 1, C 2, D 3);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+impl<T: AsExportBytes> AsExportBytes for Option<T> {
+    const EXPORT_SIZE: u32 = VARIABLE_EXPORT_SIZE;
+
+    fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+        match self {
+            None => buf.push(0),
+            Some(it) => {
+                buf.push(1);
+                it.write_export_bytes(buf);
+            }
+        }
+    }
+}
+
+impl AsExportBytes for String {
+    const EXPORT_SIZE: u32 = VARIABLE_EXPORT_SIZE;
+
+    fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: AsExportBytes> AsExportBytes for Vec<T> {
+    const EXPORT_SIZE: u32 = VARIABLE_EXPORT_SIZE;
+
+    fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.len() as u32).to_le_bytes());
+
+        for it in self {
+            it.write_export_bytes(buf);
+        }
+    }
+}
+
+impl<T: AsExportBytes + AsDynSizeBytes + StableType> AsExportBytes for SBox<T> {
+    const EXPORT_SIZE: u32 = T::EXPORT_SIZE;
+
+    #[inline]
+    fn write_export_bytes(&self, buf: &mut Vec<u8>) {
+        (**self).write_export_bytes(buf);
+    }
+}
+
+/// Builder of a canonical, versioned snapshot of the logical contents of stable collections
+///
+/// Useful for reproducible state snapshots - e.g. to let an auditor independently verify the state
+/// of a canister. The output only depends on the contents of the added collections (and on the order
+/// in which they were added), but not on their capacity, the layout of their nodes, the history of
+/// insertions and removals or the location of the data in stable memory: hash-based collections are
+/// exported sorted, the same way as [SHashMap::canonical_iter] does, and boxed values are exported
+/// by their contents, see [AsExportBytes].
+///
+/// All integers are little-endian. The format is:
+/// ```text
+/// header:  magic "SMEX" (4 bytes) | format version (u8) | number of sections (u32)
+/// section: kind (u8, see ExportedKind) | name length (u32) | name (UTF-8)
+///          | key size (u32) | value size (u32) | number of entries (u64)
+///          | payload length in bytes (u64) | payload
+/// ```
+/// The payload is a sequence of entries, each of which is the key followed by the value, both
+/// written by [AsExportBytes]. Key and value sizes are their [AsExportBytes::EXPORT_SIZE], which is
+/// [VARIABLE_EXPORT_SIZE] for types with a variable-length representation. Sequences ([SVec],
+/// [SLog]) and sets have no keys or values respectively - the size of the missing part is `0`.
+///
+/// The whole export is built on the heap, so it is only intended for collections that fit into it.
+///
+/// # Example
+/// ```rust
+/// # use ic_stable_memory::collections::{SHashMap, SVec};
+/// # use ic_stable_memory::stable_memory_init;
+/// # use ic_stable_memory::utils::export::StableExport;
+/// # unsafe { ic_stable_memory::mem::clear(); }
+/// # stable_memory_init();
+/// let mut balances = SHashMap::new();
+/// let mut history = SVec::new();
+///
+/// for i in 0..10u64 {
+///     balances.insert(i, i * 100).expect("Out of memory");
+///     history.push(i).expect("Out of memory");
+/// }
+///
+/// let snapshot = StableExport::new()
+///     .add_hash_map("balances", &balances)
+///     .add_vec("history", &history)
+///     .finish();
+///
+/// assert_eq!(&snapshot[0..4], b"SMEX");
+/// ```
+pub struct StableExport {
+    buf: Vec<u8>,
+    sections: u32,
+}
+
+impl StableExport {
+    /// Creates a new empty [StableExport]
+    pub fn new() -> Self {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(&EXPORT_MAGIC);
+        buf.push(EXPORT_FORMAT_VERSION);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+
+        Self { buf, sections: 0 }
+    }
+
+    /// Adds a section with elements of an [SVec]
+    pub fn add_vec<T>(mut self, name: &str, vec: &SVec<T>) -> Self
+    where
+        T: StableType + AsFixedSizeBytes + AsExportBytes,
+    {
+        let payload =
+            self.begin_section(ExportedKind::Vec, name, 0, T::EXPORT_SIZE, vec.len() as u64);
+
+        for it in vec.iter() {
+            it.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Adds a section with elements of an [SLog]
+    pub fn add_log<T>(mut self, name: &str, log: &SLog<T>) -> Self
+    where
+        T: StableType + AsFixedSizeBytes + AsExportBytes,
+    {
+        let payload = self.begin_section(ExportedKind::Log, name, 0, T::EXPORT_SIZE, log.len());
+
+        let elems = log.rev_iter().collect::<Vec<_>>();
+        for it in elems.iter().rev() {
+            it.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Adds a section with entries of an [SBTreeMap]
+    pub fn add_btree_map<K, V, const B: usize>(
+        mut self,
+        name: &str,
+        map: &SBTreeMap<K, V, B>,
+    ) -> Self
+    where
+        K: StableType + AsFixedSizeBytes + AsExportBytes + Ord,
+        V: StableType + AsFixedSizeBytes + AsExportBytes,
+    {
+        let payload = self.begin_section(
+            ExportedKind::BTreeMap,
+            name,
+            K::EXPORT_SIZE,
+            V::EXPORT_SIZE,
+            map.len(),
+        );
+
+        for (k, v) in map.iter() {
+            k.write_export_bytes(&mut self.buf);
+            v.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Adds a section with elements of an [SBTreeSet]
+    pub fn add_btree_set<T>(mut self, name: &str, set: &SBTreeSet<T>) -> Self
+    where
+        T: StableType + AsFixedSizeBytes + AsExportBytes + Ord,
+    {
+        let payload =
+            self.begin_section(ExportedKind::BTreeSet, name, T::EXPORT_SIZE, 0, set.len());
+
+        for it in set.iter() {
+            it.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Adds a section with entries of an [SHashMap], sorted by key
+    ///
+    /// See [SHashMap::canonical_iter].
+    pub fn add_hash_map<K, V, S>(mut self, name: &str, map: &SHashMap<K, V, S>) -> Self
+    where
+        K: StableType + AsFixedSizeBytes + AsExportBytes + Hash + Eq + Ord + Clone,
+        V: StableType + AsFixedSizeBytes + AsExportBytes + Clone,
+        S: StableHasherFactory,
+    {
+        let payload = self.begin_section(
+            ExportedKind::HashMap,
+            name,
+            K::EXPORT_SIZE,
+            V::EXPORT_SIZE,
+            map.len() as u64,
+        );
+
+        for (k, v) in map.canonical_iter() {
+            k.write_export_bytes(&mut self.buf);
+            v.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Adds a section with elements of an [SHashSet], sorted in ascending order
+    pub fn add_hash_set<T>(mut self, name: &str, set: &SHashSet<T>) -> Self
+    where
+        T: StableType + AsFixedSizeBytes + AsExportBytes + Hash + Eq + Ord + Clone,
+    {
+        let payload = self.begin_section(
+            ExportedKind::HashSet,
+            name,
+            T::EXPORT_SIZE,
+            0,
+            set.len() as u64,
+        );
+
+        let mut elems = set.iter().map(|it| (*it).clone()).collect::<Vec<_>>();
+        elems.sort_unstable();
+
+        for it in elems {
+            it.write_export_bytes(&mut self.buf);
+        }
+
+        self.end_section(payload);
+        self
+    }
+
+    /// Returns the bytes of this export
+    pub fn finish(mut self) -> Vec<u8> {
+        let offset = EXPORT_MAGIC.len() + 1;
+        self.buf[offset..(offset + 4)].copy_from_slice(&self.sections.to_le_bytes());
+
+        self.buf
+    }
+
+    // writes the header of a section, returns the offset of its payload length
+    fn begin_section(
+        &mut self,
+        kind: ExportedKind,
+        name: &str,
+        key_size: u32,
+        value_size: u32,
+        len: u64,
+    ) -> usize {
+        self.sections += 1;
+
+        self.buf.push(kind as u8);
+        self.buf
+            .extend_from_slice(&(name.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(name.as_bytes());
+        self.buf.extend_from_slice(&key_size.to_le_bytes());
+        self.buf.extend_from_slice(&value_size.to_le_bytes());
+        self.buf.extend_from_slice(&len.to_le_bytes());
+
+        let payload_len_offset = self.buf.len();
+        self.buf.extend_from_slice(&0u64.to_le_bytes());
+
+        payload_len_offset
+    }
+
+    fn end_section(&mut self, payload_len_offset: usize) {
+        let payload_len = (self.buf.len() - payload_len_offset - u64::SIZE) as u64;
+
+        self.buf[payload_len_offset..(payload_len_offset + u64::SIZE)]
+            .copy_from_slice(&payload_len.to_le_bytes());
+    }
+}
+
+impl Default for StableExport {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn push_fixed<T: AsFixedSizeBytes>(buf: &mut Vec<u8>, it: &T) {
+    let from = buf.len();
+    buf.resize(from + T::SIZE, 0);
+
+    it.as_fixed_size_bytes(&mut buf[from..]);
+}
+
+const fn sum_export_sizes(sizes: &[u32]) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+
+    while i < sizes.len() {
+        if sizes[i] == VARIABLE_EXPORT_SIZE {
+            return VARIABLE_EXPORT_SIZE;
+        }
+
+        sum += sizes[i];
+        i += 1;
+    }
+
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collections::{SBTreeMap, SBTreeSet, SHashMap, SHashSet, SLog, SVec};
+    use crate::utils::export::{
+        AsExportBytes, ExportedKind, StableExport, EXPORT_FORMAT_VERSION, VARIABLE_EXPORT_SIZE,
+    };
+    use crate::{_debug_validate_allocator, get_allocated_size, stable, stable_memory_init, SBox};
+
+    #[test]
+    fn export_works_fine() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            let mut log = SLog::new();
+            let mut btree_map = SBTreeMap::new();
+            let mut btree_set = SBTreeSet::new();
+            let mut h1 = SHashMap::new();
+            let mut h2 = SHashMap::new_with_capacity(100).unwrap();
+            let mut hash_set = SHashSet::new();
+
+            for i in 0..50u64 {
+                vec.push(i).unwrap();
+                log.push(i as u32).unwrap();
+                btree_map.insert(i, i as u8).unwrap();
+                btree_set.insert(i).unwrap();
+                h1.insert(i, i * 2).unwrap();
+                h2.insert(49 - i, (49 - i) * 2).unwrap();
+                hash_set.insert(i).unwrap();
+            }
+
+            let e1 = StableExport::new()
+                .add_vec("vec", &vec)
+                .add_log("log", &log)
+                .add_btree_map("btree_map", &btree_map)
+                .add_btree_set("btree_set", &btree_set)
+                .add_hash_map("hash_map", &h1)
+                .add_hash_set("hash_set", &hash_set)
+                .finish();
+
+            assert_eq!(&e1[0..4], b"SMEX");
+            assert_eq!(e1[4], EXPORT_FORMAT_VERSION);
+            assert_eq!(u32::from_le_bytes(e1[5..9].try_into().unwrap()), 6);
+
+            // the first section
+            assert_eq!(e1[9], ExportedKind::Vec as u8);
+            assert_eq!(u32::from_le_bytes(e1[10..14].try_into().unwrap()), 3);
+            assert_eq!(&e1[14..17], b"vec");
+            assert_eq!(u32::from_le_bytes(e1[17..21].try_into().unwrap()), 0);
+            assert_eq!(u32::from_le_bytes(e1[21..25].try_into().unwrap()), 8);
+            assert_eq!(u64::from_le_bytes(e1[25..33].try_into().unwrap()), 50);
+            assert_eq!(u64::from_le_bytes(e1[33..41].try_into().unwrap()), 400);
+            assert_eq!(u64::from_le_bytes(e1[41..49].try_into().unwrap()), 0);
+            assert_eq!(u64::from_le_bytes(e1[49..57].try_into().unwrap()), 1);
+
+            // hash maps with the same contents are exported identically
+            let a = StableExport::new().add_hash_map("m", &h1).finish();
+            let b = StableExport::new().add_hash_map("m", &h2).finish();
+            assert_eq!(a, b);
+
+            let payload = &a[(a.len() - 50 * 16)..];
+            for i in 0..50u64 {
+                let from = i as usize * 16;
+
+                assert_eq!(&payload[from..(from + 8)], &i.to_le_bytes());
+                assert_eq!(&payload[(from + 8)..(from + 16)], &(i * 2).to_le_bytes());
+            }
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+
+    #[test]
+    fn boxed_values_are_exported_by_contents() {
+        stable::clear();
+        stable_memory_init();
+
+        {
+            let mut v1 = SVec::new();
+            let mut v2 = SVec::new();
+            let mut junk = Vec::new();
+
+            for i in 0..20u64 {
+                v1.push(SBox::new(format!("value {i}")).unwrap()).unwrap();
+
+                junk.push(SBox::new(i).unwrap());
+                v2.push(SBox::new(format!("value {i}")).unwrap()).unwrap();
+            }
+
+            let e1 = StableExport::new().add_vec("v", &v1).finish();
+            let e2 = StableExport::new().add_vec("v", &v2).finish();
+
+            assert_eq!(e1, e2);
+
+            // value size
+            assert_eq!(
+                u32::from_le_bytes(e1[19..23].try_into().unwrap()),
+                VARIABLE_EXPORT_SIZE
+            );
+
+            let mut expected = Vec::new();
+            for i in 0..20u64 {
+                format!("value {i}").write_export_bytes(&mut expected);
+            }
+
+            assert_eq!(&e1[(e1.len() - expected.len())..], &expected[..]);
+            assert_eq!(
+                u64::from_le_bytes(e1[31..39].try_into().unwrap()),
+                expected.len() as u64
+            );
+
+            assert_eq!(
+                <(u64, [u8; 3], Option<u8>)>::EXPORT_SIZE,
+                VARIABLE_EXPORT_SIZE
+            );
+            assert_eq!(<(u64, [u16; 3], bool)>::EXPORT_SIZE, 15);
+        }
+
+        _debug_validate_allocator();
+        assert_eq!(get_allocated_size(), 0);
+    }
+}
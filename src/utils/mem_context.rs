@@ -10,6 +10,10 @@
 //! canister's stable memory, than in its heap.
 //!
 //! This makes it possible to write full-scale tests which use stable memory as their main memory.
+//!
+//! Both backends implement the [StableMemory] trait. Outside of wasm, any other implementation of this
+//! trait can be plugged in with [stable::set_backend] - e.g. to inspect or to fail stable memory
+//! operations, when unit-testing stable structures natively.
 
 use std::cmp::min;
 
@@ -20,21 +24,41 @@ pub const PAGE_SIZE_BYTES: u64 = 64 * 1024;
 #[derive(Debug, Copy, Clone)]
 pub struct OutOfMemory;
 
-pub(crate) trait MemContext {
+/// A backend of stable memory, which is used by this crate
+///
+/// Mirrors the raw stable memory API of the IC: the memory consists of [PAGE_SIZE_BYTES] sized pages,
+/// it can only grow and all reads and writes are performed within grown pages.
+pub trait StableMemory {
+    /// Returns the number of grown pages
     fn size_pages(&self) -> u64;
+
+    /// Grows the memory by `new_pages` pages, returning the previous number of pages
+    ///
+    /// Returns [OutOfMemory], if it is impossible to grow.
     fn grow(&mut self, new_pages: u64) -> Result<u64, OutOfMemory>;
+
+    /// Reads `buf.len()` bytes starting from `offset` into `buf`
     fn read(&self, offset: u64, buf: &mut [u8]);
+
+    /// Writes `buf` starting from `offset`
     fn write(&mut self, offset: u64, buf: &[u8]);
+
+    /// Wipes out the memory, making it zero pages again
+    fn clear(&mut self);
 }
 
-#[derive(Clone)]
-pub(crate) struct StableMemContext;
+/// Real stable memory of a canister
+///
+/// Only available, when compiled to wasm.
+#[cfg(target_family = "wasm")]
+#[derive(Clone, Copy, Default)]
+pub struct IcStableMemory;
 
 #[cfg(target_family = "wasm")]
 use ic_cdk::api::stable::{stable64_grow, stable64_read, stable64_size, stable64_write};
 
 #[cfg(target_family = "wasm")]
-impl MemContext for StableMemContext {
+impl StableMemory for IcStableMemory {
     #[inline]
     fn size_pages(&self) -> u64 {
         stable64_size()
@@ -54,20 +78,32 @@ impl MemContext for StableMemContext {
     fn write(&mut self, offset: u64, buf: &[u8]) {
         stable64_write(offset, buf)
     }
+
+    /// # Panics
+    /// Always panics, since stable memory of a canister can't shrink.
+    fn clear(&mut self) {
+        panic!("Stable memory of a canister can't be cleared");
+    }
 }
 
-#[derive(Clone)]
-pub(crate) struct TestMemContext {
-    pub pages: Vec<[u8; PAGE_SIZE_BYTES as usize]>,
+/// Stable memory emulation, which stores pages in heap memory
+///
+/// Used by default, when compiled to something other than wasm. Never runs out of memory by itself -
+/// use [set_grow_ceiling_bytes](crate::set_grow_ceiling_bytes) to emulate that.
+#[derive(Clone, Default)]
+pub struct HeapStableMemory {
+    pages: Vec<[u8; PAGE_SIZE_BYTES as usize]>,
 }
 
-impl TestMemContext {
-    const fn default() -> Self {
+impl HeapStableMemory {
+    /// Creates an empty (zero pages) memory
+    #[inline]
+    pub const fn new() -> Self {
         Self { pages: Vec::new() }
     }
 }
 
-impl MemContext for TestMemContext {
+impl StableMemory for HeapStableMemory {
     #[inline]
     fn size_pages(&self) -> u64 {
         self.pages.len() as u64
@@ -156,45 +192,102 @@ impl MemContext for TestMemContext {
                 ..(start_page_size + pages_in_between * PAGE_SIZE_BYTES as usize + last_page_size)],
         );
     }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
 }
 
 #[cfg(target_family = "wasm")]
 pub mod stable {
-    use crate::utils::mem_context::{MemContext, OutOfMemory, StableMemContext};
+    use crate::utils::mem_context::{IcStableMemory, OutOfMemory, StableMemory};
 
     #[inline]
     pub fn size_pages() -> u64 {
-        MemContext::size_pages(&StableMemContext)
+        StableMemory::size_pages(&IcStableMemory)
     }
 
     #[inline]
     pub fn grow(new_pages: u64) -> Result<u64, OutOfMemory> {
-        MemContext::grow(&mut StableMemContext, new_pages)
+        StableMemory::grow(&mut IcStableMemory, new_pages)
     }
 
     #[inline]
     pub fn read(offset: u64, buf: &mut [u8]) {
-        MemContext::read(&StableMemContext, offset, buf)
+        StableMemory::read(&IcStableMemory, offset, buf)
     }
 
     #[inline]
     pub fn write(offset: u64, buf: &[u8]) {
-        MemContext::write(&mut StableMemContext, offset, buf)
+        StableMemory::write(&mut IcStableMemory, offset, buf)
     }
 }
 
 #[cfg(not(target_family = "wasm"))]
 pub mod stable {
-    use crate::utils::mem_context::{MemContext, OutOfMemory, TestMemContext};
+    use crate::utils::mem_context::{HeapStableMemory, OutOfMemory, StableMemory};
     use std::cell::RefCell;
 
     thread_local! {
-        static CONTEXT: RefCell<TestMemContext> = RefCell::new(TestMemContext::default());
+        static CONTEXT: RefCell<Box<dyn StableMemory>> = RefCell::new(Box::new(HeapStableMemory::new()));
+    }
+
+    /// Replaces the stable memory backend of the current thread
+    ///
+    /// By default [HeapStableMemory] is used. The previous backend is dropped together with all its
+    /// data, so this function should be called before the allocator is initialized - e.g. before
+    /// [stable_memory_init](crate::stable_memory_init).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use ic_stable_memory::utils::mem_context::{StableMemory, OutOfMemory, PAGE_SIZE_BYTES};
+    /// # use ic_stable_memory::{stable, stable_memory_init, SBox};
+    /// // a flat stable memory, which can't grow more than 10 pages
+    /// struct SmallMemory(Vec<u8>);
+    ///
+    /// impl StableMemory for SmallMemory {
+    ///     fn size_pages(&self) -> u64 {
+    ///         self.0.len() as u64 / PAGE_SIZE_BYTES
+    ///     }
+    ///
+    ///     fn grow(&mut self, new_pages: u64) -> Result<u64, OutOfMemory> {
+    ///         let prev_pages = self.size_pages();
+    ///         if prev_pages + new_pages > 10 {
+    ///             return Err(OutOfMemory);
+    ///         }
+    ///
+    ///         self.0.resize(((prev_pages + new_pages) * PAGE_SIZE_BYTES) as usize, 0);
+    ///         Ok(prev_pages)
+    ///     }
+    ///
+    ///     fn read(&self, offset: u64, buf: &mut [u8]) {
+    ///         buf.copy_from_slice(&self.0[offset as usize..offset as usize + buf.len()]);
+    ///     }
+    ///
+    ///     fn write(&mut self, offset: u64, buf: &[u8]) {
+    ///         self.0[offset as usize..offset as usize + buf.len()].copy_from_slice(buf);
+    ///     }
+    ///
+    ///     fn clear(&mut self) {
+    ///         self.0.clear();
+    ///     }
+    /// }
+    ///
+    /// stable::set_backend(SmallMemory(Vec::new()));
+    /// stable_memory_init();
+    ///
+    /// assert!(SBox::new(vec![0u8; 1024]).is_ok());
+    /// assert!(SBox::new(vec![0u8; PAGE_SIZE_BYTES as usize * 10]).is_err());
+    /// ```
+    #[inline]
+    pub fn set_backend<M: StableMemory + 'static>(backend: M) {
+        CONTEXT.with(|it| *it.borrow_mut() = Box::new(backend))
     }
 
     #[inline]
     pub fn clear() {
-        CONTEXT.with(|it| it.borrow_mut().pages.clear())
+        CONTEXT.with(|it| it.borrow_mut().clear())
     }
 
     #[inline]
@@ -220,9 +313,13 @@ pub mod stable {
 
 #[cfg(test)]
 mod tests {
-    use crate::{stable, PAGE_SIZE_BYTES};
+    use crate::collections::SVec;
+    use crate::utils::mem_context::{HeapStableMemory, OutOfMemory, StableMemory};
+    use crate::{stable, stable_memory_init, PAGE_SIZE_BYTES};
     use rand::seq::SliceRandom;
     use rand::{thread_rng, Rng};
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     #[test]
     fn random_works_fine() {
@@ -273,4 +370,54 @@ mod tests {
 
         assert_eq!(buf[25..PAGE_SIZE_BYTES as usize * 10 - 25], buf1);
     }
+
+    struct CountingMemory {
+        inner: HeapStableMemory,
+        writes: Rc<Cell<usize>>,
+    }
+
+    impl StableMemory for CountingMemory {
+        fn size_pages(&self) -> u64 {
+            self.inner.size_pages()
+        }
+
+        fn grow(&mut self, new_pages: u64) -> Result<u64, OutOfMemory> {
+            self.inner.grow(new_pages)
+        }
+
+        fn read(&self, offset: u64, buf: &mut [u8]) {
+            self.inner.read(offset, buf)
+        }
+
+        fn write(&mut self, offset: u64, buf: &[u8]) {
+            self.writes.set(self.writes.get() + 1);
+            self.inner.write(offset, buf)
+        }
+
+        fn clear(&mut self) {
+            self.inner.clear()
+        }
+    }
+
+    #[test]
+    fn custom_backend_works_fine() {
+        let writes = Rc::new(Cell::new(0));
+        stable::set_backend(CountingMemory {
+            inner: HeapStableMemory::new(),
+            writes: writes.clone(),
+        });
+        stable_memory_init();
+
+        {
+            let mut vec = SVec::new();
+            let before = writes.get();
+            vec.push(10u64).unwrap();
+
+            assert!(writes.get() > before);
+            assert!(stable::size_pages() > 0);
+        }
+
+        stable::clear();
+        assert_eq!(stable::size_pages(), 0);
+    }
 }
@@ -2,6 +2,7 @@
 
 #[doc(hidden)]
 pub mod certification;
+pub mod export;
 #[doc(hidden)]
 pub mod math;
 pub mod mem_context;